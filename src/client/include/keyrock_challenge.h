@@ -0,0 +1,50 @@
+/*
+ * C API of the keyrock_challenge client library, see src/ffi.rs.
+ * Link against libkeyrock_challenge_client (cdylib or staticlib).
+ */
+#ifndef KEYROCK_CHALLENGE_H
+#define KEYROCK_CHALLENGE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct kc_client kc_client;
+
+typedef struct {
+    double price;
+    double amount;
+    /* 1 = Binance, 2 = Bitstamp, 0 = unknown to this library */
+    int32_t exchange_id;
+} kc_level;
+
+typedef struct {
+    uint64_t sequence;
+    bool has_spread;
+    /* only meaningful if has_spread is set */
+    double spread;
+    kc_level *bids;
+    size_t bids_len;
+    kc_level *asks;
+    size_t asks_len;
+} kc_summary;
+
+/* NULL if the url is invalid or the server can not be reached */
+kc_client *kc_connect(const char *url);
+
+/* NULL if no summary arrived within timeout_ms or the stream ended, 0 does not wait */
+kc_summary *kc_poll_summary(kc_client *client, uint32_t timeout_ms);
+
+void kc_summary_free(kc_summary *summary);
+
+void kc_free(kc_client *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,6 +1,9 @@
-use keyrock_challenge_proto::orderbook::Summary;
-use std::io::{Write, StdoutLock};
 use colored::Colorize;
+use keyrock_challenge_proto::orderbook::Summary;
+use std::{
+    io::{StdoutLock, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 fn clear_console(lock: &mut StdoutLock) {
     let _ = write!(lock, "{esc}c", esc = 27 as char);
@@ -20,8 +23,32 @@ fn pad_right(original: &str, padded: &mut String, length: u8, character: char) {
     }
 }
 
-fn render_spread(lock: &mut StdoutLock, spread: f64) {
-    let _ = writeln!(lock, "{} {}", "Spread:".bold(), spread);
+fn render_spread(lock: &mut StdoutLock, spread: Option<f64>) {
+    match spread {
+        Some(spread) => {
+            let _ = write!(lock, "{} {}", "Spread:".bold(), spread);
+        }
+        None => {
+            let _ = write!(lock, "{} -", "Spread:".bold());
+        }
+    }
+}
+
+fn render_snapshot_ages(lock: &mut StdoutLock, summary: &Summary) {
+    let mut ages: Vec<_> = summary.snapshot_age_ms.iter().collect();
+    ages.sort();
+
+    for (exchange, age) in ages {
+        let _ = write!(lock, "  {} {}ms", exchange, age);
+    }
+    if summary.aggregated_at_us != 0 {
+        let now_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_micros() as u64);
+        let latency_us = now_us.saturating_sub(summary.aggregated_at_us);
+        let _ = write!(lock, "  latency {}ms", latency_us / 1000);
+    }
+    let _ = writeln!(lock);
 }
 
 fn render_spread_padding(lock: &mut StdoutLock) {
@@ -66,11 +93,18 @@ fn render_table(lock: &mut StdoutLock, summary: &Summary) {
             amount_padding_size,
             ' ',
         );
-        let _ = write!(lock, "{} {} {}", price_padded.red(), amount_padded, ask.exchange);
+        let _ = write!(
+            lock,
+            "{} {} {}",
+            price_padded.red(),
+            amount_padded,
+            ask.exchange
+        );
         let _ = writeln!(lock);
     }
 
     render_spread(lock, summary.spread);
+    render_snapshot_ages(lock, summary);
 
     for bid in &summary.bids {
         render_spread_padding(lock);
@@ -0,0 +1,297 @@
+//! Typed counterparts of the wire messages. The proto carries prices as floats, exchanges as names
+//! and timestamps as unix microseconds; converting a [`orderbook::Summary`] into a [`Summary`]
+//! parses them into exact decimals, an exchange enum and [`SystemTime`]s once, and fails with a
+//! [`ConversionError`] naming the offending field instead of handing out a malformed book.
+
+use keyrock_challenge_proto::orderbook;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub use keyrock_challenge_core::decimal::Decimal;
+
+/**
+ * The exchanges of the proto. Exchanges added to the server after this library was built keep the
+ * name they are published with.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Exchange {
+    Binance,
+    Bitstamp,
+    Kraken,
+    Coinbase,
+    Okx,
+    Other(String),
+}
+
+impl Exchange {
+    fn from_wire(exchange_id: i32, name: &str) -> Exchange {
+        match orderbook::Exchange::from_i32(exchange_id) {
+            Some(orderbook::Exchange::Binance) => Exchange::Binance,
+            Some(orderbook::Exchange::Bitstamp) => Exchange::Bitstamp,
+            Some(orderbook::Exchange::Kraken) => Exchange::Kraken,
+            Some(orderbook::Exchange::Coinbase) => Exchange::Coinbase,
+            Some(orderbook::Exchange::Okx) => Exchange::Okx,
+            Some(orderbook::Exchange::Unspecified) | None => Exchange::Other(name.to_string()),
+        }
+    }
+}
+
+/**
+ * Why a wire message could not be converted, with the field and the value that was rejected.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionError {
+    /// not finite or out of range of a decimal
+    InvalidDecimal { field: &'static str, value: f64 },
+    /// a price or an amount of a level that is not positive
+    NotPositive { field: &'static str, value: f64 },
+    /// a unix timestamp that does not fit a `SystemTime`
+    InvalidTimestamp { field: &'static str, value: u64 },
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::InvalidDecimal { field, value } => {
+                write!(f, "{} {} is not a valid decimal", field, value)
+            }
+            ConversionError::NotPositive { field, value } => {
+                write!(f, "{} {} is not positive", field, value)
+            }
+            ConversionError::InvalidTimestamp { field, value } => {
+                write!(f, "{} {} is not a valid timestamp", field, value)
+            }
+        }
+    }
+}
+
+impl Error for ConversionError {}
+
+fn decimal(field: &'static str, value: f64) -> Result<Decimal, ConversionError> {
+    Decimal::try_from(value).map_err(|_| ConversionError::InvalidDecimal { field, value })
+}
+
+fn positive(field: &'static str, value: f64) -> Result<Decimal, ConversionError> {
+    let decimal = decimal(field, value)?;
+    match decimal.is_positive() {
+        true => Ok(decimal),
+        false => Err(ConversionError::NotPositive { field, value }),
+    }
+}
+
+fn optional_decimal(
+    field: &'static str,
+    value: Option<f64>,
+) -> Result<Option<Decimal>, ConversionError> {
+    value.map(|value| decimal(field, value)).transpose()
+}
+
+fn timestamp(field: &'static str, unix_us: u64) -> Result<SystemTime, ConversionError> {
+    UNIX_EPOCH
+        .checked_add(Duration::from_micros(unix_us))
+        .ok_or(ConversionError::InvalidTimestamp {
+            field,
+            value: unix_us,
+        })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contribution {
+    pub exchange: Exchange,
+    pub amount: Decimal,
+    pub provenance: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub exchange: Exchange,
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// only set if the level combines the same price of several exchanges
+    pub contributors: Vec<Contribution>,
+    /// hash of the raw exchange message the level was read from, if the server exposes it
+    pub provenance: Option<u64>,
+}
+
+fn provenance(provenance: u64) -> Option<u64> {
+    match provenance {
+        0 => None,
+        provenance => Some(provenance),
+    }
+}
+
+impl TryFrom<orderbook::Level> for Level {
+    type Error = ConversionError;
+
+    fn try_from(level: orderbook::Level) -> Result<Self, Self::Error> {
+        Ok(Level {
+            exchange: Exchange::from_wire(level.exchange_id, &level.exchange),
+            price: positive("price", level.price)?,
+            amount: positive("amount", level.amount)?,
+            contributors: level
+                .contributors
+                .into_iter()
+                .map(|contribution| {
+                    Ok(Contribution {
+                        exchange: Exchange::from_wire(
+                            contribution.exchange_id,
+                            &contribution.exchange,
+                        ),
+                        amount: positive("contribution amount", contribution.amount)?,
+                        provenance: provenance(contribution.provenance),
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            provenance: provenance(level.provenance),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub symbol: String,
+    pub sequence: u64,
+    /// set on the first summary published after the server (re)started
+    pub restarted: bool,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+    /// negative while the merged book is crossed
+    pub spread: Option<Decimal>,
+    pub mid: Option<Decimal>,
+    pub vwap: Option<Decimal>,
+    pub microprice: Option<Decimal>,
+    /// none for servers not reporting it
+    pub aggregated_at: Option<SystemTime>,
+    /// event time of each exchange's contributing snapshot, as reported by the exchange
+    pub exchange_timestamps: HashMap<String, SystemTime>,
+    /// a heartbeat without levels, published while fewer venues than the quorum are fresh
+    pub quorum_lost: bool,
+    /// a heartbeat without levels, published during a scheduled quiet period
+    pub quiet_period: bool,
+    /// the stream of a venue leads the others badly, the book may not reflect the market
+    pub lead_degraded: bool,
+    /// a heartbeat without levels, published while an admin paused publishing
+    pub paused: bool,
+}
+
+impl TryFrom<orderbook::Summary> for Summary {
+    type Error = ConversionError;
+
+    fn try_from(summary: orderbook::Summary) -> Result<Self, Self::Error> {
+        let levels = |levels: Vec<orderbook::Level>| -> Result<Vec<Level>, ConversionError> {
+            levels.into_iter().map(Level::try_from).collect()
+        };
+        Ok(Summary {
+            bids: levels(summary.bids)?,
+            asks: levels(summary.asks)?,
+            spread: optional_decimal("spread", summary.spread)?,
+            mid: optional_decimal("mid", summary.mid)?,
+            vwap: optional_decimal("vwap", summary.vwap)?,
+            microprice: optional_decimal("microprice", summary.microprice)?,
+            aggregated_at: match summary.aggregated_at_us {
+                0 => None,
+                unix_us => Some(timestamp("aggregated_at_us", unix_us)?),
+            },
+            exchange_timestamps: summary
+                .exchange_timestamp_us
+                .into_iter()
+                .map(|(exchange, unix_us)| {
+                    Ok((exchange, timestamp("exchange_timestamp_us", unix_us)?))
+                })
+                .collect::<Result<_, _>>()?,
+            symbol: summary.symbol,
+            sequence: summary.sequence,
+            restarted: summary.restarted,
+            quorum_lost: summary.quorum_lost,
+            quiet_period: summary.quiet_period,
+            lead_degraded: summary.lead_degraded,
+            paused: summary.paused,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConversionError, Decimal, Exchange, Summary};
+    use keyrock_challenge_proto::orderbook::{self, Contribution, Level};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    fn level(exchange_id: i32, exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            exchange_id,
+            price,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_convert_summary_to_domain_types() {
+        // Arrange
+        let mut bid = level(1, "Binance", 0.0745, 1.5);
+        bid.contributors = vec![Contribution {
+            exchange: "Coinbase".to_string(),
+            exchange_id: 0,
+            amount: 0.5,
+            provenance: 7,
+        }];
+        let summary = orderbook::Summary {
+            symbol: "ethbtc".to_string(),
+            sequence: 7,
+            bids: vec![bid],
+            asks: vec![level(2, "Bitstamp", 0.0746, 2.)],
+            spread: Some(0.0001),
+            aggregated_at_us: 1_500_000,
+            exchange_timestamp_us: [("Binance".to_string(), 1_000_000)].into(),
+            ..Default::default()
+        };
+
+        // Act
+        let summary = Summary::try_from(summary).unwrap();
+
+        // Assert
+        assert!(summary.symbol == "ethbtc" && summary.sequence == 7);
+        assert!(summary.bids[0].exchange == Exchange::Binance);
+        assert!(summary.bids[0].price == decimal("0.0745"));
+        assert!(
+            summary.bids[0].contributors[0].exchange == Exchange::Other("Coinbase".to_string())
+        );
+        assert!(summary.bids[0].contributors[0].provenance == Some(7));
+        assert!(
+            summary.asks[0].exchange == Exchange::Bitstamp && summary.asks[0].provenance.is_none()
+        );
+        assert!(summary.spread == Some(decimal("0.0001")) && summary.mid.is_none());
+        assert!(summary.aggregated_at == Some(UNIX_EPOCH + Duration::from_millis(1500)));
+        assert!(summary.exchange_timestamps["Binance"] == UNIX_EPOCH + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_reject_malformed_levels() {
+        let with_bid = |price: f64, amount: f64| orderbook::Summary {
+            bids: vec![level(1, "Binance", price, amount)],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Summary::try_from(with_bid(f64::NAN, 1.)),
+            Err(ConversionError::InvalidDecimal { field: "price", .. })
+        ));
+        assert!(
+            Summary::try_from(with_bid(0.0745, 0.)).unwrap_err()
+                == ConversionError::NotPositive {
+                    field: "amount",
+                    value: 0.
+                }
+        );
+        assert!(Summary::try_from(with_bid(0.0745, 1.)).is_ok());
+    }
+}
@@ -0,0 +1,247 @@
+//! A C-compatible API subscribing to the book summaries of a server:
+//!
+//! ```c
+//! kc_client *client = kc_connect("http://[::1]:8080");
+//! kc_summary *summary = kc_poll_summary(client, 1000);
+//! if (summary) {
+//!     ...
+//!     kc_summary_free(summary);
+//! }
+//! kc_free(client);
+//! ```
+//!
+//! Every client owns a runtime streaming the summaries in the background, polling only takes the
+//! next buffered one. A client that does not keep up holds up its stream until it polls again, and
+//! once the server's queue for it is full it misses the oldest summaries instead.
+
+use keyrock_challenge_proto::orderbook::{
+    orderbook_aggregator_client, BookSummaryRequest, Level, Summary,
+};
+use std::{
+    ffi::{c_char, CStr},
+    ptr,
+    time::Duration,
+};
+use tokio::{
+    runtime::Runtime,
+    sync::mpsc::{self, Receiver},
+};
+use tokio_stream::StreamExt;
+
+const SUMMARY_BUFFER_SIZE: usize = 64;
+
+pub struct Client {
+    runtime: Runtime,
+    summaries: Receiver<Summary>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CLevel {
+    pub price: f64,
+    pub amount: f64,
+    /// the `Exchange` enum of the proto, `0` for exchanges this library does not know yet
+    pub exchange_id: i32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct CSummary {
+    pub sequence: u64,
+    pub has_spread: bool,
+    /// only meaningful if `has_spread` is set
+    pub spread: f64,
+    pub bids: *mut CLevel,
+    pub bids_len: usize,
+    pub asks: *mut CLevel,
+    pub asks_len: usize,
+}
+
+fn into_c_levels(levels: Vec<Level>) -> (*mut CLevel, usize) {
+    let levels: Box<[CLevel]> = levels
+        .into_iter()
+        .map(|level| CLevel {
+            price: level.price,
+            amount: level.amount,
+            exchange_id: level.exchange_id,
+        })
+        .collect();
+    let len = levels.len();
+    (Box::into_raw(levels) as *mut CLevel, len)
+}
+
+/**
+ * # Safety
+ * `levels` and `len` have to stem from `into_c_levels` and must not be used afterwards.
+ */
+unsafe fn free_c_levels(levels: *mut CLevel, len: usize) {
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(levels, len)));
+}
+
+fn into_c_summary(summary: Summary) -> CSummary {
+    let (bids, bids_len) = into_c_levels(summary.bids);
+    let (asks, asks_len) = into_c_levels(summary.asks);
+    CSummary {
+        sequence: summary.sequence,
+        has_spread: summary.spread.is_some(),
+        spread: summary.spread.unwrap_or_default(),
+        bids,
+        bids_len,
+        asks,
+        asks_len,
+    }
+}
+
+fn connect(url: String) -> Result<Client, Box<dyn std::error::Error>> {
+    let runtime = Runtime::new()?;
+    let mut stream = runtime.block_on(async {
+        let mut client =
+            orderbook_aggregator_client::OrderbookAggregatorClient::connect(url).await?;
+        Ok::<_, Box<dyn std::error::Error>>(
+            client
+                .book_summary(BookSummaryRequest::default())
+                .await?
+                .into_inner(),
+        )
+    })?;
+
+    let (tx, summaries) = mpsc::channel(SUMMARY_BUFFER_SIZE);
+    runtime.spawn(async move {
+        while let Some(Ok(summary)) = stream.next().await {
+            if tx.send(summary).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Client { runtime, summaries })
+}
+
+/**
+ * Connects to the server at the given url and subscribes to its book summaries.
+ * Returns null if the url is invalid or the server can not be reached.
+ *
+ * # Safety
+ * `url` has to be a valid, null-terminated string.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn kc_connect(url: *const c_char) -> *mut Client {
+    if url.is_null() {
+        return ptr::null_mut();
+    }
+    let url = match CStr::from_ptr(url).to_str() {
+        Ok(url) => url.to_string(),
+        Err(_) => return ptr::null_mut(),
+    };
+    match connect(url) {
+        Ok(client) => Box::into_raw(Box::new(client)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/**
+ * Takes the next summary, waiting at most `timeout_ms` for it. Returns null if none arrived in
+ * time or the stream ended. A returned summary has to be released with `kc_summary_free`.
+ *
+ * # Safety
+ * `client` has to stem from `kc_connect` and must not have been freed.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn kc_poll_summary(client: *mut Client, timeout_ms: u32) -> *mut CSummary {
+    let client = match client.as_mut() {
+        Some(client) => client,
+        None => return ptr::null_mut(),
+    };
+    let summaries = &mut client.summaries;
+    let summary = match timeout_ms {
+        0 => summaries.try_recv().ok(),
+        _ => client.runtime.block_on(async {
+            tokio::time::timeout(Duration::from_millis(timeout_ms as u64), summaries.recv())
+                .await
+                .ok()
+                .flatten()
+        }),
+    };
+    match summary {
+        Some(summary) => Box::into_raw(Box::new(into_c_summary(summary))),
+        None => ptr::null_mut(),
+    }
+}
+
+/**
+ * # Safety
+ * `summary` has to stem from `kc_poll_summary` and must not be used afterwards. Null is ignored.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn kc_summary_free(summary: *mut CSummary) {
+    if summary.is_null() {
+        return;
+    }
+    let summary = Box::from_raw(summary);
+    free_c_levels(summary.bids, summary.bids_len);
+    free_c_levels(summary.asks, summary.asks_len);
+}
+
+/**
+ * Closes the subscription and releases the client.
+ *
+ * # Safety
+ * `client` has to stem from `kc_connect` and must not be used afterwards. Null is ignored.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn kc_free(client: *mut Client) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{into_c_summary, kc_connect, kc_summary_free, CLevel};
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+    use std::{ffi::CString, ptr, slice};
+
+    #[test]
+    fn should_convert_summary_to_c_layout() {
+        // Arrange
+        let summary = Summary {
+            spread: Some(0.5),
+            bids: vec![Level {
+                price: 10.,
+                amount: 2.,
+                exchange_id: 1,
+                ..Default::default()
+            }],
+            sequence: 7,
+            ..Default::default()
+        };
+
+        // Act
+        let converted = Box::into_raw(Box::new(into_c_summary(summary)));
+
+        // Assert
+        unsafe {
+            let c_summary = &*converted;
+            assert!(c_summary.sequence == 7 && c_summary.has_spread && c_summary.spread == 0.5);
+            let bids = slice::from_raw_parts(c_summary.bids, c_summary.bids_len);
+            assert!(
+                bids == [CLevel {
+                    price: 10.,
+                    amount: 2.,
+                    exchange_id: 1
+                }]
+            );
+            assert!(c_summary.asks_len == 0);
+            kc_summary_free(converted);
+        }
+    }
+
+    #[test]
+    fn should_return_null_for_invalid_url() {
+        let url = CString::new("not a url").unwrap();
+
+        unsafe {
+            assert!(kc_connect(url.as_ptr()).is_null());
+            assert!(kc_connect(ptr::null()).is_null());
+        }
+    }
+}
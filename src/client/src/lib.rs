@@ -0,0 +1,8 @@
+//! The client library. Besides the Rust API of the proto crate it offers typed counterparts of the
+//! wire messages in [`domain`], the reassembly of delta streams in [`reassembly`], and exports a
+//! minimal C API in [`ffi`], declared in `include/keyrock_challenge.h`, for trading systems that
+//! are not written in Rust.
+
+pub mod domain;
+pub mod ffi;
+pub mod reassembly;
@@ -1,6 +1,6 @@
 mod console_renderer;
 
-use keyrock_challenge_proto::orderbook::{orderbook_aggregator_client, Empty};
+use keyrock_challenge_proto::orderbook::{orderbook_aggregator_client, BookSummaryRequest};
 use tokio_stream::StreamExt;
 
 const SERVER_URL: &str = "http://[::1]:8080";
@@ -10,7 +10,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut client =
         orderbook_aggregator_client::OrderbookAggregatorClient::connect(SERVER_URL).await?;
 
-    let mut stream = client.book_summary(Empty {}).await?.into_inner();
+    // renders every summary unless a rate is passed after the symbol
+    let max_rate_hz = match std::env::args().nth(2) {
+        Some(max_rate_hz) => max_rate_hz.parse()?,
+        None => 0,
+    };
+    let mut request = tonic::Request::new(BookSummaryRequest {
+        max_rate_hz,
+        ..Default::default()
+    });
+    // the server streams its first symbol unless another one is selected
+    if let Some(symbol) = std::env::args().nth(1) {
+        request.metadata_mut().insert("x-symbol", symbol.parse()?);
+    }
+    let mut stream = client.book_summary(request).await?.into_inner();
 
     while let Some(summary) = stream.next().await {
         if let Ok(summary) = summary {
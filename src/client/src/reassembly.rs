@@ -0,0 +1,136 @@
+//! Reassembling the summaries of a `BookSummaryDeltas` stream. The server sends a full snapshot
+//! first and every `snapshot_interval` updates, and in between only the changes to the previous
+//! summary. A [`Reassembler`] keeps the summary reassembled so far and applies each update to it,
+//! so the caller handles complete summaries just like those of `BookSummary`.
+
+use keyrock_challenge_core::delta::{self, DeltaError};
+use keyrock_challenge_proto::orderbook::{summary_update::Update, Summary, SummaryUpdate};
+use std::{error::Error, fmt};
+
+/**
+ * Why an update could not be applied. The reassembler waits for the next snapshot after either.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReassemblyError {
+    /// a delta arrived before a snapshot, or after an update that failed
+    MissingSnapshot,
+    /// the changes of a delta do not fit the summary they are applied to
+    Mismatch { sequence: u64, error: DeltaError },
+}
+
+impl fmt::Display for ReassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReassemblyError::MissingSnapshot => write!(f, "delta without a preceding snapshot"),
+            ReassemblyError::Mismatch { sequence, error } => {
+                write!(f, "delta of sequence {} does not fit: {}", sequence, error)
+            }
+        }
+    }
+}
+
+impl Error for ReassemblyError {}
+
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    summary: Option<Summary>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler::default()
+    }
+
+    /**
+     * Applies the update and returns the complete summary it stands for. An update without content,
+     * e.g. one of a newer server, is skipped with None.
+     */
+    pub fn apply(&mut self, update: SummaryUpdate) -> Result<Option<Summary>, ReassemblyError> {
+        let summary = match update.update {
+            Some(Update::Snapshot(snapshot)) => snapshot,
+            Some(Update::Delta(changes)) => {
+                let mut summary = self
+                    .summary
+                    .take()
+                    .ok_or(ReassemblyError::MissingSnapshot)?;
+                let sequence = changes.sequence;
+                delta::apply(&mut summary, changes)
+                    .map_err(|error| ReassemblyError::Mismatch { sequence, error })?;
+                summary
+            }
+            None => return Ok(None),
+        };
+        self.summary = Some(summary.clone());
+        Ok(Some(summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reassembler, ReassemblyError};
+    use keyrock_challenge_core::delta::{self, DeltaError};
+    use keyrock_challenge_proto::orderbook::{
+        summary_update::Update, Level, Summary, SummaryUpdate,
+    };
+
+    fn summary(sequence: u64, levels: usize) -> Summary {
+        Summary {
+            symbol: "ethbtc".to_string(),
+            sequence,
+            bids: (0..levels)
+                .map(|i| Level {
+                    exchange: "Binance".to_string(),
+                    price: 0.0745 - i as f64 * 0.0001,
+                    amount: sequence as f64,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn update(update: Update) -> SummaryUpdate {
+        SummaryUpdate {
+            update: Some(update),
+        }
+    }
+
+    #[test]
+    fn should_reassemble_summaries_until_a_delta_does_not_fit() {
+        // Arrange
+        let mut reassembler = Reassembler::new();
+        let (first, second, third) = (summary(1, 3), summary(2, 2), summary(3, 3));
+        let mut changed = third.clone();
+        changed.bids[2].amount = 9.;
+        // only changes the third level, which a book of a single level lacks
+        let beyond = delta::diff(&third, &changed);
+
+        // Act
+        let orphaned = reassembler.apply(update(Update::Delta(beyond.clone())));
+        let snapshot = reassembler.apply(update(Update::Snapshot(first.clone())));
+        let removed = reassembler.apply(update(Update::Delta(delta::diff(&first, &second))));
+        let added = reassembler.apply(update(Update::Delta(delta::diff(&second, &third))));
+        reassembler
+            .apply(update(Update::Snapshot(summary(4, 1))))
+            .unwrap();
+        let mismatch = reassembler.apply(update(Update::Delta(beyond.clone())));
+        let lost = reassembler.apply(update(Update::Delta(beyond)));
+
+        // Assert
+        assert!(orphaned == Err(ReassemblyError::MissingSnapshot));
+        assert!(snapshot == Ok(Some(first)) && removed == Ok(Some(second)));
+        assert!(added == Ok(Some(third)));
+        let gap = DeltaError::Gap {
+            expected: 2,
+            got: 1,
+        };
+        assert!(
+            mismatch
+                == Err(ReassemblyError::Mismatch {
+                    sequence: 3,
+                    error: gap
+                })
+        );
+        assert!(lost == Err(ReassemblyError::MissingSnapshot));
+    }
+}
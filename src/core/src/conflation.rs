@@ -0,0 +1,188 @@
+//! Conflating updates to at most one publish per interval. Updates within the interval after a
+//! publish are held back and published together, with the freshest books, once the interval passed.
+//! The interval is either fixed or adapts to how busy the market is: an [`AdaptiveInterval`]
+//! tightens it while the top of the book moves a lot and relaxes it again in quiet periods.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Default)]
+pub struct Conflation {
+    published_at: Option<Instant>,
+    /// whether updates were held back since the last publish
+    pending: bool,
+}
+
+impl Conflation {
+    pub fn new() -> Self {
+        Conflation::default()
+    }
+
+    /**
+     * Records a publish, which takes along all updates held back until now.
+     */
+    pub fn published(&mut self, now: Instant) {
+        self.published_at = Some(now);
+        self.pending = false;
+    }
+
+    /**
+     * Holds an update back if the interval did not pass since the last publish. Returns whether it
+     * was held back, otherwise it is to be published right away.
+     */
+    pub fn hold_back(&mut self, now: Instant, interval: Duration) -> bool {
+        if self.remaining(now, interval).is_zero() {
+            return false;
+        }
+        self.pending = true;
+        true
+    }
+
+    /**
+     * How long until the next publish is allowed, zero once the interval passed.
+     */
+    pub fn remaining(&self, now: Instant, interval: Duration) -> Duration {
+        self.published_at.map_or(Duration::ZERO, |published_at| {
+            interval.saturating_sub(now.duration_since(published_at))
+        })
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+}
+
+/// how quickly the observed activity is forgotten
+const ACTIVITY_HALF_LIFE: Duration = Duration::from_secs(5);
+/// top-of-book changes per second at which the market counts as fully busy
+const BUSY_CHANGES_PER_SEC: f64 = 20.;
+/// spread movement in basis points of the mid per second at which the market counts as fully busy
+const BUSY_SPREAD_BPS_PER_SEC: f64 = 10.;
+
+/**
+ * A conflation interval between `min` and `max`, derived from the top-of-book change frequency and
+ * the spread volatility. Both are exponentially decaying rates, so a burst tightens the interval
+ * right away and it relaxes back to `max` within a few half-lives once the market calmed down.
+ */
+#[derive(Debug, Clone)]
+pub struct AdaptiveInterval {
+    min: Duration,
+    max: Duration,
+    /// top-of-book changes, decayed to `observed_at`
+    changes: f64,
+    /// absolute spread movement in basis points of the mid, decayed to `observed_at`
+    spread_movement: f64,
+    observed_at: Option<Instant>,
+    last_spread_bps: Option<f64>,
+}
+
+impl AdaptiveInterval {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        AdaptiveInterval {
+            min,
+            max,
+            changes: 0.,
+            spread_movement: 0.,
+            observed_at: None,
+            last_spread_bps: None,
+        }
+    }
+
+    fn decay(&self, now: Instant) -> f64 {
+        self.observed_at.map_or(1., |observed_at| {
+            let elapsed = now.saturating_duration_since(observed_at);
+            0.5_f64.powf(elapsed.as_secs_f64() / ACTIVITY_HALF_LIFE.as_secs_f64())
+        })
+    }
+
+    /**
+     * Records an update, whether it changed the top of the book and the spread in basis points of
+     * the mid after it, if there is one.
+     */
+    pub fn observe(&mut self, now: Instant, top_changed: bool, spread_bps: Option<f64>) {
+        let decay = self.decay(now);
+        self.changes = self.changes * decay + if top_changed { 1. } else { 0. };
+        self.spread_movement *= decay;
+        if let (Some(last), Some(spread_bps)) = (self.last_spread_bps, spread_bps) {
+            self.spread_movement += (spread_bps - last).abs();
+        }
+        self.last_spread_bps = spread_bps.or(self.last_spread_bps);
+        self.observed_at = Some(now);
+    }
+
+    /**
+     * How busy the market currently is, 0 while it is quiet and 1 or more while it is fully busy.
+     */
+    pub fn activity(&self, now: Instant) -> f64 {
+        // the decayed sum of events over its half-life approximates their rate per half-life
+        let per_sec = self.decay(now) * std::f64::consts::LN_2 / ACTIVITY_HALF_LIFE.as_secs_f64();
+        self.changes * per_sec / BUSY_CHANGES_PER_SEC
+            + self.spread_movement * per_sec / BUSY_SPREAD_BPS_PER_SEC
+    }
+
+    /**
+     * The interval for the current activity, `max` while quiet and `min` while fully busy,
+     * interpolated geometrically in between.
+     */
+    pub fn interval(&self, now: Instant) -> Duration {
+        let busy = self.activity(now).min(1.);
+        let ratio = self.min.as_secs_f64() / self.max.as_secs_f64();
+        self.max.mul_f64(ratio.powf(busy)).clamp(self.min, self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdaptiveInterval, Conflation};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_hold_back_updates_within_the_interval() {
+        // Arrange
+        let interval = Duration::from_millis(100);
+        let start = Instant::now();
+        let mut conflation = Conflation::new();
+
+        // Act
+        let first = conflation.hold_back(start, interval);
+        conflation.published(start);
+        let within = conflation.hold_back(start + Duration::from_millis(40), interval);
+        let remaining = conflation.remaining(start + Duration::from_millis(40), interval);
+        let pending = conflation.is_pending();
+        let after = conflation.hold_back(start + interval, interval);
+        conflation.published(start + interval);
+
+        // Assert
+        assert!(!first && within && pending);
+        assert!(remaining == Duration::from_millis(60));
+        assert!(!after && !conflation.is_pending());
+    }
+
+    #[test]
+    fn should_tighten_the_interval_while_the_market_is_busy() {
+        // Arrange
+        let (min, max) = (Duration::from_millis(10), Duration::from_millis(1000));
+        let mut adaptive = AdaptiveInterval::new(min, max);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        // Act
+        let quiet = adaptive.interval(start);
+        for tick in 0..500 {
+            adaptive.observe(at(tick * 10), true, Some(2.));
+        }
+        let busy = adaptive.interval(at(5_000));
+        let calming = adaptive.interval(at(20_000));
+        let calm = adaptive.interval(at(90_000));
+        let mut volatile = AdaptiveInterval::new(min, max);
+        for tick in 0..100 {
+            volatile.observe(at(tick * 100), false, Some((tick % 2) as f64 * 5.));
+        }
+
+        // Assert
+        assert!(quiet == max);
+        assert!(busy == min);
+        assert!(calming > min && calming < max);
+        assert!(calm > Duration::from_millis(990));
+        assert!(volatile.interval(at(10_000)) < Duration::from_millis(100));
+    }
+}
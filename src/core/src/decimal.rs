@@ -0,0 +1,194 @@
+//! Exact prices and amounts. The venues quote short decimals which rarely have an exact binary
+//! representation, so as floats a level of `0.0745` becomes `0.074499999999999997` and spreads pick
+//! up artifacts such as `1.7000000000003124e-05`. Inside the server the books are held as fixed-point
+//! decimals instead and only converted to the nearest float at the proto boundary.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt,
+    ops::{Add, Sub},
+    str::FromStr,
+};
+
+/// decimal places a value is held with, finer digits are dropped
+const SCALE: u32 = 18;
+const ONE: i128 = 10i128.pow(SCALE);
+
+/**
+ * A fixed-point decimal with 18 decimal places, parsed from plain decimals like `-0.0745`.
+ * Exact up to about 1.7e20, beyond that the arithmetic overflows.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Decimal(i128);
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal(0);
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    /**
+     * The float closest to the exact value.
+     */
+    pub fn to_f64(self) -> f64 {
+        self.to_string()
+            .parse()
+            .expect("A decimal is formatted as a valid float")
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match raw.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, raw),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if (integer.is_empty() && fraction.is_empty())
+            || !all_digits(integer)
+            || !all_digits(fraction)
+        {
+            return Err(());
+        }
+
+        let fraction = &fraction[..fraction.len().min(SCALE as usize)];
+        let integer = match integer {
+            "" => 0,
+            integer => integer.parse::<i128>().map_err(|_| ())?,
+        };
+        let fraction = match fraction {
+            "" => 0,
+            fraction => {
+                fraction.parse::<i128>().map_err(|_| ())?
+                    * 10i128.pow(SCALE - fraction.len() as u32)
+            }
+        };
+        let units = integer
+            .checked_mul(ONE)
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or(())?;
+        Ok(Decimal(match negative {
+            true => -units,
+            false => units,
+        }))
+    }
+}
+
+/**
+ * The exact value of the float as it is printed, fails if it is not finite or too large.
+ */
+impl TryFrom<f64> for Decimal {
+    type Error = ();
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        match value.is_finite() {
+            true => value.to_string().parse(),
+            false => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = self.0.unsigned_abs();
+        let (integer, fraction) = (units / ONE as u128, units % ONE as u128);
+        if self.0 < 0 {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", integer)?;
+        if fraction != 0 {
+            let fraction = format!("{:0width$}", fraction, width = SCALE as usize);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: Decimal) -> Decimal {
+        Decimal(self.0 + other.0)
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: Decimal) -> Decimal {
+        Decimal(self.0 - other.0)
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|_| de::Error::custom("invalid decimal"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decimal;
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn should_parse_and_format_decimals() {
+        assert!(decimal("0.07451100").to_string() == "0.074511");
+        assert!(decimal("-12.5").to_string() == "-12.5");
+        assert!(decimal(".5") == decimal("0.5") && decimal("3").to_string() == "3");
+        assert!(decimal("0.0000000000000000019") == decimal("0.000000000000000001"));
+        assert!(["", ".", "-", "1e-5", "+1", "1.2.3", "abc"]
+            .iter()
+            .all(|raw| raw.parse::<Decimal>().is_err()));
+    }
+
+    #[test]
+    #[allow(clippy::excessive_precision)]
+    fn should_hold_floats_of_the_test_data_exactly() {
+        // the floats of the recorded test data as they are printed with full precision
+        let price = Decimal::try_from(0.074505000000000002).unwrap();
+
+        assert!(price == decimal("0.074505"));
+        assert!(price.to_f64() == 0.074505);
+        assert!(Decimal::try_from(f64::NAN).is_err() && Decimal::try_from(1e300).is_err());
+        // finer than the scale, such a price is rejected as non-positive
+        assert!(Decimal::try_from(1e-320).unwrap() == Decimal::ZERO);
+    }
+
+    #[test]
+    fn should_compute_spreads_exactly() {
+        assert!(decimal("0.074505") - decimal("0.074488") == decimal("0.000017"));
+        assert!((decimal("0.074505") - decimal("0.074488")).to_f64() == 0.000017);
+        assert!((decimal("0.0745") - decimal("0.07448857")).to_f64() == 0.00001143);
+        assert!(decimal("0.0745") - decimal("0.0745") == Decimal::ZERO);
+        assert!((decimal("0.0745") - decimal("0.0745"))
+            .to_f64()
+            .is_sign_positive());
+        assert!(decimal("0.0744") - decimal("0.0745") == decimal("-0.0001"));
+    }
+
+    #[test]
+    fn should_not_accumulate_errors_when_summing_amounts() {
+        let float_sum = (0..10).fold(0., |total, _| total + 0.1);
+        let decimal_sum = (0..10).fold(Decimal::ZERO, |total, _| total + decimal("0.1"));
+
+        assert!(float_sum != 1.);
+        assert!(decimal_sum == decimal("1") && decimal_sum.to_f64() == 1.);
+        assert!((decimal("1.1") + decimal("2.2")).to_f64() == 3.3);
+    }
+}
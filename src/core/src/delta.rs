@@ -0,0 +1,181 @@
+//! The changes between two summaries of a symbol. Most ticks only move a few levels, so a
+//! [`SummaryDelta`] carries the levels that differ from the level at the same index of the previous
+//! summary and the new lengths of the ladders, which drops the levels beyond them, along with every
+//! other field of the summary. The delta recordings and the delta streams of the server are encoded
+//! with [`diff`], their readers reconstruct the summaries with [`apply`].
+
+use keyrock_challenge_proto::orderbook::{Level, LevelChange, Summary, SummaryDelta};
+use std::{error::Error, fmt};
+
+/**
+ * Why the changes do not fit the summary they are applied to, usually as an update in between was
+ * lost.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaError {
+    /// a change without its level
+    MissingLevel { index: u32 },
+    /// a ladder needing `expected` levels, e.g. to change the level at an index beyond its end,
+    /// while it holds `got` only
+    Gap { expected: usize, got: usize },
+}
+
+impl fmt::Display for DeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeltaError::MissingLevel { index } => write!(f, "change {} lacks its level", index),
+            DeltaError::Gap { expected, got } => {
+                write!(f, "expected a ladder of {} levels, got {}", expected, got)
+            }
+        }
+    }
+}
+
+impl Error for DeltaError {}
+
+fn diff_side(previous: &[Level], current: &[Level]) -> Vec<LevelChange> {
+    current
+        .iter()
+        .enumerate()
+        .filter(|(index, level)| previous.get(*index) != Some(level))
+        .map(|(index, level)| LevelChange {
+            index: index as u32,
+            level: Some(level.clone()),
+        })
+        .collect()
+}
+
+fn apply_side(
+    levels: &mut Vec<Level>,
+    changes: Vec<LevelChange>,
+    len: u32,
+) -> Result<(), DeltaError> {
+    for change in changes {
+        let index = change.index as usize;
+        let level = (change.level).ok_or(DeltaError::MissingLevel {
+            index: change.index,
+        })?;
+        match index {
+            index if index < levels.len() => levels[index] = level,
+            index if index == levels.len() => levels.push(level),
+            _ => {
+                return Err(DeltaError::Gap {
+                    expected: index,
+                    got: levels.len(),
+                })
+            }
+        }
+    }
+    levels.truncate(len as usize);
+    match levels.len() == len as usize {
+        true => Ok(()),
+        false => Err(DeltaError::Gap {
+            expected: len as usize,
+            got: levels.len(),
+        }),
+    }
+}
+
+/**
+ * The changes turning the previous summary into the current one, which must be of the same symbol.
+ */
+pub fn diff(previous: &Summary, current: &Summary) -> SummaryDelta {
+    SummaryDelta {
+        sequence: current.sequence,
+        restarted: current.restarted,
+        spread: current.spread,
+        raw_spread: current.raw_spread,
+        mid: current.mid,
+        vwap: current.vwap,
+        microprice: current.microprice,
+        snapshot_age_ms: current.snapshot_age_ms.clone(),
+        exchange_timestamp_us: current.exchange_timestamp_us.clone(),
+        aggregated_at_us: current.aggregated_at_us,
+        extensions: current.extensions.clone(),
+        quorum_lost: current.quorum_lost,
+        quiet_period: current.quiet_period,
+        lead_degraded: current.lead_degraded,
+        paused: current.paused,
+        bids: diff_side(&previous.bids, &current.bids),
+        asks: diff_side(&previous.asks, &current.asks),
+        bids_len: current.bids.len() as u32,
+        asks_len: current.asks.len() as u32,
+    }
+}
+
+/**
+ * Applies the changes to the summary they were taken from. Fails for changes that do not fit it,
+ * e.g. a level beyond the end of its ladder, leaving the summary partially changed.
+ */
+pub fn apply(summary: &mut Summary, delta: SummaryDelta) -> Result<(), DeltaError> {
+    summary.sequence = delta.sequence;
+    summary.restarted = delta.restarted;
+    summary.spread = delta.spread;
+    summary.raw_spread = delta.raw_spread;
+    summary.mid = delta.mid;
+    summary.vwap = delta.vwap;
+    summary.microprice = delta.microprice;
+    summary.snapshot_age_ms = delta.snapshot_age_ms;
+    summary.exchange_timestamp_us = delta.exchange_timestamp_us;
+    summary.aggregated_at_us = delta.aggregated_at_us;
+    summary.extensions = delta.extensions;
+    summary.quorum_lost = delta.quorum_lost;
+    summary.quiet_period = delta.quiet_period;
+    summary.lead_degraded = delta.lead_degraded;
+    summary.paused = delta.paused;
+    apply_side(&mut summary.bids, delta.bids, delta.bids_len)?;
+    apply_side(&mut summary.asks, delta.asks, delta.asks_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, diff, DeltaError};
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+
+    fn level(price: f64, amount: f64) -> Level {
+        Level {
+            exchange: "Binance".to_string(),
+            exchange_id: 1,
+            price,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_carry_changed_and_removed_levels_only() {
+        // Arrange
+        let previous = Summary {
+            symbol: "ethbtc".to_string(),
+            sequence: 1,
+            bids: vec![level(0.0745, 1.), level(0.0744, 2.), level(0.0743, 3.)],
+            asks: vec![level(0.0746, 1.), level(0.0747, 2.)],
+            ..Default::default()
+        };
+        let current = Summary {
+            sequence: 2,
+            paused: true,
+            bids: vec![level(0.0745, 1.), level(0.0744, 5.)],
+            asks: vec![level(0.0746, 1.), level(0.0747, 2.), level(0.0748, 3.)],
+            ..previous.clone()
+        };
+
+        // Act
+        let delta = diff(&previous, &current);
+        let mut applied = previous.clone();
+        let result = apply(&mut applied, delta.clone());
+        let gap = apply(&mut Summary::default(), delta.clone());
+
+        // Assert
+        assert!(result.is_ok() && applied == current);
+        assert!(delta.bids.len() == 1 && delta.bids[0].index == 1 && delta.bids_len == 2);
+        assert!(delta.asks.len() == 1 && delta.asks[0].index == 2 && delta.asks_len == 3);
+        // the second bid follows a first one the empty summary lacks
+        assert!(
+            gap == Err(DeltaError::Gap {
+                expected: 1,
+                got: 0
+            })
+        );
+    }
+}
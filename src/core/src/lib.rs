@@ -0,0 +1,13 @@
+//! The aggregation core of the orderbook server: the exact decimals and validated snapshots the
+//! venues' books are held as, the strategies merging them, the staleness timeouts evicting stalled
+//! venues, the conflation of updates and the deltas between summaries.
+//!
+//! Nothing in here depends on an async runtime or a transport. The server drives it from its tokio
+//! tasks and publishes over gRPC, other services can embed the same merging logic with their own.
+
+pub mod conflation;
+pub mod decimal;
+pub mod delta;
+pub mod merge_strategy;
+pub mod orderbook_snapshot;
+pub mod staleness;
@@ -0,0 +1,173 @@
+use crate::orderbook_snapshot::{BookLevel, Contributor};
+use std::str::FromStr;
+
+/**
+ * How the ladders of two venues are merged into one. Parsed from `interleave`,
+ * `larger-amount-first` or `combine-prices`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeStrategy {
+    /// interleaves the ladders by price, on equal prices the ask of the first and the bid of the
+    /// second venue go first
+    #[default]
+    Interleave,
+    /// orders by price, on equal prices the level with the larger amount goes first
+    LargerAmountFirst,
+    /// orders by price and sums up the levels of equal prices into one level
+    CombinePrices,
+}
+
+impl FromStr for MergeStrategy {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "interleave" => Ok(MergeStrategy::Interleave),
+            "larger-amount-first" => Ok(MergeStrategy::LargerAmountFirst),
+            "combine-prices" => Ok(MergeStrategy::CombinePrices),
+            _ => Err(()),
+        }
+    }
+}
+
+/**
+ * Merges two ladders sorted with the best offer at position 0 into the best `depth` levels.
+ * The side states if the ladders contain bids (false) or asks (true).
+ */
+pub fn larger_amount_first(
+    levels_01: &[BookLevel],
+    levels_02: &[BookLevel],
+    side: bool,
+    depth: usize,
+) -> Vec<BookLevel> {
+    let mut merged: Vec<BookLevel> = levels_01.iter().chain(levels_02).cloned().collect();
+    merged.sort_by(|a, b| {
+        let by_price = match side {
+            true => a.price.cmp(&b.price),
+            false => b.price.cmp(&a.price),
+        };
+        by_price.then(b.amount.cmp(&a.amount))
+    });
+    merged.truncate(depth);
+    merged
+}
+
+fn contributors(level: &BookLevel) -> Vec<Contributor> {
+    match level.contributors.is_empty() {
+        true => vec![Contributor {
+            exchange: level.exchange.clone(),
+            exchange_id: level.exchange_id,
+            amount: level.amount,
+            provenance: level.provenance,
+        }],
+        false => level.contributors.clone(),
+    }
+}
+
+/**
+ * Adds the amount of the level to the combined one. The combined level is attributed to the
+ * exchange contributing the largest amount, the first one on equal amounts.
+ */
+fn combine(combined: &mut BookLevel, level: &BookLevel) {
+    let mut contributions = contributors(combined);
+    contributions.extend(contributors(level));
+    combined.amount = combined.amount + level.amount;
+
+    let largest = contributions
+        .iter()
+        .reduce(
+            |largest, contribution| match contribution.amount > largest.amount {
+                true => contribution,
+                false => largest,
+            },
+        )
+        .expect("A combined level has contributors");
+    combined.exchange = largest.exchange.clone();
+    combined.exchange_id = largest.exchange_id;
+    combined.contributors = contributions;
+}
+
+/**
+ * Merges two ladders sorted with the best offer at position 0 into the best `depth` prices, with
+ * the levels of equal prices combined into one. The side states if the ladders contain bids
+ * (false) or asks (true).
+ */
+pub fn combine_prices(
+    levels_01: &[BookLevel],
+    levels_02: &[BookLevel],
+    side: bool,
+    depth: usize,
+) -> Vec<BookLevel> {
+    let mut sorted: Vec<&BookLevel> = levels_01.iter().chain(levels_02).collect();
+    sorted.sort_by(|a, b| match side {
+        true => a.price.cmp(&b.price),
+        false => b.price.cmp(&a.price),
+    });
+
+    let mut merged: Vec<BookLevel> = Vec::with_capacity(depth);
+    for level in sorted {
+        let full = merged.len() == depth;
+        match merged.last_mut() {
+            Some(last) if last.price == level.price => combine(last, level),
+            _ if full => break,
+            _ => merged.push(level.clone()),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine_prices, larger_amount_first};
+    use crate::{decimal::Decimal, orderbook_snapshot::BookLevel};
+
+    fn level(exchange: &str, price: &str, amount: &str) -> BookLevel {
+        BookLevel {
+            exchange: exchange.to_string(),
+            price: price.parse().unwrap(),
+            amount: amount.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn should_put_larger_amount_first_on_equal_prices() {
+        // Arrange
+        let bids_01 = [level("Binance", "10", "1"), level("Binance", "9", "1")];
+        let bids_02 = [level("Bitstamp", "10", "2"), level("Bitstamp", "8", "1")];
+
+        // Act
+        let merged = larger_amount_first(&bids_01, &bids_02, false, 2);
+
+        // Assert
+        assert!(merged == vec![bids_02[0].clone(), bids_01[0].clone()]);
+    }
+
+    #[test]
+    fn should_combine_levels_of_equal_prices() {
+        // Arrange
+        let asks_01 = [level("Binance", "10", "0.1"), level("Binance", "11", "1")];
+        let asks_02 = [level("Bitstamp", "10", "0.2"), level("Bitstamp", "12", "1")];
+
+        // Act
+        let merged = combine_prices(&asks_01, &asks_02, true, 2);
+        let merged_again = combine_prices(&merged, &[level("Kraken", "10", "0.4")], true, 2);
+
+        // Assert
+        assert!(merged.len() == 2 && merged[1].price == decimal("11"));
+        assert!(merged[1].contributors.is_empty());
+        // exactly 0.3, unlike 0.1 + 0.2 as floats
+        assert!(merged[0].amount == decimal("0.3") && merged[0].exchange == "Bitstamp");
+        let contributors: Vec<&str> = merged_again[0]
+            .contributors
+            .iter()
+            .map(|contributor| contributor.exchange.as_str())
+            .collect();
+        assert!(contributors == vec!["Binance", "Bitstamp", "Kraken"]);
+        assert!(merged_again[0].amount == decimal("0.7") && merged_again[0].exchange == "Kraken");
+    }
+}
@@ -0,0 +1,411 @@
+use crate::decimal::Decimal;
+use keyrock_challenge_proto::orderbook::{Contribution, Level};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/**
+ * The amount a venue contributes to a combined level.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Contributor {
+    pub exchange: String,
+    pub exchange_id: i32,
+    pub amount: Decimal,
+    /// the provenance of the contributed level
+    #[serde(default)]
+    pub provenance: u64,
+}
+
+/**
+ * A level of a venue's book with its exact price and amount. Converted into a published [`Level`]
+ * only once the books are merged.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BookLevel {
+    pub exchange: String,
+    pub exchange_id: i32,
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// set on levels combined out of the levels of several venues only
+    pub contributors: Vec<Contributor>,
+    /// a hash of the raw exchange message the level was read from, 0 if unknown
+    #[serde(default)]
+    pub provenance: u64,
+}
+
+impl From<&BookLevel> for Level {
+    fn from(level: &BookLevel) -> Self {
+        Level {
+            exchange: level.exchange.clone(),
+            price: level.price.to_f64(),
+            amount: level.amount.to_f64(),
+            exchange_id: level.exchange_id,
+            contributors: level
+                .contributors
+                .iter()
+                .map(|contributor| Contribution {
+                    exchange: contributor.exchange.clone(),
+                    exchange_id: contributor.exchange_id,
+                    amount: contributor.amount.to_f64(),
+                    provenance: contributor.provenance,
+                })
+                .collect(),
+            provenance: level.provenance,
+        }
+    }
+}
+
+/**
+ * The best levels of a venue. Built through the [`SnapshotBuilder`], each side holds exactly as many
+ * levels as the depth of the book.
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderbookSnapshot {
+    /// None if the venue sent an empty side
+    pub bids: Option<Vec<BookLevel>>,
+    pub asks: Option<Vec<BookLevel>>,
+    /// event time reported by the exchange in microseconds since the unix epoch, if provided
+    pub exchange_timestamp_us: Option<u64>,
+}
+
+impl OrderbookSnapshot {
+    /**
+     * Marks every level as read from the raw message with the given hash.
+     */
+    pub fn set_provenance(&mut self, provenance: u64) {
+        for level in self.bids.iter_mut().chain(self.asks.iter_mut()).flatten() {
+            level.provenance = provenance;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Side {
+    Bids,
+    Asks,
+}
+
+/**
+ * How many levels each side of a book holds, parsed from `<bids>:<asks>`. Consumers of the bids and
+ * the asks may need different depths, e.g. 25 bids and only 10 asks.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SideDepths {
+    pub bids: usize,
+    pub asks: usize,
+}
+
+impl SideDepths {
+    pub fn symmetric(depth: usize) -> Self {
+        SideDepths {
+            bids: depth,
+            asks: depth,
+        }
+    }
+
+    pub fn of(&self, side: Side) -> usize {
+        match side {
+            Side::Bids => self.bids,
+            Side::Asks => self.asks,
+        }
+    }
+
+    /**
+     * The depth of the deeper side.
+     */
+    pub fn max(&self) -> usize {
+        self.bids.max(self.asks)
+    }
+}
+
+impl FromStr for SideDepths {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (bids, asks) = raw.split_once(':').ok_or(())?;
+        let depths = SideDepths {
+            bids: bids.parse().map_err(|_| ())?,
+            asks: asks.parse().map_err(|_| ())?,
+        };
+        match depths.bids == 0 || depths.asks == 0 {
+            true => Err(()),
+            false => Ok(depths),
+        }
+    }
+}
+
+/**
+ * Why a snapshot was rejected. The index refers to the offending level of the side.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotError {
+    /// the payload of the venue could not be parsed into levels
+    Malformed(Side),
+    /// the side is not empty but holds less levels than the depth of the book
+    TooShallow { side: Side, levels: usize },
+    /// a price or amount that is zero or negative
+    NonPositive { side: Side, index: usize },
+    /// bids that are not descending or asks that are not ascending
+    Unsorted { side: Side, index: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Malformed(side) => write!(f, "malformed {:?}", side),
+            SnapshotError::TooShallow { side, levels } => {
+                write!(f, "only {} levels of {:?}", levels, side)
+            }
+            SnapshotError::NonPositive { side, index } => {
+                write!(f, "non-positive level {} of {:?}", index, side)
+            }
+            SnapshotError::Unsorted { side, index } => {
+                write!(f, "level {} of {:?} out of order", index, side)
+            }
+        }
+    }
+}
+
+/**
+ * Validates the levels of one side and keeps the best `depth` of them. An empty side is not an
+ * error, how that is handled is up to the aggregator's `EmptyBookPolicy`.
+ */
+fn validate(
+    side: Side,
+    mut levels: Vec<BookLevel>,
+    depth: usize,
+) -> Result<Option<Vec<BookLevel>>, SnapshotError> {
+    if levels.is_empty() {
+        return Ok(None);
+    }
+    levels.truncate(depth);
+
+    if let Some(index) = levels
+        .iter()
+        .position(|level| !level.price.is_positive() || !level.amount.is_positive())
+    {
+        return Err(SnapshotError::NonPositive { side, index });
+    }
+
+    let in_order = |better: &BookLevel, worse: &BookLevel| match side {
+        Side::Bids => better.price >= worse.price,
+        Side::Asks => better.price <= worse.price,
+    };
+    if let Some(index) = levels
+        .windows(2)
+        .position(|pair| !in_order(&pair[0], &pair[1]))
+    {
+        return Err(SnapshotError::Unsorted {
+            side,
+            index: index + 1,
+        });
+    }
+
+    match levels.len() {
+        len if len < depth => Err(SnapshotError::TooShallow { side, levels: len }),
+        _ => Ok(Some(levels)),
+    }
+}
+
+/**
+ * Constructs snapshots that are guaranteed to be sorted, deep enough and free of non-positive
+ * values, so the aggregator does not have to trust every connector to check that on its own.
+ */
+#[derive(Debug, Default)]
+pub struct SnapshotBuilder {
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+}
+
+impl SnapshotBuilder {
+    pub fn new() -> Self {
+        SnapshotBuilder::default()
+    }
+
+    /**
+     * The bids from best to worst. Levels beyond the depth of the book are dropped.
+     */
+    pub fn bids(mut self, bids: Vec<BookLevel>) -> Self {
+        self.bids = bids;
+        self
+    }
+
+    /**
+     * The asks from best to worst. Levels beyond the depth of the book are dropped.
+     */
+    pub fn asks(mut self, asks: Vec<BookLevel>) -> Self {
+        self.asks = asks;
+        self
+    }
+
+    /**
+     * Fails unless each side is either empty or holds at least `depth` valid levels.
+     */
+    pub fn build(self, depth: usize) -> Result<OrderbookSnapshot, SnapshotError> {
+        self.build_sides(SideDepths::symmetric(depth))
+    }
+
+    /**
+     * Like `build`, with a depth of its own for each side.
+     */
+    pub fn build_sides(self, depths: SideDepths) -> Result<OrderbookSnapshot, SnapshotError> {
+        Ok(OrderbookSnapshot {
+            bids: validate(Side::Bids, self.bids, depths.bids)?,
+            asks: validate(Side::Asks, self.asks, depths.asks)?,
+            exchange_timestamp_us: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BookLevel, Contributor, OrderbookSnapshot, Side, SideDepths, SnapshotBuilder, SnapshotError,
+    };
+    use crate::decimal::Decimal;
+    use keyrock_challenge_proto::orderbook::Level;
+
+    fn levels(prices: &[f64], amount: f64) -> Vec<BookLevel> {
+        prices
+            .iter()
+            .map(|price| BookLevel {
+                exchange: "Binance".to_string(),
+                price: Decimal::try_from(*price).unwrap(),
+                amount: Decimal::try_from(amount).unwrap(),
+                exchange_id: 1,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_build_valid_snapshot_truncated_to_depth() {
+        // Act
+        let snapshot = SnapshotBuilder::new()
+            .bids(levels(&[10., 9., 9.], 1.))
+            .asks(vec![])
+            .build(2)
+            .unwrap();
+
+        // Assert
+        assert!(snapshot.bids.unwrap().len() == 2);
+        assert!(snapshot.asks.is_none());
+    }
+
+    #[test]
+    fn should_mark_every_level_with_its_provenance() {
+        // Arrange
+        let mut snapshot = SnapshotBuilder::new()
+            .bids(levels(&[10., 9.], 1.))
+            .asks(levels(&[11., 12.], 1.))
+            .build(2)
+            .unwrap();
+
+        // Act
+        snapshot.set_provenance(42);
+
+        // Assert
+        let mut levels = snapshot.bids.iter().chain(snapshot.asks.iter()).flatten();
+        assert!(levels.clone().count() == 4 && levels.all(|level| level.provenance == 42));
+    }
+
+    #[test]
+    fn should_build_each_side_to_a_depth_of_its_own() {
+        // Arrange
+        let depths: SideDepths = "3:1".parse().unwrap();
+
+        // Act
+        let snapshot = SnapshotBuilder::new()
+            .bids(levels(&[10., 9., 8., 7.], 1.))
+            .asks(levels(&[11., 12.], 1.))
+            .build_sides(depths)
+            .unwrap();
+
+        // Assert
+        assert!(snapshot.bids.unwrap().len() == 3 && snapshot.asks.unwrap().len() == 1);
+        assert!(depths.max() == 3 && depths.of(Side::Asks) == 1);
+        assert!("0:10".parse::<SideDepths>().is_err() && "25".parse::<SideDepths>().is_err());
+    }
+
+    #[test]
+    fn should_round_trip_snapshot_through_json() {
+        // Arrange
+        let snapshot = SnapshotBuilder::new()
+            .bids(levels(&[10., 9.], 1.))
+            .build(2)
+            .unwrap();
+
+        // Act
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let deserialized: OrderbookSnapshot = serde_json::from_str(&json).unwrap();
+
+        // Assert
+        assert!(deserialized.bids == snapshot.bids && deserialized.asks.is_none());
+    }
+
+    #[test]
+    fn should_reject_snapshot_violating_invariants() {
+        let build = |bids: Vec<BookLevel>, asks: Vec<BookLevel>| {
+            SnapshotBuilder::new()
+                .bids(bids)
+                .asks(asks)
+                .build(2)
+                .unwrap_err()
+        };
+
+        assert!(
+            build(levels(&[10.], 1.), levels(&[11., 12.], 1.))
+                == SnapshotError::TooShallow {
+                    side: Side::Bids,
+                    levels: 1
+                }
+        );
+        assert!(
+            build(levels(&[10., 9.], 1.), levels(&[12., 11.], 1.))
+                == SnapshotError::Unsorted {
+                    side: Side::Asks,
+                    index: 1
+                }
+        );
+        assert!(
+            build(levels(&[10., 9.], 0.), levels(&[11., 12.], 1.))
+                == SnapshotError::NonPositive {
+                    side: Side::Bids,
+                    index: 0
+                }
+        );
+        assert!(
+            build(levels(&[10., 1e-20], 1.), levels(&[11., 12.], 1.))
+                == SnapshotError::NonPositive {
+                    side: Side::Bids,
+                    index: 1
+                }
+        );
+    }
+
+    #[test]
+    fn should_convert_to_the_nearest_floats_at_the_proto_boundary() {
+        // Arrange
+        let level = BookLevel {
+            exchange: "Binance".to_string(),
+            price: "0.074511".parse().unwrap(),
+            amount: "0.85".parse().unwrap(),
+            exchange_id: 1,
+            contributors: vec![Contributor {
+                exchange: "Bitstamp".to_string(),
+                exchange_id: 2,
+                amount: "0.1".parse().unwrap(),
+                provenance: 7,
+            }],
+            provenance: 3,
+        };
+
+        // Act
+        let converted = Level::from(&level);
+
+        // Assert
+        assert!(converted.price == 0.074511 && converted.amount == 0.85);
+        assert!(converted.contributors[0].amount == 0.1 && converted.exchange_id == 1);
+        assert!(converted.provenance == 3 && converted.contributors[0].provenance == 7);
+    }
+}
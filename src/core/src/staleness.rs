@@ -0,0 +1,94 @@
+//! Evicting the books of a venue whose stream stalled. Without an update the last snapshot would be
+//! merged forever although the venue's market has long moved on, so a venue that has not delivered
+//! within its staleness timeout is left out of the merge until it delivers again.
+
+use std::{str::FromStr, time::Duration};
+
+/**
+ * The staleness timeout of a single exchange, parsed from `<exchange>=<ms>`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleAfter {
+    pub exchange: String,
+    pub timeout: Duration,
+}
+
+impl FromStr for StaleAfter {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (exchange, ms) = raw.rsplit_once('=').ok_or(())?;
+        let ms = ms.parse::<u64>().map_err(|_| ())?;
+        if exchange.is_empty() || ms == 0 {
+            return Err(());
+        }
+        Ok(StaleAfter {
+            exchange: exchange.to_string(),
+            timeout: Duration::from_millis(ms),
+        })
+    }
+}
+
+/**
+ * The staleness timeouts of all exchanges, none of them is ever evicted by default.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Staleness {
+    /// applies to every exchange without a timeout of its own
+    pub default_timeout: Option<Duration>,
+    pub timeouts: Vec<StaleAfter>,
+}
+
+impl Staleness {
+    pub fn timeout(&self, exchange: &str) -> Option<Duration> {
+        self.timeouts
+            .iter()
+            .find(|stale_after| stale_after.exchange == exchange)
+            .map(|stale_after| stale_after.timeout)
+            .or(self.default_timeout)
+    }
+
+    pub fn is_stale(&self, exchange: &str, age: Duration) -> bool {
+        self.timeout(exchange).is_some_and(|timeout| age > timeout)
+    }
+
+    /**
+     * How often venues are checked for staleness, half the shortest timeout. None if no timeout is set.
+     */
+    pub fn check_interval(&self) -> Option<Duration> {
+        self.timeouts
+            .iter()
+            .map(|stale_after| stale_after.timeout)
+            .chain(self.default_timeout)
+            .min()
+            .map(|timeout| timeout / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StaleAfter, Staleness};
+    use std::time::Duration;
+
+    #[test]
+    fn should_prefer_the_timeout_of_the_exchange() {
+        // Arrange
+        let staleness = Staleness {
+            default_timeout: Some(Duration::from_millis(2000)),
+            timeouts: vec!["Bitstamp=500".parse().unwrap()],
+        };
+
+        // Act
+        let bitstamp = staleness.timeout("Bitstamp");
+        let binance = staleness.timeout("Binance");
+
+        // Assert
+        assert!(bitstamp == Some(Duration::from_millis(500)));
+        assert!(binance == Some(Duration::from_millis(2000)));
+        assert!(staleness.is_stale("Bitstamp", Duration::from_millis(501)));
+        assert!(!staleness.is_stale("Binance", Duration::from_millis(501)));
+        assert!(staleness.check_interval() == Some(Duration::from_millis(250)));
+        assert!(Staleness::default().check_interval().is_none());
+        assert!("Bitstamp=0".parse::<StaleAfter>().is_err());
+    }
+}
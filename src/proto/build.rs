@@ -1,4 +1,11 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/orderbook.proto")?;
+    // the message types are always generated, the stubs only for the enabled features
+    let mut builder = tonic_build::configure()
+        .build_client(std::env::var_os("CARGO_FEATURE_CLIENT").is_some())
+        .build_server(std::env::var_os("CARGO_FEATURE_SERVER").is_some());
+    if std::env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        builder = builder.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    }
+    builder.compile(&["proto/orderbook.proto"], &["proto"])?;
     Ok(())
 }
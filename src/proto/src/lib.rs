@@ -1,3 +1,12 @@
+//! The messages and gRPC stubs of the orderbook services.
+//!
+//! Consumers which only need the message types can depend on this crate with
+//! `default-features = false`, which leaves out tonic entirely. The `client` and `server` features
+//! add the respective stubs, `serde` derives `Serialize` and `Deserialize` for every message so
+//! they can be mirrored as JSON.
+
+// oneofs are generated as enums, the ones holding a Summary next to a small marker included
+#[allow(clippy::large_enum_variant)]
 pub mod orderbook {
-    tonic::include_proto!("orderbook");
+    include!(concat!(env!("OUT_DIR"), "/orderbook.rs"));
 }
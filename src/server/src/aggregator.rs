@@ -1,442 +1,2857 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
-use crate::{orderbook_snapshot::OrderbookSnapshot, spmc::Spmc};
-use keyrock_challenge_proto::orderbook::{Level, Summary};
+use crate::{
+    audit, book_analytics,
+    clock::{self, Clock},
+    empty_book_policy::EmptyBookPolicy,
+    enrichment::{self, Enricher},
+    exchange_registry::{self, DisplayNames},
+    inbound_queue::InboundQueue,
+    journal::Journal,
+    lead_compensation::LeadCompensator,
+    lead_policy::LeadPolicy,
+    log,
+    maintenance::{self, MaintenanceWindow},
+    publish_trigger::{self, PublishTrigger},
+    quiet_period::{self, QuietPeriod},
+    quorum::Quorum,
+    schema::Quarantine,
+    sequence_store::SequenceStore,
+    shadow::Shadow,
+    source_selector::{SourceKind, SourceSelector},
+    spmc::Spmc,
+    spread_smoothing::{SpreadSmoother, SpreadSmoothing},
+    stage_timings,
+};
+use keyrock_challenge_core::{
+    conflation::{AdaptiveInterval, Conflation},
+    decimal::Decimal,
+    merge_strategy::{self, MergeStrategy},
+    orderbook_snapshot::{BookLevel, OrderbookSnapshot, SideDepths},
+    staleness::Staleness,
+};
+use keyrock_challenge_proto::orderbook::{
+    AuditFinding, AuditReport, ExchangeSnapshot, Health, Level, PauseState, QuietMode, Summary,
+    TickTimings, VenueHealth, VenueStatus,
+};
+use prost::Message;
+use serde_json::json;
 
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, watch, Mutex};
 
-const DEPTH: usize = 10;
-const LEAD_TOLERANCE: usize = 3;
+/// how many levels per side the books are merged from and published with unless configured otherwise
+pub const DEFAULT_DEPTH: usize = 10;
+pub const DEFAULT_LEAD_TOLERANCE: usize = 3;
+const SOURCE_FRESHNESS: Duration = Duration::from_secs(5);
+/// a venue counts as live while its latest snapshot is younger than this
+const LIVE_WITHIN: Duration = Duration::from_secs(10);
 
-fn copy_level(level: &Level) -> Level {
-    Level {
-        price: level.price,
-        amount: level.amount,
-        exchange: (&level.exchange).to_string(),
+/**
+ * The latest books of a venue together with its state, indexed by its venue id.
+ */
+#[derive(Debug)]
+struct Venue {
+    exchange: String,
+    best_bids: Option<Vec<BookLevel>>,
+    best_asks: Option<Vec<BookLevel>>,
+    received_at: Option<Instant>,
+    /// event time of the held books as reported by the exchange, in unix microseconds
+    exchange_timestamp_us: Option<u64>,
+    /// since when the venue's books have been incomplete
+    incomplete_since: Option<Instant>,
+    excluded: bool,
+    /// as last reported by the exchange's system status API
+    status: VenueStatus,
+    /// how many ticks in a row the venue delivered without any other venue ticking
+    lead: usize,
+    /// whether the venue's books were left out as stale when last checked
+    evicted: bool,
+    /// whether the books were invalidated because the connection dropped
+    disconnected: bool,
+    /// whether the books were invalidated because a message of the venue was missed
+    resyncing: bool,
+}
+
+impl Venue {
+    fn new(exchange: String) -> Self {
+        Venue {
+            exchange,
+            best_bids: None,
+            best_asks: None,
+            received_at: None,
+            exchange_timestamp_us: None,
+            incomplete_since: None,
+            excluded: false,
+            status: VenueStatus::Unknown,
+            lead: 0,
+            evicted: false,
+            disconnected: false,
+            resyncing: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Aggregator {
+    venues: Vec<Venue>,
+    empty_book_policy: EmptyBookPolicy,
+    lead_policy: LeadPolicy,
+    /// the number of updates in a row a stream may lead the others by before the lead policy applies
+    lead_tolerance: usize,
+    merge_strategy: MergeStrategy,
+    /// merges alongside the published strategy for comparison only
+    shadow: Option<Shadow>,
+    spmc: Arc<Mutex<Spmc<Summary>>>,
+    /// the task broadcasting the summaries to the spmc, they are broadcast right away without one
+    delivery: Option<mpsc::Sender<Summary>>,
+    debug_spmc: Option<Arc<Mutex<Spmc<TickTimings>>>>,
+    snapshot_spmc: Option<Arc<Mutex<Spmc<ExchangeSnapshot>>>>,
+    /// the summary published last, for in-process consumers which only care about the current book
+    latest_summary: watch::Sender<Option<Summary>>,
+    source_selector: SourceSelector,
+    /// the venue id and the queue towards the aggregator of each source, indexed by source id
+    inbound_queues: Vec<(usize, InboundQueue)>,
+    lead_compensator: LeadCompensator,
+    sequence_store: SequenceStore,
+    spread_smoother: Option<SpreadSmoother>,
+    enrichers: Vec<Box<dyn Enricher>>,
+    publish_trigger: PublishTrigger,
+    /// the top of the book published last, for the top-of-book publish trigger
+    published_top: Option<(Option<Level>, Option<Level>)>,
+    /// when a summary was published last and whether updates are waiting since, for the conflated
+    /// publish trigger
+    conflation: Conflation,
+    /// set for the adaptive publish trigger only
+    adaptive_interval: Option<AdaptiveInterval>,
+    /// whether each venue contributed and when its books were received, as of the latest summary
+    published_inputs: Vec<(bool, Option<Instant>)>,
+    audits: u64,
+    failed_audits: u64,
+    symbol: String,
+    /// levels of each side of the published summary
+    depths: SideDepths,
+    /// levels per side of the venues' books, the depth can be changed at runtime up to this
+    max_depth: usize,
+    /// the labels the exchanges are published with
+    display_names: DisplayNames,
+    /// publish the hashes of the raw messages the levels were read from
+    expose_provenance: bool,
+    maintenance: Vec<MaintenanceWindow>,
+    staleness: Staleness,
+    /// how many venues have to be live for the aggregator to be ready
+    min_live: usize,
+    quorum: Option<Quorum>,
+    quiet_periods: Vec<QuietPeriod>,
+    /// since when an admin paused publishing, if it is paused
+    paused_since: Option<Instant>,
+    /// the length of the pauses that ended
+    paused_total: Duration,
+    pauses: u32,
+    journal: Arc<Journal>,
+    /// where the connectors keep the messages deviating from their schema, in strict schema mode
+    quarantine: Option<Arc<Quarantine>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Aggregator {
+    pub fn new(
+        spmc: Arc<Mutex<Spmc<Summary>>>,
+        debug_spmc: Option<Arc<Mutex<Spmc<TickTimings>>>>,
+        sequence_store: SequenceStore,
+        symbol: String,
+        exchanges: Vec<String>,
+    ) -> Aggregator {
+        let venues = exchanges.len();
+        Aggregator {
+            venues: exchanges.into_iter().map(Venue::new).collect(),
+            empty_book_policy: EmptyBookPolicy::default(),
+            lead_policy: LeadPolicy::default(),
+            lead_tolerance: DEFAULT_LEAD_TOLERANCE,
+            merge_strategy: MergeStrategy::default(),
+            shadow: None,
+            spmc,
+            delivery: None,
+            debug_spmc,
+            snapshot_spmc: None,
+            latest_summary: watch::channel(None).0,
+            source_selector: SourceSelector::new(SOURCE_FRESHNESS),
+            inbound_queues: Vec::new(),
+            lead_compensator: LeadCompensator::new(Duration::ZERO, venues),
+            sequence_store,
+            spread_smoother: None,
+            enrichers: Vec::new(),
+            publish_trigger: PublishTrigger::default(),
+            published_top: None,
+            conflation: Conflation::new(),
+            adaptive_interval: None,
+            published_inputs: Vec::new(),
+            audits: 0,
+            failed_audits: 0,
+            symbol,
+            depths: SideDepths::symmetric(DEFAULT_DEPTH),
+            max_depth: DEFAULT_DEPTH,
+            display_names: DisplayNames::default(),
+            expose_provenance: false,
+            maintenance: Vec::new(),
+            staleness: Staleness::default(),
+            min_live: venues,
+            quorum: None,
+            quiet_periods: Vec::new(),
+            paused_since: None,
+            paused_total: Duration::ZERO,
+            pauses: 0,
+            journal: Arc::new(Journal::in_memory()),
+            quarantine: None,
+            clock: clock::system(),
+        }
+    }
+
+    /**
+     * Registers a connector feeding the venue with the given id, its index in the exchanges the
+     * aggregator was created with. The returned source id has to be passed to `process`.
+     */
+    pub fn register_source(&mut self, venue_id: usize, kind: SourceKind) -> usize {
+        self.inbound_queues.push((venue_id, InboundQueue::new()));
+        self.source_selector.register(venue_id, kind)
+    }
+
+    /**
+     * The queue the connector of the source hands its snapshots to, drained by `inbound_queue::run`.
+     */
+    pub fn inbound_queue(&self, source_id: usize) -> InboundQueue {
+        self.inbound_queues[source_id].1.clone()
+    }
+
+    /**
+     * Sets after how long without a snapshot the book of each exchange is evicted from the
+     * aggregation, until its next snapshot.
+     */
+    pub fn set_staleness(&mut self, staleness: Staleness) {
+        self.staleness = staleness;
+    }
+
+    /**
+     * Excludes the exchange from (or re-includes it into) the published aggregation.
+     * Its snapshots are still processed while excluded, so it is up to date once it is included again.
+     */
+    pub fn set_excluded(&mut self, exchange: &str, excluded: bool) -> Result<(), ()> {
+        self.venue_mut(exchange).ok_or(())?.excluded = excluded;
+        Ok(())
+    }
+
+    fn venue_mut(&mut self, exchange: &str) -> Option<&mut Venue> {
+        self.venues
+            .iter_mut()
+            .find(|venue| venue.exchange == exchange)
+    }
+
+    /**
+     * Enables delaying the faster venue by its latency advantage over the slower one, up to the given window.
+     */
+    pub fn set_lead_compensation(&mut self, window: Duration) {
+        self.lead_compensator = LeadCompensator::new(window, self.venues.len());
+    }
+
+    /**
+     * Hands the summaries to a task broadcasting them to the spmc, so waiting for the fan-out does
+     * not hold the aggregator.
+     */
+    pub fn set_delivery(&mut self, delivery: mpsc::Sender<Summary>) {
+        self.delivery = Some(delivery);
+    }
+
+    /**
+     * Publishes every accepted snapshot to the given spmc as it was normalized by the connector.
+     */
+    pub fn set_snapshot_spmc(&mut self, snapshot_spmc: Arc<Mutex<Spmc<ExchangeSnapshot>>>) {
+        self.snapshot_spmc = Some(snapshot_spmc);
+    }
+
+    pub fn latest_summary(&self) -> watch::Receiver<Option<Summary>> {
+        self.latest_summary.subscribe()
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn set_journal(&mut self, journal: Arc<Journal>) {
+        self.journal = journal;
+    }
+
+    /**
+     * Where the connectors record the messages they dropped.
+     */
+    pub fn journal(&self) -> Arc<Journal> {
+        self.journal.clone()
+    }
+
+    /**
+     * Makes the connectors validate the messages of the venues against their schema, quarantining
+     * those deviating from it.
+     */
+    pub fn set_quarantine(&mut self, quarantine: Arc<Quarantine>) {
+        self.quarantine = Some(quarantine);
+    }
+
+    pub fn quarantine(&self) -> Option<Arc<Quarantine>> {
+        self.quarantine.clone()
+    }
+
+    pub fn set_merge_strategy(&mut self, merge_strategy: MergeStrategy) {
+        self.merge_strategy = merge_strategy;
+    }
+
+    /**
+     * Merges every tick with the shadow's strategy as well and publishes how it compares to the
+     * published summary to the shadow's spmc.
+     */
+    pub fn set_shadow(&mut self, shadow: Shadow) {
+        self.shadow = Some(shadow);
+    }
+
+    pub fn set_empty_book_policy(&mut self, empty_book_policy: EmptyBookPolicy) {
+        self.empty_book_policy = empty_book_policy;
+    }
+
+    pub fn set_lead_policy(&mut self, lead_policy: LeadPolicy) {
+        self.lead_policy = lead_policy;
+    }
+
+    /**
+     * Sets the number of updates in a row after which a stream leads the others too far, at least 1.
+     */
+    pub fn set_lead_tolerance(&mut self, lead_tolerance: usize) -> Result<(), ()> {
+        match lead_tolerance {
+            0 => Err(()),
+            lead_tolerance => {
+                self.lead_tolerance = lead_tolerance;
+                Ok(())
+            }
+        }
+    }
+
+    /**
+     * Publishes the smoothed spread in `spread`, the unsmoothed one stays available in `raw_spread`.
+     */
+    pub fn set_spread_smoothing(&mut self, smoothing: SpreadSmoothing) {
+        self.spread_smoother = Some(SpreadSmoother::new(smoothing));
+    }
+
+    pub fn set_publish_trigger(&mut self, publish_trigger: PublishTrigger) {
+        self.publish_trigger = publish_trigger;
+        self.adaptive_interval = match publish_trigger {
+            PublishTrigger::Adaptive { min, max } => Some(AdaptiveInterval::new(min, max)),
+            _ => None,
+        };
+    }
+
+    /**
+     * The current conflation interval, None unless the publish trigger conflates.
+     */
+    pub fn conflation_interval(&self) -> Option<Duration> {
+        match (self.publish_trigger, &self.adaptive_interval) {
+            (PublishTrigger::Conflated(interval), _) => Some(interval),
+            (_, Some(adaptive)) => Some(adaptive.interval(self.clock.now())),
+            _ => None,
+        }
+    }
+
+    /**
+     * Adds the extension of the enricher to every published summary.
+     */
+    pub fn add_enricher(&mut self, enricher: Box<dyn Enricher>) {
+        self.enrichers.push(enricher);
+    }
+
+    /**
+     * Sets how many levels of each side are merged and published at startup, raising the max depth
+     * to the deeper side if needed.
+     */
+    pub fn set_side_depths(&mut self, depths: SideDepths) -> Result<(), ()> {
+        if depths.bids == 0 || depths.asks == 0 {
+            return Err(());
+        }
+        self.depths = depths;
+        self.max_depth = self.max_depth.max(depths.max());
+        Ok(())
+    }
+
+    /**
+     * The published depth of the deeper side.
+     */
+    pub fn depth(&self) -> usize {
+        self.depths.max()
+    }
+
+    pub fn side_depths(&self) -> SideDepths {
+        self.depths
+    }
+
+    /**
+     * Sets how many levels per side the venues' books hold. The connectors build their snapshots
+     * with this depth, so it has to be set before they are started.
+     */
+    pub fn set_max_depth(&mut self, max_depth: usize) -> Result<(), ()> {
+        match max_depth >= self.depths.max() {
+            true => {
+                self.max_depth = max_depth;
+                Ok(())
+            }
+            false => Err(()),
+        }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /**
+     * Changes the published depth of the running aggregator, at most to the max depth since the
+     * venues' books are not any deeper. The replayable summaries are truncated to a smaller depth
+     * and a summary with the new depth is published right away.
+     */
+    pub async fn resize_depth(&mut self, depths: SideDepths) -> Result<(), ()> {
+        if depths.bids == 0 || depths.asks == 0 || depths.max() > self.max_depth {
+            return Err(());
+        }
+        self.depths = depths;
+        self.published_top = None;
+        self.spmc.lock().await.update_history(|summary| {
+            summary.bids.truncate(depths.bids);
+            summary.asks.truncate(depths.asks);
+        });
+        self.publish(None).await;
+        Ok(())
+    }
+
+    pub fn set_expose_provenance(&mut self, expose_provenance: bool) {
+        self.expose_provenance = expose_provenance;
+    }
+
+    /**
+     * Relabels the published levels, exchange snapshots and snapshot ages. The internal exchange
+     * names stay in use everywhere else, e.g. in the health and the admin API.
+     */
+    pub fn set_display_names(&mut self, display_names: DisplayNames) {
+        self.display_names = display_names;
+    }
+
+    /**
+     * Excludes an exchange for as long as one of its maintenance windows is ongoing, independent of
+     * whether it was excluded through `set_excluded`.
+     */
+    pub fn set_maintenance(&mut self, maintenance: Vec<MaintenanceWindow>) {
+        self.maintenance = maintenance;
+    }
+
+    /**
+     * Stores the status the exchange reported and returns the previous one. An exchange reporting
+     * maintenance is degraded and left out of the aggregation until it reports otherwise.
+     */
+    pub fn set_venue_status(
+        &mut self,
+        exchange: &str,
+        status: VenueStatus,
+    ) -> Result<VenueStatus, ()> {
+        let venue = self.venue_mut(exchange).ok_or(())?;
+        Ok(std::mem::replace(&mut venue.status, status))
+    }
+
+    /**
+     * Sets how many venues have to be live for the aggregator to be ready. The others are expected
+     * to catch up while it is already serving.
+     */
+    pub fn set_min_live(&mut self, min_live: usize) -> Result<(), ()> {
+        match min_live {
+            min_live if (1..=self.venues.len()).contains(&min_live) => {
+                self.min_live = min_live;
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    /**
+     * Only publishes summaries while at least the quorum of venues is fresh, heartbeats otherwise.
+     */
+    pub fn set_quorum(&mut self, quorum: Quorum) -> Result<(), ()> {
+        match quorum.min_venues {
+            min_venues if (1..=self.venues.len()).contains(&min_venues) => {
+                self.quorum = Some(quorum);
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    /**
+     * Publishes nothing or only heartbeats during the given daily periods.
+     */
+    pub fn set_quiet_periods(&mut self, quiet_periods: Vec<QuietPeriod>) {
+        self.quiet_periods = quiet_periods;
+    }
+
+    fn quiet_mode(&self) -> QuietMode {
+        quiet_period::quiet_mode(&self.quiet_periods, self.clock.system_now())
+    }
+
+    fn unix_now_us(&self) -> u64 {
+        self.clock
+            .system_now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64
+    }
+
+    fn has_quorum(&self) -> bool {
+        let quorum = match self.quorum {
+            Some(quorum) => quorum,
+            None => return true,
+        };
+        let now = self.clock.now();
+        let fresh_venues = self
+            .venues
+            .iter()
+            .filter(|venue| {
+                self.contributes(venue)
+                    && venue.received_at.is_some_and(|received_at| {
+                        now.duration_since(received_at) <= quorum.max_age
+                    })
+            })
+            .count();
+        fresh_venues >= quorum.min_venues
+    }
+
+    /**
+     * Pauses or resumes publishing. While paused, only heartbeats flagged with `paused` are
+     * published, and the merged book is published right away on resuming.
+     */
+    pub async fn set_paused(&mut self, paused: bool) {
+        let now = self.clock.now();
+        match (self.paused_since, paused) {
+            (None, true) => {
+                log::warning!(symbol = %self.symbol, "Publishing of {} paused", self.symbol);
+                self.paused_since = Some(now);
+                self.pauses += 1;
+                self.publish_heartbeat().await;
+            }
+            (Some(since), false) => {
+                log::warning!(symbol = %self.symbol, "Publishing of {} resumed", self.symbol);
+                self.paused_since = None;
+                self.paused_total += now.duration_since(since);
+                self.publish(None).await;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_since.is_some()
+    }
+
+    pub fn pause_state(&self) -> PauseState {
+        let paused_for = self.paused_since.map_or(Duration::ZERO, |since| {
+            self.clock.now().duration_since(since)
+        });
+        PauseState {
+            symbol: self.symbol.clone(),
+            paused: self.is_paused(),
+            paused_for_ms: paused_for.as_millis() as u64,
+            total_paused_ms: (self.paused_total + paused_for).as_millis() as u64,
+            pauses: self.pauses,
+        }
+    }
+
+    /**
+     * Publishes a heartbeat if the quorum is lost, called periodically while a quorum is required.
+     */
+    pub async fn publish_heartbeat_if_blind(&mut self) {
+        if !self.has_quorum() && self.quiet_mode() == QuietMode::None && !self.is_paused() {
+            self.publish_heartbeat().await;
+        }
+    }
+
+    /**
+     * Publishes a heartbeat during a quiet period in heartbeat mode or while paused, called
+     * periodically.
+     */
+    pub async fn publish_heartbeat_if_quiet(&mut self) {
+        if self.quiet_mode() == QuietMode::Heartbeat || self.is_paused() {
+            self.publish_heartbeat().await;
+        }
+    }
+
+    async fn publish_heartbeat(&mut self) {
+        let mut heartbeat = Summary {
+            symbol: self.symbol.clone(),
+            quorum_lost: !self.has_quorum(),
+            quiet_period: self.quiet_mode() != QuietMode::None,
+            paused: self.is_paused(),
+            aggregated_at_us: self.unix_now_us(),
+            epoch: self.sequence_store.epoch(),
+            ..Default::default()
+        };
+        (heartbeat.sequence, heartbeat.restarted) = self.sequence_store.next();
+        // the first summary after regaining the quorum, after a quiet period or a pause is published
+        // even if its top is unchanged
+        self.published_top = None;
+        self.latest_summary.send_replace(Some(heartbeat.clone()));
+        self.deliver(heartbeat).await;
+    }
+
+    async fn deliver(&self, summary: Summary) {
+        match &self.delivery {
+            // closed only once the pipeline stopped
+            Some(delivery) => {
+                let _ = delivery.send(summary).await;
+            }
+            None => self.spmc.lock().await.broadcast(summary).await,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.live_venues() >= self.min_live
+    }
+
+    fn live_venues(&self) -> usize {
+        self.venue_health()
+            .iter()
+            .filter(|venue| venue.live)
+            .count()
+    }
+
+    pub fn health(&self) -> Health {
+        let live_venues = self.live_venues();
+        Health {
+            venues: self.venue_health(),
+            ready: live_venues >= self.min_live,
+            live_venues: live_venues as u32,
+            min_live_venues: self.min_live as u32,
+            quiet_mode: self.quiet_mode() as i32,
+            conflation_interval_ms: self
+                .conflation_interval()
+                .map_or(0, |interval| interval.as_millis() as u32),
+            pause: Some(self.pause_state()),
+        }
+    }
+
+    /**
+     * The internal state of the pipeline, for debugging a live incident: the books and the trackers
+     * of each venue, the lead and publishing state and the subscribers of the summaries.
+     */
+    pub async fn dump_state(&self) -> serde_json::Value {
+        let now = self.clock.now();
+        let ms_since = |instant: Option<Instant>| {
+            instant.map(|instant| now.duration_since(instant).as_millis() as u64)
+        };
+        let venues: Vec<_> = (self.venues.iter().enumerate())
+            .map(|(venue_id, venue)| {
+                json!({
+                    "exchange": venue.exchange,
+                    "bids": venue.best_bids,
+                    "asks": venue.best_asks,
+                    "received_ms_ago": ms_since(venue.received_at),
+                    "exchange_timestamp_us": venue.exchange_timestamp_us,
+                    "incomplete_for_ms": ms_since(venue.incomplete_since),
+                    "status": format!("{:?}", venue.status),
+                    "excluded": venue.excluded,
+                    "lead": venue.lead,
+                    "lead_compensation_delay_us":
+                        self.lead_compensator.delay(venue_id).as_micros() as u64,
+                    "lead_compensation_pending": self.lead_compensator.pending(venue_id),
+                    "stale_after_ms": (self.staleness.timeout(&venue.exchange))
+                        .map(|timeout| timeout.as_millis() as u64),
+                    "evicted": venue.evicted,
+                    "disconnected": venue.disconnected,
+                    "resyncing": venue.resyncing,
+                })
+            })
+            .collect();
+        let subscribers: Vec<_> = (self.spmc.lock().await.subscribers().into_iter())
+            .map(|subscriber| {
+                json!({
+                    "client": subscriber.client,
+                    "buffer_size": subscriber.buffer_size,
+                    "queued": subscriber.queued,
+                    "dropped": subscriber.dropped,
+                })
+            })
+            .collect();
+
+        json!({
+            "symbol": self.symbol,
+            "venues": venues,
+            "lead": {
+                "policy": format!("{:?}", self.lead_policy),
+                "tolerance": self.lead_tolerance,
+                "leading": self.leading_venue().map(|venue| &venue.exchange),
+            },
+            "publish_trigger": format!("{:?}", self.publish_trigger),
+            "latest_sequence": (self.latest_summary.borrow().as_ref())
+                .map(|summary| summary.sequence),
+            "health": self.health(),
+            "subscribers": subscribers,
+        })
+    }
+
+    fn venue_health(&self) -> Vec<VenueHealth> {
+        let now = self.clock.now();
+        self.venues
+            .iter()
+            .enumerate()
+            .map(|(venue_id, venue)| {
+                let inbound_queues = self
+                    .inbound_queues
+                    .iter()
+                    .filter(|(queue_venue_id, _)| *queue_venue_id == venue_id)
+                    .map(|(_, queue)| queue);
+                let degraded = self.is_degraded(venue);
+                let stale = self.is_stale(venue);
+                let age = venue
+                    .received_at
+                    .map(|received_at| now.duration_since(received_at));
+                VenueHealth {
+                    exchange: venue.exchange.clone(),
+                    exchange_id: exchange_registry::exchange_id(&venue.exchange) as i32,
+                    status: venue.status as i32,
+                    degraded,
+                    excluded: self.is_excluded(venue),
+                    snapshot_age_ms: age.map(|age| age.as_millis() as u64),
+                    live: !degraded && !stale && age.is_some_and(|age| age < LIVE_WITHIN),
+                    stale,
+                    inbound_queue_depth: inbound_queues
+                        .clone()
+                        .map(InboundQueue::depth)
+                        .sum::<usize>() as u32,
+                    overloaded: inbound_queues.clone().any(InboundQueue::is_conflating),
+                    disconnected: venue.disconnected,
+                    resyncing: venue.resyncing,
+                }
+            })
+            .collect()
+    }
+
+    /**
+     * The exchanges currently left out of the aggregation, either on request or due to maintenance.
+     */
+    pub fn excluded_exchanges(&self) -> Vec<String> {
+        self.venues
+            .iter()
+            .filter(|venue| self.is_excluded(venue))
+            .map(|venue| venue.exchange.clone())
+            .collect()
+    }
+
+    fn in_maintenance(&self, exchange: &str) -> bool {
+        maintenance::active_until(&self.maintenance, exchange, self.clock.system_now()).is_some()
+    }
+
+    /**
+     * In maintenance, either according to the status the exchange reported or a maintenance window.
+     */
+    fn is_degraded(&self, venue: &Venue) -> bool {
+        venue.status == VenueStatus::Maintenance || self.in_maintenance(&venue.exchange)
+    }
+
+    fn is_excluded(&self, venue: &Venue) -> bool {
+        venue.excluded || self.is_degraded(venue)
+    }
+
+    fn is_stale(&self, venue: &Venue) -> bool {
+        let now = self.clock.now();
+        venue.received_at.is_some_and(|received_at| {
+            self.staleness
+                .is_stale(&venue.exchange, now.duration_since(received_at))
+        })
+    }
+
+    /**
+     * Leaves the books of venues without an update within their staleness timeout out and publishes
+     * the aggregation of the remaining ones, called periodically while a timeout is set.
+     */
+    pub async fn evict_stale(&mut self) {
+        let stale: Vec<bool> = self
+            .venues
+            .iter()
+            .map(|venue| self.is_stale(venue))
+            .collect();
+        let mut evicted = false;
+        for (venue, stale) in self.venues.iter_mut().zip(stale) {
+            if stale && !venue.evicted {
+                log::warning!(
+                    exchange = %venue.exchange,
+                    "No update from {} within its staleness timeout, leaving its books out",
+                    venue.exchange
+                );
+                evicted = true;
+            }
+            venue.evicted = stale;
+        }
+        if evicted {
+            self.publish(None).await;
+        }
+    }
+
+    /**
+     * Invalidates the books of the source's venue once its connection dropped, so the outage is not
+     * published as a frozen book until the staleness timeout, and publishes the aggregation of the
+     * remaining venues. Snapshots still queued from the dropped connection are discarded. The venue
+     * contributes again with the first snapshot after the reconnect.
+     */
+    pub async fn invalidate_source(&mut self, source_id: usize) {
+        if let Some((venue_id, held_books)) = self.clear_source(source_id) {
+            let venue = &mut self.venues[venue_id];
+            venue.disconnected = true;
+            if held_books {
+                log::warning!(
+                    exchange = %venue.exchange,
+                    "{} disconnected, leaving its books out until it delivers again",
+                    venue.exchange
+                );
+                self.publish(None).await;
+            }
+        }
+    }
+
+    /**
+     * Invalidates the books of the source's venue once its connector detected that the local book
+     * diverged, e.g. because of a gap in the venue's sequence, and publishes the aggregation of the
+     * remaining venues. The venue contributes again with the first snapshot after resynchronizing.
+     */
+    pub async fn resync_source(&mut self, source_id: usize) {
+        if let Some((venue_id, held_books)) = self.clear_source(source_id) {
+            let venue = &mut self.venues[venue_id];
+            venue.resyncing = true;
+            if held_books {
+                log::warning!(
+                    exchange = %venue.exchange,
+                    "{} book diverged, leaving it out until it was resynchronized",
+                    venue.exchange
+                );
+                self.publish(None).await;
+            }
+        }
+    }
+
+    /**
+     * Discards the queued snapshots and the books of the source's venue, unless another source
+     * keeps feeding it. Returns the venue along with whether it held books.
+     */
+    fn clear_source(&mut self, source_id: usize) -> Option<(usize, bool)> {
+        if let Some((_, queue)) = self.inbound_queues.get(source_id) {
+            queue.clear();
+        }
+        let venue_id = self
+            .source_selector
+            .disconnect(source_id, self.clock.now())?;
+        let venue = &mut self.venues[venue_id];
+        let held_books = venue.received_at.is_some();
+        venue.best_bids = None;
+        venue.best_asks = None;
+        venue.received_at = None;
+        venue.exchange_timestamp_us = None;
+        venue.incomplete_since = None;
+        Some((venue_id, held_books))
+    }
+
+    /**
+     * Stores the snapshot of the given source and publishes the updated aggregation.
+     * The timings are expected to contain the parse and normalize durations measured by the connector,
+     * the remaining stages are filled in here.
+     */
+    pub async fn process(
+        &mut self,
+        source_id: usize,
+        snapshot: OrderbookSnapshot,
+        mut timings: TickTimings,
+    ) {
+        let venue_id = match self.source_selector.accept(source_id, self.clock.now()) {
+            Some(venue_id) => venue_id,
+            None => return,
+        };
+
+        if let Some(snapshot_spmc) = &self.snapshot_spmc {
+            let mut snapshot_spmc = snapshot_spmc.lock().await;
+            if !snapshot_spmc.is_empty() {
+                let exchange = &self.venues[venue_id].exchange;
+                let mut exchange_snapshot = ExchangeSnapshot {
+                    exchange: self.display_names.label(exchange).to_string(),
+                    exchange_id: exchange_registry::exchange_id(exchange) as i32,
+                    symbol: self.symbol.clone(),
+                    bids: (snapshot.bids.iter().flatten())
+                        .map(|level| self.published(level))
+                        .collect(),
+                    asks: (snapshot.asks.iter().flatten())
+                        .map(|level| self.published(level))
+                        .collect(),
+                    exchange_timestamp_us: snapshot.exchange_timestamp_us,
+                };
+                self.display_names.relabel(&mut exchange_snapshot.bids);
+                self.display_names.relabel(&mut exchange_snapshot.asks);
+                snapshot_spmc.broadcast(exchange_snapshot).await;
+            }
+        }
+
+        let now = self.clock.now();
+        let unix_now_us = self.unix_now_us();
+        let latency_us = snapshot
+            .exchange_timestamp_us
+            .map(|exchange_timestamp_us| unix_now_us.saturating_sub(exchange_timestamp_us));
+
+        for (id, venue) in self.venues.iter_mut().enumerate() {
+            venue.lead = match id == venue_id {
+                true => venue.lead + 1,
+                false => 0,
+            };
+        }
+        let venue = &self.venues[venue_id];
+        if self.stream_exceeded_lead_tolerance(venue.lead) {
+            // the merged book may not reflect the actual spread anymore, the lead policy decides
+            // what is published meanwhile
+            Aggregator::log_lead_warning(&venue.exchange, venue.lead);
+        }
+
+        self.lead_compensator
+            .push(venue_id, snapshot, now, latency_us);
+        if !self.store_released(now) {
+            return;
+        }
+        timings.exchange = self.venues[venue_id].exchange.clone();
+        self.publish_update(now, Some(timings)).await;
+    }
+
+    /**
+     * Stores and publishes the snapshots the lead compensation held back whose delay passed without
+     * a later snapshot releasing them. Returns how long until the next held back snapshot is due,
+     * None if none is held back.
+     */
+    pub async fn release_held_back(&mut self) -> Option<Duration> {
+        let now = self.clock.now();
+        if self.store_released(now) {
+            self.publish_update(now, None).await;
+        }
+        self.lead_compensator.next_release(now)
+    }
+
+    /**
+     * Stores the snapshots the lead compensation releases at the given time, returns whether there
+     * were any.
+     */
+    fn store_released(&mut self, now: Instant) -> bool {
+        let released = self.lead_compensator.release(now);
+        if released.is_empty() {
+            return false;
+        }
+        for (venue_id, received_at, snapshot) in released {
+            if let Some(adaptive) = &mut self.adaptive_interval {
+                let venue = &self.venues[venue_id];
+                let before =
+                    Aggregator::top_of_book(venue.best_bids.as_deref(), venue.best_asks.as_deref());
+                let after =
+                    Aggregator::top_of_book(snapshot.bids.as_deref(), snapshot.asks.as_deref());
+                adaptive.observe(received_at, before != after, Aggregator::spread_bps(after));
+            }
+            self.store(venue_id, received_at, snapshot);
+        }
+        true
+    }
+
+    /**
+     * Publishes the stored books after an update, unless the publish trigger, the lead policy or
+     * the conflation hold the summary back.
+     */
+    async fn publish_update(&mut self, now: Instant, timings: Option<TickTimings>) {
+        if !self.publish_trigger.on_update() {
+            return;
+        }
+        if self.lead_policy == LeadPolicy::SuppressPublish && self.leading_venue().is_some() {
+            return;
+        }
+        if let Some(interval) = self.conflation_interval() {
+            if self.conflation.hold_back(now, interval) {
+                return;
+            }
+        }
+        self.publish(timings).await;
+    }
+
+    /**
+     * Publishes the current aggregation, called on every interval of the timer publish trigger.
+     */
+    pub async fn publish_on_timer(&mut self) {
+        self.publish(None).await;
+    }
+
+    /**
+     * Publishes the updates conflated since the last summary once the interval passed, with the
+     * freshest books. Returns how long to wait until the next summary may be published, None
+     * unless the publish trigger conflates.
+     */
+    pub async fn publish_conflated(&mut self) -> Option<Duration> {
+        let interval = self.conflation_interval()?;
+        let remaining = self.conflation.remaining(self.clock.now(), interval);
+        if !remaining.is_zero() {
+            return Some(remaining);
+        }
+        if self.conflation.is_pending() {
+            self.publish(None).await;
+        }
+        // the adaptive interval may have changed with the updates published
+        self.conflation_interval()
+    }
+
+    /**
+     * Merges and publishes the stored books. The timings of the tick causing the publish, if any,
+     * are completed and sent on the debug stream.
+     */
+    async fn publish(&mut self, mut timings: Option<TickTimings>) {
+        self.conflation.published(self.clock.now());
+        if self.quiet_mode() != QuietMode::None || self.is_paused() {
+            // heartbeats are published periodically during a quiet period or a pause, not on every
+            // update
+            self.published_top = None;
+            return;
+        }
+        if !self.has_quorum() {
+            self.publish_heartbeat().await;
+            return;
+        }
+        let (summary, merge_ns) = stage_timings::timed(|| self.summarize());
+        let mut summary = match summary {
+            Some(summary) => summary,
+            None => return,
+        };
+        if self.publish_trigger == PublishTrigger::TopOfBookChange {
+            let top = publish_trigger::top_of_book(&summary);
+            if self.published_top.as_ref() == Some(&top) {
+                return;
+            }
+            self.published_top = Some(top);
+        }
+        (summary.sequence, summary.restarted) = self.sequence_store.next();
+        summary.epoch = self.sequence_store.epoch();
+        summary.raw_spread = summary.spread;
+        summary.lead_degraded =
+            self.lead_policy == LeadPolicy::MarkDegraded && self.leading_venue().is_some();
+        self.published_inputs = self.merge_inputs();
+        let shadow_summary = self
+            .shadow
+            .as_ref()
+            .and_then(|shadow| self.merge_books_with(shadow.strategy));
+        let shadow_comparison = match (&mut self.shadow, shadow_summary) {
+            (Some(shadow), Some(shadow_summary)) => Some(shadow.compare(&summary, shadow_summary)),
+            _ => None,
+        };
+        if let (Some(spread_smoother), Some(raw_spread)) =
+            (&mut self.spread_smoother, summary.raw_spread)
+        {
+            summary.spread = Some(spread_smoother.smooth(raw_spread));
+        }
+        enrichment::enrich(&self.enrichers, &mut summary);
+        self.relabel(&mut summary);
+
+        let debugging = match (&self.debug_spmc, &timings) {
+            (Some(debug_spmc), Some(_)) => !debug_spmc.lock().await.is_empty(),
+            _ => false,
+        };
+        if let Some(timings) = timings.as_mut().filter(|_| debugging) {
+            timings.merge_ns = merge_ns;
+            timings.encode_ns = stage_timings::timed(|| summary.encode_to_vec()).1;
+        }
+
+        let fan_out_started = Instant::now();
+        self.latest_summary.send_replace(Some(summary.clone()));
+        self.deliver(summary).await;
+        let fan_out_ns = stage_timings::elapsed_ns(fan_out_started);
+
+        if let (Some(shadow), Some(shadow_comparison)) = (&self.shadow, shadow_comparison) {
+            shadow.publish(shadow_comparison).await;
+        }
+
+        if let (Some(debug_spmc), Some(mut timings)) =
+            (self.debug_spmc.as_ref().filter(|_| debugging), timings)
+        {
+            timings.fan_out_ns = fan_out_ns;
+            debug_spmc.lock().await.broadcast(timings).await;
+        }
+    }
+
+    fn merge_inputs(&self) -> Vec<(bool, Option<Instant>)> {
+        self.venues
+            .iter()
+            .map(|venue| (self.contributes(venue), venue.received_at))
+            .collect()
+    }
+
+    /**
+     * Checks that every venue's held books are sorted and uncrossed and, as long as they are still
+     * the ones the latest summary was merged from, that merging them again yields its levels.
+     */
+    pub fn audit(&mut self) -> AuditReport {
+        let mut findings: Vec<AuditFinding> = self
+            .venues
+            .iter()
+            .flat_map(|venue| {
+                audit::ladder_findings(
+                    &venue.exchange,
+                    venue.best_bids.as_deref().unwrap_or_default(),
+                    venue.best_asks.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect();
+
+        let published = self
+            .latest_summary
+            .borrow()
+            .clone()
+            .filter(|summary| !summary.quorum_lost);
+        let compared_sequence = match published {
+            Some(published) if self.published_inputs == self.merge_inputs() => {
+                let mut derived = self.merge_books().unwrap_or_default();
+                self.relabel(&mut derived);
+                findings.extend(audit::merge_findings("bid", &published.bids, &derived.bids));
+                findings.extend(audit::merge_findings("ask", &published.asks, &derived.asks));
+                Some(published.sequence)
+            }
+            _ => None,
+        };
+
+        self.audits += 1;
+        if !findings.is_empty() {
+            self.failed_audits += 1;
+        }
+        AuditReport {
+            at_ms: self
+                .clock
+                .system_now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            compared_sequence,
+            findings,
+            audits: self.audits,
+            failed_audits: self.failed_audits,
+        }
+    }
+
+    fn relabel(&self, summary: &mut Summary) {
+        self.display_names.relabel(&mut summary.bids);
+        self.display_names.relabel(&mut summary.asks);
+        let relabel = |by_exchange: &mut HashMap<String, u64>| {
+            *by_exchange = std::mem::take(by_exchange)
+                .into_iter()
+                .map(|(exchange, value)| (self.display_names.label(&exchange).to_string(), value))
+                .collect();
+        };
+        relabel(&mut summary.snapshot_age_ms);
+        relabel(&mut summary.exchange_timestamp_us);
+    }
+
+    fn store(&mut self, venue_id: usize, received_at: Instant, snapshot: OrderbookSnapshot) {
+        let holding = matches!(self.empty_book_policy, EmptyBookPolicy::Hold(_));
+        let venue = &mut self.venues[venue_id];
+
+        let complete = snapshot.bids.is_some() && snapshot.asks.is_some();
+        if complete {
+            venue.incomplete_since = None;
+        } else if venue.incomplete_since.is_none() {
+            venue.incomplete_since = Some(received_at);
+        }
+        // while holding, the last complete book is kept as it is
+        if complete || !holding {
+            venue.best_bids = snapshot.bids;
+            venue.best_asks = snapshot.asks;
+            venue.received_at = Some(received_at);
+            venue.exchange_timestamp_us = snapshot.exchange_timestamp_us;
+        }
+        venue.disconnected = false;
+        venue.resyncing = false;
+    }
+
+    fn summarize(&self) -> Option<Summary> {
+        let mut summary = self.merge_books()?;
+        summary.snapshot_age_ms = self.snapshot_ages(self.clock.now());
+        summary.exchange_timestamp_us = self
+            .venues
+            .iter()
+            .filter(|venue| self.contributes(venue))
+            .filter_map(|venue| Some((venue.exchange.clone(), venue.exchange_timestamp_us?)))
+            .collect();
+        summary.aggregated_at_us = self.unix_now_us();
+        summary.symbol = self.symbol.clone();
+        Some(summary)
+    }
+
+    /**
+     * The age in milliseconds of each contributing venue's latest snapshot, keyed by the exchange name.
+     */
+    fn snapshot_ages(&self, now: Instant) -> HashMap<String, u64> {
+        self.venues
+            .iter()
+            .filter(|venue| self.contributes(venue))
+            .filter_map(|venue| {
+                let received_at = venue.received_at?;
+                Some((
+                    venue.exchange.clone(),
+                    now.duration_since(received_at).as_millis() as u64,
+                ))
+            })
+            .collect()
+    }
+
+    fn contributes(&self, venue: &Venue) -> bool {
+        self.books(venue) != (None, None)
+    }
+
+    /**
+     * The sides of a venue that go into the merge according to the empty book policy, none if the
+     * venue is excluded or stale.
+     */
+    fn books<'a>(&self, venue: &'a Venue) -> (Option<&'a [BookLevel]>, Option<&'a [BookLevel]>) {
+        if self.is_excluded(venue) || self.is_stale(venue) || self.is_outpaced(venue) {
+            return (None, None);
+        }
+        let complete = match (&venue.best_bids, &venue.best_asks) {
+            (Some(best_bids), Some(best_asks)) => (Some(&best_bids[..]), Some(&best_asks[..])),
+            _ => (None, None),
+        };
+
+        match self.empty_book_policy {
+            EmptyBookPolicy::Skip => complete,
+            EmptyBookPolicy::OneSided => (venue.best_bids.as_deref(), venue.best_asks.as_deref()),
+            EmptyBookPolicy::Hold(duration) => match venue.incomplete_since {
+                Some(incomplete_since)
+                    if self.clock.now().duration_since(incomplete_since) > duration =>
+                {
+                    (None, None)
+                }
+                _ => complete,
+            },
+        }
+    }
+
+    /**
+     * Merges the books of all contributing venues.
+     * Returns None if no venue has delivered a snapshot yet or all of them are excluded.
+     */
+    fn merge_books(&self) -> Option<Summary> {
+        self.merge_books_with(self.merge_strategy)
+    }
+
+    fn merge_books_with(&self, strategy: MergeStrategy) -> Option<Summary> {
+        let (best_bids, best_asks): (Vec<_>, Vec<_>) =
+            self.venues.iter().map(|venue| self.books(venue)).unzip();
+        let (bids, asks) = (
+            best_bids.into_iter().flatten(),
+            best_asks.into_iter().flatten(),
+        );
+        let bids = Aggregator::merge_side(strategy, bids, false, self.depths.bids);
+        let asks = Aggregator::merge_side(strategy, asks, true, self.depths.asks);
+
+        if bids.is_empty() && asks.is_empty() {
+            return None;
+        }
+        let spread = match (asks.first(), bids.first()) {
+            (Some(best_ask), Some(best_bid)) => Some((best_ask.price - best_bid.price).to_f64()),
+            _ => None,
+        };
+
+        Some(Summary {
+            spread,
+            mid: book_analytics::mid(&bids, &asks),
+            vwap: book_analytics::vwap(&bids, &asks),
+            microprice: book_analytics::microprice(&bids, &asks),
+            bids: bids.iter().map(|level| self.published(level)).collect(),
+            asks: asks.iter().map(|level| self.published(level)).collect(),
+            ..Default::default()
+        })
+    }
+
+    /**
+     * Converts a level for publishing, along with its provenance only if that is exposed.
+     */
+    fn published(&self, level: &BookLevel) -> Level {
+        let mut published = Level::from(level);
+        if !self.expose_provenance {
+            published.provenance = 0;
+            for contributor in &mut published.contributors {
+                contributor.provenance = 0;
+            }
+        }
+        published
+    }
+
+    /**
+     * Merges the ladders of the venues one after the other into the ladder merged so far, in the
+     * order of the venue ids. The strategies keep their order for equal levels this way.
+     */
+    fn merge_side<'a>(
+        strategy: MergeStrategy,
+        ladders: impl IntoIterator<Item = &'a [BookLevel]>,
+        side: bool,
+        depth: usize,
+    ) -> Vec<BookLevel> {
+        let mut ladders = ladders.into_iter();
+        let first = match ladders.next() {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        if strategy == MergeStrategy::Interleave {
+            // the levels are only cloned once they made it into the merged book
+            let mut merged: Vec<&BookLevel> = first.iter().take(depth).collect();
+            let mut buffer = Vec::with_capacity(depth);
+            for levels in ladders {
+                Aggregator::merge(&mut buffer, &merged, levels, depth, side);
+                std::mem::swap(&mut merged, &mut buffer);
+            }
+            return merged.into_iter().cloned().collect();
+        }
+        let first = first.iter().take(depth).cloned().collect();
+        ladders.fold(first, |merged, levels| match strategy {
+            MergeStrategy::LargerAmountFirst => {
+                merge_strategy::larger_amount_first(&merged, levels, side, depth)
+            }
+            _ => merge_strategy::combine_prices(&merged, levels, side, depth),
+        })
+    }
+
+    /**
+     * The price and amount of the best bid and the best ask of a venue's books.
+     */
+    fn top_of_book(
+        bids: Option<&[BookLevel]>,
+        asks: Option<&[BookLevel]>,
+    ) -> [Option<(Decimal, Decimal)>; 2] {
+        let best = |levels: Option<&[BookLevel]>| {
+            let best = levels?.first()?;
+            Some((best.price, best.amount))
+        };
+        [best(bids), best(asks)]
+    }
+
+    /**
+     * The spread of a top of the book in basis points of its mid.
+     */
+    fn spread_bps(top_of_book: [Option<(Decimal, Decimal)>; 2]) -> Option<f64> {
+        let (bid, ask) = match top_of_book {
+            [Some((bid, _)), Some((ask, _))] => (bid.to_f64(), ask.to_f64()),
+            _ => return None,
+        };
+        Some((ask - bid) / ((ask + bid) / 2.) * 10_000.)
+    }
+
+    fn stream_exceeded_lead_tolerance(&self, lead: usize) -> bool {
+        lead >= self.lead_tolerance
+    }
+
+    /**
+     * The venue whose stream exceeded the lead tolerance, if any. Only the venue of the latest
+     * update can have a lead, so there is at most one.
+     */
+    fn leading_venue(&self) -> Option<&Venue> {
+        self.venues
+            .iter()
+            .find(|venue| self.stream_exceeded_lead_tolerance(venue.lead))
+    }
+
+    /**
+     * Left out of the merge since another venue leads beyond the tolerance, for the single exchange
+     * lead policy.
+     */
+    fn is_outpaced(&self, venue: &Venue) -> bool {
+        self.lead_policy == LeadPolicy::PublishSingleExchange
+            && self
+                .leading_venue()
+                .is_some_and(|leading| leading.exchange != venue.exchange)
+    }
+
+    fn log_lead_warning(exchange_name: &str, lead: usize) {
+        log::warning!(
+            exchange = exchange_name,
+            "{} stream is {} ticks ahead",
+            exchange_name,
+            lead
+        );
+    }
+
+    /**
+     * Merges two ladders sorted with the best offer at position 0 into the best `depth` levels,
+     * clearing `merged` first. Only references are written, so reusing the buffer the merge does not
+     * allocate. On equal prices the first ladder's ask and the second ladder's bid go first.
+     * The side states if the ladders contain bids (false) or asks (true).
+     */
+    fn merge<'a>(
+        merged: &mut Vec<&'a BookLevel>,
+        levels_01: &[&'a BookLevel],
+        levels_02: &'a [BookLevel],
+        depth: usize,
+        side: bool,
+    ) {
+        merged.clear();
+        let (mut index_01, mut index_02) = (0, 0);
+
+        while merged.len() < depth {
+            let next = match (levels_01.get(index_01), levels_02.get(index_02)) {
+                (Some(level_01), Some(level_02)) => {
+                    let first = match side {
+                        true => level_01.price <= level_02.price,
+                        false => level_01.price > level_02.price,
+                    };
+                    match first {
+                        true => {
+                            index_01 += 1;
+                            *level_01
+                        }
+                        false => {
+                            index_02 += 1;
+                            level_02
+                        }
+                    }
+                }
+                (Some(level_01), None) => {
+                    index_01 += 1;
+                    *level_01
+                }
+                (None, Some(level_02)) => {
+                    index_02 += 1;
+                    level_02
+                }
+                (None, None) => break,
+            };
+            merged.push(next);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aggregator;
+    use crate::{
+        aggregator::DEFAULT_DEPTH,
+        capture::Capture,
+        clock::{Clock, ManualClock},
+        empty_book_policy::EmptyBookPolicy,
+        lead_policy::LeadPolicy,
+        maintenance::MaintenanceWindow,
+        publish_trigger::PublishTrigger,
+        quiet_period::QuietPeriod,
+        quorum::Quorum,
+        sequence_store::SequenceStore,
+        source_selector::SourceKind,
+        spmc::Spmc,
+        test_fixtures,
+    };
+    use init_with::InitWith;
+    use keyrock_challenge_core::{
+        decimal::Decimal,
+        orderbook_snapshot::{BookLevel, OrderbookSnapshot, SideDepths, SnapshotBuilder},
+        staleness::Staleness,
+    };
+    use keyrock_challenge_proto::orderbook::{
+        AuditFindingKind, QuietMode, TickTimings, VenueStatus,
+    };
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant, SystemTime},
+    };
+    use tokio::sync::Mutex;
+
+    fn decimal(value: f64) -> Decimal {
+        Decimal::try_from(value).unwrap()
+    }
+
+    fn merge(
+        levels_01: &[BookLevel],
+        levels_02: &[BookLevel],
+        depth: usize,
+        side: bool,
+    ) -> Vec<BookLevel> {
+        let levels_01: Vec<&BookLevel> = levels_01.iter().collect();
+        let mut merged = Vec::with_capacity(depth);
+        Aggregator::merge(&mut merged, &levels_01, levels_02, depth, side);
+        merged.into_iter().cloned().collect()
+    }
+
+    /**
+     * The recursive merge the iterative one replaced, cloning every level. Kept as the reference the
+     * iterative merge is checked and benchmarked against.
+     */
+    fn recursive_merge(
+        merged: &mut Vec<BookLevel>,
+        levels_01: &[BookLevel],
+        levels_02: &[BookLevel],
+        index_01: usize,
+        index_02: usize,
+        side: bool,
+    ) {
+        let exhausted = index_01 >= levels_01.len() && index_02 >= levels_02.len();
+        if merged.len() == merged.capacity() || exhausted {
+            return;
+        }
+        let (level, index_01, index_02) = match (levels_01.get(index_01), levels_02.get(index_02)) {
+            (Some(level_01), Some(level_02)) if side == (level_01.price <= level_02.price) => {
+                (level_01, index_01 + 1, index_02)
+            }
+            (Some(_), Some(level_02)) | (None, Some(level_02)) => {
+                (level_02, index_01, index_02 + 1)
+            }
+            (Some(level_01), None) => (level_01, index_01 + 1, index_02),
+            (None, None) => unreachable!(),
+        };
+        merged.push(level.clone());
+        recursive_merge(merged, levels_01, levels_02, index_01, index_02, side)
+    }
+
+    /**
+     * A ladder of `len` levels with many equal prices, and with equal prices across ladders.
+     */
+    fn ladder(exchange: &str, len: usize, side: bool, seed: u64) -> Vec<BookLevel> {
+        let mut price = 1_000u64;
+        (0..len as u64)
+            .map(|i| {
+                let step = (i * 7 + seed) % 3;
+                price = match side {
+                    true => price + step,
+                    false => price - step,
+                };
+                BookLevel {
+                    price: Decimal::try_from(price as f64 / 100.).unwrap(),
+                    amount: decimal((i % 5 + 1) as f64),
+                    exchange: exchange.to_string(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    fn levels(exchange: &str, best_price: f64, step: f64) -> Vec<BookLevel> {
+        (0..DEFAULT_DEPTH)
+            .map(|i| BookLevel {
+                price: decimal(best_price + step * i as f64),
+                amount: decimal(1.),
+                exchange: exchange.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn aggregator() -> Aggregator {
+        let mut aggregator = Aggregator::new(
+            Arc::new(Mutex::new(Spmc::new())),
+            None,
+            SequenceStore::open(None).unwrap(),
+            "ethbtc".to_string(),
+            vec!["Binance".to_string(), "Bitstamp".to_string()],
+        );
+        aggregator.venues[0].best_bids = Some(levels("Binance", 10., -1.));
+        aggregator.venues[0].best_asks = Some(levels("Binance", 11., 1.));
+        aggregator.venues[1].best_bids = Some(levels("Bitstamp", 10.5, -1.));
+        aggregator.venues[1].best_asks = Some(levels("Bitstamp", 12., 1.));
+        aggregator
+    }
+
+    #[test]
+    fn should_leave_excluded_exchange_out_of_summary() {
+        // Arrange
+        let mut aggregator = aggregator();
+
+        // Act
+        aggregator.set_excluded("Bitstamp", true).unwrap();
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(summary.spread == Some(1.));
+        assert!(summary.bids.iter().all(|level| level.exchange == "Binance"));
+        assert!(summary.asks.iter().all(|level| level.exchange == "Binance"));
+        assert!(aggregator.excluded_exchanges() == vec!["Bitstamp".to_string()]);
+    }
+
+    #[test]
+    fn should_degrade_venue_reporting_maintenance() {
+        // Arrange
+        let mut aggregator = aggregator();
+
+        // Act
+        let previous = aggregator.set_venue_status("Bitstamp", VenueStatus::Maintenance);
+        let summary = aggregator.merge_books().unwrap();
+        let health = aggregator.health().venues;
+
+        // Assert
+        assert!(previous == Ok(VenueStatus::Unknown));
+        assert!(summary.bids.iter().all(|level| level.exchange == "Binance"));
+        assert!(!health[0].degraded && !health[0].excluded);
+        assert!(health[1].degraded && health[1].excluded);
+        assert!(health[1].status == VenueStatus::Maintenance as i32);
+        assert!(aggregator
+            .set_venue_status("Kraken", VenueStatus::Operational)
+            .is_err());
+    }
+
+    #[test]
+    fn should_be_ready_once_enough_venues_are_live() {
+        // Arrange
+        let mut aggregator = aggregator();
+        aggregator.set_min_live(1).unwrap();
+        let not_ready = aggregator.health();
+
+        // Act
+        aggregator.store(1, Instant::now(), without_asks("Bitstamp"));
+        let health = aggregator.health();
+
+        // Assert
+        assert!(!not_ready.ready && not_ready.live_venues == 0);
+        assert!(health.ready && health.live_venues == 1 && health.min_live_venues == 1);
+        assert!(!health.venues[0].live && health.venues[1].live);
+        assert!(aggregator.set_min_live(2).is_ok() && !aggregator.is_ready());
+        assert!(aggregator.set_min_live(3).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_dump_the_state_of_every_venue_and_subscriber() {
+        // Arrange
+        let mut aggregator = aggregator();
+        aggregator.set_excluded("Bitstamp", true).unwrap();
+        let _receiver = aggregator.spmc.lock().await.create_receiver(4);
+
+        // Act
+        let state = aggregator.dump_state().await;
+
+        // Assert
+        assert!(state["symbol"] == "ethbtc");
+        assert!(state["venues"][0]["exchange"] == "Binance");
+        assert!(state["venues"][0]["bids"][0]["exchange"] == "Binance");
+        assert!(state["venues"][1]["excluded"] == true);
+        assert!(state["subscribers"][0]["buffer_size"] == 4);
+        assert!(state["health"]["venues"].as_array().unwrap().len() == 2);
+    }
+
+    #[test]
+    fn should_merge_more_than_two_venues() {
+        // Arrange
+        let mut aggregator = Aggregator::new(
+            Arc::new(Mutex::new(Spmc::new())),
+            None,
+            SequenceStore::open(None).unwrap(),
+            "ethbtc".to_string(),
+            ["Binance", "Bitstamp", "Kraken"].map(String::from).to_vec(),
+        );
+
+        // Act
+        for (venue_id, exchange, best_bid) in [
+            (0, "Binance", 10.),
+            (1, "Bitstamp", 10.5),
+            (2, "Kraken", 10.25),
+        ] {
+            let snapshot = OrderbookSnapshot {
+                bids: Some(levels(exchange, best_bid, -1.)),
+                asks: Some(levels(exchange, best_bid + 1., 1.)),
+                exchange_timestamp_us: None,
+            };
+            aggregator.store(venue_id, Instant::now(), snapshot);
+        }
+        aggregator.set_excluded("Bitstamp", true).unwrap();
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        let exchanges: Vec<&str> = summary.bids[..3]
+            .iter()
+            .map(|level| level.exchange.as_str())
+            .collect();
+        assert!(exchanges == vec!["Kraken", "Binance", "Kraken"]);
+        assert!(summary.bids.len() == DEFAULT_DEPTH && summary.spread == Some(0.75));
+        assert!(aggregator.health().venues.len() == 3);
+        assert!(aggregator.set_min_live(3).is_ok() && aggregator.set_min_live(4).is_err());
+    }
+
+    #[test]
+    fn should_merge_configured_depth() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let ladder = |exchange: &str, best_price: f64, step: f64| {
+            (0..25)
+                .map(|i| BookLevel {
+                    price: decimal(best_price + step * i as f64),
+                    amount: decimal(1.),
+                    exchange: exchange.to_string(),
+                    ..Default::default()
+                })
+                .collect()
+        };
+
+        // Act
+        aggregator
+            .set_side_depths(SideDepths::symmetric(20))
+            .unwrap();
+        for (venue_id, exchange, best_bid) in [(0, "Binance", 100.), (1, "Bitstamp", 100.5)] {
+            let snapshot = SnapshotBuilder::new()
+                .bids(ladder(exchange, best_bid, -1.))
+                .asks(ladder(exchange, best_bid + 1., 1.))
+                .build(aggregator.depth())
+                .unwrap();
+            aggregator.store(venue_id, Instant::now(), snapshot);
+        }
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(summary.bids.len() == 20 && summary.asks.len() == 20);
+        assert!(summary.bids[19].price == 91. && summary.asks[19].price == 110.5);
+        assert!(aggregator
+            .set_side_depths(SideDepths::symmetric(0))
+            .is_err());
+    }
+
+    #[test]
+    fn should_merge_each_side_to_its_own_depth() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let depths = SideDepths { bids: 6, asks: 2 };
+        aggregator.set_side_depths(depths).unwrap();
+        let depth = aggregator.max_depth();
+        let ladder = |exchange: &str, best_price: f64, step: f64| -> Vec<BookLevel> {
+            (0..depth)
+                .map(|i| BookLevel {
+                    price: decimal(best_price + step * i as f64),
+                    amount: decimal(1.),
+                    exchange: exchange.to_string(),
+                    ..Default::default()
+                })
+                .collect()
+        };
+
+        // Act
+        for (venue_id, exchange, best_bid) in [(0, "Binance", 100.), (1, "Bitstamp", 100.5)] {
+            let snapshot = SnapshotBuilder::new()
+                .bids(ladder(exchange, best_bid, -1.))
+                .asks(ladder(exchange, best_bid + 1., 1.))
+                .build(depth)
+                .unwrap();
+            aggregator.store(venue_id, Instant::now(), snapshot);
+        }
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(summary.bids.len() == 6 && summary.asks.len() == 2);
+        assert!(aggregator.depth() == 6 && aggregator.max_depth() >= 6);
+        assert!(aggregator
+            .set_side_depths(SideDepths { bids: 3, asks: 0 })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn should_resize_depth_at_runtime() {
+        // Arrange
+        let mut aggregator = aggregator();
+        aggregator.spmc = Arc::new(Mutex::new(Spmc::with_history(4)));
+        let capture = Capture::new(Arc::new(ManualClock::new()));
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        aggregator
+            .set_side_depths(SideDepths::symmetric(5))
+            .unwrap();
+        aggregator.publish(None).await;
+
+        // Act
+        let shallower = aggregator.resize_depth(SideDepths::symmetric(3)).await;
+        let deeper = aggregator
+            .resize_depth(SideDepths::symmetric(DEFAULT_DEPTH))
+            .await;
+        let too_deep = aggregator
+            .resize_depth(SideDepths::symmetric(DEFAULT_DEPTH + 1))
+            .await;
+
+        // Assert
+        assert!(shallower.is_ok() && deeper.is_ok() && too_deep.is_err());
+        let published: Vec<usize> = capture
+            .captured()
+            .iter()
+            .map(|captured| captured.item.bids.len())
+            .collect();
+        assert!(published == vec![5, 3, DEFAULT_DEPTH]);
+        let replayed: Vec<usize> = (aggregator.spmc.lock().await)
+            .history()
+            .map(|summary| summary.asks.len())
+            .collect();
+        assert!(replayed == vec![3, 3, DEFAULT_DEPTH]);
+        assert!(aggregator.depth() == DEFAULT_DEPTH && aggregator.set_max_depth(5).is_err());
     }
-}
 
-#[derive(Debug)]
-pub struct Aggregator {
-    best_bids_01: Option<[Level; DEPTH]>,
-    best_bids_02: Option<[Level; DEPTH]>,
-    best_asks_01: Option<[Level; DEPTH]>,
-    best_asks_02: Option<[Level; DEPTH]>,
-    spmc: Arc<Mutex<Spmc>>,
-    exchange_01_name: String,
-    exchange_02_name: String,
-    lead_01: usize,
-    lead_02: usize,
-}
+    #[test]
+    fn should_not_summarize_if_all_exchanges_are_excluded() {
+        // Arrange
+        let mut aggregator = aggregator();
 
-impl Aggregator {
-    pub fn new(
-        spmc: Arc<Mutex<Spmc>>,
-        exchange_01_name: String,
-        exchange_02_name: String,
-    ) -> Aggregator {
-        Aggregator {
-            best_bids_01: None,
-            best_bids_02: None,
-            best_asks_01: None,
-            best_asks_02: None,
-            spmc,
-            exchange_01_name,
-            exchange_02_name,
-            lead_01: 0,
-            lead_02: 0,
-        }
-    }
-    pub async fn process(&mut self, source_id: usize, snapshot: OrderbookSnapshot<DEPTH>) {
-        match source_id {
-            0 => {
-                self.best_bids_01 = Some(snapshot.bids);
-                self.best_asks_01 = Some(snapshot.asks);
-                self.lead_01 += 1;
-                self.lead_02 = 0;
-
-                if Aggregator::stream_exceeded_lead_tolerance(self.lead_01) {
-                    // In a production scenario, we might not even want to publish the aggregation here since it may not
-                    // reflecting the actual spread anymore
-                    Aggregator::log_lead_warning(&self.exchange_01_name, self.lead_01);
-                }
+        // Act
+        aggregator.set_excluded("Binance", true).unwrap();
+        aggregator.set_excluded("Bitstamp", true).unwrap();
+
+        // Assert
+        assert!(aggregator.merge_books().is_none());
+        assert!(aggregator.set_excluded("Kraken", true).is_err());
+    }
+
+    fn without_asks(exchange: &str) -> OrderbookSnapshot {
+        OrderbookSnapshot {
+            bids: Some(levels(exchange, 10.75, -1.)),
+            asks: None,
+            exchange_timestamp_us: None,
+        }
+    }
+
+    #[test]
+    fn should_skip_venue_with_empty_side() {
+        // Arrange
+        let mut aggregator = aggregator();
+
+        // Act
+        aggregator.store(1, Instant::now(), without_asks("Bitstamp"));
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(summary.spread == Some(1.));
+        assert!(summary.bids.iter().all(|level| level.exchange == "Binance"));
+        assert!(!aggregator
+            .snapshot_ages(Instant::now())
+            .contains_key("Bitstamp"));
+    }
+
+    #[test]
+    fn should_publish_decimal_exact_spreads() {
+        // Arrange
+        let mut aggregator = aggregator();
+        aggregator.venues[0].best_asks = Some(levels("Binance", 0.0745, 0.0001));
+        aggregator.venues[1].best_bids = Some(levels("Bitstamp", 0.0745, -0.0001));
+        aggregator.venues[0].best_bids = Some(levels("Binance", 0.074488, -0.0001));
+
+        // Act
+        let locked = aggregator.merge_books().unwrap();
+        aggregator.venues[1].best_bids = Some(levels("Bitstamp", 0.07448, -0.0001));
+        let spread = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(locked.spread == Some(0.) && locked.spread.unwrap().is_sign_positive());
+        assert!(locked.bids[0].exchange == "Bitstamp" && locked.asks[0].exchange == "Binance");
+        assert!(spread.spread == Some(0.000012));
+    }
+
+    #[test]
+    fn should_derive_prices_from_merged_book() {
+        // Arrange
+        let mut aggregator = aggregator();
+        aggregator.venues[1].best_bids.as_mut().unwrap()[0].amount = decimal(3.);
+
+        // Act
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(summary.mid == Some(10.75));
+        assert!(summary.microprice == Some(10.875));
+        assert!(summary.vwap.is_some_and(|vwap| vwap > 9. && vwap < 11.));
+    }
+
+    #[test]
+    fn should_publish_one_sided_summary_without_spread() {
+        // Arrange
+        let mut aggregator = aggregator();
+        aggregator.set_empty_book_policy(EmptyBookPolicy::OneSided);
+        aggregator.set_excluded("Binance", true).unwrap();
+
+        // Act
+        aggregator.store(1, Instant::now(), without_asks("Bitstamp"));
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(summary.spread.is_none() && summary.mid.is_none());
+        assert!(summary.bids[0].price == 10.75 && summary.asks.is_empty());
+    }
+
+    #[test]
+    fn should_hold_last_complete_book_for_configured_time() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        aggregator.set_empty_book_policy(EmptyBookPolicy::Hold(Duration::from_secs(5)));
+        aggregator.set_excluded("Binance", true).unwrap();
+
+        // Act
+        aggregator.store(1, clock.now(), without_asks("Bitstamp"));
+        let held = aggregator.merge_books().unwrap();
+        clock.advance(Duration::from_secs(6));
+        let expired = aggregator.merge_books();
+
+        // Assert
+        assert!(held.spread == Some(1.5) && held.bids[0].price == 10.5);
+        assert!(expired.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_publish_normalized_snapshot_before_merging() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let source = aggregator.register_source(1, SourceKind::PartialBook);
+        let snapshot_spmc = Arc::new(Mutex::new(Spmc::new()));
+        let mut rx = snapshot_spmc.lock().await.create_receiver(1);
+        aggregator.set_snapshot_spmc(snapshot_spmc);
+        aggregator.set_excluded("Bitstamp", true).unwrap();
+
+        // Act
+        aggregator
+            .process(source, without_asks("Bitstamp"), TickTimings::default())
+            .await;
+
+        // Assert
+        let snapshot = rx.try_recv().unwrap();
+        assert!(snapshot.exchange == "Bitstamp" && snapshot.symbol == "ethbtc");
+        assert!(snapshot.bids.len() == DEFAULT_DEPTH && snapshot.asks.is_empty());
+    }
+
+    #[test]
+    fn should_only_publish_provenance_if_exposed() {
+        // Arrange
+        let mut aggregator = aggregator();
+        for level in aggregator.venues[0].best_bids.iter_mut().flatten() {
+            level.provenance = 42;
+        }
+
+        // Act
+        let hidden = aggregator.merge_books().unwrap();
+        aggregator.set_expose_provenance(true);
+        let exposed = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(hidden.bids.iter().all(|level| level.provenance == 0));
+        assert!(exposed.bids.iter().any(|level| level.provenance == 42));
+    }
+
+    #[tokio::test]
+    async fn should_leave_a_disconnected_venue_out_until_it_delivers_again() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let source = aggregator.register_source(1, SourceKind::PartialBook);
+        let snapshot = || OrderbookSnapshot {
+            bids: Some(levels("Bitstamp", 10.5, -1.)),
+            asks: Some(levels("Bitstamp", 12., 1.)),
+            exchange_timestamp_us: None,
+        };
+        aggregator
+            .process(source, snapshot(), TickTimings::default())
+            .await;
+
+        // Act
+        aggregator.invalidate_source(source).await;
+        let during_outage = aggregator.merge_books();
+        let health = aggregator.health();
+        aggregator
+            .process(source, snapshot(), TickTimings::default())
+            .await;
+
+        // Assert
+        let during_outage = during_outage.unwrap();
+        assert!(during_outage
+            .bids
+            .iter()
+            .all(|level| level.exchange == "Binance"));
+        assert!(health.venues[1].disconnected && !health.venues[1].live);
+        assert!(aggregator.merge_books().unwrap().bids[0].exchange == "Bitstamp");
+        assert!(!aggregator.health().venues[1].disconnected);
+    }
+
+    #[tokio::test]
+    async fn should_leave_a_resyncing_venue_out_until_its_fresh_snapshot() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let source = aggregator.register_source(1, SourceKind::PartialBook);
+        let snapshot = || OrderbookSnapshot {
+            bids: Some(levels("Bitstamp", 10.5, -1.)),
+            asks: Some(levels("Bitstamp", 12., 1.)),
+            exchange_timestamp_us: None,
+        };
+        aggregator
+            .process(source, snapshot(), TickTimings::default())
+            .await;
+
+        // Act
+        aggregator
+            .inbound_queue(source)
+            .push(snapshot(), TickTimings::default());
+        aggregator.resync_source(source).await;
+        let resyncing = aggregator.merge_books().unwrap();
+        let health = aggregator.health();
+        aggregator
+            .process(source, snapshot(), TickTimings::default())
+            .await;
+
+        // Assert
+        assert!(resyncing
+            .bids
+            .iter()
+            .all(|level| level.exchange == "Binance"));
+        assert!(aggregator.inbound_queue(source).depth() == 0);
+        assert!(health.venues[1].resyncing && !health.venues[1].disconnected);
+        assert!(aggregator.merge_books().unwrap().bids[0].exchange == "Bitstamp");
+        assert!(!aggregator.health().venues[1].resyncing);
+    }
+
+    #[tokio::test]
+    async fn should_only_publish_top_of_book_changes() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let capture = Capture::new(Arc::new(ManualClock::new()));
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        aggregator.set_publish_trigger(PublishTrigger::TopOfBookChange);
+        let source = aggregator.register_source(1, SourceKind::PartialBook);
+        let snapshot = |best_bid: f64, second_amount: f64| {
+            let mut bids = levels("Bitstamp", best_bid, -1.);
+            bids[1].amount = decimal(second_amount);
+            OrderbookSnapshot {
+                bids: Some(bids),
+                asks: Some(levels("Bitstamp", 12., 1.)),
+                exchange_timestamp_us: None,
             }
-            1 => {
-                self.best_bids_02 = Some(snapshot.bids);
-                self.best_asks_02 = Some(snapshot.asks);
-                self.lead_01 = 0;
-                self.lead_02 = 1;
-
-                if Aggregator::stream_exceeded_lead_tolerance(self.lead_01) {
-                    // In a production scenario, we might not even want to publish the aggregation here since it may not
-                    // reflecting the actual spread anymore
-                    Aggregator::log_lead_warning(&self.exchange_02_name, self.lead_02);
-                } 
+        };
+
+        // Act
+        for (best_bid, second_amount) in [(10.5, 1.), (10.5, 2.), (10.6, 2.)] {
+            aggregator
+                .process(
+                    source,
+                    snapshot(best_bid, second_amount),
+                    TickTimings::default(),
+                )
+                .await;
+        }
+
+        // Assert
+        let published = capture.captured();
+        assert!(published.len() == 2);
+        assert!(published[1].item.bids[0].price == 10.6);
+        assert!(published[1].item.sequence == published[0].item.sequence + 1);
+    }
+
+    #[tokio::test]
+    async fn should_apply_lead_policy_once_a_stream_exceeds_lead_tolerance() {
+        for policy in [
+            LeadPolicy::PublishAnyway,
+            LeadPolicy::SuppressPublish,
+            LeadPolicy::PublishSingleExchange,
+            LeadPolicy::MarkDegraded,
+        ] {
+            // Arrange
+            let mut aggregator = aggregator();
+            let capture = Capture::new(Arc::new(ManualClock::new()));
+            aggregator.spmc.lock().await.set_capture(capture.clone());
+            aggregator.set_lead_policy(policy);
+            let source = aggregator.register_source(1, SourceKind::PartialBook);
+
+            // Act
+            // the third update in a row of Bitstamp exceeds the tolerance
+            for _ in 0..3 {
+                let snapshot = OrderbookSnapshot {
+                    bids: Some(levels("Bitstamp", 10.5, -1.)),
+                    asks: Some(levels("Bitstamp", 12., 1.)),
+                    exchange_timestamp_us: None,
+                };
+                aggregator
+                    .process(source, snapshot, TickTimings::default())
+                    .await;
             }
-            _ => panic!("The aggregator currently only supports two market streams"),
-        }
-
-        if self.best_bids_01.is_some() && self.best_bids_02.is_some() {
-            let mut merged_best_bids = Vec::<Level>::with_capacity(DEPTH);
-            let mut merged_best_asks = Vec::<Level>::with_capacity(DEPTH);
-            Aggregator::merge(
-                &mut merged_best_bids,
-                self.best_bids_01.as_ref().unwrap(),
-                self.best_bids_02.as_ref().unwrap(),
-                0,
-                0,
-                false,
-            );
-            Aggregator::merge(
-                &mut merged_best_asks,
-                self.best_asks_01.as_ref().unwrap(),
-                self.best_asks_02.as_ref().unwrap(),
-                0,
-                0,
-                true,
-            );
 
-            let mut smpc = self.spmc.lock().await;
-            smpc.broadcast(Summary {
-                spread: merged_best_asks.first().unwrap().price
-                    - merged_best_bids.first().unwrap().price,
-                bids: merged_best_bids,
-                asks: merged_best_asks,
-            })
+            // Assert
+            let published = capture.captured();
+            let from_binance = |index: usize| {
+                published[index]
+                    .item
+                    .asks
+                    .iter()
+                    .any(|level| level.exchange == "Binance")
+            };
+            match policy {
+                LeadPolicy::SuppressPublish => assert!(published.len() == 2),
+                LeadPolicy::PublishSingleExchange => {
+                    assert!(published.len() == 3 && from_binance(1) && !from_binance(2))
+                }
+                _ => assert!(published.len() == 3 && from_binance(2)),
+            }
+            let degraded: Vec<bool> = published
+                .iter()
+                .map(|captured| captured.item.lead_degraded)
+                .collect();
+            assert!(degraded.last() == Some(&(policy == LeadPolicy::MarkDegraded)));
+            assert!(!degraded[0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_let_a_stream_lead_up_to_the_lead_tolerance() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let capture = Capture::new(Arc::new(ManualClock::new()));
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        aggregator.set_lead_policy(LeadPolicy::SuppressPublish);
+        assert!(aggregator.set_lead_tolerance(0).is_err());
+        aggregator.set_lead_tolerance(5).unwrap();
+        let source = aggregator.register_source(1, SourceKind::PartialBook);
+
+        // Act
+        for _ in 0..5 {
+            let snapshot = OrderbookSnapshot {
+                bids: Some(levels("Bitstamp", 10.5, -1.)),
+                asks: Some(levels("Bitstamp", 12., 1.)),
+                exchange_timestamp_us: None,
+            };
+            aggregator
+                .process(source, snapshot, TickTimings::default())
+                .await;
+        }
+
+        // Assert
+        // only the fifth update in a row exceeds the tolerance
+        assert!(capture.captured().len() == 4);
+    }
+
+    #[tokio::test]
+    async fn should_release_held_back_snapshots_without_a_later_snapshot() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let capture = Capture::new(clock.clone());
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        aggregator.set_lead_compensation(Duration::from_millis(100));
+        let binance = aggregator.register_source(0, SourceKind::PartialBook);
+        let bitstamp = aggregator.register_source(1, SourceKind::PartialBook);
+        let unix_now_us = clock
+            .system_now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        let snapshot = |exchange: &str, best_bid: f64, latency_us: u64| OrderbookSnapshot {
+            bids: Some(levels(exchange, best_bid, -1.)),
+            asks: Some(levels(exchange, 12., 1.)),
+            exchange_timestamp_us: Some(unix_now_us - latency_us),
+        };
+        aggregator
+            .process(
+                bitstamp,
+                snapshot("Bitstamp", 10.5, 50_000),
+                TickTimings::default(),
+            )
             .await;
-            return;
+        // Binance is 30ms faster, so its snapshot is held back that long
+        aggregator
+            .process(
+                binance,
+                snapshot("Binance", 11.5, 20_000),
+                TickTimings::default(),
+            )
+            .await;
+        assert!(capture.captured().len() == 1);
+        assert!(aggregator.release_held_back().await == Some(Duration::from_millis(30)));
+
+        // Act
+        clock.advance(Duration::from_millis(30));
+        let next_release = aggregator.release_held_back().await;
+
+        // Assert
+        let published = capture.captured();
+        assert!(next_release.is_none());
+        assert!(published.len() == 2);
+        assert!(published[1].item.bids[0].exchange == "Binance");
+        assert!(published[1].item.bids[0].price == 11.5);
+    }
+
+    #[tokio::test]
+    async fn should_conflate_updates_within_interval() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let capture = Capture::new(clock.clone());
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        let interval = Duration::from_millis(100);
+        aggregator.set_publish_trigger(PublishTrigger::Conflated(interval));
+        let source = aggregator.register_source(1, SourceKind::PartialBook);
+        let snapshot = |best_bid: f64| OrderbookSnapshot {
+            bids: Some(levels("Bitstamp", best_bid, -1.)),
+            asks: Some(levels("Bitstamp", 12., 1.)),
+            exchange_timestamp_us: None,
+        };
+
+        // Act
+        for best_bid in [10.5, 10.6, 10.7] {
+            aggregator
+                .process(source, snapshot(best_bid), TickTimings::default())
+                .await;
+            clock.advance(Duration::from_millis(10));
         }
+        let early = aggregator.publish_conflated().await;
+        clock.advance(Duration::from_millis(70));
+        let due = aggregator.publish_conflated().await;
+        let idle = aggregator.publish_conflated().await;
 
-        if self.best_bids_01.is_some() {
-            let mut smpc = self.spmc.lock().await;
-            smpc.broadcast(Summary {
-                spread: self.best_asks_01.as_ref().unwrap().first().unwrap().price
-                    - self.best_bids_01.as_ref().unwrap().first().unwrap().price,
-                bids: self.best_bids_01.as_ref().unwrap().to_vec(),
-                asks: self.best_asks_01.as_ref().unwrap().to_vec(),
-            })
-            .await
-        } else {
-            let mut smpc = self.spmc.lock().await;
-            smpc.broadcast(Summary {
-                spread: self.best_asks_02.as_ref().unwrap().first().unwrap().price
-                    - self.best_bids_02.as_ref().unwrap().first().unwrap().price,
-                bids: self.best_bids_02.as_ref().unwrap().to_vec(),
-                asks: self.best_asks_02.as_ref().unwrap().to_vec(),
-            })
-            .await
+        // Assert
+        let published = capture.captured();
+        assert!(early == Some(Duration::from_millis(70)));
+        assert!(due == Some(interval) && idle == Some(interval));
+        assert!(published.len() == 2);
+        assert!(published[0].item.bids[0].price == 10.5);
+        // the freshest books, the update in between is skipped
+        assert!(published[1].item.bids[0].price == 10.7);
+    }
+
+    #[tokio::test]
+    async fn should_conflate_to_a_shorter_interval_while_the_top_of_book_moves() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let (min, max) = (Duration::from_millis(10), Duration::from_millis(1000));
+        aggregator.set_publish_trigger(PublishTrigger::Adaptive { min, max });
+        let source = aggregator.register_source(1, SourceKind::PartialBook);
+        let quiet = aggregator.health().conflation_interval_ms;
+
+        // Act
+        for tick in 0..200 {
+            let snapshot = OrderbookSnapshot {
+                bids: Some(levels("Bitstamp", 10.5 + (tick % 2) as f64 * 0.1, -1.)),
+                asks: Some(levels("Bitstamp", 12., 1.)),
+                exchange_timestamp_us: None,
+            };
+            aggregator
+                .process(source, snapshot, TickTimings::default())
+                .await;
+            clock.advance(Duration::from_millis(20));
         }
+        let busy = aggregator.conflation_interval().unwrap();
+        clock.advance(Duration::from_secs(120));
+
+        // Assert
+        assert!(quiet == 1000);
+        assert!(busy < Duration::from_millis(100));
+        assert!(aggregator.conflation_interval().unwrap() > Duration::from_millis(990));
+    }
+
+    #[tokio::test]
+    async fn should_audit_published_summary_against_held_books() {
+        // Arrange
+        let mut aggregator = aggregator();
+        aggregator.publish(None).await;
+
+        // Act
+        let consistent = aggregator.audit();
+        // a published level that does not follow from the held books
+        aggregator.latest_summary.send_modify(|summary| {
+            summary.as_mut().unwrap().bids[3].amount += 1.;
+        });
+        let mismatch = aggregator.audit();
+        aggregator.venues[1].best_asks.as_mut().unwrap().swap(0, 1);
+        aggregator.venues[0].received_at = Some(aggregator.clock.now());
+        let changed = aggregator.audit();
+
+        // Assert
+        assert!(consistent.findings.is_empty() && consistent.compared_sequence.is_some());
+        assert!(mismatch.findings.len() == 1);
+        assert!(mismatch.findings[0].kind() == AuditFindingKind::MergeMismatch);
+        // the books changed since, so only the ladders are checked
+        assert!(changed.compared_sequence.is_none());
+        assert!(changed.findings.len() == 1 && changed.findings[0].exchange == "Bitstamp");
+        assert!(changed.audits == 3 && changed.failed_audits == 2);
     }
 
-    fn stream_exceeded_lead_tolerance(lead: usize) -> bool {
-        lead >= LEAD_TOLERANCE
+    #[tokio::test]
+    async fn should_publish_heartbeats_below_quorum() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let capture = Capture::new(clock.clone());
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        aggregator
+            .set_quorum(Quorum {
+                min_venues: 2,
+                max_age: Duration::from_millis(500),
+            })
+            .unwrap();
+        aggregator.venues[0].received_at = Some(clock.now());
+        aggregator.venues[1].received_at = Some(clock.now());
+
+        // Act
+        aggregator.publish(None).await;
+        aggregator.publish_heartbeat_if_blind().await;
+        clock.advance(Duration::from_millis(400));
+        aggregator.venues[0].received_at = Some(clock.now());
+        clock.advance(Duration::from_millis(200));
+        aggregator.publish(None).await;
+        aggregator.publish_heartbeat_if_blind().await;
+
+        // Assert
+        let published = capture.captured();
+        assert!(published.len() == 3);
+        assert!(!published[0].item.quorum_lost && !published[0].item.bids.is_empty());
+        assert!(published[1].item.quorum_lost && published[1].item.bids.is_empty());
+        assert!(published[2].item.sequence == published[1].item.sequence + 1);
+        assert!(aggregator.set_quorum("3:500".parse().unwrap()).is_err());
     }
 
-    fn log_lead_warning(exchange_name: &str, lead: usize) {
-        println!(
-            "[WARNING]: {} stream is {} ticks ahead",
-            exchange_name, lead
+    #[tokio::test]
+    async fn should_only_publish_heartbeats_during_quiet_period() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let capture = Capture::new(clock.clone());
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        let time_of_day = clock
+            .system_now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            % (24 * 60 * 60);
+        let minute = Duration::from_secs(60);
+        // a period from a minute ago until in a minute, possibly over midnight
+        let at = |secs: u64| Duration::from_secs(secs % (24 * 60 * 60));
+        aggregator.set_quiet_periods(vec![QuietPeriod {
+            start: at(time_of_day + 24 * 60 * 60 - 60),
+            end: at(time_of_day + 60),
+            mode: QuietMode::Heartbeat,
+        }]);
+
+        // Act
+        aggregator.publish(None).await;
+        aggregator.publish_heartbeat_if_quiet().await;
+        let health = aggregator.health();
+        clock.advance(2 * minute);
+        aggregator.publish_heartbeat_if_quiet().await;
+        aggregator.publish(None).await;
+
+        // Assert
+        let published = capture.captured();
+        assert!(published.len() == 2);
+        assert!(published[0].item.quiet_period && published[0].item.bids.is_empty());
+        assert!(!published[1].item.quiet_period && !published[1].item.bids.is_empty());
+        assert!(health.quiet_mode == QuietMode::Heartbeat as i32);
+        assert!(aggregator.health().quiet_mode == QuietMode::None as i32);
+    }
+
+    #[tokio::test]
+    async fn should_only_publish_heartbeats_while_paused() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let capture = Capture::new(clock.clone());
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+
+        // Act
+        aggregator.set_paused(true).await;
+        aggregator.set_paused(true).await;
+        aggregator.publish(None).await;
+        clock.advance(Duration::from_secs(2));
+        aggregator.publish_heartbeat_if_quiet().await;
+        let paused = aggregator.health().pause.unwrap();
+        aggregator.set_paused(false).await;
+        aggregator.publish_heartbeat_if_quiet().await;
+        clock.advance(Duration::from_secs(5));
+
+        // Assert
+        let published = capture.captured();
+        assert!(published.len() == 3);
+        assert!(published[..2]
+            .iter()
+            .all(|summary| summary.item.paused && summary.item.bids.is_empty()));
+        assert!(!published[2].item.paused && !published[2].item.bids.is_empty());
+        assert!(paused.paused && paused.paused_for_ms == 2_000 && paused.pauses == 1);
+        let resumed = aggregator.pause_state();
+        assert!(!resumed.paused && resumed.paused_for_ms == 0 && resumed.total_paused_ms == 2_000);
+    }
+
+    #[tokio::test]
+    async fn should_publish_exchange_and_aggregation_timestamps() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let capture = Capture::new(clock.clone());
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        let unix_now_us = |clock: &ManualClock| {
+            clock
+                .system_now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64
+        };
+        let exchange_timestamp_us = unix_now_us(&clock) - 3_000;
+
+        // Act
+        aggregator.store(
+            1,
+            clock.now(),
+            OrderbookSnapshot {
+                bids: Some(levels("Bitstamp", 10.5, -1.)),
+                asks: Some(levels("Bitstamp", 12., 1.)),
+                exchange_timestamp_us: Some(exchange_timestamp_us),
+            },
         );
+        clock.advance(Duration::from_millis(2));
+        aggregator.publish(None).await;
+
+        // Assert
+        let summary = &capture.captured()[0].item;
+        assert!(summary.exchange_timestamp_us.len() == 1);
+        assert!(summary.exchange_timestamp_us["Bitstamp"] == exchange_timestamp_us);
+        assert!(summary.aggregated_at_us == unix_now_us(&clock));
+        assert!(summary.aggregated_at_us - summary.exchange_timestamp_us["Bitstamp"] == 5_000);
     }
 
-    /**
-     * Merges two arrays of orderbook levels. 
-     * Expects both arrays to be sorted with the best offer being at position 0.
-     * The side states if the arrays contain bids (false) or asks (true)
-     */
-    fn merge(
-        merged: &mut Vec<Level>,
-        levels_01: &[Level; DEPTH],
-        levels_02: &[Level; DEPTH],
-        index_01: usize,
-        index_02: usize,
-        side: bool,
-    ) {
-        if merged.len() == merged.capacity() {
-            return;
-        }
+    #[tokio::test]
+    async fn should_evict_books_of_stale_exchange() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let clock = Arc::new(ManualClock::new());
+        aggregator.set_clock(clock.clone());
+        let capture = Capture::new(clock.clone());
+        aggregator.spmc.lock().await.set_capture(capture.clone());
+        aggregator.set_staleness(Staleness {
+            default_timeout: Some(Duration::from_millis(500)),
+            timeouts: Vec::new(),
+        });
+        aggregator.venues[0].received_at = Some(clock.now());
+        aggregator.venues[1].received_at = Some(clock.now());
 
-        let mut new_index_01 = index_01;
-        let mut new_index_02 = index_02;
-
-        if side {
-            // asks
-            if new_index_01 >= DEPTH {
-                merged.push(copy_level(&levels_02[index_02]));
-                new_index_02 += 1;
-            } else if new_index_02 >= DEPTH {
-                merged.push(copy_level(&levels_01[index_01]));
-                new_index_01 += 1;
-            } else {
-                let level_01 = &levels_01[index_01];
-                let level_02 = &levels_02[index_02];
-
-                if level_01.price > level_02.price {
-                    merged.push(copy_level(level_02));
-                    new_index_02 += 1;
-                } else {
-                    merged.push(copy_level(level_01));
-                    new_index_01 += 1;
-                }
+        // Act
+        clock.advance(Duration::from_millis(400));
+        aggregator.venues[0].received_at = Some(clock.now());
+        aggregator.evict_stale().await;
+        clock.advance(Duration::from_millis(200));
+        aggregator.evict_stale().await;
+        aggregator.evict_stale().await;
+        let health = aggregator.health();
+        aggregator.venues[1].received_at = Some(clock.now());
+        let recovered = aggregator.merge_books().unwrap();
+
+        // Assert
+        let published = capture.captured();
+        assert!(published.len() == 1);
+        let summary = &published[0].item;
+        assert!(summary.bids.iter().all(|level| level.exchange == "Binance"));
+        assert!(summary.snapshot_age_ms.keys().eq(["Binance"]));
+        assert!(health.venues[1].stale && !health.venues[1].live && !health.venues[0].stale);
+        assert!(recovered.bids[0].exchange == "Bitstamp");
+    }
+
+    #[test]
+    fn should_exclude_exchange_during_maintenance() {
+        // Arrange
+        let mut aggregator = aggregator();
+        let now = SystemTime::now();
+
+        // Act
+        aggregator.set_maintenance(vec![MaintenanceWindow {
+            exchange: "Binance".to_string(),
+            start: now - Duration::from_secs(60),
+            end: now + Duration::from_secs(60),
+        }]);
+        let summary = aggregator.merge_books().unwrap();
+
+        // Assert
+        assert!(summary
+            .bids
+            .iter()
+            .all(|level| level.exchange == "Bitstamp"));
+        assert!(aggregator.excluded_exchanges() == vec!["Binance".to_string()]);
+        aggregator.set_maintenance(Vec::new());
+        assert!(aggregator.excluded_exchanges().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_publish_expected_summary_for_recorded_fixtures() {
+        for fixture in test_fixtures::load_all(DEFAULT_DEPTH) {
+            // Arrange
+            let spmc = Arc::new(Mutex::new(Spmc::new()));
+            let mut rx = spmc.lock().await.create_receiver(fixture.ticks.len());
+            let mut aggregator = Aggregator::new(
+                spmc,
+                None,
+                SequenceStore::open(None).unwrap(),
+                "ethbtc".to_string(),
+                vec!["Binance".to_string(), "Bitstamp".to_string()],
+            );
+            let sources = [
+                aggregator.register_source(0, SourceKind::PartialBook),
+                aggregator.register_source(1, SourceKind::PartialBook),
+            ];
+
+            // Act
+            for (exchange, snapshot) in fixture.ticks {
+                let venue_id = match exchange.as_str() {
+                    "Binance" => 0,
+                    _ => 1,
+                };
+                aggregator
+                    .process(sources[venue_id], snapshot, TickTimings::default())
+                    .await;
             }
-        } else {
-            // bids
-            if new_index_01 >= DEPTH {
-                merged.push(copy_level(&levels_02[index_02]));
-                new_index_02 += 1;
-            } else if new_index_02 >= DEPTH {
-                merged.push(copy_level(&levels_01[index_01]));
-                new_index_01 += 1;
-            } else {
-                let level_01 = &levels_01[index_01];
-                let level_02 = &levels_02[index_02];
-
-                if level_01.price > level_02.price {
-                    merged.push(copy_level(level_01));
-                    new_index_01 += 1;
-                } else {
-                    merged.push(copy_level(level_02));
-                    new_index_02 += 1;
-                }
+            let mut published = None;
+            while let Ok(summary) = rx.try_recv() {
+                published = Some(summary);
             }
-        }
 
-        Aggregator::merge(
-            merged,
-            levels_01,
-            levels_02,
-            new_index_01,
-            new_index_02,
-            side,
-        )
+            // Assert
+            let summary =
+                published.unwrap_or_else(|| panic!("{}: nothing published", fixture.name));
+            assert!(
+                summary.spread == Some(fixture.spread),
+                "{}: spread",
+                fixture.name
+            );
+            assert!(
+                summary.raw_spread == Some(fixture.spread),
+                "{}: raw spread",
+                fixture.name
+            );
+            assert!(summary.bids == fixture.bids, "{}: bids", fixture.name);
+            assert!(summary.asks == fixture.asks, "{}: asks", fixture.name);
+            assert!(summary.symbol == "ethbtc", "{}: symbol", fixture.name);
+            assert!(
+                aggregator.latest_summary().borrow().as_ref() == Some(&summary),
+                "{}: latest summary",
+                fixture.name
+            );
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::Aggregator;
-    use crate::aggregator::DEPTH;
-    use init_with::InitWith;
-    use keyrock_challenge_proto::orderbook::Level;
+    #[tokio::test]
+    async fn should_capture_fixture_summaries_at_their_virtual_publish_time() {
+        for fixture in test_fixtures::load_all(DEFAULT_DEPTH) {
+            // Arrange
+            let clock = Arc::new(ManualClock::new());
+            let capture = Capture::new(clock.clone());
+            let spmc = Arc::new(Mutex::new(Spmc::new()));
+            spmc.lock().await.set_capture(capture.clone());
+            let mut aggregator = Aggregator::new(
+                spmc,
+                None,
+                SequenceStore::open(None).unwrap(),
+                "ethbtc".to_string(),
+                vec!["Binance".to_string(), "Bitstamp".to_string()],
+            );
+            aggregator.set_clock(clock.clone());
+            let sources = [
+                aggregator.register_source(0, SourceKind::PartialBook),
+                aggregator.register_source(1, SourceKind::PartialBook),
+            ];
+            let ticks = fixture.ticks.len();
+
+            // Act
+            for (exchange, snapshot) in fixture.ticks {
+                clock.advance(Duration::from_millis(100));
+                let venue_id = match exchange.as_str() {
+                    "Binance" => 0,
+                    _ => 1,
+                };
+                aggregator
+                    .process(sources[venue_id], snapshot, TickTimings::default())
+                    .await;
+            }
+            let captured = capture.captured();
+
+            // Assert
+            let last = captured
+                .last()
+                .unwrap_or_else(|| panic!("{}: nothing published", fixture.name));
+            assert!(
+                last.at == Duration::from_millis(100) * ticks as u32,
+                "{}: published after the last tick",
+                fixture.name
+            );
+            assert!(
+                captured.windows(2).all(|pair| pair[0].at <= pair[1].at),
+                "{}: captured in order",
+                fixture.name
+            );
+            assert!(
+                last.item.bids == fixture.bids && last.item.asks == fixture.asks,
+                "{}: levels",
+                fixture.name
+            );
+        }
+    }
 
     #[test]
     fn should_merge_bids() {
         // Arrange
-        let mut merged = Vec::<Level>::with_capacity(DEPTH);
-        let levels_01 = <[Level; DEPTH]>::init_with_indices(|i| Level {
-            price: 20. - i as f64,
-            amount: 13.,
+        let levels_01 = <[BookLevel; DEFAULT_DEPTH]>::init_with_indices(|i| BookLevel {
+            price: decimal(20. - i as f64),
+            amount: decimal(13.),
             exchange: String::new(),
+            ..Default::default()
         });
-        let levels_02 = <[Level; DEPTH]>::init_with_indices(|i| Level {
-            price: 26. - 2. * i as f64,
-            amount: 37.,
+        let levels_02 = <[BookLevel; DEFAULT_DEPTH]>::init_with_indices(|i| BookLevel {
+            price: decimal(26. - 2. * i as f64),
+            amount: decimal(37.),
             exchange: String::new(),
+            ..Default::default()
         });
 
         // Act
-        Aggregator::merge(&mut merged, &levels_01, &levels_02, 0, 0, false);
+        let merged = merge(&levels_01, &levels_02, DEFAULT_DEPTH, false);
 
         // Assert
-        assert!(merged[0].amount == 37. && merged[0].price == 26.);
-        assert!(merged[1].amount == 37. && merged[1].price == 24.);
-        assert!(merged[2].amount == 37. && merged[2].price == 22.);
-        assert!(merged[3].amount == 37. && merged[3].price == 20.);
-        assert!(merged[4].amount == 13. && merged[4].price == 20.);
-        assert!(merged[5].amount == 13. && merged[5].price == 19.);
-        assert!(merged[6].amount == 37. && merged[6].price == 18.);
-        assert!(merged[7].amount == 13. && merged[7].price == 18.);
-        assert!(merged[8].amount == 13. && merged[8].price == 17.);
-        assert!(merged[9].amount == 37. && merged[9].price == 16.);
+        assert!(merged[0].amount == decimal(37.) && merged[0].price == decimal(26.));
+        assert!(merged[1].amount == decimal(37.) && merged[1].price == decimal(24.));
+        assert!(merged[2].amount == decimal(37.) && merged[2].price == decimal(22.));
+        assert!(merged[3].amount == decimal(37.) && merged[3].price == decimal(20.));
+        assert!(merged[4].amount == decimal(13.) && merged[4].price == decimal(20.));
+        assert!(merged[5].amount == decimal(13.) && merged[5].price == decimal(19.));
+        assert!(merged[6].amount == decimal(37.) && merged[6].price == decimal(18.));
+        assert!(merged[7].amount == decimal(13.) && merged[7].price == decimal(18.));
+        assert!(merged[8].amount == decimal(13.) && merged[8].price == decimal(17.));
+        assert!(merged[9].amount == decimal(37.) && merged[9].price == decimal(16.));
     }
 
     #[test]
     fn should_merge_asks() {
         // Arrange
-        let mut merged = Vec::<Level>::with_capacity(DEPTH);
-        let levels_01 = <[Level; DEPTH]>::init_with_indices(|i| Level {
-            price: 10. + i as f64,
-            amount: 13.,
+        let levels_01 = <[BookLevel; DEFAULT_DEPTH]>::init_with_indices(|i| BookLevel {
+            price: decimal(10. + i as f64),
+            amount: decimal(13.),
             exchange: String::new(),
+            ..Default::default()
         });
-        let levels_02 = <[Level; DEPTH]>::init_with_indices(|i| Level {
-            price: 6. + 2. * i as f64,
-            amount: 37.,
+        let levels_02 = <[BookLevel; DEFAULT_DEPTH]>::init_with_indices(|i| BookLevel {
+            price: decimal(6. + 2. * i as f64),
+            amount: decimal(37.),
             exchange: String::new(),
+            ..Default::default()
         });
 
         // Act
-        Aggregator::merge(&mut merged, &levels_01, &levels_02, 0, 0, true);
+        let merged = merge(&levels_01, &levels_02, DEFAULT_DEPTH, true);
 
         // Assert
-        assert!(merged[0].amount == 37. && merged[0].price == 6.);
-        assert!(merged[1].amount == 37. && merged[1].price == 8.);
-        assert!(merged[2].amount == 13. && merged[2].price == 10.);
-        assert!(merged[3].amount == 37. && merged[3].price == 10.);
-        assert!(merged[4].amount == 13. && merged[4].price == 11.);
-        assert!(merged[5].amount == 13. && merged[5].price == 12.);
-        assert!(merged[6].amount == 37. && merged[6].price == 12.);
-        assert!(merged[7].amount == 13. && merged[7].price == 13.);
-        assert!(merged[8].amount == 13. && merged[8].price == 14.);
-        assert!(merged[9].amount == 37. && merged[9].price == 14.);
+        assert!(merged[0].amount == decimal(37.) && merged[0].price == decimal(6.));
+        assert!(merged[1].amount == decimal(37.) && merged[1].price == decimal(8.));
+        assert!(merged[2].amount == decimal(13.) && merged[2].price == decimal(10.));
+        assert!(merged[3].amount == decimal(37.) && merged[3].price == decimal(10.));
+        assert!(merged[4].amount == decimal(13.) && merged[4].price == decimal(11.));
+        assert!(merged[5].amount == decimal(13.) && merged[5].price == decimal(12.));
+        assert!(merged[6].amount == decimal(37.) && merged[6].price == decimal(12.));
+        assert!(merged[7].amount == decimal(13.) && merged[7].price == decimal(13.));
+        assert!(merged[8].amount == decimal(13.) && merged[8].price == decimal(14.));
+        assert!(merged[9].amount == decimal(37.) && merged[9].price == decimal(14.));
     }
 
     #[test]
     fn should_merge_real_data_bids() {
         // Arrange
-        let mut merged = Vec::<Level>::with_capacity(DEPTH * 2);
         let levels_01 = [
-            Level {
-                price: 0.074505000000000002,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074505),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074501999999999999,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074502),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074500999999999998,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074501),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074496000000000007,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074496),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074492000000000003,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074492),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074490000000000001,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07449),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074489,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074489),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074487999999999999,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074488),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074485999999999997,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074486),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074484999999999996,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.074485),
+                amount: decimal(1.),
                 exchange: "Binance".to_string(),
+                ..Default::default()
             },
         ];
         let levels_02 = [
-            Level {
-                price: 0.074488570000000004,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07448857),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074467909999999998,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07446791),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074462249999999994,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07446225),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074442809999999998,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07444281),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074435570000000006,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07443557),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074430650000000001,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07443065),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074423119999999995,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07442312),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074420920000000002,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07442092),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074418860000000003,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07441886),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
-            Level {
-                price: 0.074410000000000004,
-                amount: 1.,
+            BookLevel {
+                price: decimal(0.07441),
+                amount: decimal(1.),
                 exchange: "Bitstamp".to_string(),
+                ..Default::default()
             },
         ];
 
         // Act
-        Aggregator::merge(&mut merged, &levels_01, &levels_02, 0, 0, false);
+        let merged = merge(&levels_01, &levels_02, DEFAULT_DEPTH * 2, false);
 
         // Assert
-        assert!(
-            merged[0].price == 0.074505000000000002 && merged[0].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[1].price == 0.074501999999999999 && merged[1].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[2].price == 0.074500999999999998 && merged[2].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[3].price == 0.074496000000000007 && merged[3].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[4].price == 0.074492000000000003 && merged[4].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[5].price == 0.074490000000000001 && merged[5].exchange == "Binance".to_string()
-        );
-        assert!(merged[6].price == 0.074489 && merged[6].exchange == "Binance".to_string());
-        assert!(
-            merged[7].price == 0.074488570000000004 && merged[7].exchange == "Bitstamp".to_string()
-        );
-        assert!(
-            merged[8].price == 0.074487999999999999 && merged[8].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[9].price == 0.074485999999999997 && merged[9].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[10].price == 0.074484999999999996
-                && merged[10].exchange == "Binance".to_string()
-        );
-        assert!(
-            merged[11].price == 0.074467909999999998
-                && merged[11].exchange == "Bitstamp".to_string()
-        );
-        assert!(
-            merged[12].price == 0.074462249999999994
-                && merged[12].exchange == "Bitstamp".to_string()
-        );
-        assert!(
-            merged[19].price == 0.074410000000000004
-                && merged[19].exchange == "Bitstamp".to_string()
-        );
+        assert!(merged[0].price == decimal(0.074505) && merged[0].exchange == "Binance");
+        assert!(merged[1].price == decimal(0.074502) && merged[1].exchange == "Binance");
+        assert!(merged[2].price == decimal(0.074501) && merged[2].exchange == "Binance");
+        assert!(merged[3].price == decimal(0.074496) && merged[3].exchange == "Binance");
+        assert!(merged[4].price == decimal(0.074492) && merged[4].exchange == "Binance");
+        assert!(merged[5].price == decimal(0.07449) && merged[5].exchange == "Binance");
+        assert!(merged[6].price == decimal(0.074489) && merged[6].exchange == "Binance");
+        assert!(merged[7].price == decimal(0.07448857) && merged[7].exchange == "Bitstamp");
+        assert!(merged[8].price == decimal(0.074488) && merged[8].exchange == "Binance");
+        assert!(merged[9].price == decimal(0.074486) && merged[9].exchange == "Binance");
+        assert!(merged[10].price == decimal(0.074485) && merged[10].exchange == "Binance");
+        assert!(merged[11].price == decimal(0.07446791) && merged[11].exchange == "Bitstamp");
+        assert!(merged[12].price == decimal(0.07446225) && merged[12].exchange == "Bitstamp");
+        assert!(merged[19].price == decimal(0.07441) && merged[19].exchange == "Bitstamp");
+    }
+
+    #[test]
+    fn should_merge_like_recursive_merge() {
+        for (side, depth, seed) in [
+            (false, 10, 0),
+            (true, 10, 1),
+            (false, 25, 2),
+            (true, 100, 3),
+        ] {
+            // Arrange
+            let levels_01 = ladder("Binance", depth, side, seed);
+            let levels_02 = ladder("Bitstamp", depth / 2, side, seed + 1);
+            let mut expected = Vec::with_capacity(depth);
+
+            // Act
+            let merged = merge(&levels_01, &levels_02, depth, side);
+            recursive_merge(&mut expected, &levels_01, &levels_02, 0, 0, side);
+
+            // Assert
+            assert!(merged == expected, "side {}, depth {}", side, depth);
+        }
+    }
+
+    /**
+     * Compares the iterative merge with the recursive one it replaced, run with
+     * `cargo test --release -- --ignored --nocapture bench_merge`.
+     */
+    #[test]
+    #[ignore]
+    fn bench_merge() {
+        const ROUNDS: u32 = 20_000;
+        for depth in [10, 20, 100] {
+            let levels_01 = ladder("Binance", depth, false, 0);
+            let levels_02 = ladder("Bitstamp", depth, false, 1);
+            let references: Vec<&BookLevel> = levels_01.iter().collect();
+
+            let started = Instant::now();
+            for _ in 0..ROUNDS {
+                let mut merged = Vec::with_capacity(depth);
+                recursive_merge(&mut merged, &levels_01, &levels_02, 0, 0, false);
+                std::hint::black_box(merged);
+            }
+            let recursive = started.elapsed() / ROUNDS;
+
+            let mut merged = Vec::with_capacity(depth);
+            let started = Instant::now();
+            for _ in 0..ROUNDS {
+                Aggregator::merge(&mut merged, &references, &levels_02, depth, false);
+                std::hint::black_box(&merged);
+            }
+            let iterative = started.elapsed() / ROUNDS;
+
+            println!(
+                "depth {:>3}: recursive {:>8?}, iterative {:>8?}",
+                depth, recursive, iterative
+            );
+        }
     }
 }
@@ -0,0 +1,136 @@
+//! A self-audit of the aggregation. The merged book is re-derived from the snapshots held per venue
+//! and compared with the summary published last, and every venue's ladders have to be sorted and
+//! uncrossed. Any finding hints at a bug in the merge or a connector and is reported as an alert.
+
+use crate::{aggregator::Aggregator, clock::Clock, log};
+use keyrock_challenge_core::orderbook_snapshot::BookLevel;
+use keyrock_challenge_proto::orderbook::{AuditFinding, AuditFindingKind, Level};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+fn finding(kind: AuditFindingKind, exchange: &str, detail: String) -> AuditFinding {
+    AuditFinding {
+        kind: kind as i32,
+        exchange: exchange.to_string(),
+        detail,
+    }
+}
+
+/**
+ * Checks that the bids descend and the asks ascend in price and that the best bid is below the
+ * best ask. Either side may be missing.
+ */
+pub fn ladder_findings(
+    exchange: &str,
+    bids: &[BookLevel],
+    asks: &[BookLevel],
+) -> Vec<AuditFinding> {
+    let mut findings = Vec::new();
+    if let Some(index) = bids
+        .windows(2)
+        .position(|pair| pair[0].price < pair[1].price)
+    {
+        findings.push(finding(
+            AuditFindingKind::Unsorted,
+            exchange,
+            format!("bid {} is below bid {}", index, index + 1),
+        ));
+    }
+    if let Some(index) = asks
+        .windows(2)
+        .position(|pair| pair[0].price > pair[1].price)
+    {
+        findings.push(finding(
+            AuditFindingKind::Unsorted,
+            exchange,
+            format!("ask {} is above ask {}", index, index + 1),
+        ));
+    }
+    if let (Some(best_bid), Some(best_ask)) = (bids.first(), asks.first()) {
+        if best_bid.price >= best_ask.price {
+            findings.push(finding(
+                AuditFindingKind::Crossed,
+                exchange,
+                format!("best bid {} >= best ask {}", best_bid.price, best_ask.price),
+            ));
+        }
+    }
+    findings
+}
+
+/**
+ * Describes where the re-derived levels differ from the published ones, if they do.
+ */
+pub fn merge_findings(side: &str, published: &[Level], derived: &[Level]) -> Option<AuditFinding> {
+    if published == derived {
+        return None;
+    }
+    let index = published
+        .iter()
+        .zip(derived)
+        .position(|(published, derived)| published != derived)
+        .unwrap_or(published.len().min(derived.len()));
+    Some(finding(
+        AuditFindingKind::MergeMismatch,
+        "",
+        format!(
+            "{} {} differs, published {} levels, re-derived {}",
+            side,
+            index,
+            published.len(),
+            derived.len()
+        ),
+    ))
+}
+
+/**
+ * Audits the aggregation once per interval and raises an alert for every failed audit.
+ */
+pub async fn run(
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        clock.sleep(interval).await;
+        let report = aggregator_arc.lock().await.audit();
+        for finding in &report.findings {
+            log::alert!(
+                "Book audit failed, {:?} {}: {}",
+                finding.kind(),
+                finding.exchange,
+                finding.detail
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ladder_findings;
+    use keyrock_challenge_core::orderbook_snapshot::BookLevel;
+    use keyrock_challenge_proto::orderbook::AuditFindingKind;
+
+    fn levels(prices: &[&str]) -> Vec<BookLevel> {
+        prices
+            .iter()
+            .map(|price| BookLevel {
+                price: price.parse().unwrap(),
+                amount: "1".parse().unwrap(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_find_unsorted_and_crossed_ladders() {
+        let sorted = ladder_findings("Binance", &levels(&["3", "2"]), &levels(&["4", "5"]));
+        let unsorted = ladder_findings("Binance", &levels(&["2", "3"]), &levels(&["4", "5"]));
+        let crossed = ladder_findings("Binance", &levels(&["4", "2"]), &levels(&["4", "5"]));
+
+        assert!(sorted.is_empty());
+        assert!(unsorted.len() == 1 && unsorted[0].kind() == AuditFindingKind::Unsorted);
+        assert!(crossed.len() == 1 && crossed[0].kind() == AuditFindingKind::Crossed);
+        assert!(crossed[0].exchange == "Binance");
+    }
+}
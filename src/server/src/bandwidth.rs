@@ -0,0 +1,154 @@
+//! Accounts the bytes sent to each subscriber identity and enforces per-identity caps. A subscriber
+//! exceeding its cap is not disconnected, it only gets fewer summaries: until the current second is
+//! over, newer summaries replace the one waiting to be sent.
+
+use keyrock_challenge_proto::orderbook::SubscriberBandwidth;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/**
+ * A cap of an identity in kilobytes per second, parsed from `<identity>=<kb>`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BandwidthCap {
+    pub identity: String,
+    pub bytes_per_sec: u64,
+}
+
+impl FromStr for BandwidthCap {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (identity, kb) = raw.rsplit_once('=').ok_or(())?;
+        let kb = kb.parse::<u64>().map_err(|_| ())?;
+        if identity.is_empty() || kb == 0 {
+            return Err(());
+        }
+        Ok(BandwidthCap {
+            identity: identity.to_string(),
+            bytes_per_sec: kb * 1024,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    total_bytes: u64,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    conflated: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Bandwidth {
+    /// applies to every identity without a cap of its own
+    default_cap: Option<u64>,
+    caps: HashMap<String, u64>,
+    usage: HashMap<String, Usage>,
+}
+
+impl Bandwidth {
+    pub fn new(default_cap: Option<u64>, caps: Vec<BandwidthCap>) -> Self {
+        Bandwidth {
+            default_cap,
+            caps: caps
+                .into_iter()
+                .map(|cap| (cap.identity, cap.bytes_per_sec))
+                .collect(),
+            usage: HashMap::new(),
+        }
+    }
+
+    fn cap(&self, identity: &str) -> Option<u64> {
+        self.caps.get(identity).copied().or(self.default_cap)
+    }
+
+    pub fn record(&mut self, identity: &str, bytes: usize, now: Instant) {
+        let usage = self.usage.entry(identity.to_string()).or_default();
+        if usage
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= WINDOW)
+        {
+            usage.window_start = Some(now);
+            usage.window_bytes = 0;
+        }
+        usage.total_bytes += bytes as u64;
+        usage.window_bytes += bytes as u64;
+    }
+
+    pub fn record_conflated(&mut self, identity: &str) {
+        self.usage
+            .entry(identity.to_string())
+            .or_default()
+            .conflated += 1;
+    }
+
+    /**
+     * How long the identity has to wait until it may be sent to again, zero if it is within its cap.
+     */
+    pub fn throttle(&self, identity: &str, now: Instant) -> Duration {
+        let (cap, usage) = match (self.cap(identity), self.usage.get(identity)) {
+            (Some(cap), Some(usage)) => (cap, usage),
+            _ => return Duration::ZERO,
+        };
+        match usage.window_start {
+            Some(start) if usage.window_bytes >= cap => {
+                WINDOW.saturating_sub(now.duration_since(start))
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn stats(&self) -> Vec<SubscriberBandwidth> {
+        let mut stats: Vec<SubscriberBandwidth> = self
+            .usage
+            .iter()
+            .map(|(identity, usage)| SubscriberBandwidth {
+                identity: identity.clone(),
+                total_bytes: usage.total_bytes,
+                cap_bytes_per_sec: self.cap(identity),
+                conflated: usage.conflated,
+            })
+            .collect();
+        stats.sort_by(|a, b| a.identity.cmp(&b.identity));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bandwidth, BandwidthCap};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_throttle_identity_over_its_cap_until_the_window_ends() {
+        // Arrange
+        let cap: BandwidthCap = "dashboard=1".parse().unwrap();
+        let mut bandwidth = Bandwidth::new(None, vec![cap]);
+        let started = Instant::now();
+        let at = |ms: u64| started + Duration::from_millis(ms);
+
+        // Act
+        bandwidth.record("dashboard", 600, at(0));
+        let within = bandwidth.throttle("dashboard", at(100));
+        bandwidth.record("dashboard", 600, at(100));
+        let exceeded = bandwidth.throttle("dashboard", at(200));
+        bandwidth.record("trader", 5000, at(200));
+
+        // Assert
+        assert!(within.is_zero());
+        assert!(exceeded == Duration::from_millis(800));
+        assert!(bandwidth.throttle("dashboard", at(1000)).is_zero());
+        assert!(bandwidth.throttle("trader", at(200)).is_zero());
+        let stats = bandwidth.stats();
+        assert!(stats[0].identity == "dashboard" && stats[0].total_bytes == 1200);
+        assert!(stats[0].cap_bytes_per_sec == Some(1024));
+        assert!(stats[1].cap_bytes_per_sec.is_none());
+        assert!("dashboard=0".parse::<BandwidthCap>().is_err());
+    }
+}
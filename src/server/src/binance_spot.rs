@@ -1,157 +1,300 @@
-use std::sync::Arc;
-
-use crate::{aggregator::Aggregator, OrderbookSnapshot};
+use crate::{
+    connector_sdk::{self, ExchangeConnector, LocalBook, ReconnectPolicy, Update},
+    diagnostics::Probe,
+    exchange_status::{self, StatusEndpoint},
+    log,
+    schema::{Field, Shape},
+    trade_through::Trade,
+};
+use keyrock_challenge_core::orderbook_snapshot::{OrderbookSnapshot, Side};
+use keyrock_challenge_proto::orderbook::VenueStatus;
 use serde_json::Value;
-use tokio::sync::Mutex;
+use tokio::sync::mpsc::Sender;
 use tungstenite::connect;
 use url::Url;
 
-use keyrock_challenge_proto::orderbook::Level;
+const EXCHANGE: &str = "Binance";
+/// the levels per side of the REST snapshot seeding the book, the deepest the diff stream keeps up
+pub const MAX_DEPTH: usize = 1000;
+
+pub const STATUS_ENDPOINT: StatusEndpoint = StatusEndpoint {
+    exchange: EXCHANGE,
+    url: "https://api.binance.com/sapi/v1/system/status",
+    parse: parse_status,
+};
+
+const LEVEL: Shape = Shape::Tuple(&[Shape::Decimal, Shape::Decimal]);
+/// a diff event, the only message of the stream
+static DIFF_SCHEMA: Shape = Shape::Object(&[
+    Field::required("e", Shape::String),
+    Field::optional("E", Shape::Number),
+    Field::optional("s", Shape::String),
+    Field::required("U", Shape::Number),
+    Field::required("u", Shape::Number),
+    Field::required("b", Shape::Array(&LEVEL)),
+    Field::required("a", Shape::Array(&LEVEL)),
+]);
+
+/**
+ * The streams of a symbol, e.g. `ethbtc@depth@100ms`.
+ */
+fn url(symbol: &str, stream: &str) -> String {
+    format!("wss://stream.binance.com:9443/ws/{}@{}", symbol, stream)
+}
+
+/**
+ * The REST snapshot of the book of a symbol, which the diff stream is applied to.
+ */
+fn snapshot_url(symbol: &str) -> String {
+    format!(
+        "https://api.binance.com/api/v3/depth?symbol={}&limit={}",
+        symbol.to_uppercase(),
+        MAX_DEPTH
+    )
+}
+
+pub fn probe(symbol: &str) -> Probe {
+    Probe {
+        exchange: EXCHANGE,
+        url: url(symbol, "depth@100ms"),
+        subscribe: None,
+    }
+}
+
+/**
+ * Parses `{"status": 0, "msg": "normal"}`, where a status of 1 stands for system maintenance.
+ */
+fn parse_status(raw: &Value) -> Result<VenueStatus, ()> {
+    match raw["status"].as_u64() {
+        Some(0) => Ok(VenueStatus::Operational),
+        Some(1) => Ok(VenueStatus::Maintenance),
+        _ => Err(()),
+    }
+}
+
+/**
+ * How a diff event relates to the update id of the book.
+ */
+#[derive(Debug, PartialEq, Eq)]
+enum Continuity {
+    Follows,
+    /// all of its changes are contained in the book already
+    Outdated,
+    /// events between the book and this one are missing, the book has to be seeded again
+    Gap,
+}
 
-fn deserialize(raw: &str) -> Result<OrderbookSnapshot<10>, ()> {
-    let deserialization = serde_json::from_str(raw);
+/**
+ * The full book of the symbol, seeded from the REST snapshot and kept up to date with the diff
+ * events following it.
+ */
+#[derive(Debug, Default)]
+struct Book {
+    levels: LocalBook,
+    /// the id of the last update contained in the book, None until it was seeded
+    last_update_id: Option<u64>,
+}
 
-    let deserialized: Value = match deserialization {
-        Ok(des) => des,
-        Err(_) => return Err(()),
-    };
+impl Book {
+    fn apply(&mut self, side: Side, raw: &Value) -> Result<(), ()> {
+        for entry in raw.as_array().ok_or(())? {
+            self.levels.set(
+                side,
+                connector_sdk::parse_decimal(&entry[0])?,
+                connector_sdk::parse_decimal(&entry[1])?,
+            );
+        }
+        Ok(())
+    }
 
-    let exchange = "Binance";
-    let bids = &deserialized["bids"];
-    let asks = &deserialized["asks"];
+    /**
+     * Replaces the book with the REST snapshot, `{"lastUpdateId": 160, "bids": [["0.0745", "1.5"],
+     * ...], "asks": [...]}`.
+     */
+    fn seed(&mut self, snapshot: &Value) -> Result<(), ()> {
+        let last_update_id = snapshot["lastUpdateId"].as_u64().ok_or(())?;
+        self.levels.clear();
+        self.last_update_id = None;
+        self.apply(Side::Bids, &snapshot["bids"])?;
+        self.apply(Side::Asks, &snapshot["asks"])?;
+        self.last_update_id = Some(last_update_id);
+        Ok(())
+    }
+
+    /**
+     * Applies a diff event, `{"e": "depthUpdate", "E": 1672515782136, "U": 157, "u": 160, "b":
+     * [["0.0745", "0"], ...], "a": [...]}`, where a quantity of zero removes the level. Only the
+     * event containing the update following the book is applied: its first update id `U` is at most
+     * and its final update id `u` at least the update id after the book's.
+     */
+    fn update(&mut self, event: &Value) -> Result<Continuity, ()> {
+        let last_update_id = self.last_update_id.ok_or(())?;
+        let first = event["U"].as_u64().ok_or(())?;
+        let last = event["u"].as_u64().ok_or(())?;
+        if last <= last_update_id {
+            return Ok(Continuity::Outdated);
+        }
+        if first > last_update_id + 1 {
+            self.last_update_id = None;
+            return Ok(Continuity::Gap);
+        }
+        self.apply(Side::Bids, &event["b"])?;
+        self.apply(Side::Asks, &event["a"])?;
+        self.last_update_id = Some(last);
+        Ok(Continuity::Follows)
+    }
+}
 
-    if bids.is_null() || asks.is_null() {
+fn deserialize(book: &mut Book, event: &Value, depth: usize) -> Result<Update, ()> {
+    if event["e"].as_str() != Some("depthUpdate") {
         return Err(());
     }
+    match book.update(event)? {
+        Continuity::Follows => {}
+        Continuity::Outdated => return Ok(Update::Stale),
+        Continuity::Gap => return Ok(Update::Gap),
+    }
+    let mut snapshot: OrderbookSnapshot = book
+        .levels
+        .snapshot(EXCHANGE, depth)
+        .map_err(|error| connector_sdk::reject(EXCHANGE, error))?;
+    snapshot.exchange_timestamp_us = event["E"].as_u64().map(|ms| ms * 1000);
+    Ok(Update::Snapshot(snapshot))
+}
 
-    Ok(OrderbookSnapshot {
-        bids: [
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[0][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[0][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[1][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[1][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[2][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[2][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[3][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[3][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[4][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[4][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[5][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[5][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[6][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[6][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[7][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[7][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[8][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[8][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[9][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[9][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-        ],
-        asks: [
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[0][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[0][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[1][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[1][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[2][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[2][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[3][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[3][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[4][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[4][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[5][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[5][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[6][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[6][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[7][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[7][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[8][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[8][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[9][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[9][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-        ],
+/**
+ * Parses `{"e": "trade", "p": "0.0745", "q": "1.2", "T": 1672515782136, "m": true, ...}`, where `m`
+ * is set if the buyer was the maker, i.e. the taker sold.
+ */
+fn deserialize_trade(deserialized: &Value) -> Result<Trade, ()> {
+    if deserialized["e"].as_str() != Some("trade") {
+        return Err(());
+    }
+    Ok(Trade {
+        exchange: EXCHANGE,
+        price: connector_sdk::parse_decimal(&deserialized["p"])?,
+        amount: connector_sdk::parse_decimal(&deserialized["q"])?,
+        buy: !deserialized["m"].as_bool().ok_or(())?,
+        exchange_timestamp_us: deserialized["T"].as_u64().map(|ms| ms * 1000),
     })
 }
 
-pub async fn run_stream(source_id: usize, aggregator_arc: Arc<Mutex<Aggregator>>) {
-    let (mut socket, _) =
-        connect(Url::parse("wss://stream.binance.com:9443/ws/ethbtc@depth10@100ms").unwrap())
-            .expect("Unable to connect to Binance Exchange");
+/**
+ * The diff stream of the symbol. The REST snapshot is fetched once the first event arrived, so the
+ * events buffered by the stream in the meantime connect to it.
+ */
+#[derive(Debug, Default)]
+struct BinanceConnector {
+    symbol: String,
+    book: Book,
+}
+
+impl ExchangeConnector for BinanceConnector {
+    fn url(&self, symbol: &str, _: usize) -> String {
+        url(symbol, "depth@100ms")
+    }
+
+    fn subscribe(&mut self, symbol: &str, _: usize) -> Vec<String> {
+        self.symbol = symbol.to_string();
+        self.book = Book::default();
+        Vec::new()
+    }
+
+    fn next_snapshot(&mut self, message: &Value, depth: usize) -> Update {
+        if self.book.last_update_id.is_none() {
+            let seeded = exchange_status::http_get(&snapshot_url(&self.symbol))
+                .map_err(|error| error.to_string())
+                .and_then(|body| serde_json::from_str(&body).map_err(|error| error.to_string()))
+                .and_then(|snapshot| {
+                    self.book
+                        .seed(&snapshot)
+                        .map_err(|_| "Unexpected payload".to_string())
+                });
+            if let Err(error) = seeded {
+                log::warning!("Unable to seed the {} book: {}", EXCHANGE, error);
+                return Update::Reconnect;
+            }
+        }
+        deserialize(&mut self.book, message, depth).unwrap_or(Update::Ignored)
+    }
+
+    fn schema(&self, _: &Value) -> Option<&'static Shape> {
+        Some(&DIFF_SCHEMA)
+    }
+}
+
+pub fn connector() -> Box<dyn ExchangeConnector> {
+    Box::<BinanceConnector>::default()
+}
+
+async fn run_trade_session(symbol: &str, tx: Sender<Trade>) -> Result<(), tungstenite::Error> {
+    let (mut socket, _) = connect(Url::parse(&url(symbol, "trade")).unwrap())?;
 
     loop {
-        let msg = socket
-            .read_message()
-            .expect("Unable to read from message from Binance websocket stream");
-        let content = msg
-            .into_text()
-            .expect("Unable to read from message from Binance websocket stream");
-        let deserialization = deserialize(&content);
-
-        if let Ok(snapshot) = deserialization {
-            aggregator_arc
-                .lock()
-                .await
-                .process(source_id, snapshot)
-                .await;
+        let msg = socket.read_message()?;
+        let content = msg.into_text()?;
+        let trade = serde_json::from_str::<Value>(&content)
+            .map_err(|_| ())
+            .and_then(|deserialized| deserialize_trade(&deserialized));
+        if let Ok(trade) = trade {
+            if tx.send(trade).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub async fn run_trades(symbol: String, tx: Sender<Trade>, policy: ReconnectPolicy) {
+    connector_sdk::run_with_reconnect(EXCHANGE, policy, || run_trade_session(&symbol, tx.clone()))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize, Book, Continuity, Side, DIFF_SCHEMA};
+    use crate::{connector_sdk::Update, schema};
+    use keyrock_challenge_core::decimal::Decimal;
+    use serde_json::{json, Value};
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    fn event(first: u64, last: u64, bids: Value) -> Value {
+        json!({"e": "depthUpdate", "E": 1672515782136u64, "U": first, "u": last, "b": bids, "a": []})
+    }
+
+    #[test]
+    fn should_apply_the_events_following_the_snapshot() {
+        // Arrange
+        let mut book = Book::default();
+        let snapshot = json!({
+            "lastUpdateId": 160,
+            "bids": [["0.0745", "1.5"], ["0.0744", "3.0"]],
+            "asks": [["0.0746", "1.0"]]
+        });
+
+        // Act
+        let unseeded = book.update(&event(150, 158, json!([])));
+        book.seed(&snapshot).unwrap();
+        let outdated = book.update(&event(150, 160, json!([])));
+        let straddling = deserialize(&mut book, &event(158, 162, json!([["0.0745", "0"]])), 1);
+        let following = book.update(&event(163, 165, json!([["0.07445", "2.0"]])));
+        let gap = book.update(&event(170, 171, json!([])));
+
+        // Assert
+        assert!(schema::validate(&DIFF_SCHEMA, &event(163, 165, json!([]))).is_empty());
+        assert!(unseeded.is_err());
+        assert!(outdated == Ok(Continuity::Outdated));
+        match straddling {
+            Ok(Update::Snapshot(snapshot)) => {
+                assert!(snapshot.bids.unwrap()[0].price == decimal("0.0744"));
+                assert!(snapshot.exchange_timestamp_us == Some(1_672_515_782_136_000));
+            }
+            other => panic!("Expected a snapshot, got {:?}", other),
         }
+        assert!(following == Ok(Continuity::Follows));
+        assert!(book.levels.best(Side::Bids, 1)[0].0 == decimal("0.07445"));
+        assert!(gap == Ok(Continuity::Gap) && book.last_update_id.is_none());
     }
 }
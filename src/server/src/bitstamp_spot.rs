@@ -1,169 +1,328 @@
-use crate::{aggregator::Aggregator, OrderbookSnapshot};
-use keyrock_challenge_proto::orderbook::Level;
-use serde_json::Value;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use crate::{
+    connector_sdk::{
+        self, ExchangeConnector, LocalBook, ReconnectPolicy, Sequence, SequenceTracker, Update,
+    },
+    diagnostics::Probe,
+    exchange_status::{self, StatusEndpoint},
+    log,
+    schema::{Field, Shape},
+    trade_through::Trade,
+};
+use keyrock_challenge_core::orderbook_snapshot::{OrderbookSnapshot, Side};
+use keyrock_challenge_proto::orderbook::VenueStatus;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::Sender;
 use tungstenite::{connect, Message};
 use url::Url;
 
-fn deserialize(raw: &str) -> Result<OrderbookSnapshot<10>, ()> {
-    let deserialization = serde_json::from_str(raw);
+const EXCHANGE: &str = "Bitstamp";
+/// the diff channel keeps the full book seeded from the REST snapshot, the best 1000 levels of it
+/// can be merged
+pub const MAX_DEPTH: usize = 1000;
 
-    let deserialized: Value = match deserialization {
-        Ok(des) => des,
-        Err(_) => return Err(()),
-    };
+pub const STATUS_ENDPOINT: StatusEndpoint = StatusEndpoint {
+    exchange: EXCHANGE,
+    url: "https://status.bitstamp.net/api/v2/scheduled-maintenances/active.json",
+    parse: parse_status,
+};
 
-    let exchange = "Bitstamp";
-    let data = &deserialized["data"];
-    let bids = &data["bids"];
-    let asks = &deserialized["data"]["asks"];
+const URL: &str = "wss://ws.bitstamp.net/";
 
-    if bids.is_null() || asks.is_null() {
-        return Err(());
+const LEVEL: Shape = Shape::Tuple(&[Shape::Decimal, Shape::Decimal]);
+/// a diff of the book, the events of the socket are not validated
+static DIFF_SCHEMA: Shape = Shape::Object(&[
+    Field::required("event", Shape::String),
+    Field::optional("channel", Shape::String),
+    Field::required(
+        "data",
+        Shape::Object(&[
+            Field::optional("timestamp", Shape::Decimal),
+            Field::required("microtimestamp", Shape::Decimal),
+            Field::required("bids", Shape::Array(&LEVEL)),
+            Field::required("asks", Shape::Array(&LEVEL)),
+        ]),
+    ),
+]);
+
+/**
+ * The REST snapshot of the full book of a symbol, which the diff channel is applied to.
+ */
+fn snapshot_url(symbol: &str) -> String {
+    format!("https://www.bitstamp.net/api/v2/order_book/{}/", symbol)
+}
+
+/**
+ * Subscribes to a channel of a symbol, e.g. `diff_order_book_ethbtc`.
+ */
+fn subscribe(channel: &str, symbol: &str) -> String {
+    json!({
+        "event": "bts:subscribe",
+        "data": {
+            "channel": format!("{}_{}", channel, symbol)
+        }
+    })
+    .to_string()
+}
+
+pub fn probe(symbol: &str) -> Probe {
+    Probe {
+        exchange: EXCHANGE,
+        url: URL.to_string(),
+        subscribe: Some(subscribe("diff_order_book", symbol)),
+    }
+}
+
+/**
+ * Parses the active maintenances listed on the status page, `{"scheduled_maintenances": [...]}`.
+ */
+fn parse_status(raw: &Value) -> Result<VenueStatus, ()> {
+    match raw["scheduled_maintenances"].as_array() {
+        Some(maintenances) if maintenances.is_empty() => Ok(VenueStatus::Operational),
+        Some(_) => Ok(VenueStatus::Maintenance),
+        None => Err(()),
+    }
+}
+
+fn parse_microtimestamp(raw: &Value) -> Result<u64, ()> {
+    raw.as_str()
+        .and_then(|raw| raw.parse::<u64>().ok())
+        .ok_or(())
+}
+
+/**
+ * How a diff relates to the book, by their microtimestamps.
+ */
+#[derive(Debug, PartialEq, Eq)]
+enum Continuity {
+    Follows,
+    /// the diff happened before the REST snapshot, which contains its changes already
+    Outdated,
+    /// the diff did not happen after the last applied one, the book has to be rebuilt
+    OutOfOrder,
+}
+
+/**
+ * The full book of the symbol, seeded from the REST snapshot and kept up to date with the diffs
+ * happening after it, in the order of their microtimestamps.
+ */
+#[derive(Debug, Default)]
+struct Book {
+    levels: LocalBook,
+    /// the microtimestamp of the REST snapshot, None until the book was seeded
+    seeded_at: Option<u64>,
+    /// the microtimestamps of the applied diffs
+    sequence_tracker: SequenceTracker,
+}
+
+impl Book {
+    fn apply(&mut self, side: Side, raw: &Value) -> Result<(), ()> {
+        for entry in raw.as_array().ok_or(())? {
+            self.levels.set(
+                side,
+                connector_sdk::parse_decimal(&entry[0])?,
+                connector_sdk::parse_decimal(&entry[1])?,
+            );
+        }
+        Ok(())
+    }
+
+    /**
+     * Replaces the book with the REST snapshot, `{"microtimestamp": "1672515782136447", "bids":
+     * [["0.0745", "1.5"], ...], "asks": [...]}`.
+     */
+    fn seed(&mut self, snapshot: &Value) -> Result<(), ()> {
+        let microtimestamp = parse_microtimestamp(&snapshot["microtimestamp"])?;
+        *self = Book::default();
+        self.apply(Side::Bids, &snapshot["bids"])?;
+        self.apply(Side::Asks, &snapshot["asks"])?;
+        self.seeded_at = Some(microtimestamp);
+        Ok(())
+    }
+
+    /**
+     * Applies a diff, `{"event": "data", "data": {"microtimestamp": "1672515782136447", "bids":
+     * [["0.0745", "0"], ...], "asks": [...]}}`, where an amount of zero removes the level. Returns
+     * the microtimestamp of the diff along with how it relates to the book.
+     */
+    fn update(&mut self, diff: &Value) -> Result<(u64, Continuity), ()> {
+        let seeded_at = self.seeded_at.ok_or(())?;
+        let data = &diff["data"];
+        let microtimestamp = parse_microtimestamp(&data["microtimestamp"])?;
+        if microtimestamp <= seeded_at {
+            return Ok((microtimestamp, Continuity::Outdated));
+        }
+        if self.sequence_tracker.observe(microtimestamp) == Sequence::Stale {
+            self.seeded_at = None;
+            return Ok((microtimestamp, Continuity::OutOfOrder));
+        }
+        self.apply(Side::Bids, &data["bids"])?;
+        self.apply(Side::Asks, &data["asks"])?;
+        Ok((microtimestamp, Continuity::Follows))
     }
+}
 
-    Ok(OrderbookSnapshot {
-        bids: [
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[0][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[0][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[1][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[1][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[2][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[2][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[3][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[3][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[4][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[4][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[5][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[5][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[6][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[6][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[7][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[7][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[8][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[8][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: bids[9][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: bids[9][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-        ],
-        asks: [
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[0][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[0][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[1][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[1][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[2][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[2][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[3][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[3][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[4][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[4][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[5][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[5][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[6][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[6][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[7][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[7][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[8][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[8][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-            Level {
-                exchange: exchange.to_string(),
-                price: asks[9][0].as_str().unwrap().parse::<f64>().unwrap(),
-                amount: asks[9][1].as_str().unwrap().parse::<f64>().unwrap(),
-            },
-        ],
+fn deserialize(book: &mut Book, diff: &Value, depth: usize) -> Result<Update, ()> {
+    match diff["event"].as_str() {
+        Some("data") => {}
+        // sent ahead of a maintenance of the server the socket is connected to
+        Some("bts:request_reconnect") => return Ok(Update::Reconnect),
+        _ => return Err(()),
+    }
+    let microtimestamp = match book.update(diff)? {
+        (microtimestamp, Continuity::Follows) => microtimestamp,
+        (_, Continuity::Outdated) => return Ok(Update::Stale),
+        (_, Continuity::OutOfOrder) => return Ok(Update::Resubscribe),
+    };
+    let mut snapshot: OrderbookSnapshot = book
+        .levels
+        .snapshot(EXCHANGE, depth)
+        .map_err(|error| connector_sdk::reject(EXCHANGE, error))?;
+    snapshot.exchange_timestamp_us = Some(microtimestamp);
+    Ok(Update::Snapshot(snapshot))
+}
+
+/**
+ * Parses `{"event": "trade", "data": {"price_str": "0.0745", "amount_str": "1.2", "type": 0,
+ * "microtimestamp": "1672515782136447", ...}}`, where a type of 0 stands for a buy.
+ */
+fn deserialize_trade(deserialized: &Value) -> Result<Trade, ()> {
+    if deserialized["event"].as_str() != Some("trade") {
+        return Err(());
+    }
+    let data = &deserialized["data"];
+    Ok(Trade {
+        exchange: EXCHANGE,
+        price: connector_sdk::parse_decimal(&data["price_str"])?,
+        amount: connector_sdk::parse_decimal(&data["amount_str"])?,
+        buy: data["type"].as_u64().ok_or(())? == 0,
+        exchange_timestamp_us: data["microtimestamp"]
+            .as_str()
+            .and_then(|raw| raw.parse::<u64>().ok()),
     })
 }
 
-pub async fn run_stream(source_id: usize, aggregator_arc: Arc<Mutex<Aggregator>>) {
-    let (mut socket, _) = connect(Url::parse("wss://ws.bitstamp.net/").unwrap())
-        .expect("Unable to connect to Bitstamp Exchange");
+/**
+ * The diff channel of the symbol, unthrottled unlike the channels sending the best levels. The REST
+ * snapshot is fetched once the first diff arrived, so the diffs following it are received already.
+ */
+#[derive(Debug, Default)]
+struct BitstampConnector {
+    symbol: String,
+    book: Book,
+}
+
+impl ExchangeConnector for BitstampConnector {
+    fn url(&self, _: &str, _: usize) -> String {
+        URL.to_string()
+    }
+
+    fn subscribe(&mut self, symbol: &str, _: usize) -> Vec<String> {
+        self.symbol = symbol.to_string();
+        self.book = Book::default();
+        vec![subscribe("diff_order_book", symbol)]
+    }
 
-    socket
-        .write_message(Message::Text(
-            r#"
-        {
-          "event": "bts:subscribe",
-          "data": {
-            "channel": "detail_order_book_ethbtc"
-          }
+    fn next_snapshot(&mut self, message: &Value, depth: usize) -> Update {
+        if self.book.seeded_at.is_none() && message["event"].as_str() == Some("data") {
+            let seeded = exchange_status::http_get(&snapshot_url(&self.symbol))
+                .map_err(|error| error.to_string())
+                .and_then(|body| serde_json::from_str(&body).map_err(|error| error.to_string()))
+                .and_then(|snapshot| {
+                    self.book
+                        .seed(&snapshot)
+                        .map_err(|_| "Unexpected payload".to_string())
+                });
+            if let Err(error) = seeded {
+                log::warning!("Unable to seed the {} book: {}", EXCHANGE, error);
+                return Update::Reconnect;
+            }
         }
-    "#
-            .into(),
-        ))
-        .expect("Unable to write message to Bitstamp websocket stream");
+        deserialize(&mut self.book, message, depth).unwrap_or(Update::Ignored)
+    }
+
+    fn schema(&self, message: &Value) -> Option<&'static Shape> {
+        (message["event"].as_str() == Some("data")).then_some(&DIFF_SCHEMA)
+    }
+}
+
+pub fn connector() -> Box<dyn ExchangeConnector> {
+    Box::<BitstampConnector>::default()
+}
+
+async fn run_trade_session(symbol: &str, tx: Sender<Trade>) -> Result<(), tungstenite::Error> {
+    let (mut socket, _) = connect(Url::parse(URL).unwrap())?;
+
+    socket.write_message(Message::Text(subscribe("live_trades", symbol)))?;
 
     loop {
-        let msg = socket
-            .read_message()
-            .expect("Unable to read from message from Bitstamp websocket stream");
-        let content = msg
-            .into_text()
-            .expect("Unable to read from message from Bitstamp websocket stream");
-        let deserialization = deserialize(&content);
-
-        if let Ok(snapshot) = deserialization {
-            aggregator_arc
-                .lock()
-                .await
-                .process(source_id, snapshot)
-                .await;
+        let msg = socket.read_message()?;
+        let content = msg.into_text()?;
+        let trade = serde_json::from_str::<Value>(&content)
+            .map_err(|_| ())
+            .and_then(|deserialized| deserialize_trade(&deserialized));
+        if let Ok(trade) = trade {
+            if tx.send(trade).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub async fn run_trades(symbol: String, tx: Sender<Trade>, policy: ReconnectPolicy) {
+    connector_sdk::run_with_reconnect(EXCHANGE, policy, || run_trade_session(&symbol, tx.clone()))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize, Book, Continuity, Side, DIFF_SCHEMA};
+    use crate::{connector_sdk::Update, schema};
+    use keyrock_challenge_core::decimal::Decimal;
+    use serde_json::{json, Value};
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    fn diff(microtimestamp: u64, bids: Value) -> Value {
+        json!({
+            "event": "data",
+            "channel": "diff_order_book_ethbtc",
+            "data": {"microtimestamp": microtimestamp.to_string(), "bids": bids, "asks": []}
+        })
+    }
+
+    #[test]
+    fn should_apply_the_diffs_after_the_snapshot_in_order() {
+        // Arrange
+        let mut book = Book::default();
+        let snapshot = json!({
+            "microtimestamp": "1000",
+            "bids": [["0.0745", "1.5"], ["0.0744", "3.0"]],
+            "asks": [["0.0746", "1.0"]]
+        });
+
+        // Act
+        let unseeded = book.update(&diff(900, json!([])));
+        book.seed(&snapshot).unwrap();
+        let outdated = book.update(&diff(1000, json!([["0.0745", "0"]])));
+        let following = deserialize(&mut book, &diff(1200, json!([["0.0745", "0"]])), 1);
+        let out_of_order = book.update(&diff(1100, json!([])));
+        let reconnect = deserialize(&mut book, &json!({"event": "bts:request_reconnect"}), 1);
+
+        // Assert
+        assert!(schema::validate(&DIFF_SCHEMA, &diff(1200, json!([["0.0745", "0"]]))).is_empty());
+        assert!(unseeded.is_err());
+        assert!(outdated == Ok((1000, Continuity::Outdated)));
+        match following {
+            Ok(Update::Snapshot(snapshot)) => {
+                assert!(snapshot.bids.unwrap()[0].price == decimal("0.0744"));
+                assert!(snapshot.exchange_timestamp_us == Some(1200));
+            }
+            other => panic!("Expected a snapshot, got {:?}", other),
         }
+        assert!(book.levels.best(Side::Asks, 1)[0].0 == decimal("0.0746"));
+        assert!(out_of_order == Ok((1100, Continuity::OutOfOrder)) && book.seeded_at.is_none());
+        assert!(matches!(reconnect, Ok(Update::Reconnect)));
     }
 }
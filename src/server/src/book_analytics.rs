@@ -0,0 +1,98 @@
+//! Prices derived from the merged book, published with every summary so that clients do not have to
+//! recompute them from the levels on every tick.
+
+use keyrock_challenge_core::orderbook_snapshot::BookLevel;
+
+/// levels per side the VWAP is computed over
+pub const VWAP_DEPTH: usize = 10;
+
+/**
+ * The price halfway between the best bid and the best ask, none if a side is empty.
+ */
+pub fn mid(bids: &[BookLevel], asks: &[BookLevel]) -> Option<f64> {
+    let (best_bid, best_ask) = (bids.first()?, asks.first()?);
+    Some((best_bid.price + best_ask.price).to_f64() / 2.)
+}
+
+/**
+ * The average price of the best `VWAP_DEPTH` levels of both sides weighted by their amounts, none if
+ * a side is empty.
+ */
+pub fn vwap(bids: &[BookLevel], asks: &[BookLevel]) -> Option<f64> {
+    if bids.is_empty() || asks.is_empty() {
+        return None;
+    }
+    let levels = bids
+        .iter()
+        .take(VWAP_DEPTH)
+        .chain(asks.iter().take(VWAP_DEPTH));
+    let (notional, amount) = levels.fold((0., 0.), |(notional, amount), level| {
+        let level_amount = level.amount.to_f64();
+        (
+            notional + level.price.to_f64() * level_amount,
+            amount + level_amount,
+        )
+    });
+    Some(notional / amount)
+}
+
+/**
+ * The best bid and ask weighted by the amount on the opposite side. It leans towards the side with
+ * less amount at the top of the book, where the price is more likely to move next.
+ */
+pub fn microprice(bids: &[BookLevel], asks: &[BookLevel]) -> Option<f64> {
+    let (best_bid, best_ask) = (bids.first()?, asks.first()?);
+    let (bid_amount, ask_amount) = (best_bid.amount.to_f64(), best_ask.amount.to_f64());
+    Some(
+        (best_bid.price.to_f64() * ask_amount + best_ask.price.to_f64() * bid_amount)
+            / (bid_amount + ask_amount),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{microprice, mid, vwap, VWAP_DEPTH};
+    use keyrock_challenge_core::orderbook_snapshot::BookLevel;
+
+    fn level(price: &str, amount: &str) -> BookLevel {
+        BookLevel {
+            exchange: "Binance".to_string(),
+            price: price.parse().unwrap(),
+            amount: amount.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_derive_prices_from_the_top_of_the_book() {
+        // Arrange
+        let bids = vec![level("100", "3"), level("99", "1")];
+        let asks = vec![level("101", "1"), level("102", "5")];
+
+        // Act
+        let mid = mid(&bids, &asks);
+        let vwap = vwap(&bids, &asks);
+        let microprice = microprice(&bids, &asks);
+
+        // Assert
+        assert!(mid == Some(100.5));
+        assert!(vwap == Some((300. + 99. + 101. + 510.) / 10.));
+        // three times the amount on the bid, so the price is more likely to move up
+        assert!(microprice == Some(100.75));
+    }
+
+    #[test]
+    fn should_only_weigh_the_best_levels() {
+        // Arrange
+        let mut bids = vec![level("100", "1"); VWAP_DEPTH];
+        bids.push(level("1", "1000"));
+        let asks = vec![level("102", "1"); VWAP_DEPTH];
+
+        // Act
+        let vwap = vwap(&bids, &asks);
+
+        // Assert
+        assert!(vwap == Some(101.));
+        assert!(mid(&bids, &[]).is_none() && microprice(&[], &asks).is_none());
+    }
+}
@@ -0,0 +1,147 @@
+//! Samples the structure of the merged book periodically, so that changes in how the liquidity is
+//! composed can be monitored over time: how many of the best levels each venue provides, and how
+//! much amount the best levels add up to and how far from the mid they reach.
+
+use crate::{clock::Clock, spmc::Spmc};
+use keyrock_challenge_proto::orderbook::{BookShape, Level, ShapeSample, Summary, VenueLevelCount};
+use std::{sync::Arc, time::Duration, time::UNIX_EPOCH};
+use tokio::sync::{watch, Mutex};
+
+/// the counts of best levels a side is sampled at, as far as the side is deep
+pub const SAMPLE_LEVELS: [usize; 5] = [1, 5, 10, 20, 50];
+
+fn sample(levels: &[Level], top_levels: usize, mid: Option<f64>) -> ShapeSample {
+    let levels = &levels[..top_levels];
+    let mut venues: Vec<VenueLevelCount> = Vec::new();
+    for level in levels {
+        match venues
+            .iter_mut()
+            .find(|venue| venue.exchange == level.exchange)
+        {
+            Some(venue) => venue.levels += 1,
+            None => venues.push(VenueLevelCount {
+                exchange: level.exchange.clone(),
+                levels: 1,
+            }),
+        }
+    }
+    ShapeSample {
+        top_levels: top_levels as u32,
+        cumulative_amount: levels.iter().map(|level| level.amount).sum(),
+        distance_bps: match (levels.last(), mid) {
+            (Some(level), Some(mid)) if mid > 0. => Some((level.price - mid).abs() / mid * 10_000.),
+            _ => None,
+        },
+        venues,
+    }
+}
+
+fn samples(levels: &[Level], mid: Option<f64>) -> Vec<ShapeSample> {
+    let mut top_levels: Vec<usize> = (SAMPLE_LEVELS.iter().copied())
+        .filter(|top_levels| *top_levels < levels.len())
+        .collect();
+    if !levels.is_empty() {
+        top_levels.push(levels.len());
+    }
+    (top_levels.into_iter())
+        .map(|top_levels| sample(levels, top_levels, mid))
+        .collect()
+}
+
+/**
+ * The shape of the summary's book, sampled at `sampled_at_us`.
+ */
+pub fn book_shape(summary: &Summary, sampled_at_us: u64) -> BookShape {
+    BookShape {
+        symbol: summary.symbol.clone(),
+        sequence: summary.sequence,
+        sampled_at_us,
+        bids: samples(&summary.bids, summary.mid),
+        asks: samples(&summary.asks, summary.mid),
+    }
+}
+
+/**
+ * Samples the shape of the latest summary every `interval` and publishes it. Heartbeats without
+ * levels are not sampled.
+ */
+pub async fn run(
+    latest_summary: watch::Receiver<Option<Summary>>,
+    interval: Duration,
+    spmc: Arc<Mutex<Spmc<BookShape>>>,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        clock.sleep(interval).await;
+        let summary = match latest_summary.borrow().clone() {
+            Some(summary) if !summary.bids.is_empty() || !summary.asks.is_empty() => summary,
+            _ => continue,
+        };
+        let sampled_at_us = (clock.system_now().duration_since(UNIX_EPOCH).unwrap()).as_micros();
+        let mut spmc = spmc.lock().await;
+        if !spmc.is_empty() {
+            spmc.broadcast(book_shape(&summary, sampled_at_us as u64))
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::book_shape;
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_sample_the_best_levels_of_each_side() {
+        // Arrange
+        let mut bids: Vec<Level> = (0..6)
+            .map(|i| level("Binance", 99. - i as f64, 1.))
+            .collect();
+        bids[1].exchange = "Bitstamp".to_string();
+        let summary = Summary {
+            symbol: "ethbtc".to_string(),
+            sequence: 7,
+            bids,
+            asks: vec![level("Bitstamp", 101., 2.)],
+            mid: Some(100.),
+            ..Default::default()
+        };
+
+        // Act
+        let shape = book_shape(&summary, 1_000);
+
+        // Assert
+        assert!(shape.symbol == "ethbtc" && shape.sequence == 7 && shape.sampled_at_us == 1_000);
+        let top_levels: Vec<u32> = shape.bids.iter().map(|sample| sample.top_levels).collect();
+        assert!(top_levels == vec![1, 5, 6]);
+        assert!(shape.bids[1].cumulative_amount == 5.);
+        // the fifth bid is at 95
+        assert!(shape.bids[1].distance_bps == Some(500.));
+        let venues: Vec<(&str, u32)> = (shape.bids[1].venues.iter())
+            .map(|venue| (venue.exchange.as_str(), venue.levels))
+            .collect();
+        assert!(venues == vec![("Binance", 4), ("Bitstamp", 1)]);
+        assert!(shape.asks.len() == 1 && shape.asks[0].venues[0].levels == 1);
+    }
+
+    #[test]
+    fn should_leave_the_distance_out_without_a_mid() {
+        let summary = Summary {
+            bids: vec![level("Binance", 99., 1.)],
+            ..Default::default()
+        };
+
+        let shape = book_shape(&summary, 0);
+
+        assert!(shape.bids[0].distance_bps.is_none() && shape.asks.is_empty());
+    }
+}
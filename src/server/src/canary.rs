@@ -0,0 +1,212 @@
+//! An end-to-end canary. It subscribes to the server's own `BookSummary` stream over the loopback
+//! interface, exactly like an external subscriber, and raises an alert whenever what it receives
+//! diverges from what was published: summaries missing from the sequence, summaries arriving later
+//! than the tolerated latency, or nothing arriving at all while the aggregator keeps publishing.
+
+use crate::{clock::Clock, log};
+use keyrock_challenge_proto::orderbook::{
+    orderbook_aggregator_client::OrderbookAggregatorClient, BookSummaryRequest, Summary,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::watch;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/**
+ * The url the canary reaches a listener on, the loopback address if it listens on all interfaces.
+ */
+pub fn url(listen: SocketAddr) -> String {
+    let ip = match listen.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    format!("http://{}", SocketAddr::new(ip, listen.port()))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Divergence {
+    /// the summaries between the last received and the current one never arrived
+    Gap { from: u64, to: u64 },
+    /// the summary arrived this long after it was aggregated
+    Late { sequence: u64, latency: Duration },
+    /// no summary arrived, though the one with this sequence was published after the last received
+    Stalled { published: u64 },
+}
+
+/**
+ * Compares what the canary receives with what was published.
+ */
+#[derive(Debug)]
+struct Canary {
+    max_latency: Duration,
+    last_sequence: Option<u64>,
+}
+
+impl Canary {
+    fn new(max_latency: Duration) -> Self {
+        Canary {
+            max_latency,
+            last_sequence: None,
+        }
+    }
+
+    fn receive(&mut self, summary: &Summary, now: SystemTime) -> Vec<Divergence> {
+        let mut divergences = Vec::new();
+        match self.last_sequence {
+            Some(last) if !summary.restarted && summary.sequence > last + 1 => {
+                divergences.push(Divergence::Gap {
+                    from: last + 1,
+                    to: summary.sequence - 1,
+                })
+            }
+            _ => {}
+        }
+        self.last_sequence = Some(summary.sequence);
+
+        let aggregated_at = UNIX_EPOCH + Duration::from_micros(summary.aggregated_at_us);
+        let latency = now.duration_since(aggregated_at).unwrap_or_default();
+        if summary.aggregated_at_us != 0 && latency > self.max_latency {
+            divergences.push(Divergence::Late {
+                sequence: summary.sequence,
+                latency,
+            });
+        }
+        divergences
+    }
+
+    /**
+     * Checks the latest published summary after nothing arrived for the tolerated latency.
+     */
+    fn idle(&self, published: Option<&Summary>) -> Option<Divergence> {
+        let published = published?.sequence;
+        match self.last_sequence {
+            Some(last) if published <= last => None,
+            _ => Some(Divergence::Stalled { published }),
+        }
+    }
+}
+
+fn alert(divergence: &Divergence) {
+    match divergence {
+        Divergence::Gap { from, to } => {
+            log::alert!("Canary missed the summaries {} to {}", from, to)
+        }
+        Divergence::Late { sequence, latency } => log::alert!(
+            "Canary received summary {} {}ms after its aggregation",
+            sequence,
+            latency.as_millis()
+        ),
+        Divergence::Stalled { published } => log::alert!(
+            "Canary received nothing, though summaries up to {} were published",
+            published
+        ),
+    }
+}
+
+/**
+ * Subscribes to the server behind the url for as long as it runs, comparing every summary with
+ * the published ones. Reconnects with a backoff if the stream fails, which is alerted as well.
+ */
+pub async fn run(
+    url: String,
+    published: watch::Receiver<Option<Summary>>,
+    max_latency: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    let mut canary = Canary::new(max_latency);
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let result: Result<(), Box<dyn std::error::Error + Send + Sync>> = async {
+            let mut client = OrderbookAggregatorClient::connect(url.clone()).await?;
+            let mut stream = client
+                .book_summary(BookSummaryRequest::default())
+                .await?
+                .into_inner();
+            backoff = INITIAL_BACKOFF;
+            // the subscription starts with the latest summary, nothing before it counts as missed
+            canary.last_sequence = None;
+
+            loop {
+                tokio::select! {
+                    summary = stream.message() => match summary? {
+                        Some(summary) => canary
+                            .receive(&summary, clock.system_now())
+                            .iter()
+                            .for_each(alert),
+                        None => return Ok(()),
+                    },
+                    _ = clock.sleep(max_latency) => {
+                        if let Some(divergence) = canary.idle(published.borrow().as_ref()) {
+                            alert(&divergence);
+                        }
+                    }
+                }
+            }
+        }
+        .await;
+
+        match result {
+            Ok(_) => log::alert!(
+                "Canary stream closed, reconnecting in {}ms",
+                backoff.as_millis()
+            ),
+            Err(error) => log::alert!(
+                "Canary stream failed ({}), reconnecting in {}ms",
+                error,
+                backoff.as_millis()
+            ),
+        }
+        clock.sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{url, Canary, Divergence};
+    use keyrock_challenge_proto::orderbook::Summary;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn summary(sequence: u64, aggregated_at_ms: u64) -> Summary {
+        Summary {
+            sequence,
+            aggregated_at_us: aggregated_at_ms * 1_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_detect_gaps_late_summaries_and_stalls() {
+        // Arrange
+        let mut canary = Canary::new(Duration::from_millis(100));
+        let at = |ms: u64| UNIX_EPOCH + Duration::from_millis(ms);
+
+        // Act
+        let first = canary.receive(&summary(7, 1_000), at(1_050));
+        let gap = canary.receive(&summary(10, 1_100), at(1_150));
+        let late = canary.receive(&summary(11, 1_200), at(1_350));
+        let caught_up = canary.idle(Some(&summary(11, 1_200)));
+        let stalled = canary.idle(Some(&summary(12, 1_300)));
+
+        // Assert
+        assert!(first.is_empty());
+        assert!(gap == [Divergence::Gap { from: 8, to: 9 }]);
+        assert!(
+            late == [Divergence::Late {
+                sequence: 11,
+                latency: Duration::from_millis(150)
+            }]
+        );
+        assert!(caught_up.is_none());
+        assert!(stalled == Some(Divergence::Stalled { published: 12 }));
+        assert!(url("0.0.0.0:8080".parse().unwrap()) == "http://127.0.0.1:8080");
+        assert!(url("[::1]:8080".parse().unwrap()) == "http://[::1]:8080");
+    }
+}
@@ -0,0 +1,50 @@
+//! A test sink for an [`Spmc`](crate::spmc::Spmc) recording every broadcast item in order,
+//! stamped with the time of a virtual clock. Tests can thereby assert what a pipeline published,
+//! and when, without a receiver that has to keep up or a gRPC stream.
+
+use crate::clock::Clock;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captured<T> {
+    /// the time of the clock at the broadcast, relative to the creation of the capture
+    pub at: Duration,
+    pub item: T,
+}
+
+/**
+ * Clones share the recorded items, so one can be handed to the spmc and one kept by the test.
+ */
+#[derive(Debug, Clone)]
+pub struct Capture<T> {
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    captured: Arc<Mutex<Vec<Captured<T>>>>,
+}
+
+impl<T: Clone> Capture<T> {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Capture {
+            started: clock.now(),
+            clock,
+            captured: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn record(&self, item: &T) {
+        self.captured.lock().unwrap().push(Captured {
+            at: self.clock.now().duration_since(self.started),
+            item: item.clone(),
+        });
+    }
+
+    /**
+     * Everything broadcast so far, oldest first.
+     */
+    pub fn captured(&self) -> Vec<Captured<T>> {
+        self.captured.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,95 @@
+//! The replay of a late-joining subscriber. The summaries of the recent past still held in the replay
+//! buffer are streamed first, paced like they were published but faster, followed by a marker and the
+//! live summaries, so a chart can be drawn from a single stream without gaps or duplicates.
+
+use keyrock_challenge_proto::orderbook::{CatchUpRequest, Summary};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// the longest pause between two replayed summaries, however long the market was quiet
+const MAX_PAUSE: Duration = Duration::from_secs(1);
+
+/**
+ * The buffered summaries the request asks for, from the oldest to the newest.
+ */
+pub fn replay<'a>(
+    history: impl Iterator<Item = &'a Summary>,
+    request: &CatchUpRequest,
+    now: SystemTime,
+) -> Vec<Summary> {
+    let oldest_us = match request.max_age_ms {
+        0 => 0,
+        max_age_ms => now
+            .checked_sub(Duration::from_millis(max_age_ms))
+            .and_then(|oldest| oldest.duration_since(UNIX_EPOCH).ok())
+            .map(|oldest| oldest.as_micros() as u64)
+            .unwrap_or(0),
+    };
+    let mut replay: Vec<Summary> = history
+        .filter(|summary| summary.aggregated_at_us >= oldest_us)
+        .cloned()
+        .collect();
+    if request.max_summaries != 0 {
+        let skipped = replay.len().saturating_sub(request.max_summaries as usize);
+        replay.drain(..skipped);
+    }
+    replay
+}
+
+/**
+ * How long to wait before replaying the summary following the previous one, the time between their
+ * aggregations divided by the speed.
+ */
+pub fn pause(previous: &Summary, next: &Summary, speed: u32) -> Duration {
+    if speed == 0 {
+        return Duration::ZERO;
+    }
+    let gap = next
+        .aggregated_at_us
+        .saturating_sub(previous.aggregated_at_us);
+    std::cmp::min(Duration::from_micros(gap) / speed, MAX_PAUSE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pause, replay};
+    use keyrock_challenge_proto::orderbook::{CatchUpRequest, Summary};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn summary(sequence: u64, aggregated_at_ms: u64) -> Summary {
+        Summary {
+            sequence,
+            aggregated_at_us: aggregated_at_ms * 1_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_replay_the_requested_history_at_speed() {
+        // Arrange
+        let history: Vec<Summary> = (1..=5).map(|i| summary(i, i * 1_000)).collect();
+        let now = UNIX_EPOCH + Duration::from_secs(6);
+        let request = |max_summaries: u32, max_age_ms: u64| CatchUpRequest {
+            max_summaries,
+            max_age_ms,
+            speed: 10,
+        };
+        let sequences = |replay: Vec<Summary>| -> Vec<u64> {
+            replay.iter().map(|summary| summary.sequence).collect()
+        };
+
+        // Act
+        let all = replay(history.iter(), &request(0, 0), now);
+        let newest = replay(history.iter(), &request(2, 0), now);
+        let recent = replay(history.iter(), &request(0, 3_000), now);
+        let both = replay(history.iter(), &request(1, 3_000), now);
+
+        // Assert
+        assert!(sequences(all) == [1, 2, 3, 4, 5]);
+        assert!(sequences(newest) == [4, 5]);
+        assert!(sequences(recent) == [3, 4, 5]);
+        assert!(sequences(both) == [5]);
+        assert!(pause(&history[0], &history[1], 10) == Duration::from_millis(100));
+        assert!(pause(&history[0], &history[1], 0) == Duration::ZERO);
+        assert!(pause(&summary(1, 0), &summary(2, 60_000), 2) == Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,114 @@
+//! The source of time for everything that measures ages or waits: connectors, the aggregator's
+//! staleness logic and the conflation timers. Production code runs on the [`SystemClock`], tests and
+//! replays can use a [`ManualClock`] which only moves when it is advanced.
+
+use futures::future::BoxFuture;
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::watch;
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    fn system_now(&self) -> SystemTime;
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/**
+ * A virtual clock starting at the moment it was created. Time only passes through `advance`, which
+ * also wakes every sleep that is due by then.
+ */
+#[derive(Debug)]
+pub struct ManualClock {
+    started: Instant,
+    system_started: SystemTime,
+    elapsed: watch::Sender<Duration>,
+}
+
+#[allow(dead_code)]
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            started: Instant::now(),
+            system_started: SystemTime::now(),
+            elapsed: watch::channel(Duration::ZERO).0,
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let elapsed = *self.elapsed.borrow() + by;
+        self.elapsed.send_replace(elapsed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.started + *self.elapsed.borrow()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system_started + *self.elapsed.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let mut elapsed = self.elapsed.subscribe();
+        let until = *elapsed.borrow() + duration;
+
+        Box::pin(async move {
+            while *elapsed.borrow_and_update() < until {
+                // the clock is gone, so the time will never come
+                if elapsed.changed().await.is_err() {
+                    futures::future::pending::<()>().await;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ManualClock};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn should_only_wake_sleep_once_advanced_past_it() {
+        // Arrange
+        let clock = ManualClock::new();
+        let started = clock.now();
+        let sleep = clock.sleep(Duration::from_secs(10));
+        tokio::pin!(sleep);
+
+        // Act & Assert
+        assert!(futures::poll!(&mut sleep).is_pending());
+        clock.advance(Duration::from_secs(6));
+        assert!(futures::poll!(&mut sleep).is_pending());
+        clock.advance(Duration::from_secs(4));
+        assert!(futures::poll!(&mut sleep).is_ready());
+        assert!(clock.now().duration_since(started) == Duration::from_secs(10));
+    }
+}
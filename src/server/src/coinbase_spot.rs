@@ -0,0 +1,347 @@
+//! The Coinbase connector. The level2 channel sends the full book once on subscription and then
+//! batches of changed levels, which the connector applies to its local copy of the book before
+//! handing the aggregator the best levels of it.
+
+use crate::{
+    connector_sdk::{self, ExchangeConnector, LocalBook, ReconnectPolicy, Update},
+    diagnostics::Probe,
+    exchange_status::StatusEndpoint,
+    schema::{Field, Shape},
+    trade_through::Trade,
+};
+use keyrock_challenge_core::orderbook_snapshot::{OrderbookSnapshot, Side};
+use keyrock_challenge_proto::orderbook::VenueStatus;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::Sender;
+use tungstenite::{connect, Message};
+use url::Url;
+
+const EXCHANGE: &str = "Coinbase";
+/// the level2 channel sends the full book, the best 1000 levels of it can be merged
+pub const MAX_DEPTH: usize = 1000;
+
+pub const STATUS_ENDPOINT: StatusEndpoint = StatusEndpoint {
+    exchange: EXCHANGE,
+    url: "https://status.coinbase.com/api/v2/scheduled-maintenances/active.json",
+    parse: parse_status,
+};
+
+const URL: &str = "wss://ws-feed.exchange.coinbase.com";
+
+const LEVEL: Shape = Shape::Tuple(&[Shape::Decimal, Shape::Decimal]);
+/// the side, the price and the new size of a level
+const CHANGE: Shape = Shape::Tuple(&[Shape::String, Shape::Decimal, Shape::Decimal]);
+static SNAPSHOT_SCHEMA: Shape = Shape::Object(&[
+    Field::required("type", Shape::String),
+    Field::optional("product_id", Shape::String),
+    Field::required("bids", Shape::Array(&LEVEL)),
+    Field::required("asks", Shape::Array(&LEVEL)),
+    Field::optional("time", Shape::String),
+]);
+static UPDATE_SCHEMA: Shape = Shape::Object(&[
+    Field::required("type", Shape::String),
+    Field::optional("product_id", Shape::String),
+    Field::required("changes", Shape::Array(&CHANGE)),
+    Field::optional("time", Shape::String),
+]);
+
+/**
+ * The Coinbase product of a symbol, e.g. `ETH-BTC` for `ethbtc`. A symbol without a known quote
+ * asset is passed on uppercased, for Coinbase to reject its subscription.
+ */
+fn product_id(symbol: &str) -> String {
+    match connector_sdk::split_symbol(symbol) {
+        Some((base, quote)) => format!("{}-{}", base, quote).to_uppercase(),
+        None => symbol.to_uppercase(),
+    }
+}
+
+/**
+ * Subscribes to a channel of a symbol, e.g. `level2_batch` for `ETH-BTC`.
+ */
+fn subscribe(channel: &str, symbol: &str) -> String {
+    json!({
+        "type": "subscribe",
+        "product_ids": [product_id(symbol)],
+        "channels": [channel]
+    })
+    .to_string()
+}
+
+pub fn probe(symbol: &str) -> Probe {
+    Probe {
+        exchange: EXCHANGE,
+        url: URL.to_string(),
+        subscribe: Some(subscribe("level2_batch", symbol)),
+    }
+}
+
+/**
+ * Parses the active maintenances listed on the status page, `{"scheduled_maintenances": [...]}`.
+ */
+fn parse_status(raw: &Value) -> Result<VenueStatus, ()> {
+    match raw["scheduled_maintenances"].as_array() {
+        Some(maintenances) if maintenances.is_empty() => Ok(VenueStatus::Operational),
+        Some(_) => Ok(VenueStatus::Maintenance),
+        None => Err(()),
+    }
+}
+
+/**
+ * Parses a UTC timestamp such as `"2019-08-14T20:42:27.265123Z"` into unix microseconds.
+ */
+fn parse_time_us(raw: &Value) -> Option<u64> {
+    let (date, time) = raw.as_str()?.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let micros = format!("{:0<6}", &fraction[..fraction.len().min(6)])
+        .parse::<u64>()
+        .ok()?;
+
+    // the days since the epoch in the proleptic Gregorian calendar, counted from March on so the
+    // leap day ends the year
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(seconds * 1_000_000 + micros)
+}
+
+/**
+ * The book of the subscription, built from its snapshot and kept up to date with its updates.
+ */
+#[derive(Debug, Default)]
+struct Book {
+    levels: LocalBook,
+    /// whether the snapshot arrived, updates before it have nothing to apply to
+    synced: bool,
+}
+
+impl Book {
+    /**
+     * Applies a level2 message, `{"type": "snapshot", "bids": [["0.0745", "1.5"], ...], "asks":
+     * [...]}` for the snapshot and `{"type": "l2update", "changes": [["buy", "0.0745", "0"], ...],
+     * "time": "..."}` for updates, where a size of zero removes the level. Other messages such as
+     * the subscription confirmation fail, as do updates before the snapshot. Returns the time of the
+     * message, if it has one.
+     */
+    fn update(&mut self, deserialized: &Value) -> Result<Option<u64>, ()> {
+        match deserialized["type"].as_str() {
+            Some("snapshot") => {
+                self.levels.clear();
+                for (side, raw) in [
+                    (Side::Bids, &deserialized["bids"]),
+                    (Side::Asks, &deserialized["asks"]),
+                ] {
+                    for entry in raw.as_array().ok_or(())? {
+                        self.levels.set(
+                            side,
+                            connector_sdk::parse_decimal(&entry[0])?,
+                            connector_sdk::parse_decimal(&entry[1])?,
+                        );
+                    }
+                }
+                self.synced = true;
+            }
+            Some("l2update") if self.synced => {
+                for change in deserialized["changes"].as_array().ok_or(())? {
+                    let side = match change[0].as_str() {
+                        Some("buy") => Side::Bids,
+                        Some("sell") => Side::Asks,
+                        _ => return Err(()),
+                    };
+                    self.levels.set(
+                        side,
+                        connector_sdk::parse_decimal(&change[1])?,
+                        connector_sdk::parse_decimal(&change[2])?,
+                    );
+                }
+            }
+            _ => return Err(()),
+        }
+        Ok(parse_time_us(&deserialized["time"]))
+    }
+}
+
+fn deserialize(
+    book: &mut Book,
+    deserialized: &Value,
+    depth: usize,
+) -> Result<OrderbookSnapshot, ()> {
+    let time_us = book.update(deserialized)?;
+    let mut snapshot = book
+        .levels
+        .snapshot(EXCHANGE, depth)
+        .map_err(|error| connector_sdk::reject(EXCHANGE, error))?;
+    snapshot.exchange_timestamp_us = time_us;
+    Ok(snapshot)
+}
+
+/**
+ * Parses `{"type": "match", "price": "0.0745", "size": "1.2", "side": "sell", "time": "...", ...}`,
+ * where the side is the one of the maker, i.e. a sell stands for a buy of the taker.
+ */
+fn deserialize_trade(deserialized: &Value) -> Result<Trade, ()> {
+    if deserialized["type"].as_str() != Some("match") {
+        return Err(());
+    }
+    Ok(Trade {
+        exchange: EXCHANGE,
+        price: connector_sdk::parse_decimal(&deserialized["price"])?,
+        amount: connector_sdk::parse_decimal(&deserialized["size"])?,
+        buy: deserialized["side"].as_str().ok_or(())? == "sell",
+        exchange_timestamp_us: parse_time_us(&deserialized["time"]),
+    })
+}
+
+/**
+ * The level2 channel, batched every 50 milliseconds.
+ */
+#[derive(Debug, Default)]
+struct CoinbaseConnector {
+    book: Book,
+}
+
+impl ExchangeConnector for CoinbaseConnector {
+    fn url(&self, _: &str, _: usize) -> String {
+        URL.to_string()
+    }
+
+    fn subscribe(&mut self, symbol: &str, _: usize) -> Vec<String> {
+        vec![subscribe("level2_batch", symbol)]
+    }
+
+    fn next_snapshot(&mut self, message: &Value, depth: usize) -> Update {
+        match deserialize(&mut self.book, message, depth) {
+            Ok(snapshot) => Update::Snapshot(snapshot),
+            Err(_) => Update::Ignored,
+        }
+    }
+
+    fn schema(&self, message: &Value) -> Option<&'static Shape> {
+        match message["type"].as_str() {
+            Some("snapshot") => Some(&SNAPSHOT_SCHEMA),
+            Some("l2update") => Some(&UPDATE_SCHEMA),
+            // e.g. the subscription confirmation
+            _ => None,
+        }
+    }
+}
+
+pub fn connector() -> Box<dyn ExchangeConnector> {
+    Box::<CoinbaseConnector>::default()
+}
+
+async fn run_trade_session(symbol: &str, tx: Sender<Trade>) -> Result<(), tungstenite::Error> {
+    let (mut socket, _) = connect(Url::parse(URL).unwrap())?;
+
+    socket.write_message(Message::Text(subscribe("matches", symbol)))?;
+
+    loop {
+        let msg = socket.read_message()?;
+        let content = msg.into_text()?;
+        let trade = serde_json::from_str::<Value>(&content)
+            .map_err(|_| ())
+            .and_then(|deserialized| deserialize_trade(&deserialized));
+        if let Ok(trade) = trade {
+            if tx.send(trade).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub async fn run_trades(symbol: String, tx: Sender<Trade>, policy: ReconnectPolicy) {
+    connector_sdk::run_with_reconnect(EXCHANGE, policy, || run_trade_session(&symbol, tx.clone()))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        deserialize, deserialize_trade, parse_time_us, product_id, Book, SNAPSHOT_SCHEMA,
+        UPDATE_SCHEMA,
+    };
+    use crate::schema;
+    use keyrock_challenge_core::decimal::Decimal;
+    use serde_json::json;
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn should_map_symbols_and_parse_times() {
+        assert!(product_id("ethbtc") == "ETH-BTC" && product_id("btcusdt") == "BTC-USDT");
+        assert!(parse_time_us(&json!("2019-08-14T20:42:27.265Z")) == Some(1_565_815_347_265_000));
+        assert!(
+            parse_time_us(&json!("2024-02-29T23:59:59.123456Z")) == Some(1_709_251_199_123_456)
+        );
+        assert!(parse_time_us(&json!("2024-13-01T00:00:00Z")).is_none());
+        assert!(parse_time_us(&json!("2024-01-01 00:00:00")).is_none());
+    }
+
+    #[test]
+    fn should_apply_updates_to_the_snapshot() {
+        // Arrange
+        let mut book = Book::default();
+        let snapshot = json!({
+            "type": "snapshot",
+            "product_id": "ETH-BTC",
+            "bids": [["0.0745", "1.5"], ["0.0744", "3.0"]],
+            "asks": [["0.0746", "1.0"], ["0.0747", "2.0"], ["0.0748", "2.0"]]
+        });
+        let update = json!({
+            "type": "l2update",
+            "product_id": "ETH-BTC",
+            "changes": [["sell", "0.0746", "0"], ["buy", "0.07455", "0.5"]],
+            "time": "2019-08-14T20:42:27.265Z"
+        });
+
+        // Act
+        let early = deserialize(&mut book, &update, 2);
+        let synced = deserialize(&mut book, &snapshot, 2).unwrap();
+        let updated = deserialize(&mut book, &update, 2).unwrap();
+        let subscriptions = deserialize(&mut book, &json!({"type": "subscriptions"}), 2);
+
+        // Assert
+        assert!(schema::validate(&SNAPSHOT_SCHEMA, &snapshot).is_empty());
+        assert!(schema::validate(&UPDATE_SCHEMA, &update).is_empty());
+        assert!(early.is_err() && subscriptions.is_err());
+        assert!(synced.asks.unwrap()[0].price == decimal("0.0746"));
+        assert!(synced.exchange_timestamp_us.is_none());
+        let bids = updated.bids.unwrap();
+        assert!(bids[0].price == decimal("0.07455") && bids[1].price == decimal("0.0745"));
+        assert!(bids[0].exchange == "Coinbase");
+        assert!(updated.asks.unwrap()[0].price == decimal("0.0747"));
+        assert!(updated.exchange_timestamp_us == Some(1_565_815_347_265_000));
+    }
+
+    #[test]
+    fn should_parse_trades() {
+        let trade = deserialize_trade(&json!({
+            "type": "match",
+            "price": "0.0745",
+            "size": "1.2",
+            "side": "sell",
+            "time": "2019-08-14T20:42:27.265Z"
+        }))
+        .unwrap();
+
+        assert!(trade.buy && trade.amount == decimal("1.2"));
+        assert!(deserialize_trade(&json!({"type": "last_match"})).is_err());
+    }
+}
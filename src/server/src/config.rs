@@ -0,0 +1,682 @@
+use crate::{
+    aggregator::{DEFAULT_DEPTH, DEFAULT_LEAD_TOLERANCE},
+    bandwidth::BandwidthCap,
+    config_file,
+    connector_sdk::{Heartbeat, StormLimit},
+    crossing::CrossingFilter,
+    distribution::Distribution,
+    empty_book_policy::EmptyBookPolicy,
+    enrichment::EnricherKind,
+    exchange_registry::DisplayName,
+    inbound_queue::OverloadThreshold,
+    index::Constituent,
+    lead_policy::LeadPolicy,
+    log,
+    maintenance::MaintenanceWindow,
+    memory_budget::Sizing,
+    publish_trigger::PublishTrigger,
+    quiet_period::QuietPeriod,
+    quorum::Quorum,
+    recorder::{RecordFormat, RecorderConfig, RetentionPolicy},
+    spread_smoothing::SpreadSmoothing,
+};
+use keyrock_challenge_core::{
+    merge_strategy::MergeStrategy, orderbook_snapshot::SideDepths, staleness::Staleness,
+};
+use std::{fs, path::PathBuf, str::FromStr, time::Duration};
+
+const DEFAULT_RECORD_ROTATE_MB: u64 = 256;
+const DEFAULT_RECORD_ROTATE_MINUTES: u64 = 60;
+const DEFAULT_HISTORY_RETENTION_MINUTES: u64 = 6 * 60;
+const DEFAULT_REPLAY_BUFFER: usize = 1024;
+const DEFAULT_SUBSCRIBER_QUEUE: usize = 64;
+const DEFAULT_EXCHANGE_STATUS_SECS: u64 = 60;
+const DEFAULT_AUDIT_SECS: u64 = 60;
+const DEFAULT_BOOK_SHAPE_SECS: u64 = 10;
+const DEFAULT_DRAIN_SECS: u64 = 30;
+const DEFAULT_MIN_LIVE_EXCHANGES: usize = 1;
+const DEFAULT_LISTEN: &str = "[::1]:8080";
+const DEFAULT_SYMBOL: &str = "ethbtc";
+
+/**
+ * A set of defaults for an environment, selected with `--profile` and applied before the other
+ * arguments, which can still override every single setting.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// simulated venues, plaintext gRPC and the debug stream
+    Dev,
+    /// real venues and the debug stream
+    Staging,
+    /// real venues, TLS is required, the log is written as JSON
+    Prod,
+}
+
+impl FromStr for Profile {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "dev" => Ok(Profile::Dev),
+            "staging" => Ok(Profile::Staging),
+            "prod" => Ok(Profile::Prod),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Profile {
+    fn apply(self, config: &mut Config) {
+        match self {
+            Profile::Dev => {
+                config.simulated = true;
+                config.debug_stream = true;
+            }
+            Profile::Staging => config.debug_stream = true,
+            Profile::Prod => {
+                config.require_tls = true;
+                config.log_format = log::Format::Json;
+            }
+        }
+    }
+}
+
+/**
+ * The depths of the sides of a symbol, parsed from `<symbol>=<bids>:<asks>`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolDepths {
+    pub symbol: String,
+    pub depths: SideDepths,
+}
+
+impl FromStr for SymbolDepths {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (symbol, depths) = raw.split_once('=').ok_or(())?;
+        if symbol.is_empty() {
+            return Err(());
+        }
+        Ok(SymbolDepths {
+            symbol: symbol.to_lowercase(),
+            depths: depths.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// the trading pairs aggregated, each in a pipeline of its own, the first one is the default
+    pub symbols: Vec<String>,
+    /// the least severe messages logged
+    pub log_level: log::Level,
+    /// how the log is written, only applied on startup
+    pub log_format: log::Format,
+    /// the configuration file passed with `--config`, reloaded whenever it changes
+    pub config_file: Option<PathBuf>,
+    /// address the gRPC server listens on
+    pub listen: String,
+    /// address serving the summaries for external redistribution, disabled if None
+    pub external_listen: Option<String>,
+    /// bind the listeners with SO_REUSEPORT, so a successor can take them over
+    pub reuse_port: bool,
+    /// admin url of the server whose listeners this one takes over, drained once this one is ready
+    pub take_over: Option<String>,
+    /// how long a drained server keeps feeding its subscribers before it exits
+    pub drain_timeout: Duration,
+    /// how the summaries are reduced for the external listener
+    pub distribution: Distribution,
+    /// serve gRPC over TLS, plaintext if None
+    pub tls: Option<TlsConfig>,
+    /// refuse to start without TLS
+    pub require_tls: bool,
+    /// feed the aggregator from simulated venues instead of the real exchanges
+    pub simulated: bool,
+    /// serve the `OrderbookDebug` service streaming per-tick stage timings
+    pub debug_stream: bool,
+    /// subscribe to the trade streams of the exchanges to detect trade-throughs
+    pub trade_throughs: bool,
+    /// the symbols of the weighted index and their weights, no index is published without any
+    pub index: Vec<Constituent>,
+    /// how many exchanges have to be live before serving, the others are retried in the background
+    pub min_live_exchanges: usize,
+    /// exchanges whose connectors run but which are left out of the published aggregation
+    pub excluded_exchanges: Vec<String>,
+    /// the exchanges to aggregate, all of the registry if empty
+    pub exchanges: Vec<String>,
+    /// known maintenance windows during which an exchange is excluded and not reconnected
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    /// the labels exchanges are published with instead of their internal names
+    pub display_names: Vec<DisplayName>,
+    /// publish the hash of the raw exchange message each level was read from
+    pub expose_provenance: bool,
+    /// quarantine the exchange messages deviating from the shape their connector expects
+    pub strict_schema: bool,
+    /// how many reconnects in which time make a connector cool down for how long
+    pub reconnect_storm: StormLimit,
+    /// after how long a silent exchange stream is pinged, and dropped as dead
+    pub heartbeat: Heartbeat,
+    /// relay the summaries of this upstream server instead of aggregating the exchanges
+    pub upstream: Option<String>,
+    /// how many published summaries are kept to be replayed to resuming subscribers
+    pub replay_buffer: usize,
+    /// how many summaries may queue up for each subscriber
+    pub subscriber_queue: usize,
+    /// file the last published sequence number is persisted to
+    pub sequence_file: Option<PathBuf>,
+    /// upper bound of the delay applied to the faster venue to time-align it with the slower one
+    pub lead_compensation_window: Duration,
+    /// levels per side the venues' books are merged from and the summaries are published with
+    pub depth: usize,
+    /// symbols published with a depth of their own for each side instead of the depth
+    pub side_depths: Vec<SymbolDepths>,
+    /// levels per side the venues deliver, the depth can be raised up to it at runtime
+    pub max_depth: Option<usize>,
+    /// how venues with an empty side are merged
+    pub empty_book_policy: EmptyBookPolicy,
+    /// what is published while the stream of a venue leads the others beyond the lead tolerance
+    pub lead_policy: LeadPolicy,
+    /// the number of updates in a row a stream may lead the others by before the lead policy applies
+    pub lead_tolerance: usize,
+    /// how the venues' ladders are merged into the published summary
+    pub merge_strategy: MergeStrategy,
+    /// how long without an update the books of a venue are still merged
+    pub staleness: Staleness,
+    /// when a summary is published
+    pub publish_trigger: PublishTrigger,
+    /// how many venues have to be fresh for summaries to be published instead of heartbeats
+    pub quorum: Option<Quorum>,
+    /// daily periods during which nothing or only heartbeats are published
+    pub quiet_periods: Vec<QuietPeriod>,
+    /// merged alongside for comparison on the debug stream, disabled if None
+    pub shadow_merge_strategy: Option<MergeStrategy>,
+    /// plugins adding their extensions to every published summary
+    pub enrichers: Vec<EnricherKind>,
+    /// smoothing applied to the published spread, disabled if None
+    pub spread_smoothing: Option<SpreadSmoothing>,
+    /// record the published summaries, disabled if None
+    pub recorder: Option<RecorderConfig>,
+    /// how far back the spread history and candles reach
+    pub history_retention: Duration,
+    /// recording directories loaded into the history at startup
+    pub backfill_dirs: Vec<PathBuf>,
+    /// how far back the most recent recordings are loaded into the history and the replay buffers
+    /// at startup, not at all if None
+    pub warm_start: Option<Duration>,
+    /// how large and long lasting a cross of the merged book has to be to be reported
+    pub crossing_filter: CrossingFilter,
+    /// how long a connector's queue towards the aggregator may stay how deep before it is conflated
+    pub overload: OverloadThreshold,
+    /// summaries per second handed to subscribers, shared fairly by the symbols, unlimited if None
+    pub fan_out_rate: Option<u32>,
+    /// how often the exchanges' system status APIs are polled, never if zero
+    pub exchange_status_interval: Duration,
+    /// how often the book-consistency self-audit runs, never if zero
+    pub audit_interval: Duration,
+    /// how often the shape of the merged books is sampled, never if zero
+    pub book_shape_interval: Duration,
+    /// how late a summary may reach the loopback canary subscriber, no canary runs if None
+    pub canary_latency: Option<Duration>,
+    /// file the drop journal is appended to, kept in memory only if None
+    pub journal_file: Option<PathBuf>,
+    /// bytes held by buffers and history above which load is shed, unlimited if None
+    pub memory_watermark: Option<usize>,
+    /// bytes the replay buffer, the subscriber queues and the history are sized to, if set
+    pub memory_budget: Option<usize>,
+    /// bytes per second each subscriber identity may receive, unlimited if None
+    pub default_bandwidth_cap: Option<u64>,
+    /// caps of individual identities, taking precedence over the default
+    pub bandwidth_caps: Vec<BandwidthCap>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            symbols: vec![DEFAULT_SYMBOL.to_string()],
+            log_level: log::Level::default(),
+            log_format: log::Format::default(),
+            config_file: None,
+            listen: DEFAULT_LISTEN.to_string(),
+            external_listen: None,
+            reuse_port: false,
+            take_over: None,
+            drain_timeout: Duration::from_secs(DEFAULT_DRAIN_SECS),
+            distribution: Distribution::default(),
+            tls: None,
+            require_tls: false,
+            simulated: false,
+            trade_throughs: false,
+            index: Vec::new(),
+            debug_stream: false,
+            min_live_exchanges: DEFAULT_MIN_LIVE_EXCHANGES,
+            excluded_exchanges: Vec::new(),
+            exchanges: Vec::new(),
+            maintenance_windows: Vec::new(),
+            display_names: Vec::new(),
+            expose_provenance: false,
+            strict_schema: false,
+            reconnect_storm: StormLimit::default(),
+            heartbeat: Heartbeat::default(),
+            upstream: None,
+            replay_buffer: DEFAULT_REPLAY_BUFFER,
+            subscriber_queue: DEFAULT_SUBSCRIBER_QUEUE,
+            sequence_file: None,
+            lead_compensation_window: Duration::ZERO,
+            depth: DEFAULT_DEPTH,
+            side_depths: Vec::new(),
+            max_depth: None,
+            empty_book_policy: EmptyBookPolicy::default(),
+            lead_policy: LeadPolicy::default(),
+            lead_tolerance: DEFAULT_LEAD_TOLERANCE,
+            merge_strategy: MergeStrategy::default(),
+            staleness: Staleness::default(),
+            publish_trigger: PublishTrigger::default(),
+            quorum: None,
+            quiet_periods: Vec::new(),
+            shadow_merge_strategy: None,
+            enrichers: Vec::new(),
+            spread_smoothing: None,
+            recorder: None,
+            history_retention: Duration::from_secs(DEFAULT_HISTORY_RETENTION_MINUTES * 60),
+            backfill_dirs: Vec::new(),
+            warm_start: None,
+            crossing_filter: CrossingFilter::default(),
+            overload: OverloadThreshold::default(),
+            fan_out_rate: None,
+            exchange_status_interval: Duration::from_secs(DEFAULT_EXCHANGE_STATUS_SECS),
+            audit_interval: Duration::from_secs(DEFAULT_AUDIT_SECS),
+            book_shape_interval: Duration::from_secs(DEFAULT_BOOK_SHAPE_SECS),
+            canary_latency: None,
+            journal_file: None,
+            memory_watermark: None,
+            memory_budget: None,
+            default_bandwidth_cap: None,
+            bandwidth_caps: Vec::new(),
+        }
+    }
+}
+
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, String> {
+    args.next()
+        .and_then(|raw| raw.parse::<T>().ok())
+        .ok_or_else(|| format!("{} requires a valid value", flag))
+}
+
+impl Config {
+    /**
+     * The configuration of the command line, panics if it is invalid, as on startup.
+     */
+    pub fn from_args() -> Config {
+        Config::try_from_args().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /**
+     * The configuration of the command line, or why it is invalid, e.g. to keep the running one
+     * when a reloaded configuration file is.
+     */
+    pub fn try_from_args() -> Result<Config, String> {
+        Config::parse(std::env::args().skip(1).collect())
+    }
+
+    /**
+     * The depths a symbol is published with, its side depths if configured, the depth otherwise.
+     */
+    pub fn depths_of(&self, symbol: &str) -> SideDepths {
+        self.side_depths
+            .iter()
+            .find(|side_depths| side_depths.symbol == symbol)
+            .map(|side_depths| side_depths.depths)
+            .unwrap_or(SideDepths::symmetric(self.depth))
+    }
+
+    /**
+     * Puts the arguments of the configuration file passed with `--config` ahead of the others, so
+     * those on the command line take precedence.
+     */
+    fn with_config_file(raw_args: Vec<String>) -> Result<Vec<String>, String> {
+        let path = match raw_args.iter().position(|arg| arg == "--config") {
+            Some(index) => {
+                value::<PathBuf>(&mut raw_args.iter().skip(index + 1).cloned(), "--config")?
+            }
+            None => return Ok(raw_args),
+        };
+        let mut args = fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|raw| config_file::args(&raw))
+            .map_err(|error| {
+                format!(
+                    "Unable to read the config file {}: {}",
+                    path.display(),
+                    error
+                )
+            })?;
+        args.extend(raw_args);
+        Ok(args)
+    }
+
+    fn parse(raw_args: Vec<String>) -> Result<Config, String> {
+        let raw_args = Config::with_config_file(raw_args)?;
+        let mut config = Config::default();
+        let profile = raw_args
+            .iter()
+            .position(|arg| arg == "--profile")
+            .map(|index| {
+                value::<Profile>(&mut raw_args.iter().skip(index + 1).cloned(), "--profile")
+            })
+            .transpose()?;
+        if let Some(profile) = profile {
+            profile.apply(&mut config);
+        }
+
+        let mut record_dir: Option<PathBuf> = None;
+        let mut record_rotate_mb = DEFAULT_RECORD_ROTATE_MB;
+        let mut record_rotate_minutes = DEFAULT_RECORD_ROTATE_MINUTES;
+        let mut retention = RetentionPolicy::default();
+        let mut record_format = RecordFormat::default();
+        let mut tls_cert: Option<PathBuf> = None;
+        let mut tls_key: Option<PathBuf> = None;
+        // set explicitly, these take precedence over the sizing from the memory budget
+        let mut replay_buffer: Option<usize> = None;
+        let mut subscriber_queue: Option<usize> = None;
+        let mut history_retention: Option<Duration> = None;
+        let mut symbols: Vec<String> = Vec::new();
+
+        let mut args = raw_args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // already applied before all other arguments
+                "--profile" => {
+                    value::<Profile>(&mut args, &arg)?;
+                }
+                // its arguments were put ahead of the others
+                "--config" => config.config_file = Some(value(&mut args, &arg)?),
+                "--log-level" => config.log_level = value(&mut args, &arg)?,
+                "--log-format" => config.log_format = value(&mut args, &arg)?,
+                "--symbol" => symbols.push(value::<String>(&mut args, &arg)?.to_lowercase()),
+                "--listen" => config.listen = value(&mut args, &arg)?,
+                "--external-listen" => config.external_listen = Some(value(&mut args, &arg)?),
+                "--reuse-port" => config.reuse_port = true,
+                "--take-over" => {
+                    config.take_over = Some(value(&mut args, &arg)?);
+                    // the listeners are only shared if both servers bind them with SO_REUSEPORT
+                    config.reuse_port = true;
+                }
+                "--drain-secs" => {
+                    config.drain_timeout = Duration::from_secs(value(&mut args, &arg)?)
+                }
+                "--external-depth" => config.distribution.depth = value(&mut args, &arg)?,
+                "--external-amount-decimals" => {
+                    config.distribution.amount_decimals = value(&mut args, &arg)?
+                }
+                "--external-delay-ms" => {
+                    config.distribution.delay = Duration::from_millis(value(&mut args, &arg)?)
+                }
+                "--tls-cert" => tls_cert = Some(value(&mut args, &arg)?),
+                "--tls-key" => tls_key = Some(value(&mut args, &arg)?),
+                "--simulated" => config.simulated = true,
+                "--debug-stream" => config.debug_stream = true,
+                "--trade-throughs" => config.trade_throughs = true,
+                "--index" => config.index.push(value(&mut args, &arg)?),
+                "--min-live-exchanges" => config.min_live_exchanges = value(&mut args, &arg)?,
+                "--exclude" => config.excluded_exchanges.push(value(&mut args, &arg)?),
+                "--exchange" => config.exchanges.push(value(&mut args, &arg)?),
+                "--maintenance" => config.maintenance_windows.push(value(&mut args, &arg)?),
+                "--display-name" => config.display_names.push(value(&mut args, &arg)?),
+                "--expose-provenance" => config.expose_provenance = true,
+                "--strict-schema" => config.strict_schema = true,
+                "--reconnect-storm-max" => {
+                    config.reconnect_storm.max_reconnects = value(&mut args, &arg)?
+                }
+                "--reconnect-storm-minutes" => {
+                    config.reconnect_storm.window =
+                        Duration::from_secs(value::<u64>(&mut args, &arg)? * 60)
+                }
+                "--reconnect-cool-down-minutes" => {
+                    config.reconnect_storm.cool_down =
+                        Duration::from_secs(value::<u64>(&mut args, &arg)? * 60)
+                }
+                "--heartbeat-ping-secs" => {
+                    config.heartbeat.ping_after = Duration::from_secs(value(&mut args, &arg)?)
+                }
+                "--heartbeat-timeout-secs" => {
+                    config.heartbeat.dead_after = Duration::from_secs(value(&mut args, &arg)?)
+                }
+                "--lead-compensation-ms" => {
+                    config.lead_compensation_window = Duration::from_millis(value(&mut args, &arg)?)
+                }
+                "--depth" => config.depth = value(&mut args, &arg)?,
+                "--side-depths" => config.side_depths.push(value(&mut args, &arg)?),
+                "--max-depth" => config.max_depth = Some(value(&mut args, &arg)?),
+                "--empty-book" => config.empty_book_policy = value(&mut args, &arg)?,
+                "--lead-policy" => config.lead_policy = value(&mut args, &arg)?,
+                "--lead-tolerance" => config.lead_tolerance = value(&mut args, &arg)?,
+                "--merge" => config.merge_strategy = value(&mut args, &arg)?,
+                "--stale-after-ms" => {
+                    config.staleness.default_timeout =
+                        Some(Duration::from_millis(value(&mut args, &arg)?))
+                }
+                "--stale-after" => config.staleness.timeouts.push(value(&mut args, &arg)?),
+                "--publish-on" => config.publish_trigger = value(&mut args, &arg)?,
+                "--quorum" => config.quorum = Some(value(&mut args, &arg)?),
+                "--quiet-period" => config.quiet_periods.push(value(&mut args, &arg)?),
+                "--shadow-merge" => {
+                    config.shadow_merge_strategy = Some(value(&mut args, &arg)?);
+                    // the comparisons are only published on the debug stream
+                    config.debug_stream = true;
+                }
+                "--enrich" => config.enrichers.push(value(&mut args, &arg)?),
+                "--spread-smoothing" => config.spread_smoothing = Some(value(&mut args, &arg)?),
+                "--upstream" => config.upstream = Some(value(&mut args, &arg)?),
+                "--replay-buffer" => replay_buffer = Some(value(&mut args, &arg)?),
+                "--subscriber-queue" => subscriber_queue = Some(value(&mut args, &arg)?),
+                "--sequence-file" => config.sequence_file = Some(value(&mut args, &arg)?),
+                "--record-dir" => record_dir = Some(value(&mut args, &arg)?),
+                "--record-format" => record_format = value(&mut args, &arg)?,
+                "--record-rotate-mb" => record_rotate_mb = value(&mut args, &arg)?,
+                "--record-rotate-minutes" => record_rotate_minutes = value(&mut args, &arg)?,
+                "--record-retention-days" => {
+                    retention.max_age = Some(Duration::from_secs(
+                        value::<u64>(&mut args, &arg)? * 24 * 60 * 60,
+                    ))
+                }
+                "--record-retention-gb" => {
+                    retention.max_total_bytes =
+                        Some(value::<u64>(&mut args, &arg)? * 1024 * 1024 * 1024)
+                }
+                "--record-archive-dir" => retention.archive_dir = Some(value(&mut args, &arg)?),
+                "--history-retention-minutes" => {
+                    history_retention =
+                        Some(Duration::from_secs(value::<u64>(&mut args, &arg)? * 60))
+                }
+                "--backfill-dir" => config.backfill_dirs.push(value(&mut args, &arg)?),
+                "--warm-start-minutes" => {
+                    config.warm_start =
+                        Some(Duration::from_secs(value::<u64>(&mut args, &arg)? * 60))
+                }
+                "--overload" => config.overload = value(&mut args, &arg)?,
+                "--fan-out-rate" => config.fan_out_rate = Some(value(&mut args, &arg)?),
+                "--cross-min-bps" => config.crossing_filter.min_bps = value(&mut args, &arg)?,
+                "--cross-min-ms" => {
+                    config.crossing_filter.min_duration =
+                        Duration::from_millis(value(&mut args, &arg)?)
+                }
+                "--exchange-status-secs" => {
+                    config.exchange_status_interval = Duration::from_secs(value(&mut args, &arg)?)
+                }
+                "--audit-secs" => {
+                    config.audit_interval = Duration::from_secs(value(&mut args, &arg)?)
+                }
+                "--book-shape-secs" => {
+                    config.book_shape_interval = Duration::from_secs(value(&mut args, &arg)?)
+                }
+                "--canary-ms" => {
+                    config.canary_latency = Some(Duration::from_millis(value(&mut args, &arg)?))
+                }
+                "--bandwidth-cap-kb" => {
+                    config.default_bandwidth_cap = Some(value::<u64>(&mut args, &arg)? * 1024)
+                }
+                "--bandwidth-cap" => config.bandwidth_caps.push(value(&mut args, &arg)?),
+                "--journal-file" => config.journal_file = Some(value(&mut args, &arg)?),
+                "--memory-watermark-mb" => {
+                    config.memory_watermark = Some(value::<usize>(&mut args, &arg)? * 1024 * 1024)
+                }
+                "--memory-budget-mb" => {
+                    config.memory_budget = Some(value::<usize>(&mut args, &arg)? * 1024 * 1024)
+                }
+                _ => return Err(format!("Unknown argument '{}'", arg)),
+            }
+        }
+
+        for (index, symbol) in symbols.iter().enumerate() {
+            if symbol.is_empty() || !symbol.chars().all(|char| char.is_ascii_alphanumeric()) {
+                return Err(format!("Invalid symbol '{}'", symbol));
+            }
+            if symbols[..index].contains(symbol) {
+                return Err(format!("Symbol '{}' passed twice", symbol));
+            }
+        }
+        if !symbols.is_empty() {
+            config.symbols = symbols;
+        }
+        for side_depths in &config.side_depths {
+            if !config.symbols.contains(&side_depths.symbol) {
+                return Err(format!(
+                    "Side depths for unknown symbol '{}'",
+                    side_depths.symbol
+                ));
+            }
+        }
+        for (index, constituent) in config.index.iter().enumerate() {
+            if config.index[..index]
+                .iter()
+                .any(|other| other.symbol == constituent.symbol)
+            {
+                return Err(format!(
+                    "Index constituent '{}' passed twice",
+                    constituent.symbol
+                ));
+            }
+        }
+
+        let sizing = config.memory_budget.map(Sizing::from_budget);
+        config.replay_buffer = replay_buffer
+            .or(sizing.map(|sizing| sizing.replay_buffer))
+            .unwrap_or(config.replay_buffer);
+        config.subscriber_queue = subscriber_queue
+            .or(sizing.map(|sizing| sizing.subscriber_queue))
+            .unwrap_or(config.subscriber_queue);
+        config.history_retention = history_retention
+            .or(sizing.map(|sizing| sizing.history_retention))
+            .unwrap_or(config.history_retention);
+        // the budget is kept in steady state as well
+        config.memory_watermark = config.memory_watermark.or(config.memory_budget);
+
+        config.recorder = record_dir.map(|dir| RecorderConfig {
+            dir,
+            rotate_bytes: record_rotate_mb * 1024 * 1024,
+            rotate_after: Duration::from_secs(record_rotate_minutes * 60),
+            retention,
+            format: record_format,
+        });
+        config.tls = match (tls_cert, tls_key) {
+            (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+            (None, None) => None,
+            _ => return Err("--tls-cert and --tls-key have to be passed together".to_string()),
+        };
+        if config.warm_start.is_some() && config.recorder.is_none() {
+            return Err(
+                "--warm-start-minutes loads the recordings of --record-dir, pass one".to_string(),
+            );
+        }
+        if config.require_tls && config.tls.is_none() {
+            return Err("TLS is required, pass --tls-cert and --tls-key".to_string());
+        }
+        if config.heartbeat.ping_after.is_zero()
+            || config.heartbeat.dead_after < config.heartbeat.ping_after
+        {
+            return Err(
+                "--heartbeat-ping-secs has to be positive and at most --heartbeat-timeout-secs"
+                    .to_string(),
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    fn args(raw: &str) -> Vec<String> {
+        raw.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn should_apply_profile_before_other_arguments() {
+        let dev = Config::parse(args("--exclude Binance --profile dev")).unwrap();
+        assert!(dev.simulated && dev.debug_stream && dev.excluded_exchanges == ["Binance"]);
+
+        let prod = Config::parse(args(
+            "--profile prod --simulated --tls-cert cert.pem --tls-key key.pem",
+        ))
+        .unwrap();
+        assert!(prod.simulated && !prod.debug_stream && prod.tls.is_some());
+        assert!(prod.log_format == crate::log::Format::Json);
+        assert!(dev.log_format == crate::log::Format::Pretty);
+
+        assert!(Config::parse(args("--profile prod")).is_err());
+    }
+
+    #[test]
+    fn should_read_config_file_before_other_arguments() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "symbol = [\"ethbtc\", \"btcusdt\"]\ndepth = 5\nlog_level = \"alert\"\n\
+             [reconnect_storm]\nmax = 3\n",
+        )
+        .unwrap();
+
+        // Act
+        let config =
+            Config::parse(args(&format!("--config {} --depth 7", path.display()))).unwrap();
+        let missing = Config::parse(args("--config missing.toml"));
+        std::fs::remove_file(&path).unwrap();
+
+        // Assert
+        assert!(config.symbols == ["ethbtc", "btcusdt"] && config.depth == 7);
+        assert!(config.log_level == crate::log::Level::Alert);
+        assert!(config.reconnect_storm.max_reconnects == 3);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn should_parse_symbols() {
+        assert!(Config::parse(args("")).unwrap().symbols == ["ethbtc"]);
+        let config = Config::parse(args("--symbol BTCUSDT --symbol ethbtc")).unwrap();
+        assert!(config.symbols == ["btcusdt", "ethbtc"]);
+        assert!(Config::parse(args("--symbol eth/btc")).is_err());
+        assert!(Config::parse(args("--symbol ethbtc --symbol ETHBTC")).is_err());
+    }
+
+    #[test]
+    fn should_parse_side_depths_per_symbol() {
+        let config = Config::parse(args(
+            "--symbol ethbtc --symbol btcusdt --depth 5 --side-depths BTCUSDT=25:10",
+        ))
+        .unwrap();
+        assert!(config.depths_of("btcusdt").bids == 25 && config.depths_of("btcusdt").asks == 10);
+        assert!(config.depths_of("ethbtc").bids == 5 && config.depths_of("ethbtc").asks == 5);
+        assert!(Config::parse(args("--side-depths xrpusdt=5:5")).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! The configuration file passed with `--config <path>`, written in a subset of TOML. Every key
+//! stands for the command line flag of the same name with dashes instead of underscores, prefixed
+//! with the table it is in, so the file accepts exactly what the command line does:
+//!
+//! ```toml
+//! symbol = ["ethbtc", "btcusdt"]
+//! exchange = ["Binance", "Bitstamp"]
+//! depth = 10
+//! listen = "0.0.0.0:50051"
+//! lead_compensation_ms = 20
+//! log_level = "warning"
+//! debug_stream = true
+//!
+//! [reconnect_storm]
+//! max = 5
+//! ```
+//!
+//! An array repeats the flag for each of its values, `true` passes a flag without a value and
+//! `false` leaves it out. Arrays of tables, inline tables, multi-line values and commas within the
+//! strings of an array are not supported.
+
+/**
+ * Parses a scalar value, a quoted string, a number or a bare word.
+ */
+fn scalar(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    match raw.strip_prefix('"') {
+        Some(quoted) => match quoted.strip_suffix('"') {
+            Some(string) if !string.contains('"') => Ok(string.to_string()),
+            _ => Err(format!("Unterminated string {}", raw)),
+        },
+        None if raw.is_empty() || raw.contains(char::is_whitespace) => {
+            Err(format!("Invalid value '{}'", raw))
+        }
+        None => Ok(raw.to_string()),
+    }
+}
+
+/**
+ * Drops a comment, unless the `#` is part of a string.
+ */
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, char) in line.char_indices() {
+        match char {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/**
+ * The command line arguments the file stands for, or why it could not be read, with its line.
+ */
+pub fn args(raw: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut table = String::new();
+
+    for (index, line) in raw.lines().enumerate() {
+        let invalid = |error: String| format!("line {}: {}", index + 1, error);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            table = name
+                .strip_suffix(']')
+                .filter(|name| !name.is_empty() && !name.contains(['[', ']']))
+                .ok_or_else(|| invalid(format!("Invalid table {}", line)))?
+                .trim()
+                .to_string();
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("Expected <key> = <value>, got {}", line)))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid(format!("Invalid key '{}'", key)));
+        }
+        let flag = match table.as_str() {
+            "" => format!("--{}", key),
+            table => format!("--{}_{}", table, key),
+        }
+        .replace('_', "-");
+
+        let value = value.trim();
+        let values = match value.strip_prefix('[') {
+            Some(array) => {
+                let array = array
+                    .strip_suffix(']')
+                    .ok_or_else(|| invalid(format!("Unterminated array {}", value)))?;
+                array
+                    .split(',')
+                    .map(str::trim)
+                    // a trailing comma is allowed
+                    .filter(|item| !item.is_empty())
+                    .map(scalar)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(invalid)?
+            }
+            None => vec![scalar(value).map_err(invalid)?],
+        };
+        for value in values {
+            match value.as_str() {
+                "true" => args.push(flag.clone()),
+                "false" => {}
+                _ => args.extend([flag.clone(), value]),
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::args;
+
+    #[test]
+    fn should_translate_keys_into_flags() {
+        // Arrange
+        let raw = r#"
+            # the pairs to aggregate
+            symbol = ["ethbtc", "BTCUSDT",]
+            depth = 10 # per side
+            listen = "0.0.0.0:50051"
+            debug_stream = true
+            simulated = false
+            display_name = "Binance=BIN#1"
+
+            [reconnect_storm]
+            max = 5
+        "#;
+
+        // Act
+        let args = args(raw).unwrap();
+
+        // Assert
+        let expected = "--symbol ethbtc --symbol BTCUSDT --depth 10 --listen 0.0.0.0:50051 \
+            --debug-stream --display-name Binance=BIN#1 --reconnect-storm-max 5";
+        assert!(args == expected.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn should_reject_malformed_lines() {
+        assert!(args("depth").unwrap_err().starts_with("line 1"));
+        assert!(args("\nlisten = \"0.0.0.0")
+            .unwrap_err()
+            .starts_with("line 2"));
+        assert!(args("symbol = [\"ethbtc\"").is_err());
+        assert!(args("depth = 10 levels").is_err());
+        assert!(args("[]\ndepth = 10").is_err());
+        assert!(args("log-level = \"alert\"").is_err());
+    }
+}
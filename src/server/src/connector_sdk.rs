@@ -0,0 +1,856 @@
+//! Building blocks shared by the exchange connectors.
+//!
+//! A connector usually consists of a `deserialize` function turning a raw websocket payload into an
+//! [`OrderbookSnapshot`], a session that connects, subscribes and feeds the aggregator, and a
+//! `run_stream` entry point that keeps the session alive. This module provides the pieces that are
+//! identical for every venue:
+//!
+//! - normalization helpers ([`parse_snapshot`], [`parse_levels`], [`parse_decimal`]) converting the
+//!   usual `[["price", "amount"], ...]` JSON ladders into exact levels and validated snapshots
+//! - a [`LocalBook`] for venues streaming only the changed levels after an initial snapshot
+//! - [`split_symbol`] for venues naming their pairs differently, e.g. `ETH-BTC` for `ethbtc`
+//! - a reconnect scaffold ([`run_with_reconnect`]) re-running a session with a capped backoff,
+//!   holding off during known maintenance windows and cooling down during reconnect storms
+//! - a [`Heartbeat`] pinging silent streams and failing those that stay silent, so a dead
+//!   connection is reconnected instead of waiting minutes for the TCP timeout
+//! - a [`SequenceTracker`] to detect stale or missed messages based on the venue's update ids
+//! - the [`Shape`] of the book messages, which the payloads are validated against in strict schema
+//!   mode
+//!
+//! New connectors can be stubbed with `cargo xtask new-connector <name>`.
+
+use crate::{
+    aggregator::Aggregator,
+    clock::{self, Clock},
+    exchange_registry,
+    journal::Journal,
+    log,
+    maintenance::{self, MaintenanceWindow},
+    schema::{self, Quarantine, Shape},
+    stage_timings,
+};
+use keyrock_challenge_core::{
+    decimal::Decimal,
+    orderbook_snapshot::{BookLevel, OrderbookSnapshot, Side, SnapshotBuilder, SnapshotError},
+};
+use keyrock_challenge_proto::orderbook::{DropReason, TickTimings};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    io,
+    net::TcpStream,
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+use url::Url;
+
+/**
+ * Parses a JSON number that is either encoded as a string (`"0.0745"`) or as a plain number.
+ * Strings are taken exactly unless they use an exponent, numbers as the float they denote.
+ */
+pub fn parse_decimal(raw: &Value) -> Result<Decimal, ()> {
+    match raw {
+        Value::String(string) => string.parse::<Decimal>().or_else(|_| {
+            string
+                .parse::<f64>()
+                .map_err(|_| ())
+                .and_then(Decimal::try_from)
+        }),
+        Value::Number(number) => number.as_f64().ok_or(()).and_then(Decimal::try_from),
+        _ => Err(()),
+    }
+}
+
+/**
+ * Converts a JSON ladder of `[price, amount]` pairs into at most the first `depth` levels.
+ * Fails if the ladder is malformed.
+ */
+#[allow(dead_code)] // used by connectors stubbed with `cargo xtask new-connector`
+pub fn parse_levels(exchange: &str, raw: &Value, depth: usize) -> Result<Vec<BookLevel>, ()> {
+    let entries = raw.as_array().ok_or(())?;
+    let exchange_id = exchange_registry::exchange_id(exchange) as i32;
+
+    entries
+        .iter()
+        .take(depth)
+        .map(|entry| {
+            Ok(BookLevel {
+                exchange: exchange.to_string(),
+                price: parse_decimal(&entry[0])?,
+                amount: parse_decimal(&entry[1])?,
+                exchange_id,
+                contributors: Vec::new(),
+                provenance: 0,
+            })
+        })
+        .collect()
+}
+
+/**
+ * Builds a snapshot out of the raw bid and ask ladders of a venue, validated by the
+ * [`SnapshotBuilder`]. An empty ladder results in a missing side.
+ */
+#[allow(dead_code)] // used by connectors stubbed with `cargo xtask new-connector`
+pub fn parse_snapshot(
+    exchange: &str,
+    bids: &Value,
+    asks: &Value,
+    depth: usize,
+) -> Result<OrderbookSnapshot, SnapshotError> {
+    let parse_side = |side: Side, raw: &Value| {
+        parse_levels(exchange, raw, depth).map_err(|_| SnapshotError::Malformed(side))
+    };
+
+    SnapshotBuilder::new()
+        .bids(parse_side(Side::Bids, bids)?)
+        .asks(parse_side(Side::Asks, asks)?)
+        .build(depth)
+}
+
+/**
+ * A short hash of a raw exchange message, the 64-bit FNV-1a of its bytes. Attached to the levels
+ * read from the message, it locates the exact frame in a capture of the venue's stream when a
+ * published price is questioned.
+ */
+pub fn provenance(raw: &str) -> u64 {
+    raw.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/**
+ * Logs why a snapshot of the exchange was dropped.
+ */
+pub fn reject(exchange: &str, error: SnapshotError) {
+    log::warning!("Dropped invalid {} snapshot: {}", exchange, error);
+}
+
+/// the quote assets a symbol may end in, `usdt` before `usd` so it is not taken for the latter
+const QUOTES: [&str; 7] = ["usdt", "usdc", "usd", "eur", "gbp", "btc", "eth"];
+
+/**
+ * Splits a symbol into its base and quote asset, e.g. `ethbtc` into `eth` and `btc`. None if the
+ * symbol does not end in a known quote asset.
+ */
+pub fn split_symbol(symbol: &str) -> Option<(&str, &str)> {
+    QUOTES
+        .iter()
+        .find(|quote| symbol.len() > quote.len() && symbol.ends_with(*quote))
+        .map(|quote| symbol.split_at(symbol.len() - quote.len()))
+}
+
+/**
+ * The book of a venue which sends it once and only the changed levels afterwards, kept up to date
+ * by applying them. Snapshots of its best levels are handed to the aggregator.
+ */
+#[derive(Debug, Default)]
+pub struct LocalBook {
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl LocalBook {
+    pub fn new() -> Self {
+        LocalBook::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    fn side(&mut self, side: Side) -> &mut BTreeMap<Decimal, Decimal> {
+        match side {
+            Side::Bids => &mut self.bids,
+            Side::Asks => &mut self.asks,
+        }
+    }
+
+    /**
+     * Sets the amount of a level, an amount of zero removes it.
+     */
+    pub fn set(&mut self, side: Side, price: Decimal, amount: Decimal) {
+        let levels = self.side(side);
+        if amount == Decimal::ZERO {
+            levels.remove(&price);
+        } else {
+            levels.insert(price, amount);
+        }
+    }
+
+    /**
+     * The best `count` levels of a side as price and amount, best first.
+     */
+    pub fn best(&self, side: Side, count: usize) -> Vec<(Decimal, Decimal)> {
+        let level = |(price, amount): (&Decimal, &Decimal)| (*price, *amount);
+        match side {
+            Side::Bids => self.bids.iter().rev().take(count).map(level).collect(),
+            Side::Asks => self.asks.iter().take(count).map(level).collect(),
+        }
+    }
+
+    /**
+     * Drops the levels beyond the depth, the lowest bids and the highest asks.
+     */
+    pub fn truncate(&mut self, depth: usize) {
+        while self.bids.len() > depth {
+            self.bids.pop_first();
+        }
+        while self.asks.len() > depth {
+            self.asks.pop_last();
+        }
+    }
+
+    /**
+     * The best `depth` levels of each side, validated by the [`SnapshotBuilder`].
+     */
+    pub fn snapshot(
+        &self,
+        exchange: &str,
+        depth: usize,
+    ) -> Result<OrderbookSnapshot, SnapshotError> {
+        let exchange_id = exchange_registry::exchange_id(exchange) as i32;
+        let level = |(price, amount): (&Decimal, &Decimal)| BookLevel {
+            exchange: exchange.to_string(),
+            exchange_id,
+            price: *price,
+            amount: *amount,
+            contributors: Vec::new(),
+            provenance: 0,
+        };
+
+        SnapshotBuilder::new()
+            .bids(self.bids.iter().rev().take(depth).map(level).collect())
+            .asks(self.asks.iter().take(depth).map(level).collect())
+            .build(depth)
+    }
+}
+
+/**
+ * A connector reconnecting more than `max_reconnects` times within `window` is in a reconnect
+ * storm, in which it stops reconnecting for `cool_down` instead of hammering the exchange.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct StormLimit {
+    pub max_reconnects: usize,
+    pub window: Duration,
+    pub cool_down: Duration,
+}
+
+impl Default for StormLimit {
+    fn default() -> Self {
+        StormLimit {
+            max_reconnects: 10,
+            window: Duration::from_secs(5 * 60),
+            cool_down: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct StormBreaker {
+    limit: StormLimit,
+    reconnects: VecDeque<Instant>,
+}
+
+impl StormBreaker {
+    fn new(limit: StormLimit) -> Self {
+        StormBreaker {
+            limit,
+            reconnects: VecDeque::new(),
+        }
+    }
+
+    /**
+     * Counts a reconnect and returns whether it tripped the breaker, which starts counting afresh.
+     */
+    fn trip(&mut self, now: Instant) -> bool {
+        while self
+            .reconnects
+            .front()
+            .is_some_and(|reconnect| now.duration_since(*reconnect) >= self.limit.window)
+        {
+            self.reconnects.pop_front();
+        }
+        self.reconnects.push_back(now);
+
+        let tripped = self.reconnects.len() > self.limit.max_reconnects;
+        if tripped {
+            self.reconnects.clear();
+        }
+        tripped
+    }
+}
+
+/**
+ * How long a stream may stay silent before it is pinged, and before it is considered dead. A dead
+ * stream fails its session like any other connection error, so it is reconnected after the backoff.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub ping_after: Duration,
+    pub dead_after: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            ping_after: Duration::from_secs(10),
+            dead_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/**
+ * Tracks when a stream was last heard from, any message including pongs counting.
+ */
+#[derive(Debug)]
+struct HeartbeatMonitor {
+    heartbeat: Heartbeat,
+    last_received: Instant,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Silence {
+    Ping,
+    Dead,
+}
+
+impl HeartbeatMonitor {
+    fn new(heartbeat: Heartbeat, now: Instant) -> Self {
+        HeartbeatMonitor {
+            heartbeat,
+            last_received: now,
+        }
+    }
+
+    fn received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    /**
+     * What to do after a read timed out, which happens once per `ping_after` of silence.
+     */
+    fn timed_out(&self, now: Instant) -> Silence {
+        match now.duration_since(self.last_received) >= self.heartbeat.dead_after {
+            true => Silence::Dead,
+            false => Silence::Ping,
+        }
+    }
+}
+
+/**
+ * Makes reads of the socket time out after the given silence, whether it is encrypted or not.
+ */
+fn set_read_timeout(socket: &Socket, timeout: Duration) -> io::Result<()> {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(timeout)),
+        MaybeTlsStream::NativeTls(stream) => stream.get_ref().set_read_timeout(Some(timeout)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// a session running at least this long is considered healthy and resets the backoff
+    pub stable_after: Duration,
+    /// no reconnect is attempted while one of these windows of the exchange is ongoing
+    pub maintenance: Vec<MaintenanceWindow>,
+    pub storm: StormLimit,
+    pub heartbeat: Heartbeat,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            stable_after: Duration::from_secs(60),
+            maintenance: Vec::new(),
+            storm: StormLimit::default(),
+            heartbeat: Heartbeat::default(),
+            clock: clock::system(),
+        }
+    }
+}
+
+/**
+ * Spreads a backoff over its upper half, so the connectors of several symbols dropped by the same
+ * outage do not all reconnect at once. The seed is any value varying between the calls.
+ */
+fn jittered(backoff: Duration, seed: u32) -> Duration {
+    let half = backoff / 2;
+    half + half.mul_f64(f64::from(seed % 1_000) / 1_000.)
+}
+
+/**
+ * Runs the given session forever. Whenever the session ends, either because the venue closed the
+ * connection or because of an error, it is started again after a jittered backoff which doubles on
+ * every consecutive failure up to the policy's maximum. During a maintenance window the next attempt is
+ * only made once the window is over. Too many reconnects in a short time trip a circuit breaker
+ * which holds off for a long cool-down, signalled by an alert.
+ */
+pub async fn run_with_reconnect<F, Fut>(exchange: &str, policy: ReconnectPolicy, mut session: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), tungstenite::Error>>,
+{
+    let mut backoff = policy.initial_backoff;
+    let mut breaker = StormBreaker::new(policy.storm);
+
+    loop {
+        let started = policy.clock.now();
+        let result = session().await;
+
+        if policy.clock.now().duration_since(started) >= policy.stable_after {
+            backoff = policy.initial_backoff;
+        }
+
+        let now = policy.clock.system_now();
+        if let Some(end) = maintenance::active_until(&policy.maintenance, exchange, now) {
+            let remaining = end.duration_since(now).unwrap_or(Duration::ZERO);
+            log::warning!(
+                "{} stream ended during maintenance, reconnecting in {}s",
+                exchange,
+                remaining.as_secs()
+            );
+            policy.clock.sleep(remaining).await;
+            backoff = policy.initial_backoff;
+            continue;
+        }
+
+        if breaker.trip(policy.clock.now()) {
+            log::alert!(
+                "{} reconnected more than {} times within {}s, cooling down for {}s",
+                exchange,
+                policy.storm.max_reconnects,
+                policy.storm.window.as_secs(),
+                policy.storm.cool_down.as_secs()
+            );
+            policy.clock.sleep(policy.storm.cool_down).await;
+            backoff = policy.initial_backoff;
+            continue;
+        }
+
+        let seed = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let delay = jittered(backoff, seed);
+        match result {
+            Ok(_) => log::warning!(
+                "{} stream closed, reconnecting in {}ms",
+                exchange,
+                delay.as_millis()
+            ),
+            Err(error) => log::warning!(
+                "{} stream failed ({}), reconnecting in {}ms",
+                exchange,
+                error,
+                delay.as_millis()
+            ),
+        }
+
+        policy.clock.sleep(delay).await;
+        backoff = std::cmp::min(backoff * 2, policy.max_backoff);
+    }
+}
+
+pub type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/**
+ * What a message of a book stream amounts to.
+ */
+#[derive(Debug)]
+pub enum Update {
+    Snapshot(OrderbookSnapshot),
+    /// a message which does not change the book, e.g. a heartbeat, or one that could not be parsed
+    Ignored,
+    /// an update older than one already applied, counted in the drop journal
+    Stale,
+    /// the local book diverged from the one of the exchange, the subscription is sent again
+    Resubscribe,
+    /// messages between the applied ones were missed, counted in the drop journal and handled like
+    /// a diverged book
+    Gap,
+    /// the stream cannot be continued, the session ends and is started again after the backoff
+    Reconnect,
+}
+
+/**
+ * The book stream of a venue. A fresh connector is created for every session, so a reconnect
+ * starts without the book and sequence of the connection before.
+ */
+pub trait ExchangeConnector: Send {
+    /**
+     * The url of the stream the book of the symbol is read from.
+     */
+    fn url(&self, symbol: &str, depth: usize) -> String;
+
+    /**
+     * The messages subscribing to the book of the symbol with at least `depth` levels per side,
+     * none if the url already subscribes.
+     */
+    fn subscribe(&mut self, symbol: &str, depth: usize) -> Vec<String>;
+
+    /**
+     * Turns a message of the stream into a snapshot of the best `depth` levels of the book.
+     */
+    fn next_snapshot(&mut self, message: &Value, depth: usize) -> Update;
+
+    /**
+     * The shape the message has to conform to in strict schema mode, None for messages which are
+     * not validated, e.g. heartbeats or subscription confirmations.
+     */
+    fn schema(&self, _message: &Value) -> Option<&'static Shape> {
+        None
+    }
+
+    /**
+     * Opens the stream of the symbol and subscribes to its book.
+     */
+    #[allow(clippy::result_large_err)]
+    fn connect(&mut self, symbol: &str, depth: usize) -> Result<Socket, tungstenite::Error> {
+        let (mut socket, _) = tungstenite::connect(Url::parse(&self.url(symbol, depth)).unwrap())?;
+        for subscription in self.subscribe(symbol, depth) {
+            socket.write_message(Message::Text(subscription))?;
+        }
+        Ok(socket)
+    }
+}
+
+/**
+ * Validates the message against the shape the connector expects for it, if the session runs in
+ * strict schema mode. A deviating message is quarantined and counted, and its violations are logged
+ * the first time the exchange commits them. Returns whether the message was quarantined.
+ */
+fn quarantined(
+    exchange: &str,
+    connector: &dyn ExchangeConnector,
+    message: &Value,
+    raw: &str,
+    quarantine: Option<&Quarantine>,
+    journal: &Journal,
+    clock: &dyn Clock,
+) -> bool {
+    let (quarantine, shape) = match (quarantine, connector.schema(message)) {
+        (Some(quarantine), Some(shape)) => (quarantine, shape),
+        _ => return false,
+    };
+    let violations = schema::validate(shape, message);
+    if violations.is_empty() {
+        return false;
+    }
+    journal.record(DropReason::SchemaViolation, exchange, 1);
+    let at_ms = (clock.system_now().duration_since(UNIX_EPOCH))
+        .unwrap_or_default()
+        .as_millis() as u64;
+    for violation in quarantine.record(exchange, &violations, raw, at_ms) {
+        log::warning!(
+            "{} payload deviates from its schema: {}",
+            exchange,
+            violation
+        );
+    }
+    true
+}
+
+async fn run_session(
+    exchange: &'static str,
+    mut connector: Box<dyn ExchangeConnector>,
+    source_id: usize,
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    heartbeat: Heartbeat,
+    clock: Arc<dyn Clock>,
+) -> Result<(), tungstenite::Error> {
+    let (depth, symbol, inbound, journal, quarantine) = {
+        let aggregator = aggregator_arc.lock().await;
+        (
+            aggregator.max_depth(),
+            aggregator.symbol().to_string(),
+            aggregator.inbound_queue(source_id),
+            aggregator.journal(),
+            aggregator.quarantine(),
+        )
+    };
+    let mut socket = connector.connect(&symbol, depth)?;
+    set_read_timeout(&socket, heartbeat.ping_after)?;
+    let mut monitor = HeartbeatMonitor::new(heartbeat, clock.now());
+
+    loop {
+        let msg = match socket.read_message() {
+            Ok(msg) => msg,
+            Err(tungstenite::Error::Io(error))
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                match monitor.timed_out(clock.now()) {
+                    Silence::Ping => socket.write_message(Message::Ping(Vec::new()))?,
+                    Silence::Dead => {
+                        log::warning!(
+                            "{} sent nothing for {}s, dropping the connection",
+                            exchange,
+                            heartbeat.dead_after.as_secs()
+                        );
+                        return Err(tungstenite::Error::Io(io::ErrorKind::TimedOut.into()));
+                    }
+                }
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        monitor.received(clock.now());
+        // pings are answered by tungstenite itself, pongs only prove the stream is alive
+        if matches!(msg, Message::Ping(_) | Message::Pong(_)) {
+            continue;
+        }
+        let content = msg.into_text()?;
+        let (deserialized, parse_ns) =
+            stage_timings::timed(|| serde_json::from_str::<Value>(&content));
+        let deserialized = match deserialized {
+            Ok(des) => des,
+            Err(_) => continue,
+        };
+        if quarantined(
+            exchange,
+            connector.as_ref(),
+            &deserialized,
+            &content,
+            quarantine.as_deref(),
+            &journal,
+            clock.as_ref(),
+        ) {
+            continue;
+        }
+        let (update, normalize_ns) =
+            stage_timings::timed(|| connector.next_snapshot(&deserialized, depth));
+
+        match update {
+            Update::Snapshot(mut snapshot) => {
+                snapshot.set_provenance(provenance(&content));
+                inbound.push(
+                    snapshot,
+                    TickTimings {
+                        parse_ns,
+                        normalize_ns,
+                        ..Default::default()
+                    },
+                )
+            }
+            Update::Ignored => {}
+            Update::Stale => journal.record(DropReason::Stale, exchange, 1),
+            Update::Resubscribe | Update::Gap => {
+                if matches!(update, Update::Gap) {
+                    journal.record(DropReason::SequenceGap, exchange, 1);
+                }
+                // the book is left out until the fresh snapshot of the new subscription arrived
+                (aggregator_arc.lock().await).resync_source(source_id).await;
+                log::warning!("{} book diverged, resubscribing", exchange);
+                for subscription in connector.subscribe(&symbol, depth) {
+                    socket.write_message(Message::Text(subscription))?;
+                }
+            }
+            Update::Reconnect => return Ok(()),
+        }
+    }
+}
+
+/**
+ * Feeds the aggregator with the book stream of a fresh connector per session, reconnecting and
+ * subscribing again per the policy whenever a session ends, including when the stream stays silent
+ * despite being pinged. The books of the venue are invalidated in the meantime.
+ */
+pub async fn run_stream(
+    exchange: &'static str,
+    connector: fn() -> Box<dyn ExchangeConnector>,
+    source_id: usize,
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    policy: ReconnectPolicy,
+) {
+    let (heartbeat, clock) = (policy.heartbeat, policy.clock.clone());
+    run_with_reconnect(exchange, policy, || {
+        let (aggregator_arc, clock) = (aggregator_arc.clone(), clock.clone());
+        async move {
+            let result = run_session(
+                exchange,
+                connector(),
+                source_id,
+                aggregator_arc.clone(),
+                heartbeat,
+                clock,
+            )
+            .await;
+            (aggregator_arc.lock().await)
+                .invalidate_source(source_id)
+                .await;
+            result
+        }
+    })
+    .await
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sequence {
+    First,
+    Next,
+    /// the update skipped the given amount of sequence numbers
+    Gap(u64),
+    /// the update is older than or equal to one that was already observed
+    Stale,
+}
+
+/**
+ * Keeps track of the last sequence number (update id, timestamp, ...) seen on a stream.
+ */
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last: Option<u64>,
+}
+
+impl SequenceTracker {
+    #[allow(dead_code)] // used by connectors stubbed with `cargo xtask new-connector`
+    pub fn new() -> Self {
+        SequenceTracker { last: None }
+    }
+
+    pub fn observe(&mut self, sequence: u64) -> Sequence {
+        let status = match self.last {
+            None => Sequence::First,
+            Some(last) if sequence <= last => return Sequence::Stale,
+            Some(last) if sequence == last + 1 => Sequence::Next,
+            Some(last) => Sequence::Gap(sequence - last - 1),
+        };
+        self.last = Some(sequence);
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        jittered, parse_decimal, parse_levels, parse_snapshot, provenance, Heartbeat,
+        HeartbeatMonitor, Sequence, SequenceTracker, Silence, StormBreaker, StormLimit,
+    };
+    use keyrock_challenge_core::{
+        orderbook_snapshot::OrderbookSnapshot, orderbook_snapshot::Side,
+        orderbook_snapshot::SnapshotError,
+    };
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_parse_string_and_number_levels() {
+        // Arrange
+        let raw = json!([["0.0745", "1.5"], [0.0744, 2.0], ["0.0743", "3"]]);
+
+        // Act
+        let levels = parse_levels("Binance", &raw, 2).unwrap();
+
+        // Assert
+        assert!(levels[0].price == "0.0745".parse().unwrap());
+        assert!(levels[1].price.to_f64() == 0.0744 && levels[1].amount.to_f64() == 2.);
+        assert!(levels.len() == 2 && levels[1].exchange == "Binance");
+        assert!(parse_decimal(&json!("1.5e-5")).unwrap() == "0.000015".parse().unwrap());
+        assert!(parse_decimal(&json!("NaN")).is_err());
+    }
+
+    #[test]
+    fn should_reject_short_or_malformed_ladders() {
+        let short = parse_snapshot("Binance", &json!([["1", "1"]]), &json!([]), 3);
+        let malformed = parse_snapshot("Binance", &json!([]), &json!([["abc", "1"]]), 1);
+
+        assert!(matches!(
+            short,
+            Err(SnapshotError::TooShallow { levels: 1, .. })
+        ));
+        assert!(malformed.unwrap_err() == SnapshotError::Malformed(Side::Asks));
+        assert!(parse_levels("Binance", &json!(null), 1).is_err());
+        assert!(matches!(
+            parse_snapshot("Binance", &json!([]), &json!([]), 1),
+            Ok(OrderbookSnapshot {
+                bids: None,
+                asks: None,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn should_track_sequences() {
+        let mut tracker = SequenceTracker::new();
+
+        assert_eq!(tracker.observe(10), Sequence::First);
+        assert_eq!(tracker.observe(11), Sequence::Next);
+        assert_eq!(tracker.observe(15), Sequence::Gap(3));
+        assert_eq!(tracker.observe(12), Sequence::Stale);
+        assert_eq!(tracker.observe(16), Sequence::Next);
+    }
+
+    #[test]
+    fn should_hash_raw_messages_for_provenance() {
+        assert!(provenance("") == 0xcbf2_9ce4_8422_2325);
+        assert!(provenance("a") == 0xaf63_dc4c_8601_ec8c);
+        assert!(provenance(r#"{"u": 160}"#) != provenance(r#"{"u": 161}"#));
+    }
+
+    #[test]
+    fn should_jitter_backoff_within_its_upper_half() {
+        let backoff = Duration::from_secs(2);
+
+        assert!(jittered(backoff, 0) == Duration::from_secs(1));
+        assert!(jittered(backoff, 500) == Duration::from_millis(1500));
+        assert!((0..5_000).all(|seed| {
+            let jittered = jittered(backoff, seed * 7_919);
+            jittered >= backoff / 2 && jittered < backoff
+        }));
+    }
+
+    #[test]
+    fn should_ping_silent_streams_until_they_are_dead() {
+        // Arrange
+        let heartbeat = Heartbeat {
+            ping_after: Duration::from_secs(10),
+            dead_after: Duration::from_secs(25),
+        };
+        let started = Instant::now();
+        let at = |secs: u64| started + Duration::from_secs(secs);
+        let mut monitor = HeartbeatMonitor::new(heartbeat, at(0));
+
+        // Act & Assert
+        assert!(monitor.timed_out(at(10)) == Silence::Ping);
+        assert!(monitor.timed_out(at(20)) == Silence::Ping);
+        // the pong arrived
+        monitor.received(at(21));
+        assert!(monitor.timed_out(at(31)) == Silence::Ping);
+        assert!(monitor.timed_out(at(46)) == Silence::Dead);
+    }
+
+    #[test]
+    fn should_trip_storm_breaker_only_within_window() {
+        // Arrange
+        let mut breaker = StormBreaker::new(StormLimit {
+            max_reconnects: 2,
+            window: Duration::from_secs(60),
+            cool_down: Duration::from_secs(600),
+        });
+        let started = Instant::now();
+        let at = |secs: u64| started + Duration::from_secs(secs);
+
+        // Act & Assert
+        assert!(!breaker.trip(at(0)));
+        assert!(!breaker.trip(at(30)));
+        // the first reconnect left the window
+        assert!(!breaker.trip(at(61)));
+        assert!(breaker.trip(at(62)));
+        // counting starts afresh after the cool-down
+        assert!(!breaker.trip(at(63)));
+    }
+}
@@ -0,0 +1,171 @@
+//! Shared consumption of a stream. The members of a consumer group take turns receiving its items,
+//! so horizontally scaled processors sharing a group id each handle a balanced subset of the stream
+//! instead of all of them processing every item.
+
+use std::collections::HashMap;
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+/**
+ * The outcome of handing an item to a group.
+ */
+#[derive(Debug)]
+pub enum Dispatch<T> {
+    Delivered,
+    /// every member's queue is full, the item is to be sent to the given member once it has room
+    Full(Sender<T>, T),
+    /// the group has no members left and was removed
+    Abandoned,
+}
+
+#[derive(Debug)]
+struct Group<T> {
+    members: Vec<Sender<T>>,
+    /// the member whose turn it is
+    next: usize,
+}
+
+#[derive(Debug)]
+pub struct ConsumerGroups<T> {
+    groups: HashMap<String, Group<T>>,
+}
+
+impl<T> Default for ConsumerGroups<T> {
+    fn default() -> Self {
+        ConsumerGroups {
+            groups: HashMap::new(),
+        }
+    }
+}
+
+impl<T> ConsumerGroups<T> {
+    /**
+     * Adds a member to the group, creating the group if it does not exist yet.
+     * Returns true if the group was created, in which case the caller starts dispatching to it.
+     */
+    pub fn join(&mut self, group_id: &str, member: Sender<T>) -> bool {
+        match self.groups.get_mut(group_id) {
+            Some(group) => {
+                group.members.push(member);
+                false
+            }
+            None => {
+                self.groups.insert(
+                    group_id.to_string(),
+                    Group {
+                        members: vec![member],
+                        next: 0,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn members(&self, group_id: &str) -> usize {
+        self.groups
+            .get(group_id)
+            .map_or(0, |group| group.members.len())
+    }
+
+    /**
+     * Hands the item to the member whose turn it is, or to the following one with room in its
+     * queue if that member is lagging behind. Members that left are removed on the way.
+     */
+    pub fn dispatch(&mut self, group_id: &str, mut item: T) -> Dispatch<T> {
+        let group = match self.groups.get_mut(group_id) {
+            Some(group) => group,
+            None => return Dispatch::Abandoned,
+        };
+        let mut tried = 0;
+        while tried < group.members.len() {
+            let index = group.next % group.members.len();
+            match group.members[index].try_send(item) {
+                Ok(()) => {
+                    group.next = index + 1;
+                    return Dispatch::Delivered;
+                }
+                Err(TrySendError::Full(returned)) => {
+                    item = returned;
+                    group.next = index + 1;
+                    tried += 1;
+                }
+                Err(TrySendError::Closed(returned)) => {
+                    item = returned;
+                    group.members.remove(index);
+                    group.next = index;
+                }
+            }
+        }
+
+        match group.members.is_empty() {
+            true => {
+                self.groups.remove(group_id);
+                Dispatch::Abandoned
+            }
+            false => {
+                let index = group.next % group.members.len();
+                group.next = index + 1;
+                Dispatch::Full(group.members[index].clone(), item)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConsumerGroups, Dispatch};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn should_balance_items_across_members() {
+        // Arrange
+        let mut groups = ConsumerGroups::default();
+        let (tx_01, mut rx_01) = mpsc::channel(8);
+        let (tx_02, mut rx_02) = mpsc::channel(1);
+        let (tx_03, rx_03) = mpsc::channel(8);
+        let created = groups.join("processors", tx_01);
+        groups.join("processors", tx_02);
+        groups.join("processors", tx_03);
+        drop(rx_03);
+
+        // Act
+        for item in 0..5 {
+            assert!(matches!(
+                groups.dispatch("processors", item),
+                Dispatch::Delivered
+            ));
+        }
+
+        // Assert
+        assert!(created && groups.members("processors") == 2);
+        let mut first = Vec::new();
+        while let Ok(item) = rx_01.try_recv() {
+            first.push(item);
+        }
+        // the second member only has room for one item, the first one takes over the rest
+        assert!(first == vec![0, 2, 3, 4]);
+        assert!(rx_02.try_recv() == Ok(1));
+    }
+
+    #[test]
+    fn should_abandon_group_without_members() {
+        // Arrange
+        let mut groups = ConsumerGroups::default();
+        let (tx, rx) = mpsc::channel(1);
+        groups.join("processors", tx);
+
+        // Act
+        let delivered = groups.dispatch("processors", 1);
+        let full = groups.dispatch("processors", 2);
+        drop(rx);
+        let abandoned = groups.dispatch("processors", 3);
+
+        // Assert
+        assert!(matches!(delivered, Dispatch::Delivered));
+        assert!(matches!(full, Dispatch::Full(_, 2)));
+        assert!(matches!(abandoned, Dispatch::Abandoned));
+        assert!(groups.members("processors") == 0);
+        assert!(groups.join("processors", mpsc::channel(1).0));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::exchange_registry;
+use keyrock_challenge_proto::orderbook::{ContributionWindow, ExchangeContribution, Summary};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+const WINDOWS_SECS: [u64; 3] = [60, 300, 900];
+
+#[derive(Debug, Default)]
+struct Bucket {
+    second: u64,
+    summaries: u64,
+    levels: u64,
+    levels_per_exchange: HashMap<String, u64>,
+    best_bids_per_exchange: HashMap<String, u64>,
+    best_asks_per_exchange: HashMap<String, u64>,
+}
+
+/**
+ * Tracks how much each exchange contributes to the published summaries.
+ * Counts are kept in one second buckets, which are summed up into the rolling windows on request.
+ */
+#[derive(Debug)]
+pub struct ContributionStats {
+    started: Instant,
+    buckets: VecDeque<Bucket>,
+}
+
+impl ContributionStats {
+    pub fn new(started: Instant) -> Self {
+        ContributionStats {
+            started,
+            buckets: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, summary: &Summary, now: Instant) {
+        let second = now.duration_since(self.started).as_secs();
+        self.evict(second);
+
+        if self.buckets.back().is_none_or(|last| last.second != second) {
+            self.buckets.push_back(Bucket {
+                second,
+                ..Default::default()
+            });
+        }
+        let bucket = self.buckets.back_mut().unwrap();
+
+        bucket.summaries += 1;
+        for level in summary.bids.iter().chain(summary.asks.iter()) {
+            bucket.levels += 1;
+            *bucket
+                .levels_per_exchange
+                .entry(exchange_registry::internal_name(
+                    level.exchange_id,
+                    &level.exchange,
+                ))
+                .or_default() += 1;
+        }
+        if let Some(level) = summary.bids.first() {
+            *bucket
+                .best_bids_per_exchange
+                .entry(exchange_registry::internal_name(
+                    level.exchange_id,
+                    &level.exchange,
+                ))
+                .or_default() += 1;
+        }
+        if let Some(level) = summary.asks.first() {
+            *bucket
+                .best_asks_per_exchange
+                .entry(exchange_registry::internal_name(
+                    level.exchange_id,
+                    &level.exchange,
+                ))
+                .or_default() += 1;
+        }
+    }
+
+    pub fn windows(&mut self, now: Instant) -> Vec<ContributionWindow> {
+        let second = now.duration_since(self.started).as_secs();
+        self.evict(second);
+
+        WINDOWS_SECS
+            .iter()
+            .map(|window_secs| {
+                let buckets: Vec<&Bucket> = self
+                    .buckets
+                    .iter()
+                    .filter(|bucket| bucket.second + window_secs > second)
+                    .collect();
+                ContributionStats::window(*window_secs, &buckets)
+            })
+            .collect()
+    }
+
+    fn window(window_secs: u64, buckets: &[&Bucket]) -> ContributionWindow {
+        let summaries: u64 = buckets.iter().map(|bucket| bucket.summaries).sum();
+        let levels: u64 = buckets.iter().map(|bucket| bucket.levels).sum();
+        let mut exchanges = HashMap::<String, (u64, u64, u64)>::new();
+
+        for bucket in buckets {
+            for (exchange, count) in &bucket.levels_per_exchange {
+                exchanges.entry(exchange.clone()).or_default().0 += count;
+            }
+            for (exchange, count) in &bucket.best_bids_per_exchange {
+                exchanges.entry(exchange.clone()).or_default().1 += count;
+            }
+            for (exchange, count) in &bucket.best_asks_per_exchange {
+                exchanges.entry(exchange.clone()).or_default().2 += count;
+            }
+        }
+
+        let share = |count: u64, total: u64| match total {
+            0 => 0.,
+            _ => count as f64 / total as f64,
+        };
+        let mut exchanges: Vec<ExchangeContribution> = exchanges
+            .into_iter()
+            .map(
+                |(exchange, (level_count, best_bid_count, best_ask_count))| ExchangeContribution {
+                    exchange,
+                    level_share: share(level_count, levels),
+                    best_bid_share: share(best_bid_count, summaries),
+                    best_ask_share: share(best_ask_count, summaries),
+                },
+            )
+            .collect();
+        exchanges.sort_by(|a, b| a.exchange.cmp(&b.exchange));
+
+        ContributionWindow {
+            window_secs: window_secs as u32,
+            summaries,
+            exchanges,
+        }
+    }
+
+    fn evict(&mut self, second: u64) {
+        let longest_window = WINDOWS_SECS[WINDOWS_SECS.len() - 1];
+        while let Some(first) = self.buckets.front() {
+            if first.second + longest_window > second {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContributionStats;
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+    use std::time::{Duration, Instant};
+
+    fn level(exchange: &str) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price: 1.,
+            amount: 1.,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_compute_contribution_shares_per_window() {
+        // Arrange
+        let started = Instant::now();
+        let mut stats = ContributionStats::new(started);
+        let old = Summary {
+            bids: vec![level("Bitstamp"), level("Bitstamp")],
+            asks: vec![level("Bitstamp"), level("Bitstamp")],
+            ..Default::default()
+        };
+        let recent = Summary {
+            bids: vec![level("Binance"), level("Bitstamp")],
+            asks: vec![level("Bitstamp"), level("Binance")],
+            ..Default::default()
+        };
+
+        // Act
+        stats.record(&old, started);
+        stats.record(&recent, started + Duration::from_secs(100));
+        let windows = stats.windows(started + Duration::from_secs(100));
+
+        // Assert
+        let last_minute = &windows[0];
+        assert!(last_minute.window_secs == 60 && last_minute.summaries == 1);
+        assert!(last_minute.exchanges[0].exchange == "Binance");
+        assert!(last_minute.exchanges[0].level_share == 0.5);
+        assert!(last_minute.exchanges[0].best_bid_share == 1.);
+        assert!(last_minute.exchanges[0].best_ask_share == 0.);
+
+        let last_five_minutes = &windows[1];
+        assert!(last_five_minutes.summaries == 2);
+        assert!(last_five_minutes.exchanges[1].exchange == "Bitstamp");
+        assert!(last_five_minutes.exchanges[1].level_share == 0.75);
+        assert!(last_five_minutes.exchanges[1].best_ask_share == 1.);
+    }
+}
@@ -0,0 +1,198 @@
+//! Detects crosses of the merged book, i.e. a venue bidding above another venue's ask. Crosses
+//! at the float-precision level flicker on and off with every tick, so a cross is only reported
+//! once it exceeds a minimum size for a minimum time. It is only over once the book uncrossed
+//! entirely, not already when it shrinks below the minimum size again.
+
+use crate::{clock::Clock, spmc::Spmc};
+use keyrock_challenge_proto::orderbook::{CrossingEvent, Summary};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc::Receiver, Mutex};
+
+#[derive(Debug, Clone, Copy)]
+pub struct CrossingFilter {
+    /// how far the best bid has to exceed the best ask, in basis points of the mid price
+    pub min_bps: f64,
+    /// how long the cross has to stay above `min_bps` before it is reported
+    pub min_duration: Duration,
+}
+
+impl Default for CrossingFilter {
+    fn default() -> Self {
+        CrossingFilter {
+            min_bps: 1.,
+            min_duration: Duration::from_millis(250),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    Uncrossed,
+    /// crossed by more than the minimum, but not yet for long enough
+    Pending(Instant, CrossingEvent),
+    Crossed(CrossingEvent),
+}
+
+/**
+ * The positive amount of basis points the best bid exceeds the best ask by if the book is crossed.
+ */
+fn crossed_bps(summary: &Summary) -> Option<f64> {
+    let (best_bid, best_ask) = (summary.bids.first()?, summary.asks.first()?);
+    let mid = (best_bid.price + best_ask.price) / 2.;
+    Some((best_bid.price - best_ask.price) / mid * 10_000.)
+}
+
+#[derive(Debug)]
+pub struct CrossingDetector {
+    filter: CrossingFilter,
+    state: State,
+}
+
+impl CrossingDetector {
+    pub fn new(filter: CrossingFilter) -> Self {
+        CrossingDetector {
+            filter,
+            state: State::Uncrossed,
+        }
+    }
+
+    /**
+     * Returns the event to publish if the summary started or ended a cross.
+     */
+    pub fn observe(
+        &mut self,
+        summary: &Summary,
+        now: Instant,
+        unix_ms: u64,
+    ) -> Option<CrossingEvent> {
+        let bps = crossed_bps(summary).unwrap_or(0.);
+
+        let state = std::mem::replace(&mut self.state, State::Uncrossed);
+        let (state, event) = match state {
+            State::Uncrossed if bps >= self.filter.min_bps => {
+                let started = CrossingEvent {
+                    symbol: summary.symbol.clone(),
+                    crossed: true,
+                    sequence: summary.sequence,
+                    bid_exchange: summary.bids[0].exchange.clone(),
+                    ask_exchange: summary.asks[0].exchange.clone(),
+                    crossed_bps: bps,
+                    started_at_ms: unix_ms,
+                };
+                self.confirm(now, started, now)
+            }
+            State::Uncrossed => (State::Uncrossed, None),
+            // a flicker, the cross did not last
+            State::Pending(..) if bps < self.filter.min_bps => (State::Uncrossed, None),
+            State::Pending(since, mut started) => {
+                started.crossed_bps = started.crossed_bps.max(bps);
+                started.sequence = summary.sequence;
+                self.confirm(since, started, now)
+            }
+            State::Crossed(mut started) if bps <= 0. => {
+                started.crossed = false;
+                started.sequence = summary.sequence;
+                (State::Uncrossed, Some(started))
+            }
+            State::Crossed(mut started) => {
+                started.crossed_bps = started.crossed_bps.max(bps);
+                (State::Crossed(started), None)
+            }
+        };
+        self.state = state;
+        event
+    }
+
+    fn confirm(
+        &self,
+        since: Instant,
+        started: CrossingEvent,
+        now: Instant,
+    ) -> (State, Option<CrossingEvent>) {
+        match now.duration_since(since) >= self.filter.min_duration {
+            true => (State::Crossed(started.clone()), Some(started)),
+            false => (State::Pending(since, started), None),
+        }
+    }
+}
+
+/**
+ * Feeds every published summary to the detector and publishes the resulting events.
+ */
+pub async fn run(
+    filter: CrossingFilter,
+    mut rx: Receiver<Summary>,
+    spmc: Arc<Mutex<Spmc<CrossingEvent>>>,
+    clock: Arc<dyn Clock>,
+) {
+    let mut detector = CrossingDetector::new(filter);
+    while let Some(summary) = rx.recv().await {
+        let unix_ms = clock
+            .system_now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        if let Some(event) = detector.observe(&summary, clock.now(), unix_ms) {
+            spmc.lock().await.broadcast(event).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CrossingDetector, CrossingFilter};
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+    use std::time::{Duration, Instant};
+
+    fn summary(sequence: u64, best_bid: f64, best_ask: f64) -> Summary {
+        let level = |exchange: &str, price: f64| Level {
+            exchange: exchange.to_string(),
+            price,
+            ..Default::default()
+        };
+        Summary {
+            bids: vec![level("Binance", best_bid)],
+            asks: vec![level("Bitstamp", best_ask)],
+            sequence,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_only_report_crosses_that_persist_and_exceed_the_minimum() {
+        // Arrange
+        let mut detector = CrossingDetector::new(CrossingFilter {
+            min_bps: 1.,
+            min_duration: Duration::from_millis(100),
+        });
+        let started = Instant::now();
+        let mut observe = |ms: u64, best_bid: f64, best_ask: f64| {
+            detector.observe(
+                &summary(ms, best_bid, best_ask),
+                started + Duration::from_millis(ms),
+                ms,
+            )
+        };
+
+        // Act & Assert
+        // crossed by 2 bps, but only for 50ms
+        assert!(observe(0, 1.0002, 1.).is_none());
+        assert!(observe(50, 1., 1.0001).is_none());
+        // crossed by a fraction of a bps
+        assert!(observe(100, 1.00000001, 1.).is_none());
+        assert!(observe(300, 1.00000001, 1.).is_none());
+        // crossed by 2 bps for 100ms
+        assert!(observe(400, 1.0002, 1.).is_none());
+        let start = observe(500, 1.0003, 1.).unwrap();
+        assert!(start.crossed && start.started_at_ms == 400 && start.sequence == 500);
+        assert!(start.bid_exchange == "Binance" && start.ask_exchange == "Bitstamp");
+        // shrinking below the minimum does not end the cross
+        assert!(observe(600, 1.00000001, 1.).is_none());
+        let end = observe(700, 1., 1.0001).unwrap();
+        assert!(!end.crossed && end.sequence == 700);
+        assert!((end.crossed_bps - 3.).abs() < 0.01);
+    }
+}
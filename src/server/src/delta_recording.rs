@@ -0,0 +1,136 @@
+//! The delta recording format. Most ticks only move a few levels, so instead of the full summary
+//! only the levels that changed since the previous summary are written. A full summary, the
+//! keyframe, starts every file and is repeated every `keyframe_interval` entries, so a corrupt entry
+//! only affects the entries up to the next keyframe.
+
+use keyrock_challenge_core::delta;
+use keyrock_challenge_proto::orderbook::{recorded_entry::Entry, Summary};
+
+/**
+ * Encodes summaries as keyframes and deltas, for the recordings as well as the delta streams.
+ */
+#[derive(Debug)]
+pub struct DeltaEncoder {
+    keyframe_interval: u32,
+    previous: Option<Summary>,
+    since_keyframe: u32,
+}
+
+impl DeltaEncoder {
+    pub fn new(keyframe_interval: u32) -> Self {
+        DeltaEncoder {
+            keyframe_interval,
+            previous: None,
+            since_keyframe: 0,
+        }
+    }
+
+    pub fn encode(&mut self, summary: &Summary) -> Entry {
+        let previous = match self.previous.replace(summary.clone()) {
+            // a delta can not express a switch of the symbol
+            Some(previous)
+                if previous.symbol == summary.symbol
+                    && self.since_keyframe + 1 < self.keyframe_interval =>
+            {
+                previous
+            }
+            _ => {
+                self.since_keyframe = 0;
+                return Entry::Full(summary.clone());
+            }
+        };
+        self.since_keyframe += 1;
+
+        Entry::Delta(delta::diff(&previous, summary))
+    }
+}
+
+/**
+ * Reconstructs the full summaries from the entries of a delta recording.
+ */
+#[derive(Debug, Default)]
+pub struct DeltaDecoder {
+    previous: Option<Summary>,
+}
+
+impl DeltaDecoder {
+    /**
+     * Fails for a delta without a preceding keyframe or one that does not fit the previous summary.
+     */
+    pub fn decode(&mut self, entry: Entry) -> Result<Summary, ()> {
+        let summary = match entry {
+            Entry::Full(summary) => summary,
+            Entry::Delta(changes) => {
+                let mut summary = self.previous.take().ok_or(())?;
+                delta::apply(&mut summary, changes).map_err(|_| ())?;
+                summary
+            }
+        };
+        self.previous = Some(summary.clone());
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeltaDecoder, DeltaEncoder};
+    use keyrock_challenge_proto::orderbook::{recorded_entry::Entry, Level, Summary};
+
+    fn summary(sequence: u64, best_bid: f64, asks: usize) -> Summary {
+        let level = |price: f64| Level {
+            exchange: "Binance".to_string(),
+            price,
+            amount: 1.,
+            exchange_id: 1,
+            ..Default::default()
+        };
+        Summary {
+            spread: Some(1.),
+            bids: (0..10).map(|i| level(best_bid - i as f64)).collect(),
+            asks: (0..asks).map(|i| level(best_bid + 1. + i as f64)).collect(),
+            sequence,
+            symbol: "ethbtc".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_reconstruct_summaries_from_keyframes_and_deltas() {
+        // Arrange
+        let mut encoder = DeltaEncoder::new(3);
+        let mut decoder = DeltaDecoder::default();
+        let summaries = vec![
+            summary(1, 100., 10),
+            summary(2, 100., 10),
+            summary(3, 101., 4),
+            summary(4, 101., 10),
+        ];
+
+        // Act
+        let entries: Vec<Entry> = summaries.iter().map(|s| encoder.encode(s)).collect();
+        let encoded_len: Vec<usize> = entries.iter().map(Entry::encoded_len).collect();
+        let keyframes: Vec<bool> = entries
+            .iter()
+            .map(|entry| matches!(entry, Entry::Full(_)))
+            .collect();
+        let decoded: Vec<Summary> = entries
+            .into_iter()
+            .map(|entry| decoder.decode(entry).unwrap())
+            .collect();
+
+        // Assert
+        assert!(decoded == summaries);
+        // the second tick only changed its sequence, the fourth one is a keyframe again
+        assert!(encoded_len[1] * 10 < encoded_len[0]);
+        assert!(keyframes == vec![true, false, false, true]);
+    }
+
+    #[test]
+    fn should_reject_delta_without_keyframe() {
+        let mut encoder = DeltaEncoder::new(10);
+        encoder.encode(&summary(1, 100., 10));
+        let delta = encoder.encode(&summary(2, 100., 10));
+
+        assert!(DeltaDecoder::default().decode(delta).is_err());
+    }
+}
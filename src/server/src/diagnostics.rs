@@ -0,0 +1,154 @@
+//! On-demand connectivity diagnostics of a venue for "is it us or them" triage. The connection of a
+//! connector is retraced step by step on a fresh socket, each step timed on its own, so a report
+//! tells a failing name resolution apart from a slow handshake or a venue that stopped sending.
+
+use keyrock_challenge_proto::orderbook::{DiagnosticStep, DiagnosticStepKind, DiagnosticsReport};
+use native_tls::TlsConnector;
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+use tungstenite::Message;
+use url::Url;
+
+/// how long each network step may take before it fails
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+ * How to reach the stream of a venue, the same way its connector does.
+ */
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub exchange: &'static str,
+    pub url: String,
+    /// sent after the websocket handshake, if the venue streams only after subscribing
+    pub subscribe: Option<String>,
+}
+
+/**
+ * Runs the step and records its outcome. The step returns a detail for the report with its value.
+ */
+fn step<T>(
+    steps: &mut Vec<DiagnosticStep>,
+    kind: DiagnosticStepKind,
+    run: impl FnOnce() -> Result<(T, String), String>,
+) -> Result<T, ()> {
+    let started = Instant::now();
+    let result = run();
+    let duration_us = started.elapsed().as_micros() as u64;
+    let (ok, detail) = match &result {
+        Ok((_, detail)) => (true, detail.clone()),
+        Err(error) => (false, error.clone()),
+    };
+    steps.push(DiagnosticStep {
+        kind: kind as i32,
+        ok,
+        duration_us,
+        detail,
+    });
+    result.map(|(value, _)| value).map_err(|_| ())
+}
+
+fn run_steps(probe: &Probe, steps: &mut Vec<DiagnosticStep>) -> Result<(), ()> {
+    let url = Url::parse(&probe.url).map_err(|_| ())?;
+    let host = url.host_str().ok_or(())?.to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let address = step(steps, DiagnosticStepKind::Dns, || {
+        let address = (host.as_str(), port)
+            .to_socket_addrs()
+            .map_err(|error| error.to_string())?
+            .next()
+            .ok_or_else(|| "No address".to_string())?;
+        Ok((address, address.ip().to_string()))
+    })?;
+    let tcp = step(steps, DiagnosticStepKind::Tcp, || {
+        let tcp = TcpStream::connect_timeout(&address, STEP_TIMEOUT)
+            .map_err(|error| error.to_string())?;
+        tcp.set_read_timeout(Some(STEP_TIMEOUT))
+            .and_then(|_| tcp.set_write_timeout(Some(STEP_TIMEOUT)))
+            .map_err(|error| error.to_string())?;
+        Ok((tcp, address.to_string()))
+    })?;
+    let tls = step(steps, DiagnosticStepKind::Tls, || {
+        let connector = TlsConnector::new().map_err(|error| error.to_string())?;
+        let tls = connector
+            .connect(&host, tcp)
+            .map_err(|error| error.to_string())?;
+        Ok((tls, String::new()))
+    })?;
+    let mut socket = step(steps, DiagnosticStepKind::WebSocket, || {
+        let (socket, response) =
+            tungstenite::client(url.as_str(), tls).map_err(|error| error.to_string())?;
+        Ok((socket, response.status().to_string()))
+    })?;
+    if let Some(subscribe) = &probe.subscribe {
+        step(steps, DiagnosticStepKind::Subscribe, || {
+            socket
+                .write_message(Message::Text(subscribe.to_string()))
+                .map_err(|error| error.to_string())?;
+            let reply = socket.read_message().map_err(|error| error.to_string())?;
+            Ok(((), reply.to_string()))
+        })?;
+    }
+    step(steps, DiagnosticStepKind::FirstMessage, || {
+        let message = socket.read_message().map_err(|error| error.to_string())?;
+        Ok(((), format!("{} bytes", message.len())))
+    })?;
+    let _ = socket.close(None);
+    Ok(())
+}
+
+/**
+ * Retraces the connection of the venue until the first message of its stream arrives. Blocks for
+ * up to the step timeout per step, the steps following a failed one are not run.
+ */
+pub fn diagnose(probe: &Probe) -> DiagnosticsReport {
+    let mut steps = Vec::new();
+    let ok = run_steps(probe, &mut steps).is_ok();
+    DiagnosticsReport {
+        exchange: probe.exchange.to_string(),
+        url: probe.url.clone(),
+        ok,
+        steps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diagnose, Probe};
+    use keyrock_challenge_proto::orderbook::DiagnosticStepKind;
+    use std::net::TcpListener;
+
+    #[test]
+    fn should_report_steps_until_the_first_failing_one() {
+        // Arrange
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = format!("wss://127.0.0.1:{}/ws", port);
+        // accepts the connection and closes it right away, so the TLS handshake fails
+        let accepting = std::thread::spawn(move || drop(listener.accept()));
+
+        // Act
+        let report = diagnose(&Probe {
+            exchange: "Binance",
+            url,
+            subscribe: None,
+        });
+        accepting.join().unwrap();
+
+        // Assert
+        assert!(!report.ok && report.exchange == "Binance" && report.steps.len() == 3);
+        let kinds: Vec<i32> = report.steps.iter().map(|step| step.kind).collect();
+        assert!(
+            kinds
+                == [
+                    DiagnosticStepKind::Dns as i32,
+                    DiagnosticStepKind::Tcp as i32,
+                    DiagnosticStepKind::Tls as i32
+                ]
+        );
+        assert!(report.steps[0].ok && report.steps[0].detail == "127.0.0.1");
+        assert!(report.steps[1].ok && !report.steps[2].ok);
+    }
+}
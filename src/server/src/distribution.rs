@@ -0,0 +1,108 @@
+//! The reduced fidelity feed for redistributing the summaries externally under the exchanges'
+//! data-licensing constraints. Listeners on the external address get summaries truncated in depth,
+//! with rounded amounts and only after a delay, while the internal listener keeps full fidelity.
+
+use crate::{clock::Clock, spmc::Spmc};
+use keyrock_challenge_proto::orderbook::{Level, Summary};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{mpsc::Receiver, watch, Mutex};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Distribution {
+    /// how many levels per side are redistributed
+    pub depth: usize,
+    /// how many decimals the amounts are rounded to
+    pub amount_decimals: u32,
+    /// how long after publishing internally a summary is redistributed
+    pub delay: Duration,
+}
+
+impl Default for Distribution {
+    fn default() -> Self {
+        Distribution {
+            depth: 5,
+            amount_decimals: 2,
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Distribution {
+    /**
+     * The summary as it may be redistributed. The extensions and the VWAP are dropped since they
+     * are derived from deeper levels than redistributed.
+     */
+    pub fn apply(&self, mut summary: Summary) -> Summary {
+        let factor = 10f64.powi(self.amount_decimals as i32);
+        let round = |levels: &mut Vec<Level>| {
+            levels.truncate(self.depth);
+            for level in levels {
+                level.amount = (level.amount * factor).round() / factor;
+                for contributor in &mut level.contributors {
+                    contributor.amount = (contributor.amount * factor).round() / factor;
+                }
+            }
+        };
+        round(&mut summary.bids);
+        round(&mut summary.asks);
+        summary.extensions.clear();
+        summary.vwap = None;
+        summary
+    }
+}
+
+/**
+ * Redistributes every published summary once its delay passed. The summaries arrive in order and
+ * are all delayed equally, so waiting for one after the other keeps the delay of each.
+ */
+pub async fn run(
+    distribution: Distribution,
+    mut rx: Receiver<Summary>,
+    spmc: Arc<Mutex<Spmc<Summary>>>,
+    latest_summary: watch::Sender<Option<Summary>>,
+    clock: Arc<dyn Clock>,
+) {
+    while let Some(summary) = rx.recv().await {
+        let due = clock.now() + distribution.delay;
+        let summary = distribution.apply(summary);
+        clock
+            .sleep(due.saturating_duration_since(clock.now()))
+            .await;
+        latest_summary.send_replace(Some(summary.clone()));
+        spmc.lock().await.broadcast(summary).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Distribution;
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+    use std::time::Duration;
+
+    #[test]
+    fn should_truncate_depth_and_round_amounts() {
+        // Arrange
+        let distribution = Distribution {
+            depth: 2,
+            amount_decimals: 1,
+            delay: Duration::ZERO,
+        };
+        let level = |amount: f64| Level {
+            price: 1.,
+            amount,
+            ..Default::default()
+        };
+        let summary = Summary {
+            bids: vec![level(1.26), level(0.04), level(3.)],
+            asks: vec![level(2.)],
+            ..Default::default()
+        };
+
+        // Act
+        let redistributed = distribution.apply(summary);
+
+        // Assert
+        assert!(redistributed.bids.len() == 2 && redistributed.asks.len() == 1);
+        assert!(redistributed.bids[0].amount == 1.3 && redistributed.bids[1].amount == 0.);
+    }
+}
@@ -0,0 +1,34 @@
+use std::{str::FromStr, time::Duration};
+
+/**
+ * What the aggregator does with a venue whose latest book has an empty side, parsed from `skip`,
+ * `one-sided` or `hold:<secs>`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyBookPolicy {
+    /// leave the venue out of the merge until it sends a complete book again
+    #[default]
+    Skip,
+    /// merge whatever side the venue still has, the summary has no spread if one side is empty overall
+    OneSided,
+    /// keep merging the venue's last complete book for the given time, then skip the venue
+    Hold(Duration),
+}
+
+impl FromStr for EmptyBookPolicy {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "skip" => Ok(EmptyBookPolicy::Skip),
+            "one-sided" => Ok(EmptyBookPolicy::OneSided),
+            _ => match raw.split_once(':') {
+                Some(("hold", secs)) => secs
+                    .parse::<u64>()
+                    .map(|secs| EmptyBookPolicy::Hold(Duration::from_secs(secs)))
+                    .map_err(|_| ()),
+                _ => Err(()),
+            },
+        }
+    }
+}
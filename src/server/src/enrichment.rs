@@ -0,0 +1,117 @@
+//! Enrichment plugins deriving custom fields from the published summaries. Every enricher adds its
+//! result to `Summary.extensions` under its name, so a deployment can ship derived data to its
+//! clients without forking the core messages.
+
+use keyrock_challenge_proto::orderbook::{BookDepth, Extension, Summary};
+use prost::Message;
+use std::{fmt::Debug, str::FromStr};
+
+pub trait Enricher: Debug + Send + Sync {
+    /// the key of the extension in the summary
+    fn name(&self) -> &'static str;
+
+    /// None leaves the extension out of this summary
+    fn enrich(&self, summary: &Summary) -> Option<Extension>;
+}
+
+/**
+ * Packs the message the way `google.protobuf.Any` does.
+ */
+pub fn pack<M: Message>(full_name: &str, message: &M) -> Extension {
+    Extension {
+        type_url: format!("type.googleapis.com/{}", full_name),
+        value: message.encode_to_vec(),
+    }
+}
+
+/**
+ * The enrichers built into the server, selected with `--enrich <name>`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnricherKind {
+    BookDepth,
+}
+
+impl FromStr for EnricherKind {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "book-depth" => Ok(EnricherKind::BookDepth),
+            _ => Err(()),
+        }
+    }
+}
+
+impl EnricherKind {
+    pub fn build(self) -> Box<dyn Enricher> {
+        match self {
+            EnricherKind::BookDepth => Box::new(BookDepthEnricher),
+        }
+    }
+}
+
+/**
+ * Sums up the amounts on each side of the merged book.
+ */
+#[derive(Debug)]
+pub struct BookDepthEnricher;
+
+impl Enricher for BookDepthEnricher {
+    fn name(&self) -> &'static str {
+        "book_depth"
+    }
+
+    fn enrich(&self, summary: &Summary) -> Option<Extension> {
+        let depth = BookDepth {
+            bid_amount: summary.bids.iter().map(|level| level.amount).sum(),
+            ask_amount: summary.asks.iter().map(|level| level.amount).sum(),
+        };
+        Some(pack("orderbook.BookDepth", &depth))
+    }
+}
+
+/**
+ * Runs every enricher on the summary and stores the extensions they returned.
+ */
+pub fn enrich(enrichers: &[Box<dyn Enricher>], summary: &mut Summary) {
+    for enricher in enrichers {
+        if let Some(extension) = enricher.enrich(summary) {
+            summary
+                .extensions
+                .insert(enricher.name().to_string(), extension);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enrich, EnricherKind};
+    use keyrock_challenge_proto::orderbook::{BookDepth, Level, Summary};
+    use prost::Message;
+
+    #[test]
+    fn should_add_packed_extension_of_every_enricher() {
+        // Arrange
+        let level = |amount: f64| Level {
+            amount,
+            ..Default::default()
+        };
+        let mut summary = Summary {
+            bids: vec![level(1.), level(2.5)],
+            asks: vec![level(4.)],
+            ..Default::default()
+        };
+        let enrichers = vec!["book-depth".parse::<EnricherKind>().unwrap().build()];
+
+        // Act
+        enrich(&enrichers, &mut summary);
+
+        // Assert
+        let extension = &summary.extensions["book_depth"];
+        assert!(extension.type_url == "type.googleapis.com/orderbook.BookDepth");
+        let depth = BookDepth::decode(extension.value.as_slice()).unwrap();
+        assert!(depth.bid_amount == 3.5 && depth.ask_amount == 4.);
+        assert!("vwap".parse::<EnricherKind>().is_err());
+    }
+}
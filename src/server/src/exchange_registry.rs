@@ -0,0 +1,140 @@
+use keyrock_challenge_proto::orderbook::{Exchange, Level};
+use std::{collections::HashMap, str::FromStr};
+
+/// the compact id of every supported exchange together with its internal name, which identifies it
+/// in the configuration, the metrics and the admin APIs, and labels its levels unless overridden
+const EXCHANGES: [(Exchange, &str); 5] = [
+    (Exchange::Binance, "Binance"),
+    (Exchange::Bitstamp, "Bitstamp"),
+    (Exchange::Kraken, "Kraken"),
+    (Exchange::Coinbase, "Coinbase"),
+    (Exchange::Okx, "OKX"),
+];
+
+pub fn exchange_id(name: &str) -> Exchange {
+    EXCHANGES
+        .iter()
+        .find(|(_, known)| *known == name)
+        .map_or(Exchange::Unspecified, |(id, _)| *id)
+}
+
+pub fn name(exchange_id: Exchange) -> Option<&'static str> {
+    EXCHANGES
+        .iter()
+        .find(|(id, _)| *id == exchange_id)
+        .map(|(_, name)| *name)
+}
+
+/**
+ * The internal name of the exchange behind a possibly relabeled level.
+ */
+pub fn internal_name(exchange_id: i32, label: &str) -> String {
+    Exchange::from_i32(exchange_id)
+        .and_then(name)
+        .unwrap_or(label)
+        .to_string()
+}
+
+/**
+ * Overrides the label of an exchange on the wire, parsed from `<exchange>=<label>`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayName {
+    pub exchange: String,
+    pub label: String,
+}
+
+impl FromStr for DisplayName {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (exchange, label) = raw.split_once('=').ok_or(())?;
+        if exchange_id(exchange) == Exchange::Unspecified || label.is_empty() {
+            return Err(());
+        }
+        Ok(DisplayName {
+            exchange: exchange.to_string(),
+            label: label.to_string(),
+        })
+    }
+}
+
+/**
+ * The labels the exchanges are published with, e.g. `BINANCE-SPOT` instead of `Binance`.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct DisplayNames {
+    labels: HashMap<String, String>,
+}
+
+impl DisplayNames {
+    pub fn new(overrides: Vec<DisplayName>) -> Self {
+        DisplayNames {
+            labels: overrides
+                .into_iter()
+                .map(|display_name| (display_name.exchange, display_name.label))
+                .collect(),
+        }
+    }
+
+    pub fn label<'a>(&'a self, exchange: &'a str) -> &'a str {
+        self.labels.get(exchange).map_or(exchange, String::as_str)
+    }
+
+    pub fn relabel(&self, levels: &mut [Level]) {
+        if self.labels.is_empty() {
+            return;
+        }
+        for level in levels {
+            if let Some(label) = self.labels.get(&level.exchange) {
+                level.exchange = label.clone();
+            }
+            for contributor in &mut level.contributors {
+                if let Some(label) = self.labels.get(&contributor.exchange) {
+                    contributor.exchange = label.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exchange_id, internal_name, name, DisplayName, DisplayNames};
+    use keyrock_challenge_proto::orderbook::{Exchange, Level};
+
+    #[test]
+    fn should_map_between_id_and_name() {
+        assert!(exchange_id("Bitstamp") == Exchange::Bitstamp);
+        assert!(exchange_id("Gemini") == Exchange::Unspecified);
+        assert!(name(Exchange::Binance) == Some("Binance"));
+        assert!(name(Exchange::Unspecified).is_none());
+    }
+
+    #[test]
+    fn should_relabel_levels_and_recover_internal_name() {
+        // Arrange
+        let display_names = DisplayNames::new(vec!["Binance=BINANCE-SPOT".parse().unwrap()]);
+        let mut levels = vec![
+            Level {
+                exchange: "Binance".to_string(),
+                exchange_id: Exchange::Binance as i32,
+                ..Default::default()
+            },
+            Level {
+                exchange: "Bitstamp".to_string(),
+                exchange_id: Exchange::Bitstamp as i32,
+                ..Default::default()
+            },
+        ];
+
+        // Act
+        display_names.relabel(&mut levels);
+
+        // Assert
+        assert!(levels[0].exchange == "BINANCE-SPOT" && levels[1].exchange == "Bitstamp");
+        assert!(internal_name(levels[0].exchange_id, &levels[0].exchange) == "Binance");
+        assert!(display_names.label("Bitstamp") == "Bitstamp");
+        assert!("Gemini=GEMINI".parse::<DisplayName>().is_err());
+    }
+}
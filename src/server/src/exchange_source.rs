@@ -0,0 +1,158 @@
+//! The registry of the exchanges the server aggregates. The position of an exchange in the registry
+//! is its venue id in the aggregator, so adding a venue only takes an [`ExchangeConnector`] and
+//! another entry here. The server aggregates the exchanges selected with `--exchange`, all by default.
+
+use crate::{
+    aggregator::Aggregator,
+    binance_spot, bitstamp_spot, coinbase_spot,
+    connector_sdk::{self, ExchangeConnector, ReconnectPolicy},
+    diagnostics::Probe,
+    exchange_status::StatusEndpoint,
+    kraken_spot, okx_spot,
+    source_selector::SourceKind,
+    trade_through::Trade,
+};
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokio::sync::{mpsc::Sender, Mutex};
+
+type Trades =
+    fn(String, Sender<Trade>, ReconnectPolicy) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+#[derive(Debug, Clone)]
+pub struct ExchangeSource {
+    pub exchange: &'static str,
+    pub kind: SourceKind,
+    /// creates the connector of a session of the book stream
+    pub connector: fn() -> Box<dyn ExchangeConnector>,
+    /// runs the trade stream of the exchange for the given symbol, sending its executions
+    pub trades: Trades,
+    /// the deepest book the connector can deliver
+    pub max_depth: usize,
+    /// polled for announced maintenance, if the exchange has a status API
+    pub status_endpoint: Option<StatusEndpoint>,
+    /// how connectivity diagnostics reach the stream of the exchange for the given symbol
+    pub probe: fn(&str) -> Probe,
+}
+
+impl ExchangeSource {
+    /**
+     * Runs the connector for the symbol of the aggregator, reporting with the given source id.
+     */
+    pub fn connect(
+        &self,
+        source_id: usize,
+        aggregator: Arc<Mutex<Aggregator>>,
+        policy: ReconnectPolicy,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        connector_sdk::run_stream(self.exchange, self.connector, source_id, aggregator, policy)
+    }
+}
+
+/**
+ * The sources of the given exchanges in registry order, all of them if none are given. Fails with
+ * the first exchange that is not in the registry.
+ */
+pub fn registry(exchanges: &[String]) -> Result<Vec<ExchangeSource>, String> {
+    let sources = all();
+    if let Some(unknown) = exchanges
+        .iter()
+        .find(|exchange| !sources.iter().any(|source| source.exchange == *exchange))
+    {
+        return Err(unknown.clone());
+    }
+    Ok(sources
+        .into_iter()
+        .filter(|source| exchanges.is_empty() || exchanges.iter().any(|e| e == source.exchange))
+        .collect())
+}
+
+fn all() -> Vec<ExchangeSource> {
+    vec![
+        ExchangeSource {
+            exchange: "Binance",
+            kind: SourceKind::DiffBook,
+            connector: binance_spot::connector,
+            trades: |symbol, tx, policy| Box::pin(binance_spot::run_trades(symbol, tx, policy)),
+            max_depth: binance_spot::MAX_DEPTH,
+            status_endpoint: Some(binance_spot::STATUS_ENDPOINT),
+            probe: binance_spot::probe,
+        },
+        ExchangeSource {
+            exchange: "Bitstamp",
+            kind: SourceKind::DiffBook,
+            connector: bitstamp_spot::connector,
+            trades: |symbol, tx, policy| Box::pin(bitstamp_spot::run_trades(symbol, tx, policy)),
+            max_depth: bitstamp_spot::MAX_DEPTH,
+            status_endpoint: Some(bitstamp_spot::STATUS_ENDPOINT),
+            probe: bitstamp_spot::probe,
+        },
+        ExchangeSource {
+            exchange: "Kraken",
+            kind: SourceKind::PartialBook,
+            connector: kraken_spot::connector,
+            trades: |symbol, tx, policy| Box::pin(kraken_spot::run_trades(symbol, tx, policy)),
+            max_depth: kraken_spot::MAX_DEPTH,
+            status_endpoint: Some(kraken_spot::STATUS_ENDPOINT),
+            probe: kraken_spot::probe,
+        },
+        ExchangeSource {
+            exchange: "Coinbase",
+            kind: SourceKind::PartialBook,
+            connector: coinbase_spot::connector,
+            trades: |symbol, tx, policy| Box::pin(coinbase_spot::run_trades(symbol, tx, policy)),
+            max_depth: coinbase_spot::MAX_DEPTH,
+            status_endpoint: Some(coinbase_spot::STATUS_ENDPOINT),
+            probe: coinbase_spot::probe,
+        },
+        ExchangeSource {
+            exchange: "OKX",
+            kind: SourceKind::PartialBook,
+            connector: okx_spot::connector,
+            trades: |symbol, tx, policy| Box::pin(okx_spot::run_trades(symbol, tx, policy)),
+            max_depth: okx_spot::MAX_DEPTH,
+            status_endpoint: Some(okx_spot::STATUS_ENDPOINT),
+            probe: okx_spot::probe,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::registry;
+    use crate::source_selector::{SourceKind, SourceSelector};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_select_the_configured_exchanges() {
+        let exchanges = |sources: Vec<super::ExchangeSource>| -> Vec<&'static str> {
+            sources.iter().map(|source| source.exchange).collect()
+        };
+        let selected = ["OKX".to_string(), "Binance".to_string()];
+
+        assert!(exchanges(registry(&selected).unwrap()) == ["Binance", "OKX"]);
+        assert!(registry(&[]).unwrap().len() == 5);
+        assert!(registry(&["Gemini".to_string()]).unwrap_err() == "Gemini");
+    }
+
+    #[test]
+    fn should_prefer_the_diff_books_of_binance_and_bitstamp() {
+        // Arrange
+        let sources = registry(&["Binance".to_string(), "Bitstamp".to_string()]).unwrap();
+        let mut selector = SourceSelector::new(Duration::from_secs(1));
+        let diff_books: Vec<usize> = (sources.iter().enumerate())
+            .map(|(venue_id, source)| selector.register(venue_id, source.kind))
+            .collect();
+        let bitstamp_partial = selector.register(1, SourceKind::PartialBook);
+        let now = Instant::now();
+
+        // Act
+        let fed_by_diff_book = selector.accept(diff_books[1], now);
+        let partial = selector.accept(bitstamp_partial, now);
+
+        // Assert
+        assert!(sources
+            .iter()
+            .all(|source| source.kind == SourceKind::DiffBook));
+        assert!(fed_by_diff_book == Some(1) && partial.is_none());
+    }
+}
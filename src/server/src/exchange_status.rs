@@ -0,0 +1,162 @@
+//! Polls the system status APIs of the exchanges. A venue announcing maintenance is degraded, and
+//! thereby left out of the aggregation, before its socket drops.
+
+use crate::{aggregator::Aggregator, clock::Clock, log};
+use keyrock_challenge_proto::orderbook::VenueStatus;
+use native_tls::TlsConnector;
+use serde_json::Value;
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Mutex;
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+ * The status API of an exchange together with the function interpreting its JSON response.
+ */
+#[derive(Debug, Clone)]
+pub struct StatusEndpoint {
+    pub exchange: &'static str,
+    pub url: &'static str,
+    pub parse: fn(&Value) -> Result<VenueStatus, ()>,
+}
+
+/**
+ * A minimal blocking HTTPS GET. HTTP/1.0 is requested so the response is neither chunked nor
+ * kept alive and the body simply lasts until the connection closes.
+ */
+pub fn http_get(url: &str) -> io::Result<String> {
+    let invalid = |error: &str| io::Error::new(io::ErrorKind::InvalidData, error.to_string());
+    let url = Url::parse(url).map_err(|_| invalid("Invalid url"))?;
+    let host = url.host_str().ok_or_else(|| invalid("Url without host"))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let tcp = TcpStream::connect((host, port))?;
+    tcp.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    tcp.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let connector = TlsConnector::new().map_err(|error| invalid(&error.to_string()))?;
+    let mut stream = connector
+        .connect(host, tcp)
+        .map_err(|error| invalid(&error.to_string()))?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\nUser-Agent: keyrock_challenge\r\n\r\n",
+        &url[url::Position::BeforePath..],
+        host
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid("Malformed response"))?;
+    match head.split(' ').nth(1) {
+        Some("200") => Ok(body.to_string()),
+        _ => Err(invalid(head.lines().next().unwrap_or_default())),
+    }
+}
+
+async fn poll(endpoint: &StatusEndpoint) -> VenueStatus {
+    let url = endpoint.url;
+    let body = tokio::task::spawn_blocking(move || http_get(url))
+        .await
+        .expect("Status request panicked");
+
+    let status = body.map_err(|error| error.to_string()).and_then(|body| {
+        serde_json::from_str::<Value>(&body)
+            .map_err(|error| error.to_string())
+            .and_then(|raw| (endpoint.parse)(&raw).map_err(|_| "Unexpected payload".to_string()))
+    });
+    match status {
+        Ok(status) => status,
+        Err(error) => {
+            log::warning!(
+                "Unable to poll the {} system status: {}",
+                endpoint.exchange,
+                error
+            );
+            VenueStatus::Unknown
+        }
+    }
+}
+
+/**
+ * Polls every endpoint once per interval and passes the statuses to the aggregator.
+ */
+pub async fn run(
+    endpoints: Vec<StatusEndpoint>,
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        for endpoint in &endpoints {
+            let status = poll(endpoint).await;
+            let previous = aggregator_arc
+                .lock()
+                .await
+                .set_venue_status(endpoint.exchange, status);
+            let maintenance_changed = |previous: VenueStatus| {
+                previous != status
+                    && (previous == VenueStatus::Maintenance || status == VenueStatus::Maintenance)
+            };
+            if previous.is_ok_and(maintenance_changed) {
+                log::warning!(
+                    "{} reports its system status as {:?}",
+                    endpoint.exchange,
+                    status
+                );
+            }
+        }
+        clock.sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{binance_spot, bitstamp_spot, coinbase_spot, kraken_spot, okx_spot};
+    use keyrock_challenge_proto::orderbook::VenueStatus;
+    use serde_json::json;
+
+    #[test]
+    fn should_parse_system_status_of_every_exchange() {
+        let binance = binance_spot::STATUS_ENDPOINT.parse;
+        let bitstamp = bitstamp_spot::STATUS_ENDPOINT.parse;
+        let kraken = kraken_spot::STATUS_ENDPOINT.parse;
+        let coinbase = coinbase_spot::STATUS_ENDPOINT.parse;
+        let okx = okx_spot::STATUS_ENDPOINT.parse;
+
+        assert!(binance(&json!({"status": 0, "msg": "normal"})) == Ok(VenueStatus::Operational));
+        assert!(
+            binance(&json!({"status": 1, "msg": "system_maintenance"}))
+                == Ok(VenueStatus::Maintenance)
+        );
+        assert!(binance(&json!({"msg": "normal"})).is_err());
+        assert!(bitstamp(&json!({"scheduled_maintenances": []})) == Ok(VenueStatus::Operational));
+        assert!(
+            bitstamp(&json!({"scheduled_maintenances": [{"name": "Upgrade"}]}))
+                == Ok(VenueStatus::Maintenance)
+        );
+        assert!(bitstamp(&json!({})).is_err());
+        assert!(
+            kraken(&json!({"error": [], "result": {"status": "online"}}))
+                == Ok(VenueStatus::Operational)
+        );
+        assert!(
+            kraken(&json!({"error": [], "result": {"status": "cancel_only"}}))
+                == Ok(VenueStatus::Maintenance)
+        );
+        assert!(kraken(&json!({"error": ["EService:Unavailable"]})).is_err());
+        assert!(coinbase(&json!({"scheduled_maintenances": []})) == Ok(VenueStatus::Operational));
+        assert!(
+            okx(&json!({"code": "0", "data": [{"state": "ongoing"}]}))
+                == Ok(VenueStatus::Maintenance)
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! Keeps the pipelines of the symbols from failing each other. The connectors read their sockets
+//! blocking, so each of them runs on a thread of its own: on a worker of the shared runtime, a read
+//! stalling in one symbol's connector would hold up the tasks of every other symbol scheduled on
+//! that worker. The tasks of a pipeline are restarted on their own when they panic, while the other
+//! tasks of the symbol and the pipelines of the other symbols keep running.
+//!
+//! A task crashing again right after its restart is held off twice as long as before, up to
+//! `MAX_BACKOFF`. Once it crashed `ESCALATE_AFTER` times in a row it is escalated with an alert, while
+//! it keeps being restarted. The [`Supervisor`] counts the crashes of the tasks of all domains for
+//! the stats.
+//!
+//! Every task runs within a span carrying its name, nested in the span of its domain, so whatever a
+//! task of a symbol's pipeline logs carries the symbol.
+
+use crate::clock::Clock;
+use crate::log;
+use futures::FutureExt;
+use keyrock_challenge_proto::orderbook::SupervisedTask;
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+};
+use tracing::{Instrument, Span};
+
+/// how long a task that panicked is held off before it is started again, doubled with every crash
+/// in a row
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// a task running this long before it crashed is restarted as if it crashed for the first time
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+/// the crashes in a row after which a task is escalated
+const ESCALATE_AFTER: u32 = 5;
+
+pub fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown cause", String::as_str),
+    }
+}
+
+/**
+ * How long a task is held off before its restart after the given number of crashes in a row.
+ */
+fn backoff(crashes_in_a_row: u32) -> Duration {
+    let doublings = crashes_in_a_row.saturating_sub(1).min(u32::BITS - 1);
+    INITIAL_BACKOFF
+        .saturating_mul(1 << doublings)
+        .min(MAX_BACKOFF)
+}
+
+/**
+ * Counts the crashes of the supervised tasks of all failure domains.
+ */
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    /// by task name, only those that crashed at least once
+    tasks: Arc<Mutex<BTreeMap<String, SupervisedTask>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Supervisor {
+    pub fn new(clock: Arc<dyn Clock>) -> Supervisor {
+        Supervisor {
+            tasks: Arc::new(Mutex::new(BTreeMap::new())),
+            clock,
+        }
+    }
+
+    /**
+     * The tasks that crashed at least once, by name.
+     */
+    pub async fn tasks(&self) -> Vec<SupervisedTask> {
+        self.tasks.lock().await.values().cloned().collect()
+    }
+
+    /**
+     * Records the crash of the task, returns whether it was escalated by this crash.
+     */
+    async fn crashed(
+        &self,
+        name: &str,
+        panic: &str,
+        crashes_in_a_row: u32,
+        restartable: bool,
+    ) -> bool {
+        let mut tasks = self.tasks.lock().await;
+        let task = tasks
+            .entry(name.to_string())
+            .or_insert_with(|| SupervisedTask {
+                name: name.to_string(),
+                ..Default::default()
+            });
+        let escalated = !restartable || crashes_in_a_row >= ESCALATE_AFTER;
+        let newly_escalated = escalated && !task.escalated;
+        task.restarts += restartable as u64;
+        task.crashes_in_a_row = crashes_in_a_row;
+        task.escalated = escalated;
+        task.last_panic = panic.to_string();
+        task.last_crash_unix_ms = (self.clock.system_now())
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        task.restartable = restartable;
+        newly_escalated
+    }
+}
+
+/**
+ * Runs the task created by the factory, starting it again with a backoff whenever it panics.
+ */
+async fn restarting<F, Fut>(name: String, task: F, supervisor: Supervisor)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut crashes_in_a_row = 0;
+    loop {
+        let started = supervisor.clock.now();
+        let panic = match AssertUnwindSafe(task()).catch_unwind().await {
+            Ok(()) => return,
+            Err(panic) => panic,
+        };
+        crashes_in_a_row = match supervisor.clock.now() - started >= HEALTHY_AFTER {
+            true => 1,
+            false => crashes_in_a_row + 1,
+        };
+        let panic = panic_message(panic.as_ref());
+        let backoff = backoff(crashes_in_a_row);
+        // held off from the crash on, not from when it was recorded
+        let held_off = supervisor.clock.sleep(backoff);
+        match supervisor
+            .crashed(&name, panic, crashes_in_a_row, true)
+            .await
+        {
+            true => log::alert!(
+                "{} crashed {} times in a row ({}), restarting it in {}ms",
+                name,
+                crashes_in_a_row,
+                panic,
+                backoff.as_millis()
+            ),
+            false => log::warning!(
+                "{} panicked ({}), restarting it in {}ms",
+                name,
+                panic,
+                backoff.as_millis()
+            ),
+        }
+        held_off.await;
+    }
+}
+
+/**
+ * The tasks of a symbol's pipeline. Dropping the domain stops them, those on the shared runtime
+ * right away, those on a thread of their own once they yield, i.e. after a blocking read returned.
+ */
+#[derive(Debug)]
+pub struct FailureDomain {
+    tasks: Vec<JoinHandle<()>>,
+    /// dropped along with the domain, which the threads of the domain wait for
+    stop: watch::Sender<()>,
+    supervisor: Supervisor,
+    /// the parent of the spans of the tasks
+    span: Span,
+}
+
+impl FailureDomain {
+    pub fn new(supervisor: Supervisor) -> FailureDomain {
+        FailureDomain {
+            tasks: Vec::new(),
+            stop: watch::channel(()).0,
+            supervisor,
+            span: Span::none(),
+        }
+    }
+
+    /**
+     * The domain of the pipeline of the symbol, whose tasks log with the symbol as a field.
+     */
+    pub fn for_symbol(supervisor: Supervisor, symbol: &str) -> FailureDomain {
+        let mut domain = FailureDomain::new(supervisor);
+        domain.span = tracing::info_span!("pipeline", symbol);
+        domain
+    }
+
+    fn task_span(&self, name: &str) -> Span {
+        tracing::info_span!(parent: &self.span, "task", name)
+    }
+
+    /**
+     * Runs a task that cannot be restarted, e.g. as it owns the receiver of a channel, on the shared
+     * runtime until the domain is dropped. A crash is escalated right away.
+     */
+    pub fn spawn<Fut>(&mut self, name: String, task: Fut)
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (supervisor, span) = (self.supervisor.clone(), self.task_span(&name));
+        self.tasks.push(tokio::spawn(
+            async move {
+                if let Err(panic) = AssertUnwindSafe(task).catch_unwind().await {
+                    let panic = panic_message(panic.as_ref());
+                    supervisor.crashed(&name, panic, 1, false).await;
+                    log::alert!("{} crashed ({}) and cannot be restarted", name, panic);
+                }
+            }
+            .instrument(span),
+        ));
+    }
+
+    /**
+     * Runs the task created by the factory on the shared runtime, starting it again whenever it
+     * panics. Meant for tasks that never block, the others belong on a thread of their own.
+     */
+    pub fn supervise<F, Fut>(&mut self, name: String, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (supervisor, span) = (self.supervisor.clone(), self.task_span(&name));
+        self.tasks.push(tokio::spawn(
+            restarting(name, task, supervisor).instrument(span),
+        ));
+    }
+
+    /**
+     * Runs the task created by the factory on a thread of its own with a single threaded runtime,
+     * starting it again whenever it panics. The thread ends once the task returned or the domain was
+     * dropped.
+     */
+    pub fn spawn_isolated<F, Fut>(&mut self, name: String, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()>,
+    {
+        let mut stop = self.stop.subscribe();
+        let (supervisor, span) = (self.supervisor.clone(), self.task_span(&name));
+        thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("Unable to build the runtime of a task");
+                runtime.block_on(async {
+                    tokio::select! {
+                        _ = restarting(name, task, supervisor).instrument(span) => {}
+                        // only fails once the domain is dropped, nothing is ever sent
+                        _ = stop.changed() => {}
+                    }
+                });
+            })
+            .expect("Unable to spawn the thread of a task");
+    }
+}
+
+impl Drop for FailureDomain {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff, FailureDomain, Supervisor, ESCALATE_AFTER, MAX_BACKOFF};
+    use crate::clock::{self, ManualClock};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::sync::mpsc;
+
+    fn domain() -> FailureDomain {
+        FailureDomain::new(Supervisor::new(clock::system()))
+    }
+
+    #[tokio::test]
+    async fn should_not_stall_other_symbols_while_one_blocks() {
+        // Arrange
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut domains = Vec::new();
+
+        // Act
+        // a connector stuck in a blocking read, with more of them than the test has threads
+        for symbol in ["ethbtc", "btcusdt"] {
+            let mut domain = domain();
+            domain.spawn_isolated(format!("{} stalled", symbol), || async {
+                std::thread::sleep(Duration::from_secs(60));
+            });
+            domains.push(domain);
+        }
+        let mut domain = domain();
+        domain.spawn_isolated("ltcbtc".to_string(), move || {
+            let tx = tx.clone();
+            async move {
+                for tick in 0..3 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    tx.send(tick).unwrap();
+                }
+            }
+        });
+        let ticks = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut ticks = Vec::new();
+            while let Some(tick) = rx.recv().await {
+                ticks.push(tick);
+            }
+            ticks
+        })
+        .await;
+
+        // Assert
+        assert!(ticks.unwrap() == [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn should_restart_a_panicking_task_on_its_own() {
+        // Arrange
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let task = |attempts: Arc<AtomicUsize>, tx: mpsc::UnboundedSender<&'static str>| {
+            move || {
+                let (attempts, tx) = (attempts.clone(), tx.clone());
+                async move {
+                    if attempts.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                        panic!("crashed");
+                    }
+                    tx.send("restarted").unwrap();
+                }
+            }
+        };
+        let supervisor = Supervisor::new(clock::system());
+        let mut domain = FailureDomain::new(supervisor.clone());
+
+        // Act
+        domain.spawn_isolated("isolated".to_string(), task(attempts.clone(), tx.clone()));
+        let first = rx.recv().await;
+        domain.supervise("supervised".to_string(), task(attempts.clone(), tx));
+        let second = rx.recv().await;
+
+        // Assert
+        assert!(first == Some("restarted") && second == Some("restarted"));
+        assert!(attempts.load(Ordering::SeqCst) == 4);
+        let tasks = supervisor.tasks().await;
+        assert!(tasks.len() == 2 && tasks.iter().all(|task| task.restarts == 1));
+        assert!(tasks[0].name == "isolated" && tasks[0].last_panic == "crashed");
+    }
+
+    #[test]
+    fn should_double_the_backoff_with_every_crash_in_a_row() {
+        assert!(backoff(1) == Duration::from_millis(500));
+        assert!(backoff(2) == Duration::from_secs(1));
+        assert!(backoff(4) == Duration::from_secs(4));
+        assert!(backoff(7) == MAX_BACKOFF && backoff(u32::MAX) == MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn should_escalate_a_task_crashing_in_a_row() {
+        // Arrange
+        let clock = Arc::new(ManualClock::new());
+        let supervisor = Supervisor::new(clock.clone());
+        let mut domain = FailureDomain::new(supervisor.clone());
+        let crashes_in_a_row = || async {
+            let tasks = supervisor.tasks().await;
+            tasks.first().map_or(0, |task| task.crashes_in_a_row)
+        };
+
+        // Act
+        domain.supervise("crashing".to_string(), || async { panic!("crashed") });
+        let mut escalated = Vec::new();
+        for crashes in 1..=ESCALATE_AFTER {
+            while crashes_in_a_row().await < crashes {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            escalated.push(supervisor.tasks().await[0].escalated);
+            clock.advance(MAX_BACKOFF);
+        }
+        domain.spawn("unrestartable".to_string(), async { panic!("crashed") });
+        while supervisor.tasks().await.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // Assert
+        assert!(escalated == [false, false, false, false, true]);
+        let tasks = supervisor.tasks().await;
+        assert!(tasks[0].restarts >= ESCALATE_AFTER as u64);
+        assert!(tasks[1].name == "unrestartable" && tasks[1].escalated && !tasks[1].restartable);
+        assert!(tasks[1].restarts == 0);
+    }
+
+    #[tokio::test]
+    async fn should_stop_the_tasks_once_dropped() {
+        // Arrange
+        let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+        let mut domain = domain();
+        let pending = |tx: mpsc::UnboundedSender<()>| async move {
+            let _tx = tx;
+            futures::future::pending::<()>().await
+        };
+        domain.spawn("spawned".to_string(), pending(tx.clone()));
+        let supervised_tx = tx.clone();
+        domain.supervise("supervised".to_string(), move || {
+            pending(supervised_tx.clone())
+        });
+        domain.spawn_isolated("isolated".to_string(), move || pending(tx.clone()));
+
+        // Act
+        drop(domain);
+        // the channel closes once every task dropped its sender
+        let stopped = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+
+        // Assert
+        assert!(stopped == Ok(None));
+    }
+}
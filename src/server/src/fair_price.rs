@@ -0,0 +1,165 @@
+//! Derives a single fair price from every merged tick for consumers who do not want the full ladder.
+//! Each venue's microprice leans from the mid towards the side with less amount at the top of the
+//! book, where the price is more likely to move next. The microprices of the venues are blended by
+//! how much of the merged book's liquidity the venues provide.
+
+use crate::spmc::Spmc;
+use keyrock_challenge_proto::orderbook::{FairPrice, Level, Summary, VenueFairPrice};
+use std::sync::Arc;
+use tokio::sync::{mpsc::Receiver, Mutex};
+
+#[derive(Debug, Default)]
+struct Venue<'a> {
+    best_bid: Option<&'a Level>,
+    best_ask: Option<&'a Level>,
+    liquidity: f64,
+}
+
+fn microprice(best_bid: &Level, best_ask: &Level) -> f64 {
+    let amount = best_bid.amount + best_ask.amount;
+    match amount > 0. {
+        true => (best_bid.price * best_ask.amount + best_ask.price * best_bid.amount) / amount,
+        false => (best_bid.price + best_ask.price) / 2.,
+    }
+}
+
+/**
+ * The fair price of the summary, none if no venue has both a bid and an ask in it.
+ */
+pub fn fair_price(summary: &Summary) -> Option<FairPrice> {
+    // the levels are sorted, so the first level of a venue on a side is its best
+    let mut venues: Vec<(&str, Venue)> = Vec::new();
+    let bids = summary.bids.iter().map(|level| (level, true));
+    let asks = summary.asks.iter().map(|level| (level, false));
+    for (level, bid) in bids.chain(asks) {
+        let index = match venues
+            .iter()
+            .position(|(exchange, _)| *exchange == level.exchange)
+        {
+            Some(index) => index,
+            None => {
+                venues.push((&level.exchange, Venue::default()));
+                venues.len() - 1
+            }
+        };
+        let venue = &mut venues[index].1;
+        match bid {
+            true => venue.best_bid.get_or_insert(level),
+            false => venue.best_ask.get_or_insert(level),
+        };
+        venue.liquidity += level.amount;
+    }
+
+    let contributing: Vec<(&str, f64, f64)> = venues
+        .iter()
+        .filter_map(|(exchange, venue)| match (venue.best_bid, venue.best_ask) {
+            (Some(best_bid), Some(best_ask)) => {
+                Some((*exchange, microprice(best_bid, best_ask), venue.liquidity))
+            }
+            _ => None,
+        })
+        .collect();
+    if contributing.is_empty() {
+        return None;
+    }
+    let liquidity: f64 = contributing.iter().map(|(_, _, liquidity)| liquidity).sum();
+    let weight = |venue_liquidity: f64| match liquidity > 0. {
+        true => venue_liquidity / liquidity,
+        false => 1. / contributing.len() as f64,
+    };
+
+    let bid_amount: f64 = summary.bids.iter().map(|level| level.amount).sum();
+    let ask_amount: f64 = summary.asks.iter().map(|level| level.amount).sum();
+    let imbalance = match bid_amount + ask_amount > 0. {
+        true => (bid_amount - ask_amount) / (bid_amount + ask_amount),
+        false => 0.,
+    };
+
+    Some(FairPrice {
+        symbol: summary.symbol.clone(),
+        sequence: summary.sequence,
+        fair_price: contributing
+            .iter()
+            .map(|(_, microprice, liquidity)| microprice * weight(*liquidity))
+            .sum(),
+        imbalance,
+        venues: contributing
+            .iter()
+            .map(|(exchange, microprice, liquidity)| VenueFairPrice {
+                exchange: exchange.to_string(),
+                microprice: *microprice,
+                weight: weight(*liquidity),
+            })
+            .collect(),
+    })
+}
+
+/**
+ * Derives the fair price of every published summary and publishes it.
+ */
+pub async fn run(mut rx: Receiver<Summary>, spmc: Arc<Mutex<Spmc<FairPrice>>>) {
+    while let Some(summary) = rx.recv().await {
+        if let Some(fair_price) = fair_price(&summary) {
+            let mut spmc = spmc.lock().await;
+            if !spmc.is_empty() {
+                spmc.broadcast(fair_price).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fair_price;
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_blend_venue_microprices_by_liquidity() {
+        // Arrange
+        let summary = Summary {
+            bids: vec![
+                level("Binance", 100., 3.),
+                level("Bitstamp", 99., 1.),
+                level("Binance", 98., 1.),
+            ],
+            asks: vec![level("Binance", 102., 1.), level("Bitstamp", 103., 1.)],
+            sequence: 7,
+            ..Default::default()
+        };
+
+        // Act
+        let fair_price = fair_price(&summary).unwrap();
+
+        // Assert
+        // Binance leans towards its thin ask: (100 * 1 + 102 * 3) / 4, Bitstamp is at its mid
+        assert!(
+            fair_price.venues[0].exchange == "Binance" && fair_price.venues[0].microprice == 101.5
+        );
+        assert!(fair_price.venues[1].microprice == 101.);
+        // Binance provides 5 of the 7 contributing units
+        assert!((fair_price.venues[0].weight - 5. / 7.).abs() < 1e-9);
+        assert!((fair_price.fair_price - (101.5 * 5. + 101. * 2.) / 7.).abs() < 1e-9);
+        assert!((fair_price.imbalance - 3. / 7.).abs() < 1e-9);
+        assert!(fair_price.sequence == 7);
+    }
+
+    #[test]
+    fn should_skip_books_without_venue_on_both_sides() {
+        let summary = Summary {
+            bids: vec![level("Binance", 100., 1.)],
+            asks: vec![level("Bitstamp", 101., 1.)],
+            ..Default::default()
+        };
+
+        assert!(fair_price(&summary).is_none());
+    }
+}
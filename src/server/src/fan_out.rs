@@ -0,0 +1,280 @@
+//! Fair scheduling of the fan-out across the symbols of a server. Every summary handed to a
+//! subscriber costs a token, and with `--fan-out-rate` the token bucket of each symbol refills at an
+//! equal share of that rate among the symbols which published within the last second. A pair
+//! publishing far beyond its share waits for its bucket to refill, which only holds up the delivery
+//! of its own summaries, so it cannot starve the delivery of the quieter pairs' updates. The time
+//! every broadcast took, waits included, is kept per symbol to verify the fairness.
+
+use crate::{clock::Clock, spmc::Spmc};
+use keyrock_challenge_proto::orderbook::SymbolDelivery;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::Receiver;
+
+/// a symbol that did not publish for this long leaves its share to the others
+const ACTIVE_WINDOW: Duration = Duration::from_secs(1);
+/// the latencies the percentile is computed over, the most recent ones
+const LATENCY_SAMPLES: usize = 1024;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    active_at: Option<Instant>,
+    broadcasts: u64,
+    deliveries: u64,
+    /// the broadcasts which had to wait for tokens
+    throttled: u64,
+    latencies_us: VecDeque<u64>,
+    total_latency_us: u64,
+    max_latency_us: u64,
+}
+
+#[derive(Debug)]
+pub struct FanOut {
+    /// the deliveries per second shared by all symbols, unlimited if None
+    rate: Option<f64>,
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl FanOut {
+    pub fn new(rate: Option<u32>, clock: Arc<dyn Clock>) -> Self {
+        FanOut {
+            rate: rate.map(f64::from),
+            clock,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn register(&self, symbol: &str) {
+        self.buckets.lock().unwrap().insert(
+            symbol.to_string(),
+            Bucket {
+                // capped to the symbol's share on the first refill
+                tokens: self.rate.unwrap_or_default(),
+                refilled_at: self.clock.now(),
+                active_at: None,
+                broadcasts: 0,
+                deliveries: 0,
+                throttled: 0,
+                latencies_us: VecDeque::with_capacity(LATENCY_SAMPLES),
+                total_latency_us: 0,
+                max_latency_us: 0,
+            },
+        );
+    }
+
+    pub fn unregister(&self, symbol: &str) {
+        self.buckets.lock().unwrap().remove(symbol);
+    }
+
+    /**
+     * Takes the tokens for handing a summary of the symbol to the given number of subscribers,
+     * waiting until its bucket holds them. A bucket holds a second of the symbol's share at most, so
+     * a broadcast to more subscribers waits for a full bucket and leaves it in debt.
+     */
+    pub async fn acquire(&self, symbol: &str, deliveries: usize) {
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return,
+        };
+        let mut throttled = false;
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let now = self.clock.now();
+                match buckets.get_mut(symbol) {
+                    Some(bucket) => bucket.active_at = Some(now),
+                    None => return,
+                }
+                let active = (buckets.values())
+                    .filter(|bucket| {
+                        (bucket.active_at)
+                            .is_some_and(|active_at| now.duration_since(active_at) < ACTIVE_WINDOW)
+                    })
+                    .count();
+                let share = rate / active as f64;
+                let bucket = buckets.get_mut(symbol).unwrap();
+                let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * share;
+                bucket.tokens = (bucket.tokens + refill).min(share);
+                bucket.refilled_at = now;
+
+                let cost = (deliveries as f64).min(share);
+                if bucket.tokens >= cost {
+                    bucket.tokens -= deliveries as f64;
+                    bucket.throttled += throttled as u64;
+                    return;
+                }
+                Duration::from_secs_f64((cost - bucket.tokens) / share)
+            };
+            throttled = true;
+            self.clock.sleep(wait).await;
+        }
+    }
+
+    /**
+     * Records a broadcast of the symbol to the given number of subscribers which started at
+     * `started`, before its tokens were acquired.
+     */
+    pub fn delivered(&self, symbol: &str, deliveries: usize, started: Instant) {
+        let latency_us = self.clock.now().duration_since(started).as_micros() as u64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = match buckets.get_mut(symbol) {
+            Some(bucket) => bucket,
+            None => return,
+        };
+        bucket.broadcasts += 1;
+        bucket.deliveries += deliveries as u64;
+        if bucket.latencies_us.len() >= LATENCY_SAMPLES {
+            bucket.latencies_us.pop_front();
+        }
+        bucket.latencies_us.push_back(latency_us);
+        bucket.total_latency_us += latency_us;
+        bucket.max_latency_us = bucket.max_latency_us.max(latency_us);
+    }
+
+    pub fn stats(&self) -> Vec<SymbolDelivery> {
+        let buckets = self.buckets.lock().unwrap();
+        let mut stats: Vec<SymbolDelivery> = buckets
+            .iter()
+            .map(|(symbol, bucket)| {
+                let mut latencies_us: Vec<u64> = bucket.latencies_us.iter().copied().collect();
+                latencies_us.sort_unstable();
+                SymbolDelivery {
+                    symbol: symbol.clone(),
+                    broadcasts: bucket.broadcasts,
+                    deliveries: bucket.deliveries,
+                    throttled: bucket.throttled,
+                    mean_latency_us: bucket.total_latency_us / bucket.broadcasts.max(1),
+                    p99_latency_us: (latencies_us.get(latencies_us.len() * 99 / 100))
+                        .copied()
+                        .unwrap_or_default(),
+                    max_latency_us: bucket.max_latency_us,
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        stats
+    }
+}
+
+/**
+ * Keeps the symbol registered for as long as its summaries are delivered.
+ */
+struct Registration {
+    fan_out: Arc<FanOut>,
+    symbol: String,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.fan_out.unregister(&self.symbol);
+    }
+}
+
+/**
+ * Hands the summaries published for the symbol to the subscribers of its spmc. The tokens are
+ * awaited with neither the spmc nor the aggregator locked, so a throttled symbol holds up neither
+ * the clients subscribing to it nor the RPCs reading its aggregator meanwhile.
+ */
+pub async fn run<T: Clone>(
+    fan_out: Arc<FanOut>,
+    symbol: String,
+    mut published: Receiver<T>,
+    spmc: Arc<tokio::sync::Mutex<Spmc<T>>>,
+) {
+    fan_out.register(&symbol);
+    let _registration = Registration {
+        fan_out: fan_out.clone(),
+        symbol: symbol.clone(),
+    };
+    while let Some(item) = published.recv().await {
+        let started = fan_out.now();
+        let deliveries = spmc.lock().await.receivers();
+        fan_out.acquire(&symbol, deliveries).await;
+        spmc.lock().await.broadcast(item).await;
+        fan_out.delivered(&symbol, deliveries, started);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, FanOut};
+    use crate::{
+        clock::{Clock, ManualClock},
+        spmc::Spmc,
+    };
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::{mpsc, Mutex};
+
+    #[tokio::test]
+    async fn should_hold_up_the_busy_symbol_only() {
+        // Arrange
+        let clock = Arc::new(ManualClock::new());
+        let fan_out = FanOut::new(Some(100), clock.clone());
+        fan_out.register("ethbtc");
+        fan_out.register("btcusdt");
+
+        // Act
+        fan_out.acquire("ethbtc", 50).await;
+        fan_out.acquire("btcusdt", 1).await;
+        fan_out.acquire("ethbtc", 50).await;
+        let started = clock.now();
+        let busy = fan_out.acquire("ethbtc", 25);
+        tokio::pin!(busy);
+        let waiting = futures::poll!(&mut busy);
+        let quiet = futures::poll!(Box::pin(fan_out.acquire("btcusdt", 1)));
+        clock.advance(Duration::from_millis(500));
+        let refilled = futures::poll!(&mut busy);
+        fan_out.delivered("ethbtc", 25, started);
+
+        // Assert
+        assert!(waiting.is_pending() && quiet.is_ready() && refilled.is_ready());
+        let stats = fan_out.stats();
+        assert!(stats[0].symbol == "btcusdt" && stats[0].throttled == 0);
+        assert!(stats[1].symbol == "ethbtc" && stats[1].throttled == 1);
+        assert!(stats[1].deliveries == 25 && stats[1].max_latency_us == 500_000);
+    }
+
+    #[tokio::test]
+    async fn should_leave_the_spmc_unlocked_while_waiting_for_tokens() {
+        // Arrange
+        let clock = Arc::new(ManualClock::new());
+        let fan_out = Arc::new(FanOut::new(Some(10), clock.clone()));
+        let spmc = Arc::new(Mutex::new(Spmc::new()));
+        let mut receivers: Vec<_> = (0..20)
+            .map(|_| spmc.try_lock().unwrap().create_receiver(2))
+            .collect();
+        let (published_tx, published_rx) = mpsc::channel(2);
+        published_tx.send(1).await.unwrap();
+        published_tx.send(2).await.unwrap();
+        let delivery = run(
+            fan_out.clone(),
+            "ethbtc".to_string(),
+            published_rx,
+            spmc.clone(),
+        );
+        tokio::pin!(delivery);
+
+        // Act
+        let waiting = futures::poll!(&mut delivery);
+        let unlocked = spmc.try_lock().is_ok();
+        clock.advance(Duration::from_secs(2));
+        let _ = futures::poll!(&mut delivery);
+
+        // Assert
+        assert!(waiting.is_pending() && unlocked);
+        for receiver in &mut receivers {
+            assert!(receiver.try_recv() == Ok(1) && receiver.try_recv() == Ok(2));
+        }
+        assert!(fan_out.stats()[0].broadcasts == 2 && fan_out.stats()[0].throttled == 1);
+    }
+}
@@ -1,56 +1,1925 @@
-use crate::spmc::Spmc;
+use crate::{
+    aggregator::Aggregator,
+    bandwidth::Bandwidth,
+    catch_up,
+    clock::{self, Clock},
+    consumer_group::{ConsumerGroups, Dispatch},
+    contribution_stats::ContributionStats,
+    delta_recording::DeltaEncoder,
+    diagnostics::{self, Probe},
+    failure_domain::Supervisor,
+    fan_out::FanOut,
+    history::History,
+    journal::Journal,
+    lead_race::LeadRace,
+    log,
+    spmc::{Spmc, Subscriber},
+};
+use keyrock_challenge_core::orderbook_snapshot::SideDepths;
 use keyrock_challenge_proto::orderbook::{
-    orderbook_aggregator_server::OrderbookAggregator, Empty, Summary,
+    catch_up_update, market_data_server::MarketData, orderbook_admin_server::OrderbookAdmin,
+    orderbook_aggregator_server::OrderbookAggregator, orderbook_debug_server::OrderbookDebug,
+    recorded_entry::Entry, stream_control::Control, summary_update, AuditReport, BatchRequest,
+    BookShape, BookSummaryRequest, Candles, CandlesRequest, CatchUpRequest, CatchUpUpdate,
+    CrossingEvent, DeltaRequest, DepthSettings, DiagnosticsReport, DiagnosticsRequest, DropJournal,
+    DropJournalRequest, DropReason, DumpStateRequest, Empty, ExchangeSnapshot, ExcludedExchanges,
+    FairPrice, GroupRequest, Health, HistoryRequest, IndexValue, LiveMarker, LogLevel,
+    LogLevelState, MemorySizing, OverloadEvent, PauseState, QuarantineReport, ResumeRequest,
+    SetDepthRequest, SetExchangeExcludedRequest, SetLogLevelRequest, SetPausedRequest,
+    ShadowComparison, SpreadHistory, StateDump, Stats, StreamControl, Summary, SummaryBatch,
+    SummaryRequest, SummaryUpdate, TickTimings, TradeThrough,
+};
+use prost::Message;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::sync::{
+    mpsc::{self, Receiver},
+    watch, Mutex,
 };
-use std::{pin::Pin, sync::Arc};
-use tokio::sync::{mpsc, Mutex};
 use tokio_stream::{wrappers::ReceiverStream, Stream};
-use tonic::{Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 
 const SPMC_BUFFER_SIZE: usize = 64;
 const GRPC_BUFFER_SIZE: usize = 64;
+const MAX_BATCH_WINDOW_MS: u32 = 60_000;
+const MAX_HISTORY_POINTS: usize = 10_000;
+const DEFAULT_SNAPSHOT_INTERVAL: u32 = 100;
+
+type RpcResult<T> = Result<Response<T>, Status>;
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+/// the summaries of a symbol and the latest one of them
+pub type SymbolSummaries = (Arc<Mutex<Spmc<Summary>>>, watch::Receiver<Option<Summary>>);
+
+/**
+ * The deadline the client passed in the `grpc-timeout` header, e.g. `250m` for 250 milliseconds.
+ */
+fn deadline<T>(request: &Request<T>) -> Option<Duration> {
+    let raw = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+    let (value, unit) = raw.split_at(raw.len().checked_sub(1)?);
+    let value = value.parse::<u64>().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(value * 60 * 60)),
+        "M" => Some(Duration::from_secs(value * 60)),
+        "S" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_millis(value)),
+        "u" => Some(Duration::from_micros(value)),
+        "n" => Some(Duration::from_nanos(value)),
+        _ => None,
+    }
+}
+
+/**
+ * Gives up on the response once the client's deadline passed, so a handler waiting for a lock does
+ * not keep working for a client that is no longer interested.
+ */
+async fn within_deadline<T>(
+    deadline: Option<Duration>,
+    response: impl Future<Output = RpcResult<T>>,
+) -> RpcResult<T> {
+    match deadline {
+        Some(deadline) => match tokio::time::timeout(deadline, response).await {
+            Ok(response) => response,
+            Err(_) => Err(Status::deadline_exceeded("The deadline passed")),
+        },
+        None => response.await,
+    }
+}
+
+/**
+ * Who bandwidth is accounted to: the `x-client-id` header if the client sent one, its IP otherwise.
+ */
+fn identity<T>(request: &Request<T>) -> String {
+    match request
+        .metadata()
+        .get("x-client-id")
+        .and_then(|id| id.to_str().ok())
+    {
+        Some(id) => id.to_string(),
+        None => request
+            .remote_addr()
+            .map_or("unknown".to_string(), |addr| addr.ip().to_string()),
+    }
+}
+
+/**
+ * The symbol the client asked for in the `x-symbol` header, the server's first symbol is served
+ * without one.
+ */
+fn symbol<T>(request: &Request<T>) -> Option<String> {
+    request
+        .metadata()
+        .get("x-symbol")
+        .and_then(|symbol| symbol.to_str().ok())
+        .map(str::to_lowercase)
+}
+
+/**
+ * The summary with only the best levels of each side, the figures derived from the whole book, e.g.
+ * the VWAP, are kept.
+ */
+fn trimmed(summary: &Summary, depth: usize) -> Summary {
+    let mut trimmed = summary.clone();
+    trimmed.bids.truncate(depth);
+    trimmed.asks.truncate(depth);
+    trimmed
+}
+
+fn unknown_symbol(symbol: &str) -> Status {
+    Status::not_found(format!("Unknown symbol '{}'", symbol))
+}
+
+fn not_ready(symbol: &str) -> Status {
+    Status::unavailable(format!(
+        "Symbol '{}' is not ready, too few exchanges are live",
+        symbol
+    ))
+}
+
+/**
+ * Accounts the bytes sent to the subscribers of an identity.
+ */
+#[derive(Debug, Clone)]
+struct Meter {
+    identity: String,
+    bandwidth: Arc<Mutex<Bandwidth>>,
+    journal: Arc<Journal>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Meter {
+    /**
+     * Sends the item into the response stream, returns false if the stream was closed.
+     */
+    async fn send<T: Message>(&self, stream_tx: &mpsc::Sender<Result<T, Status>>, item: T) -> bool {
+        let bytes = item.encoded_len();
+        if stream_tx.send(Ok(item)).await.is_err() {
+            return false;
+        }
+        self.bandwidth
+            .lock()
+            .await
+            .record(&self.identity, bytes, self.clock.now());
+        true
+    }
+}
+
+/**
+ * Forwards summaries while the identity is within its bandwidth cap. Once it exceeded the cap the
+ * latest summary is held back, replaced by newer ones, until the identity may be sent to again.
+ */
+async fn forward_capped(
+    mut rx: Subscriber<Summary>,
+    stream_tx: mpsc::Sender<Result<Summary, Status>>,
+    meter: Meter,
+) -> Subscriber<Summary> {
+    let clock = &meter.clock;
+    let mut pending: Option<Summary> = None;
+    let mut resume_at = clock.now();
+
+    loop {
+        let resume = clock.sleep(resume_at.saturating_duration_since(clock.now()));
+        tokio::select! {
+            _ = stream_tx.closed() => break,
+            summary = rx.recv() => match summary {
+                Some(summary) => {
+                    if pending.replace(summary).is_some() {
+                        meter.bandwidth.lock().await.record_conflated(&meter.identity);
+                        meter.journal.record(DropReason::Conflated, &meter.identity, 1);
+                    }
+                }
+                None => break,
+            },
+            _ = resume, if pending.is_some() => {},
+        }
+
+        let throttle = meter
+            .bandwidth
+            .lock()
+            .await
+            .throttle(&meter.identity, clock.now());
+        if !throttle.is_zero() {
+            resume_at = clock.now() + throttle;
+            continue;
+        }
+        let summary = match pending.take() {
+            Some(summary) => summary,
+            None => continue,
+        };
+        if !meter.send(&stream_tx, summary).await {
+            break;
+        }
+    }
+    rx
+}
+
+/**
+ * Drops the receiver or subscriber of a stream that ended and removes it from the spmc right away,
+ * which frees its queue instead of waiting for the next broadcast to notice.
+ */
+async fn unsubscribe<T: Clone, R>(spmc: &Mutex<Spmc<T>>, rx: R) {
+    drop(rx);
+    spmc.lock().await.prune();
+}
+
+/**
+ * Subscribes to the spmc and forwards everything it publishes into a gRPC response stream
+ * until the client disconnects.
+ */
+async fn subscribe<T: Clone + Send + 'static>(spmc: Arc<Mutex<Spmc<T>>>) -> ResponseStream<T> {
+    let mut rx = spmc.lock().await.create_receiver(SPMC_BUFFER_SIZE);
+    let (stream_tx, stream_rx) = mpsc::channel(GRPC_BUFFER_SIZE);
+    tokio::spawn(async move {
+        loop {
+            let item = tokio::select! {
+                // the client cancelled the stream, there is no need to wait for the next item
+                _ = stream_tx.closed() => break,
+                item = rx.recv() => match item {
+                    Some(item) => item,
+                    None => break,
+                },
+            };
+            if stream_tx.send(Result::<_, Status>::Ok(item)).await.is_err() {
+                break;
+            }
+        }
+        unsubscribe(&spmc, rx).await;
+    });
+
+    let output_stream = ReceiverStream::new(stream_rx);
+    Box::pin(output_stream) as ResponseStream<T>
+}
+
+type SummaryGroups = ConsumerGroups<Result<Summary, Status>>;
+
+/**
+ * Hands every summary to one member of the consumer group until all of its members left.
+ */
+async fn dispatch_group(
+    spmc: Arc<Mutex<Spmc<Summary>>>,
+    groups: Arc<Mutex<SummaryGroups>>,
+    group_id: String,
+    mut rx: Receiver<Summary>,
+) {
+    while let Some(summary) = rx.recv().await {
+        let dispatch = groups.lock().await.dispatch(&group_id, Ok(summary));
+        match dispatch {
+            Dispatch::Delivered => {}
+            // all members are lagging behind, which slows the group down rather than skipping one
+            Dispatch::Full(member, summary) => {
+                let _ = member.send(summary).await;
+            }
+            Dispatch::Abandoned => break,
+        }
+    }
+    unsubscribe(&spmc, rx).await;
+}
+
+/**
+ * Adds the summary to the batch, replacing an older summary of the same symbol.
+ */
+fn coalesce(batch: &mut Vec<Summary>, summary: Summary) {
+    match batch
+        .iter_mut()
+        .find(|batched| batched.symbol == summary.symbol)
+    {
+        Some(batched) => *batched = summary,
+        None => batch.push(summary),
+    }
+}
+
+/**
+ * The summaries of each symbol, selected with the `x-symbol` header. Shared with the pipelines, so
+ * symbols can be added and removed while the server is serving.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Symbols(Arc<RwLock<HashMap<String, (SymbolSummaries, watch::Receiver<bool>)>>>);
+
+impl Symbols {
+    /**
+     * Serves the summaries of the symbol once its pipeline reports to be ready.
+     */
+    pub fn add(
+        &self,
+        symbol: String,
+        spmc: Arc<Mutex<Spmc<Summary>>>,
+        latest_summary: watch::Receiver<Option<Summary>>,
+        ready: watch::Receiver<bool>,
+    ) {
+        self.0
+            .write()
+            .unwrap()
+            .insert(symbol, ((spmc, latest_summary), ready));
+    }
+
+    /**
+     * Stops serving the symbol, the subscriptions to it end once its pipeline stopped.
+     */
+    pub fn remove(&self, symbol: &str) {
+        self.0.write().unwrap().remove(symbol);
+    }
+
+    /**
+     * Fails for a symbol that is not served, or whose pipeline is not ready yet.
+     */
+    fn get(&self, symbol: &str) -> Result<SymbolSummaries, Status> {
+        match self.0.read().unwrap().get(symbol) {
+            Some((summaries, ready)) if *ready.borrow() => Ok(summaries.clone()),
+            Some(_) => Err(not_ready(symbol)),
+            None => Err(unknown_symbol(symbol)),
+        }
+    }
+
+    /**
+     * The summaries of every symbol served, ready or not.
+     */
+    pub fn served(&self) -> Vec<SymbolSummaries> {
+        (self.0.read().unwrap().values())
+            .map(|(summaries, _)| summaries.clone())
+            .collect()
+    }
+
+    /**
+     * The summaries of all symbols whose pipelines are ready.
+     */
+    fn all(&self) -> Vec<SymbolSummaries> {
+        (self.0.read().unwrap().values())
+            .filter(|(_, ready)| *ready.borrow())
+            .map(|(summaries, _)| summaries.clone())
+            .collect()
+    }
+}
 
 #[derive(Debug)]
 pub struct OrderbookAggregatorServer {
-    spmc: Arc<Mutex<Spmc>>,
+    spmc: Arc<Mutex<Spmc<Summary>>>,
+    contribution_stats: Arc<Mutex<ContributionStats>>,
+    history: Arc<Mutex<History>>,
+    latest_summary: watch::Receiver<Option<Summary>>,
+    /// whether the pipeline of the summaries served without the `x-symbol` header is ready
+    ready: watch::Receiver<bool>,
+    symbols: Symbols,
+    crossing_spmc: Option<Arc<Mutex<Spmc<CrossingEvent>>>>,
+    fair_price_spmc: Option<Arc<Mutex<Spmc<FairPrice>>>>,
+    book_shape_spmc: Option<Arc<Mutex<Spmc<BookShape>>>>,
+    trade_through_spmc: Option<Arc<Mutex<Spmc<TradeThrough>>>>,
+    index_spmc: Option<Arc<Mutex<Spmc<IndexValue>>>>,
+    overload_spmc: Option<Arc<Mutex<Spmc<OverloadEvent>>>>,
+    fan_out: Option<Arc<FanOut>>,
+    lead_race: Arc<Mutex<LeadRace>>,
+    /// reports the crashes of the server's tasks in the stats, if set
+    supervisor: Option<Supervisor>,
+    consumer_groups: Arc<Mutex<SummaryGroups>>,
+    bandwidth: Arc<Mutex<Bandwidth>>,
+    journal: Arc<Journal>,
+    sizing: MemorySizing,
+    clock: Arc<dyn Clock>,
 }
 
 impl OrderbookAggregatorServer {
-    pub fn new(spmc: Arc<Mutex<Spmc>>) -> OrderbookAggregatorServer {
-        OrderbookAggregatorServer { spmc }
+    pub fn new(
+        spmc: Arc<Mutex<Spmc<Summary>>>,
+        contribution_stats: Arc<Mutex<ContributionStats>>,
+        history: Arc<Mutex<History>>,
+        latest_summary: watch::Receiver<Option<Summary>>,
+    ) -> OrderbookAggregatorServer {
+        OrderbookAggregatorServer {
+            spmc,
+            contribution_stats,
+            history,
+            latest_summary,
+            ready: watch::channel(true).1,
+            symbols: Symbols::default(),
+            crossing_spmc: None,
+            fair_price_spmc: None,
+            book_shape_spmc: None,
+            trade_through_spmc: None,
+            index_spmc: None,
+            overload_spmc: None,
+            fan_out: None,
+            lead_race: Arc::new(Mutex::new(LeadRace::new())),
+            supervisor: None,
+            consumer_groups: Arc::new(Mutex::new(ConsumerGroups::default())),
+            bandwidth: Arc::new(Mutex::new(Bandwidth::default())),
+            journal: Arc::new(Journal::in_memory()),
+            sizing: MemorySizing {
+                subscriber_queue: SPMC_BUFFER_SIZE as u32,
+                ..Default::default()
+            },
+            clock: clock::system(),
+        }
+    }
+
+    /**
+     * Sets the sizing reported in the stats, the summary streams use its subscriber queue length.
+     */
+    pub fn set_sizing(&mut self, sizing: MemorySizing) {
+        self.sizing = sizing;
+    }
+
+    fn subscriber_queue(&self) -> usize {
+        self.sizing.subscriber_queue as usize
     }
-}
 
-type BookSummaryResult<T> = Result<Response<T>, Status>;
+    /**
+     * Subscribes a client to the summaries at the rate and depth it requested.
+     */
+    fn subscribe_client(
+        &self,
+        spmc: &mut Spmc<Summary>,
+        identity: &str,
+        max_rate_hz: u32,
+        depth: u32,
+    ) -> Subscriber<Summary> {
+        let mut rx = match max_rate_hz {
+            0 => spmc.subscribe(self.subscriber_queue(), identity),
+            max_rate_hz => spmc.subscribe_throttled(
+                Duration::from_secs(1) / max_rate_hz,
+                identity,
+                self.clock.clone(),
+            ),
+        };
+        // trimmed as they are queued, so the queue only holds the levels the client is sent
+        if depth > 0 {
+            rx.set_filter(move |summary| trimmed(summary, depth as usize));
+        }
+        rx
+    }
+
+    /**
+     * Replaces the bandwidth accounting of the server, including the caps to enforce.
+     */
+    pub fn set_bandwidth(&mut self, bandwidth: Bandwidth) {
+        self.bandwidth = Arc::new(Mutex::new(bandwidth));
+    }
+
+    pub fn set_journal(&mut self, journal: Arc<Journal>) {
+        self.journal = journal;
+    }
+
+    /**
+     * Serves the summaries of the symbol to clients selecting it with the `x-symbol` header, once
+     * its pipeline is ready.
+     */
+    pub fn add_symbol(
+        &mut self,
+        symbol: String,
+        spmc: Arc<Mutex<Spmc<Summary>>>,
+        latest_summary: watch::Receiver<Option<Summary>>,
+        ready: watch::Receiver<bool>,
+    ) {
+        self.symbols.add(symbol, spmc, latest_summary, ready);
+    }
+
+    /**
+     * Refuses the streams of the clients not selecting a symbol until the pipeline of the summaries
+     * passed to `new` is ready. They are served right away without it.
+     */
+    pub fn set_ready(&mut self, ready: watch::Receiver<bool>) {
+        self.ready = ready;
+    }
+
+    /**
+     * The symbols served, to add and remove some while the server is serving.
+     */
+    pub fn symbols(&self) -> Symbols {
+        self.symbols.clone()
+    }
+
+    /**
+     * The summaries of the symbol the client selected. Fails if the server has none of it, or if
+     * its pipeline is not ready yet.
+     */
+    fn summaries<T>(&self, request: &Request<T>) -> Result<SymbolSummaries, Status> {
+        match symbol(request) {
+            Some(symbol) => self.symbols.get(&symbol),
+            None if !*self.ready.borrow() => Err(Status::unavailable(
+                "The server is not ready, too few exchanges are live",
+            )),
+            None => Ok((self.spmc.clone(), self.latest_summary.clone())),
+        }
+    }
+
+    /**
+     * The summaries of the symbols listed in the batch request, else of the symbol selected with
+     * the `x-symbol` header, else of every symbol served whose pipeline is ready. Fails with the
+     * first unknown or not ready symbol.
+     */
+    fn batched_summaries(
+        &self,
+        request: &Request<BatchRequest>,
+    ) -> Result<Vec<SymbolSummaries>, Status> {
+        let listed = &request.get_ref().symbols;
+        if !listed.is_empty() {
+            return (listed.iter())
+                .map(|symbol| self.symbols.get(&symbol.to_lowercase()))
+                .collect();
+        }
+        match self.symbols.all() {
+            all if all.is_empty() || symbol(request).is_some() => {
+                self.summaries(request).map(|summaries| vec![summaries])
+            }
+            all => Ok(all),
+        }
+    }
+
+    fn meter<T>(&self, request: &Request<T>) -> Meter {
+        Meter {
+            identity: identity(request),
+            bandwidth: self.bandwidth.clone(),
+            journal: self.journal.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    pub fn set_crossing_spmc(&mut self, crossing_spmc: Arc<Mutex<Spmc<CrossingEvent>>>) {
+        self.crossing_spmc = Some(crossing_spmc);
+    }
+
+    pub fn set_fair_price_spmc(&mut self, fair_price_spmc: Arc<Mutex<Spmc<FairPrice>>>) {
+        self.fair_price_spmc = Some(fair_price_spmc);
+    }
+
+    pub fn set_book_shape_spmc(&mut self, book_shape_spmc: Arc<Mutex<Spmc<BookShape>>>) {
+        self.book_shape_spmc = Some(book_shape_spmc);
+    }
+
+    pub fn set_trade_through_spmc(&mut self, trade_through_spmc: Arc<Mutex<Spmc<TradeThrough>>>) {
+        self.trade_through_spmc = Some(trade_through_spmc);
+    }
+
+    pub fn set_index_spmc(&mut self, index_spmc: Arc<Mutex<Spmc<IndexValue>>>) {
+        self.index_spmc = Some(index_spmc);
+    }
+
+    pub fn set_overload_spmc(&mut self, overload_spmc: Arc<Mutex<Spmc<OverloadEvent>>>) {
+        self.overload_spmc = Some(overload_spmc);
+    }
+
+    pub fn set_fan_out(&mut self, fan_out: Arc<FanOut>) {
+        self.fan_out = Some(fan_out);
+    }
+
+    pub fn set_lead_race(&mut self, lead_race: Arc<Mutex<LeadRace>>) {
+        self.lead_race = lead_race;
+    }
+
+    pub fn set_supervisor(&mut self, supervisor: Supervisor) {
+        self.supervisor = Some(supervisor);
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
 
 #[tonic::async_trait]
 impl OrderbookAggregator for OrderbookAggregatorServer {
-    type BookSummaryStream = Pin<Box<dyn Stream<Item = Result<Summary, Status>> + Send>>;
+    type BookSummaryStream = ResponseStream<Summary>;
 
     async fn book_summary(
         &self,
-        _: tonic::Request<Empty>,
-    ) -> BookSummaryResult<Self::BookSummaryStream> {
-        let mut spmc = self.spmc.lock().await;
-        let mut rx = spmc.create_receiver(SPMC_BUFFER_SIZE);
+        request: tonic::Request<BookSummaryRequest>,
+    ) -> RpcResult<Self::BookSummaryStream> {
+        let meter = self.meter(&request);
+        let (spmc, _) = self.summaries(&request)?;
+        let &BookSummaryRequest { max_rate_hz, depth } = request.get_ref();
+        let rx = {
+            let mut spmc = spmc.lock().await;
+            self.subscribe_client(&mut spmc, &meter.identity, max_rate_hz, depth)
+        };
+        let (stream_tx, stream_rx) = mpsc::channel(GRPC_BUFFER_SIZE);
+        tokio::spawn(async move {
+            let rx = forward_capped(rx, stream_tx, meter).await;
+            unsubscribe(&spmc, rx).await;
+        });
+
+        let output_stream = ReceiverStream::new(stream_rx);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::BookSummaryStream
+        ))
+    }
+
+    type BookSummaryDeltasStream = ResponseStream<SummaryUpdate>;
+
+    async fn book_summary_deltas(
+        &self,
+        request: Request<DeltaRequest>,
+    ) -> RpcResult<Self::BookSummaryDeltasStream> {
+        let meter = self.meter(&request);
+        let (spmc, _) = self.summaries(&request)?;
+        let &DeltaRequest {
+            snapshot_interval,
+            max_rate_hz,
+            depth,
+        } = request.get_ref();
+        let mut rx = {
+            let mut spmc = spmc.lock().await;
+            self.subscribe_client(&mut spmc, &meter.identity, max_rate_hz, depth)
+        };
+        // the deltas are taken between the summaries sent, so those dropped from the queue of a
+        // slow client leave no gap in its book
+        let mut encoder = DeltaEncoder::new(match snapshot_interval {
+            0 => DEFAULT_SNAPSHOT_INTERVAL,
+            snapshot_interval => snapshot_interval,
+        });
+
         let (stream_tx, stream_rx) = mpsc::channel(GRPC_BUFFER_SIZE);
         tokio::spawn(async move {
             loop {
-                if let Some(new_summary) = rx.recv().await {
-                    match stream_tx.send(Result::<_, Status>::Ok(new_summary)).await {
-                        Ok(_) => {}
-                        Err(_item) => {
-                            drop(rx);
-                            break;
+                let summary = tokio::select! {
+                    _ = stream_tx.closed() => break,
+                    summary = rx.recv() => match summary {
+                        Some(summary) => summary,
+                        None => break,
+                    },
+                };
+                let update = match encoder.encode(&summary) {
+                    Entry::Full(summary) => summary_update::Update::Snapshot(summary),
+                    Entry::Delta(delta) => summary_update::Update::Delta(delta),
+                };
+                let update = SummaryUpdate {
+                    update: Some(update),
+                };
+                if !meter.send(&stream_tx, update).await {
+                    break;
+                }
+            }
+            unsubscribe(&spmc, rx).await;
+        });
+
+        let output_stream = ReceiverStream::new(stream_rx);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::BookSummaryDeltasStream
+        ))
+    }
+
+    type BookSummaryStreamStream = ResponseStream<Summary>;
+
+    async fn book_summary_stream(
+        &self,
+        request: Request<Streaming<StreamControl>>,
+    ) -> RpcResult<Self::BookSummaryStreamStream> {
+        let meter = self.meter(&request);
+        let (spmc, latest_summary) = self.summaries(&request)?;
+        let mut controls = request.into_inner();
+        let mut rx = spmc
+            .lock()
+            .await
+            .subscribe(self.subscriber_queue(), &meter.identity);
+        let (stream_tx, stream_rx) = mpsc::channel(GRPC_BUFFER_SIZE);
+        tokio::spawn(async move {
+            // a client may half-close its side and keep on receiving
+            let mut controls_open = true;
+            loop {
+                let summary = tokio::select! {
+                    _ = stream_tx.closed() => break,
+                    summary = rx.recv() => match summary {
+                        Some(summary) => summary,
+                        None => break,
+                    },
+                    control = controls.message(), if controls_open => match control {
+                        Ok(Some(StreamControl { control: Some(Control::ResendSnapshot(_)) })) => {
+                            match latest_summary.borrow().clone() {
+                                Some(summary) => summary,
+                                None => continue,
+                            }
+                        }
+                        Ok(Some(StreamControl { control: None })) => continue,
+                        Ok(None) => {
+                            controls_open = false;
+                            continue;
                         }
+                        Err(_) => break,
+                    },
+                };
+                if !meter.send(&stream_tx, summary).await {
+                    break;
+                }
+            }
+            unsubscribe(&spmc, rx).await;
+        });
+
+        let output_stream = ReceiverStream::new(stream_rx);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::BookSummaryStreamStream
+        ))
+    }
+
+    type ResumeBookSummaryStream = ResponseStream<Summary>;
+
+    async fn resume_book_summary(
+        &self,
+        request: Request<ResumeRequest>,
+    ) -> RpcResult<Self::ResumeBookSummaryStream> {
+        let meter = self.meter(&request);
+        let (spmc, _) = self.summaries(&request)?;
+        let ResumeRequest {
+            last_sequence,
+            last_epoch,
+        } = request.into_inner();
+        let (replay, mut rx) = {
+            let mut spmc = spmc.lock().await;
+            // the sequence numbers of another epoch cannot be compared, e.g. after a restart without
+            // a sequence file the downstream's last sequence may be far ahead of the new one
+            let replay: Vec<Summary> = spmc
+                .history()
+                .filter(|summary| {
+                    let other_epoch = last_epoch != 0 && summary.epoch != last_epoch;
+                    other_epoch || summary.sequence > last_sequence
+                })
+                .cloned()
+                .collect();
+            (
+                replay,
+                spmc.subscribe(self.subscriber_queue(), &meter.identity),
+            )
+        };
+
+        let (stream_tx, stream_rx) = mpsc::channel(GRPC_BUFFER_SIZE);
+        tokio::spawn(async move {
+            for summary in replay {
+                if !meter.send(&stream_tx, summary).await {
+                    unsubscribe(&spmc, rx).await;
+                    return;
+                }
+            }
+            loop {
+                let summary = tokio::select! {
+                    _ = stream_tx.closed() => break,
+                    summary = rx.recv() => match summary {
+                        Some(summary) => summary,
+                        None => break,
+                    },
+                };
+                if !meter.send(&stream_tx, summary).await {
+                    break;
+                }
+            }
+            unsubscribe(&spmc, rx).await;
+        });
+
+        let output_stream = ReceiverStream::new(stream_rx);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::ResumeBookSummaryStream
+        ))
+    }
+
+    type CatchUpBookSummaryStream = ResponseStream<CatchUpUpdate>;
+
+    async fn catch_up_book_summary(
+        &self,
+        request: Request<CatchUpRequest>,
+    ) -> RpcResult<Self::CatchUpBookSummaryStream> {
+        let meter = self.meter(&request);
+        let (spmc, _) = self.summaries(&request)?;
+        let request = request.into_inner();
+        // the receiver is created along with the replay, so the live summaries continue it
+        let (replay, mut rx) = {
+            let mut spmc = spmc.lock().await;
+            let replay = catch_up::replay(spmc.history(), &request, self.clock.system_now());
+            (
+                replay,
+                spmc.subscribe(self.subscriber_queue(), &meter.identity),
+            )
+        };
+        let clock = self.clock.clone();
+        let update = |update| CatchUpUpdate {
+            update: Some(update),
+        };
+
+        let (stream_tx, stream_rx) = mpsc::channel(GRPC_BUFFER_SIZE);
+        tokio::spawn(async move {
+            let live = LiveMarker {
+                replayed: replay.len() as u32,
+                last_replayed_sequence: replay.last().map_or(0, |summary| summary.sequence),
+            };
+            let mut previous: Option<Summary> = None;
+            for summary in replay {
+                if let Some(previous) = &previous {
+                    clock
+                        .sleep(catch_up::pause(previous, &summary, request.speed))
+                        .await;
+                }
+                previous = Some(summary.clone());
+                let summary = update(catch_up_update::Update::Summary(summary));
+                if !meter.send(&stream_tx, summary).await {
+                    unsubscribe(&spmc, rx).await;
+                    return;
+                }
+            }
+            let live = update(catch_up_update::Update::Live(live));
+            if !meter.send(&stream_tx, live).await {
+                unsubscribe(&spmc, rx).await;
+                return;
+            }
+            loop {
+                let summary = tokio::select! {
+                    _ = stream_tx.closed() => break,
+                    summary = rx.recv() => match summary {
+                        Some(summary) => summary,
+                        None => break,
+                    },
+                };
+                let summary = update(catch_up_update::Update::Summary(summary));
+                if !meter.send(&stream_tx, summary).await {
+                    break;
+                }
+            }
+            unsubscribe(&spmc, rx).await;
+        });
+
+        let output_stream = ReceiverStream::new(stream_rx);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::CatchUpBookSummaryStream
+        ))
+    }
+
+    type GroupBookSummaryStream = ResponseStream<Summary>;
+
+    async fn group_book_summary(
+        &self,
+        request: Request<GroupRequest>,
+    ) -> RpcResult<Self::GroupBookSummaryStream> {
+        let (spmc, _) = self.summaries(&request)?;
+        let symbol = symbol(&request);
+        let group_id = request.into_inner().group_id;
+        if group_id.is_empty() {
+            return Err(Status::invalid_argument("group_id must not be empty"));
+        }
+        // a group consumes the summaries of one symbol
+        let group_id = match symbol {
+            Some(symbol) => format!("{}/{}", symbol, group_id),
+            None => group_id,
+        };
+        let (stream_tx, stream_rx) = mpsc::channel(self.subscriber_queue());
+        let created = self.consumer_groups.lock().await.join(&group_id, stream_tx);
+        if created {
+            let rx = spmc.lock().await.create_receiver(self.subscriber_queue());
+            tokio::spawn(dispatch_group(
+                spmc,
+                self.consumer_groups.clone(),
+                group_id,
+                rx,
+            ));
+        }
+
+        let output_stream = ReceiverStream::new(stream_rx);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::GroupBookSummaryStream
+        ))
+    }
+
+    type BookSummaryBatchesStream = ResponseStream<SummaryBatch>;
+
+    async fn book_summary_batches(
+        &self,
+        request: Request<BatchRequest>,
+    ) -> RpcResult<Self::BookSummaryBatchesStream> {
+        let meter = self.meter(&request);
+        let summaries = self.batched_summaries(&request)?;
+        let window_ms = request.into_inner().window_ms;
+        if window_ms == 0 || window_ms > MAX_BATCH_WINDOW_MS {
+            return Err(Status::invalid_argument(format!(
+                "window_ms has to be between 1 and {}",
+                MAX_BATCH_WINDOW_MS
+            )));
+        }
+        let window = Duration::from_millis(window_ms as u64);
+
+        // the summaries of all batched symbols are merged into the one channel batches are made of
+        let (merged_tx, mut rx) = mpsc::channel(GRPC_BUFFER_SIZE);
+        for (spmc, _) in summaries {
+            let mut symbol_rx = spmc
+                .lock()
+                .await
+                .subscribe(self.subscriber_queue(), &meter.identity);
+            let merged_tx = merged_tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let summary = tokio::select! {
+                        _ = merged_tx.closed() => break,
+                        summary = symbol_rx.recv() => match summary {
+                            Some(summary) => summary,
+                            None => break,
+                        },
+                    };
+                    if merged_tx.send(summary).await.is_err() {
+                        break;
+                    }
+                }
+                unsubscribe(&spmc, symbol_rx).await;
+            });
+        }
+        drop(merged_tx);
+
+        let (stream_tx, stream_rx) = mpsc::channel(GRPC_BUFFER_SIZE);
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            // the window starts with the first summary after the previous batch was sent
+            'batches: loop {
+                let first = tokio::select! {
+                    _ = stream_tx.closed() => break,
+                    first = rx.recv() => match first {
+                        Some(first) => first,
+                        None => break,
+                    },
+                };
+                let mut batch = vec![first];
+                let window_end = clock.sleep(window);
+                tokio::pin!(window_end);
+
+                loop {
+                    tokio::select! {
+                        _ = stream_tx.closed() => break 'batches,
+                        _ = &mut window_end => break,
+                        next = rx.recv() => match next {
+                            Some(summary) => coalesce(&mut batch, summary),
+                            None => break,
+                        },
                     }
                 }
+
+                let batch = SummaryBatch { summaries: batch };
+                if !meter.send(&stream_tx, batch).await {
+                    break;
+                }
             }
+            // unsubscribes the symbols' receivers from their spmcs
+            drop(rx);
         });
 
         let output_stream = ReceiverStream::new(stream_rx);
         Ok(Response::new(
-            Box::pin(output_stream) as Self::BookSummaryStream
+            Box::pin(output_stream) as Self::BookSummaryBatchesStream
         ))
     }
+
+    type SpreadCrossingsStream = ResponseStream<CrossingEvent>;
+
+    async fn spread_crossings(&self, _: Request<Empty>) -> RpcResult<Self::SpreadCrossingsStream> {
+        match &self.crossing_spmc {
+            Some(crossing_spmc) => Ok(Response::new(subscribe(crossing_spmc.clone()).await)),
+            None => Err(Status::unavailable("The server does not detect crosses")),
+        }
+    }
+
+    type FairPricesStream = ResponseStream<FairPrice>;
+
+    async fn fair_prices(&self, _: Request<Empty>) -> RpcResult<Self::FairPricesStream> {
+        match &self.fair_price_spmc {
+            Some(fair_price_spmc) => Ok(Response::new(subscribe(fair_price_spmc.clone()).await)),
+            None => Err(Status::unavailable(
+                "The server does not derive fair prices",
+            )),
+        }
+    }
+
+    type BookShapesStream = ResponseStream<BookShape>;
+
+    async fn book_shapes(&self, _: Request<Empty>) -> RpcResult<Self::BookShapesStream> {
+        match &self.book_shape_spmc {
+            Some(book_shape_spmc) => Ok(Response::new(subscribe(book_shape_spmc.clone()).await)),
+            None => Err(Status::unavailable(
+                "The server does not sample the book shape, see --book-shape-secs",
+            )),
+        }
+    }
+
+    type TradeThroughsStream = ResponseStream<TradeThrough>;
+
+    async fn trade_throughs(&self, _: Request<Empty>) -> RpcResult<Self::TradeThroughsStream> {
+        match &self.trade_through_spmc {
+            Some(trade_through_spmc) => {
+                Ok(Response::new(subscribe(trade_through_spmc.clone()).await))
+            }
+            None => Err(Status::unavailable(
+                "The server does not detect trade-throughs, see --trade-throughs",
+            )),
+        }
+    }
+
+    type IndexStream = ResponseStream<IndexValue>;
+
+    async fn index(&self, _: Request<Empty>) -> RpcResult<Self::IndexStream> {
+        match &self.index_spmc {
+            Some(index_spmc) => Ok(Response::new(subscribe(index_spmc.clone()).await)),
+            None => Err(Status::unavailable(
+                "The server publishes no index, see --index",
+            )),
+        }
+    }
+
+    type OverloadsStream = ResponseStream<OverloadEvent>;
+
+    async fn overloads(&self, _: Request<Empty>) -> RpcResult<Self::OverloadsStream> {
+        match &self.overload_spmc {
+            Some(overload_spmc) => Ok(Response::new(subscribe(overload_spmc.clone()).await)),
+            None => Err(Status::unavailable(
+                "The server does not watch its inbound queues",
+            )),
+        }
+    }
+
+    async fn get_summary(&self, request: Request<SummaryRequest>) -> RpcResult<Summary> {
+        let (_, latest_summary) = self.summaries(&request)?;
+        let depth = request.get_ref().depth;
+        let summary = latest_summary.borrow().clone();
+        match summary {
+            Some(summary) if depth > 0 => Ok(Response::new(trimmed(&summary, depth as usize))),
+            Some(summary) => Ok(Response::new(summary)),
+            None => Err(Status::unavailable("No summary was published yet")),
+        }
+    }
+
+    async fn get_stats(&self, request: Request<Empty>) -> RpcResult<Stats> {
+        within_deadline(deadline(&request), async {
+            let mut contribution_stats = self.contribution_stats.lock().await;
+            Ok(Response::new(Stats {
+                contributions: contribution_stats.windows(self.clock.now()),
+                bandwidth: self.bandwidth.lock().await.stats(),
+                lead_race: self.lead_race.lock().await.stats(self.clock.now()),
+                sizing: Some(self.sizing.clone()),
+                tasks: match &self.supervisor {
+                    Some(supervisor) => supervisor.tasks().await,
+                    None => Vec::new(),
+                },
+                delivery: (self.fan_out.as_ref()).map_or_else(Vec::new, |fan_out| fan_out.stats()),
+            }))
+        })
+        .await
+    }
+
+    async fn get_spread_history(
+        &self,
+        request: Request<HistoryRequest>,
+    ) -> RpcResult<SpreadHistory> {
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        within_deadline(deadline, async {
+            let points =
+                self.history
+                    .lock()
+                    .await
+                    .spreads(&request.symbol, request.from_ms, request.to_ms);
+            if points.len() > MAX_HISTORY_POINTS {
+                return Err(Status::out_of_range(format!(
+                    "The range holds more than {} points, narrow it down or use GetCandles",
+                    MAX_HISTORY_POINTS
+                )));
+            }
+            Ok(Response::new(SpreadHistory { points }))
+        })
+        .await
+    }
+
+    async fn get_candles(&self, request: Request<CandlesRequest>) -> RpcResult<Candles> {
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        if request.interval_secs == 0 {
+            return Err(Status::invalid_argument("interval_secs has to be positive"));
+        }
+        within_deadline(deadline, async {
+            let candles = self.history.lock().await.candles(
+                &request.symbol,
+                request.from_ms,
+                request.to_ms,
+                request.interval_secs as u64 * 1000,
+            );
+            if candles.len() > MAX_HISTORY_POINTS {
+                return Err(Status::out_of_range(format!(
+                    "The range holds more than {} candles, narrow it down or increase the interval",
+                    MAX_HISTORY_POINTS
+                )));
+            }
+            Ok(Response::new(Candles { candles }))
+        })
+        .await
+    }
+}
+
+#[derive(Debug)]
+pub struct MarketDataServer {
+    spmc: Arc<Mutex<Spmc<ExchangeSnapshot>>>,
+}
+
+impl MarketDataServer {
+    pub fn new(spmc: Arc<Mutex<Spmc<ExchangeSnapshot>>>) -> MarketDataServer {
+        MarketDataServer { spmc }
+    }
+}
+
+#[tonic::async_trait]
+impl MarketData for MarketDataServer {
+    type ExchangeSnapshotsStream = ResponseStream<ExchangeSnapshot>;
+
+    async fn exchange_snapshots(
+        &self,
+        _: tonic::Request<Empty>,
+    ) -> RpcResult<Self::ExchangeSnapshotsStream> {
+        Ok(Response::new(subscribe(self.spmc.clone()).await))
+    }
+}
+
+#[derive(Debug)]
+pub struct OrderbookDebugServer {
+    spmc: Arc<Mutex<Spmc<TickTimings>>>,
+    shadow_spmc: Option<Arc<Mutex<Spmc<ShadowComparison>>>>,
+}
+
+impl OrderbookDebugServer {
+    pub fn new(spmc: Arc<Mutex<Spmc<TickTimings>>>) -> OrderbookDebugServer {
+        OrderbookDebugServer {
+            spmc,
+            shadow_spmc: None,
+        }
+    }
+
+    pub fn set_shadow_spmc(&mut self, shadow_spmc: Arc<Mutex<Spmc<ShadowComparison>>>) {
+        self.shadow_spmc = Some(shadow_spmc);
+    }
+}
+
+#[tonic::async_trait]
+impl OrderbookDebug for OrderbookDebugServer {
+    type StageTimingsStream = ResponseStream<TickTimings>;
+
+    async fn stage_timings(&self, _: tonic::Request<Empty>) -> RpcResult<Self::StageTimingsStream> {
+        Ok(Response::new(subscribe(self.spmc.clone()).await))
+    }
+
+    type ShadowComparisonsStream = ResponseStream<ShadowComparison>;
+
+    async fn shadow_comparisons(
+        &self,
+        _: tonic::Request<Empty>,
+    ) -> RpcResult<Self::ShadowComparisonsStream> {
+        match &self.shadow_spmc {
+            Some(shadow_spmc) => Ok(Response::new(subscribe(shadow_spmc.clone()).await)),
+            None => Err(Status::failed_precondition(
+                "The server runs no shadow merge strategy, start it with --shadow-merge",
+            )),
+        }
+    }
+}
+
+/**
+ * The aggregators of the symbols, the first one is administered without an `x-symbol` header.
+ * Shared with the pipelines, so symbols can be added and removed while the server is serving.
+ */
+#[derive(Debug, Clone)]
+pub struct Aggregators(Arc<RwLock<Vec<Arc<Mutex<Aggregator>>>>>);
+
+impl Aggregators {
+    pub fn add(&self, aggregator: Arc<Mutex<Aggregator>>) {
+        self.0.write().unwrap().push(aggregator);
+    }
+
+    pub fn remove(&self, aggregator: &Arc<Mutex<Aggregator>>) {
+        self.0
+            .write()
+            .unwrap()
+            .retain(|added| !Arc::ptr_eq(added, aggregator));
+    }
+
+    fn all(&self) -> Vec<Arc<Mutex<Aggregator>>> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn primary(&self) -> Arc<Mutex<Aggregator>> {
+        self.0.read().unwrap()[0].clone()
+    }
+}
+
+#[derive(Debug)]
+pub struct OrderbookAdminServer {
+    aggregators: Aggregators,
+    probes: Vec<fn(&str) -> Probe>,
+    /// set to request a drain, draining is not supported if None
+    drain: Option<watch::Sender<bool>>,
+}
+
+impl OrderbookAdminServer {
+    pub fn new(aggregator: Arc<Mutex<Aggregator>>) -> OrderbookAdminServer {
+        OrderbookAdminServer {
+            aggregators: Aggregators(Arc::new(RwLock::new(vec![aggregator]))),
+            probes: Vec::new(),
+            drain: None,
+        }
+    }
+
+    /**
+     * Enables `Drain`, which sets the flag for the server to stop accepting connections.
+     */
+    pub fn set_drain(&mut self, drain: watch::Sender<bool>) {
+        self.drain = Some(drain);
+    }
+
+    /**
+     * Administers the aggregator of a further symbol.
+     */
+    pub fn add_aggregator(&mut self, aggregator: Arc<Mutex<Aggregator>>) {
+        self.aggregators.add(aggregator);
+    }
+
+    /**
+     * The aggregators administered, to add and remove some while the server is serving.
+     */
+    pub fn aggregators(&self) -> Aggregators {
+        self.aggregators.clone()
+    }
+
+    /**
+     * Sets how the diagnostics reach the stream of each exchange for a symbol.
+     */
+    pub fn set_probes(&mut self, probes: Vec<fn(&str) -> Probe>) {
+        self.probes = probes;
+    }
+
+    async fn find(&self, symbol: &str) -> Result<Arc<Mutex<Aggregator>>, Status> {
+        for aggregator in self.aggregators.all() {
+            if aggregator.lock().await.symbol() == symbol {
+                return Ok(aggregator.clone());
+            }
+        }
+        Err(unknown_symbol(symbol))
+    }
+
+    /**
+     * The aggregator of the symbol in the `x-symbol` header, the first one without.
+     */
+    async fn aggregator<T>(&self, request: &Request<T>) -> Result<Arc<Mutex<Aggregator>>, Status> {
+        match symbol(request) {
+            Some(symbol) => self.find(&symbol).await,
+            None => Ok(self.aggregators.primary()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl OrderbookAdmin for OrderbookAdminServer {
+    async fn set_exchange_excluded(
+        &self,
+        request: Request<SetExchangeExcludedRequest>,
+    ) -> RpcResult<ExcludedExchanges> {
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        within_deadline(deadline, async {
+            // an exchange is excluded from the books of all symbols, they all know the same ones
+            for aggregator in self.aggregators.all() {
+                if aggregator
+                    .lock()
+                    .await
+                    .set_excluded(&request.exchange, request.excluded)
+                    .is_err()
+                {
+                    return Err(Status::not_found(format!(
+                        "Unknown exchange '{}'",
+                        request.exchange
+                    )));
+                }
+            }
+
+            Ok(Response::new(ExcludedExchanges {
+                exchanges: self.aggregators.primary().lock().await.excluded_exchanges(),
+            }))
+        })
+        .await
+    }
+
+    async fn get_health(&self, request: Request<Empty>) -> RpcResult<Health> {
+        within_deadline(deadline(&request), async {
+            let aggregator = self.aggregator(&request).await?;
+            let health = aggregator.lock().await.health();
+            Ok(Response::new(health))
+        })
+        .await
+    }
+
+    async fn get_drop_journal(
+        &self,
+        request: Request<DropJournalRequest>,
+    ) -> RpcResult<DropJournal> {
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        within_deadline(deadline, async {
+            let to_ms = match request.to_ms {
+                0 => u64::MAX,
+                to_ms => to_ms,
+            };
+            // the journal is shared by the pipelines of all symbols
+            let journal = self.aggregators.primary().lock().await.journal();
+            let entries = journal
+                .entries(request.from_ms, to_ms)
+                .map_err(|error| Status::internal(error.to_string()))?;
+            Ok(Response::new(DropJournal { entries }))
+        })
+        .await
+    }
+
+    async fn get_quarantine(&self, _: Request<Empty>) -> RpcResult<QuarantineReport> {
+        // the quarantine is shared by the pipelines of all symbols
+        match self.aggregators.primary().lock().await.quarantine() {
+            Some(quarantine) => Ok(Response::new(quarantine.report())),
+            None => Err(Status::unavailable(
+                "The server does not validate the exchange messages, see --strict-schema",
+            )),
+        }
+    }
+    async fn run_audit(&self, request: Request<Empty>) -> RpcResult<AuditReport> {
+        within_deadline(deadline(&request), async {
+            let aggregator = self.aggregator(&request).await?;
+            let report = aggregator.lock().await.audit();
+            Ok(Response::new(report))
+        })
+        .await
+    }
+
+    async fn set_depth(&self, request: Request<SetDepthRequest>) -> RpcResult<DepthSettings> {
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        within_deadline(deadline, async {
+            let aggregator = self.find(&request.symbol).await?;
+            let mut aggregator = aggregator.lock().await;
+            let depths = SideDepths {
+                bids: request.bid_depth.unwrap_or(request.depth) as usize,
+                asks: request.ask_depth.unwrap_or(request.depth) as usize,
+            };
+
+            if aggregator.resize_depth(depths).await.is_err() {
+                return Err(Status::invalid_argument(format!(
+                    "The depth of each side has to be between 1 and {}, got {} bids and {} asks",
+                    aggregator.max_depth(),
+                    depths.bids,
+                    depths.asks
+                )));
+            }
+
+            let depths = aggregator.side_depths();
+            Ok(Response::new(DepthSettings {
+                symbol: request.symbol,
+                depth: aggregator.depth() as u32,
+                max_depth: aggregator.max_depth() as u32,
+                bid_depth: depths.bids as u32,
+                ask_depth: depths.asks as u32,
+            }))
+        })
+        .await
+    }
+
+    async fn run_diagnostics(
+        &self,
+        request: Request<DiagnosticsRequest>,
+    ) -> RpcResult<DiagnosticsReport> {
+        let deadline = deadline(&request);
+        let symbol = self
+            .aggregator(&request)
+            .await?
+            .lock()
+            .await
+            .symbol()
+            .to_string();
+        let request = request.into_inner();
+        let probe = match self
+            .probes
+            .iter()
+            .map(|probe| probe(&symbol))
+            .find(|probe| probe.exchange == request.exchange)
+        {
+            Some(probe) => probe,
+            None => {
+                return Err(Status::not_found(format!(
+                    "Unknown exchange '{}'",
+                    request.exchange
+                )))
+            }
+        };
+        within_deadline(deadline, async {
+            let report = tokio::task::spawn_blocking(move || diagnostics::diagnose(&probe))
+                .await
+                .map_err(|_| Status::internal("The diagnostics panicked"))?;
+            Ok(Response::new(report))
+        })
+        .await
+    }
+
+    async fn set_paused(&self, request: Request<SetPausedRequest>) -> RpcResult<PauseState> {
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        within_deadline(deadline, async {
+            let aggregator = self.find(&request.symbol).await?;
+            let mut aggregator = aggregator.lock().await;
+            aggregator.set_paused(request.paused).await;
+            Ok(Response::new(aggregator.pause_state()))
+        })
+        .await
+    }
+
+    async fn set_log_level(
+        &self,
+        request: Request<SetLogLevelRequest>,
+    ) -> RpcResult<LogLevelState> {
+        let level = match LogLevel::from_i32(request.into_inner().level) {
+            Some(LogLevel::Warning) => log::Level::Warning,
+            Some(LogLevel::Alert) => log::Level::Alert,
+            Some(LogLevel::Off) => log::Level::Off,
+            Some(LogLevel::Unspecified) | None => {
+                return Err(Status::invalid_argument("Unknown log level"))
+            }
+        };
+        log::set_level(level);
+        let level = match log::level() {
+            log::Level::Warning => LogLevel::Warning,
+            log::Level::Alert => LogLevel::Alert,
+            log::Level::Off => LogLevel::Off,
+        };
+        Ok(Response::new(LogLevelState {
+            level: level as i32,
+        }))
+    }
+
+    async fn dump_state(&self, request: Request<DumpStateRequest>) -> RpcResult<StateDump> {
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        within_deadline(deadline, async {
+            let aggregator = self.find(&request.symbol).await?;
+            let state = aggregator.lock().await.dump_state().await;
+            Ok(Response::new(StateDump {
+                symbol: request.symbol,
+                json: serde_json::to_string_pretty(&state)
+                    .map_err(|error| Status::internal(error.to_string()))?,
+            }))
+        })
+        .await
+    }
+
+    async fn drain(&self, _: Request<Empty>) -> RpcResult<Empty> {
+        match &self.drain {
+            Some(drain) => {
+                if !drain.send_replace(true) {
+                    log::warning!("Draining, a successor took over the listeners");
+                }
+                Ok(Response::new(Empty {}))
+            }
+            None => Err(Status::unavailable(
+                "The server does not share its listeners, start it with --reuse-port",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deadline, subscribe, within_deadline, OrderbookAggregatorServer, ResponseStream};
+    use crate::{contribution_stats::ContributionStats, history::History, spmc::Spmc};
+    use keyrock_challenge_proto::orderbook::{
+        orderbook_aggregator_server::OrderbookAggregator, summary_update::Update, BatchRequest,
+        BookSummaryRequest, DeltaRequest, GroupRequest, Level, ResumeRequest, Summary,
+        SummaryRequest,
+    };
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+    use tokio::sync::{watch, Mutex};
+    use tokio_stream::StreamExt;
+    use tonic::{Code, Request, Response};
+
+    #[test]
+    fn should_parse_grpc_timeout_header() {
+        let with_timeout = |raw: &str| {
+            let mut request = Request::new(());
+            request
+                .metadata_mut()
+                .insert("grpc-timeout", raw.parse().unwrap());
+            request
+        };
+
+        assert!(deadline(&with_timeout("250m")) == Some(Duration::from_millis(250)));
+        assert!(deadline(&with_timeout("2S")) == Some(Duration::from_secs(2)));
+        assert!(deadline(&with_timeout("2x")).is_none());
+        assert!(deadline(&Request::new(())).is_none());
+    }
+
+    #[tokio::test]
+    async fn should_give_up_on_response_once_deadline_passed() {
+        // Arrange
+        let lock = Mutex::new(());
+        let _held = lock.lock().await;
+
+        // Act
+        let result = within_deadline(Some(Duration::from_millis(10)), async {
+            let _ = lock.lock().await;
+            Ok(Response::new(()))
+        })
+        .await;
+
+        // Assert
+        assert!(result.unwrap_err().code() == Code::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn should_remove_subscriber_once_its_stream_is_dropped() {
+        // Arrange
+        let spmc = Arc::new(Mutex::new(Spmc::<u64>::new()));
+        let stream = subscribe(spmc.clone()).await;
+        assert!(!spmc.lock().await.is_empty());
+
+        // Act
+        drop(stream);
+
+        // Assert
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !spmc.lock().await.is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("The subscriber was not removed");
+    }
+
+    async fn received(stream: &mut ResponseStream<Summary>) -> Vec<u64> {
+        let mut sequences = Vec::new();
+        while let Ok(Some(summary)) =
+            tokio::time::timeout(Duration::from_millis(100), stream.next()).await
+        {
+            sequences.push(summary.unwrap().sequence);
+        }
+        sequences
+    }
+
+    #[tokio::test]
+    async fn should_route_summaries_by_symbol() {
+        // Arrange
+        let ethbtc = Arc::new(Mutex::new(Spmc::new()));
+        let btcusdt = Arc::new(Mutex::new(Spmc::new()));
+        let mut server = OrderbookAggregatorServer::new(
+            ethbtc.clone(),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            watch::channel(None).1,
+        );
+        server.add_symbol(
+            "ethbtc".to_string(),
+            ethbtc.clone(),
+            watch::channel(None).1,
+            watch::channel(true).1,
+        );
+        server.add_symbol(
+            "btcusdt".to_string(),
+            btcusdt.clone(),
+            watch::channel(None).1,
+            watch::channel(true).1,
+        );
+        let for_symbol = |symbol: &str| {
+            let mut request = Request::new(BookSummaryRequest::default());
+            request
+                .metadata_mut()
+                .insert("x-symbol", symbol.parse().unwrap());
+            request
+        };
+        let mut default = server
+            .book_summary(Request::new(BookSummaryRequest::default()))
+            .await
+            .unwrap();
+        let mut selected = server.book_summary(for_symbol("BTCUSDT")).await.unwrap();
+
+        // Act
+        for (spmc, sequence) in [(&ethbtc, 1), (&btcusdt, 2), (&ethbtc, 3)] {
+            spmc.lock()
+                .await
+                .broadcast(Summary {
+                    sequence,
+                    ..Default::default()
+                })
+                .await;
+        }
+
+        // Assert
+        assert!(received(default.get_mut()).await == vec![1, 3]);
+        assert!(received(selected.get_mut()).await == vec![2]);
+        assert!(server
+            .book_summary(for_symbol("ethusdt"))
+            .await
+            .is_err_and(|status| status.code() == Code::NotFound));
+    }
+
+    #[tokio::test]
+    async fn should_refuse_the_summaries_of_symbols_that_are_not_ready() {
+        // Arrange
+        let ethbtc = Arc::new(Mutex::new(Spmc::new()));
+        let mut server = OrderbookAggregatorServer::new(
+            ethbtc.clone(),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            watch::channel(None).1,
+        );
+        let (ready_tx, ready) = watch::channel(false);
+        server.add_symbol(
+            "ethbtc".to_string(),
+            ethbtc.clone(),
+            watch::channel(None).1,
+            ready.clone(),
+        );
+        server.set_ready(ready);
+        let selected = || {
+            let mut request = Request::new(BookSummaryRequest::default());
+            request
+                .metadata_mut()
+                .insert("x-symbol", "ethbtc".parse().unwrap());
+            request
+        };
+        let refused = (
+            server
+                .book_summary(Request::new(BookSummaryRequest::default()))
+                .await,
+            server.book_summary(selected()).await,
+        );
+
+        // Act
+        ready_tx.send_replace(true);
+
+        // Assert
+        assert!(refused
+            .0
+            .is_err_and(|status| status.code() == Code::Unavailable));
+        assert!(refused
+            .1
+            .is_err_and(|status| status.code() == Code::Unavailable));
+        assert!(server
+            .book_summary(Request::new(BookSummaryRequest::default()))
+            .await
+            .is_ok());
+        assert!(server.book_summary(selected()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_trim_summaries_to_the_requested_depth() {
+        // Arrange
+        let spmc = Arc::new(Mutex::new(Spmc::new()));
+        let server = OrderbookAggregatorServer::new(
+            spmc.clone(),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            watch::channel(None).1,
+        );
+        let mut top = server
+            .book_summary(Request::new(BookSummaryRequest {
+                depth: 1,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let level = |price: f64| Level {
+            price,
+            ..Default::default()
+        };
+
+        // Act
+        spmc.lock()
+            .await
+            .broadcast(Summary {
+                bids: vec![level(2.), level(1.)],
+                asks: vec![level(3.), level(4.)],
+                vwap: Some(2.5),
+                ..Default::default()
+            })
+            .await;
+
+        // Assert
+        let summary = top.get_mut().next().await.unwrap().unwrap();
+        assert!(summary.bids == [level(2.)] && summary.asks == [level(3.)]);
+        assert!(summary.vwap == Some(2.5));
+    }
+
+    #[tokio::test]
+    async fn should_return_the_latest_summary_once_published() {
+        // Arrange
+        let (latest_tx, latest_rx) = watch::channel(None);
+        let server = OrderbookAggregatorServer::new(
+            Arc::new(Mutex::new(Spmc::new())),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            latest_rx,
+        );
+        let level = |price: f64| Level {
+            price,
+            ..Default::default()
+        };
+        let request = |depth: u32| Request::new(SummaryRequest { depth });
+
+        // Act
+        let before = server.get_summary(request(0)).await;
+        latest_tx
+            .send(Some(Summary {
+                sequence: 7,
+                bids: vec![level(2.), level(1.)],
+                ..Default::default()
+            }))
+            .unwrap();
+        let full = server.get_summary(request(0)).await.unwrap().into_inner();
+        let top = server.get_summary(request(1)).await.unwrap().into_inner();
+
+        // Assert
+        assert!(before.unwrap_err().code() == Code::Unavailable);
+        assert!(full.sequence == 7 && full.bids == [level(2.), level(1.)]);
+        assert!(top.bids == [level(2.)]);
+    }
+
+    #[tokio::test]
+    async fn should_send_deltas_between_snapshots() {
+        // Arrange
+        let spmc = Arc::new(Mutex::new(Spmc::new()));
+        let server = OrderbookAggregatorServer::new(
+            spmc.clone(),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            watch::channel(None).1,
+        );
+        let mut updates = server
+            .book_summary_deltas(Request::new(DeltaRequest {
+                snapshot_interval: 2,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let level = |price: f64, amount: f64| Level {
+            price,
+            amount,
+            ..Default::default()
+        };
+        let summary = |sequence: u64, bid_amount: f64| Summary {
+            sequence,
+            bids: vec![level(2., bid_amount), level(1., 1.)],
+            asks: vec![level(3., 1.)],
+            ..Default::default()
+        };
+
+        // Act
+        for (sequence, bid_amount) in [(1, 1.), (2, 5.), (3, 5.)] {
+            spmc.lock()
+                .await
+                .broadcast(summary(sequence, bid_amount))
+                .await;
+        }
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(updates.get_mut().next().await.unwrap().unwrap().update);
+        }
+
+        // Assert
+        assert!(matches!(&received[0], Some(Update::Snapshot(s)) if s.sequence == 1));
+        assert!(matches!(&received[2], Some(Update::Snapshot(s)) if s.sequence == 3));
+        let delta = match &received[1] {
+            Some(Update::Delta(delta)) => delta,
+            _ => panic!("expected a delta"),
+        };
+        assert!(delta.sequence == 2 && delta.bids.len() == 1 && delta.asks.is_empty());
+        assert!(delta.bids[0].index == 0 && delta.bids[0].level == Some(level(2., 5.)));
+    }
+
+    #[tokio::test]
+    async fn should_replay_all_summaries_of_a_new_epoch() {
+        // Arrange
+        let spmc = Arc::new(Mutex::new(Spmc::with_history(8)));
+        let server = OrderbookAggregatorServer::new(
+            spmc.clone(),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            watch::channel(None).1,
+        );
+        // the upstream restarted without its sequence file after publishing 500 in epoch 1
+        for (epoch, sequence) in [(1, 499), (1, 500), (2, 1), (2, 2)] {
+            let summary = Summary {
+                epoch,
+                sequence,
+                ..Default::default()
+            };
+            spmc.lock().await.broadcast(summary).await;
+        }
+        let resume = |last_epoch: u64| {
+            Request::new(ResumeRequest {
+                last_sequence: 499,
+                last_epoch,
+            })
+        };
+
+        // Act
+        let mut resumed = server.resume_book_summary(resume(1)).await.unwrap();
+        let mut unaware = server.resume_book_summary(resume(0)).await.unwrap();
+
+        // Assert
+        assert!(received(resumed.get_mut()).await == [500, 1, 2]);
+        assert!(received(unaware.get_mut()).await == [500]);
+    }
+
+    #[tokio::test]
+    async fn should_batch_the_summaries_of_all_symbols() {
+        // Arrange
+        let mut server = OrderbookAggregatorServer::new(
+            Arc::new(Mutex::new(Spmc::new())),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            watch::channel(None).1,
+        );
+        let (ethbtc, btcusdt) = (
+            Arc::new(Mutex::new(Spmc::new())),
+            Arc::new(Mutex::new(Spmc::new())),
+        );
+        server.add_symbol(
+            "ethbtc".to_string(),
+            ethbtc.clone(),
+            watch::channel(None).1,
+            watch::channel(true).1,
+        );
+        server.add_symbol(
+            "btcusdt".to_string(),
+            btcusdt.clone(),
+            watch::channel(None).1,
+            watch::channel(true).1,
+        );
+        let mut batches = server
+            .book_summary_batches(Request::new(BatchRequest {
+                window_ms: 100,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let summary = |symbol: &str, sequence: u64| Summary {
+            symbol: symbol.to_string(),
+            sequence,
+            ..Default::default()
+        };
+
+        // Act
+        ethbtc.lock().await.broadcast(summary("ethbtc", 1)).await;
+        btcusdt.lock().await.broadcast(summary("btcusdt", 1)).await;
+        ethbtc.lock().await.broadcast(summary("ethbtc", 2)).await;
+        let batch = batches.get_mut().next().await.unwrap().unwrap();
+
+        // Assert
+        let mut batched: Vec<(String, u64)> = (batch.summaries.into_iter())
+            .map(|summary| (summary.symbol, summary.sequence))
+            .collect();
+        batched.sort();
+        assert!(batched == [("btcusdt".to_string(), 1), ("ethbtc".to_string(), 2)]);
+        let unknown = server
+            .book_summary_batches(Request::new(BatchRequest {
+                window_ms: 100,
+                symbols: vec!["ethbtc".to_string(), "xrpeur".to_string()],
+            }))
+            .await;
+        assert!(unknown.err().unwrap().code() == Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn should_split_summaries_among_group_members() {
+        // Arrange
+        let spmc = Arc::new(Mutex::new(Spmc::new()));
+        let server = OrderbookAggregatorServer::new(
+            spmc.clone(),
+            Arc::new(Mutex::new(ContributionStats::new(Instant::now()))),
+            Arc::new(Mutex::new(History::new(Duration::ZERO))),
+            watch::channel(None).1,
+        );
+        let group = |group_id: &str| {
+            Request::new(GroupRequest {
+                group_id: group_id.to_string(),
+            })
+        };
+        let mut first = server
+            .group_book_summary(group("processors"))
+            .await
+            .unwrap();
+        let mut second = server
+            .group_book_summary(group("processors"))
+            .await
+            .unwrap();
+        let mut other = server.group_book_summary(group("archivers")).await.unwrap();
+
+        // Act
+        for sequence in 1..=4 {
+            spmc.lock()
+                .await
+                .broadcast(Summary {
+                    sequence,
+                    ..Default::default()
+                })
+                .await;
+        }
+
+        // Assert
+        assert!(received(first.get_mut()).await == vec![1, 3]);
+        assert!(received(second.get_mut()).await == vec![2, 4]);
+        assert!(received(other.get_mut()).await == vec![1, 2, 3, 4]);
+        assert!(server
+            .group_book_summary(group(""))
+            .await
+            .is_err_and(|status| status.code() == Code::InvalidArgument));
+    }
 }
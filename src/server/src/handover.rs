@@ -0,0 +1,81 @@
+//! Binary upgrades without a gap in the feed. A server started with `--reuse-port` binds its
+//! listeners with `SO_REUSEPORT`, so its successor can bind the same addresses while it still serves.
+//! Once the successor is ready it asks the old server to drain over `OrderbookAdmin.Drain`: the old
+//! server stops accepting connections, which the kernel then hands to the successor alone, keeps
+//! feeding its subscribers until they left or the drain timeout passed, and exits.
+
+use keyrock_challenge_proto::orderbook::{orderbook_admin_client::OrderbookAdminClient, Empty};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{error::Error, io, net::SocketAddr};
+use tokio::{net::TcpListener, sync::watch};
+
+/// how many connections may wait to be accepted
+const BACKLOG: i32 = 1024;
+
+/**
+ * Binds a listener to the address, which further processes can bind as well if `reuse_port` is set.
+ */
+pub fn bind(address: SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+    #[cfg(not(unix))]
+    if reuse_port {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is only supported on unix",
+        ));
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/**
+ * Asks the server behind the admin url to drain, after this server bound the same addresses.
+ */
+pub async fn take_over(url: String) -> Result<(), Box<dyn Error>> {
+    OrderbookAdminClient::connect(url)
+        .await?
+        .drain(Empty {})
+        .await?;
+    Ok(())
+}
+
+/**
+ * Resolves once a drain was requested, never if the sender is gone without requesting one.
+ */
+pub async fn drain_requested(mut drain: watch::Receiver<bool>) {
+    while !*drain.borrow() {
+        if drain.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bind;
+
+    #[tokio::test]
+    async fn should_share_the_address_only_with_reuse_port() {
+        // Arrange
+        let first = bind("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let address = first.local_addr().unwrap();
+        let exclusive = bind("127.0.0.1:0".parse().unwrap(), false).unwrap();
+
+        // Act
+        let successor = bind(address, true);
+        let intruder = bind(exclusive.local_addr().unwrap(), true);
+
+        // Assert
+        assert!(successor.unwrap().local_addr().unwrap() == address);
+        assert!(intruder.is_err());
+    }
+}
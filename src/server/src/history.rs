@@ -0,0 +1,295 @@
+use crate::recorder;
+use keyrock_challenge_proto::orderbook::{Candle, SpreadPoint, Summary};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io, mem,
+    ops::Bound,
+    path::Path,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    spread: f64,
+    mid: f64,
+}
+
+/// the estimated bytes held per sample, see `memory_usage`
+pub const SAMPLE_BYTES: usize = mem::size_of::<(u64, Sample)>();
+
+/**
+ * Keeps spread and mid price of the published summaries per symbol, keyed by unix milliseconds.
+ * Samples older than the retention, measured from the newest sample of the symbol, are evicted.
+ * Backfilled samples are kept apart and never evicted, they answer queries from before the first
+ * sample kept of the running server.
+ */
+#[derive(Debug)]
+pub struct History {
+    retention_ms: u64,
+    symbols: HashMap<String, BTreeMap<u64, Sample>>,
+    backfilled: HashMap<String, BTreeMap<u64, Sample>>,
+}
+
+impl History {
+    pub fn new(retention: Duration) -> Self {
+        History {
+            retention_ms: retention.as_millis() as u64,
+            symbols: HashMap::new(),
+            backfilled: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, timestamp_ms: u64, summary: &Summary) {
+        let sample = match History::sample(summary) {
+            Some(sample) => sample,
+            None => return,
+        };
+        let samples = self.symbols.entry(summary.symbol.clone()).or_default();
+        samples.insert(timestamp_ms, sample);
+
+        History::evict(samples, self.retention_ms);
+    }
+
+    fn sample(summary: &Summary) -> Option<Sample> {
+        match (summary.spread, summary.bids.first(), summary.asks.first()) {
+            (Some(spread), Some(best_bid), Some(best_ask)) => Some(Sample {
+                spread,
+                mid: (best_bid.price + best_ask.price) / 2.,
+            }),
+            _ => None,
+        }
+    }
+
+    fn evict(samples: &mut BTreeMap<u64, Sample>, retention_ms: u64) {
+        let newest = match samples.keys().next_back() {
+            Some(newest) => *newest,
+            None => return,
+        };
+        let oldest_kept = newest.saturating_sub(retention_ms);
+        while let Some(oldest) = samples.first_entry() {
+            if *oldest.key() >= oldest_kept {
+                break;
+            }
+            oldest.remove();
+        }
+    }
+
+    /**
+     * Changes the retention, samples beyond a shortened retention are evicted right away.
+     */
+    pub fn set_retention(&mut self, retention: Duration) {
+        self.retention_ms = retention.as_millis() as u64;
+        for samples in self.symbols.values_mut() {
+            History::evict(samples, self.retention_ms);
+        }
+    }
+
+    /**
+     * A rough estimate of the bytes held by the samples, ignoring the map's own overhead.
+     */
+    pub fn memory_usage(&self) -> usize {
+        (self.symbols.values())
+            .chain(self.backfilled.values())
+            .map(|samples| samples.len() * SAMPLE_BYTES)
+            .sum()
+    }
+
+    /**
+     * Keeps all summaries recorded in the directory, regardless of the retention, and returns how
+     * many were read.
+     */
+    pub fn backfill(&mut self, dir: &Path) -> io::Result<usize> {
+        let mut count = 0;
+        for path in recorder::list_recordings(dir)? {
+            for recorded in recorder::read_recording(&path)? {
+                let summary = match &recorded.summary {
+                    Some(summary) => summary,
+                    None => continue,
+                };
+                if let Some(sample) = History::sample(summary) {
+                    (self.backfilled.entry(summary.symbol.clone()).or_default())
+                        .insert(recorded.recorded_at_ms, sample);
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn spreads(&self, symbol: &str, from_ms: u64, to_ms: u64) -> Vec<SpreadPoint> {
+        self.range(symbol, from_ms, to_ms)
+            .map(|(timestamp_ms, sample)| SpreadPoint {
+                timestamp_ms: *timestamp_ms,
+                spread: sample.spread,
+            })
+            .collect()
+    }
+
+    /**
+     * Mid price candles of the given interval, aligned to multiples of the interval since the epoch.
+     * Intervals without any sample are left out.
+     */
+    pub fn candles(&self, symbol: &str, from_ms: u64, to_ms: u64, interval_ms: u64) -> Vec<Candle> {
+        let mut candles: Vec<Candle> = Vec::new();
+
+        for (timestamp_ms, sample) in self.range(symbol, from_ms, to_ms) {
+            let open_time_ms = timestamp_ms - timestamp_ms % interval_ms;
+            match candles.last_mut() {
+                Some(candle) if candle.open_time_ms == open_time_ms => {
+                    candle.high = candle.high.max(sample.mid);
+                    candle.low = candle.low.min(sample.mid);
+                    candle.close = sample.mid;
+                    candle.ticks += 1;
+                }
+                _ => candles.push(Candle {
+                    open_time_ms,
+                    open: sample.mid,
+                    high: sample.mid,
+                    low: sample.mid,
+                    close: sample.mid,
+                    ticks: 1,
+                }),
+            }
+        }
+
+        candles
+    }
+
+    fn range<'a>(
+        &'a self,
+        symbol: &str,
+        from_ms: u64,
+        to_ms: u64,
+    ) -> impl Iterator<Item = (&'a u64, &'a Sample)> {
+        let live = self.symbols.get(symbol);
+        // backfilled samples only fill in the time before the first live sample
+        let backfilled_until = match live.and_then(|samples| samples.keys().next()) {
+            Some(first_live_ms) if *first_live_ms <= to_ms => Bound::Excluded(*first_live_ms),
+            _ => Bound::Included(to_ms),
+        };
+        let backfilled = (self.backfilled.get(symbol))
+            .filter(|_| match backfilled_until {
+                Bound::Excluded(until_ms) => from_ms < until_ms,
+                _ => from_ms <= to_ms,
+            })
+            .into_iter()
+            .flat_map(move |samples| samples.range((Bound::Included(from_ms), backfilled_until)));
+        let live = live
+            .filter(|_| from_ms <= to_ms)
+            .into_iter()
+            .flat_map(move |samples| samples.range(from_ms..=to_ms));
+        backfilled.chain(live)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::recorder::{RecordFormat, Recorder, RecorderConfig, RetentionPolicy};
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+    use std::{fs, time::Duration};
+
+    fn summary(bid: f64, ask: f64) -> Summary {
+        let level = |price: f64| Level {
+            exchange: "Binance".to_string(),
+            price,
+            amount: 1.,
+            ..Default::default()
+        };
+        Summary {
+            spread: Some(ask - bid),
+            bids: vec![level(bid)],
+            asks: vec![level(ask)],
+            symbol: "ethbtc".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_build_candles_and_evict_beyond_retention() {
+        // Arrange
+        let mut history = History::new(Duration::from_secs(120));
+
+        // Act
+        history.insert(0, &summary(1., 3.));
+        history.insert(60_000, &summary(3., 5.));
+        history.insert(61_000, &summary(5., 7.));
+        history.insert(62_000, &summary(1., 5.));
+        history.insert(180_000, &summary(2., 4.));
+
+        // Assert
+        assert!(history.spreads("ethbtc", 0, u64::MAX).len() == 4);
+        assert!(history.spreads("ethbtc", 61_000, 62_000)[1].spread == 4.);
+        let candles = history.candles("ethbtc", 0, u64::MAX, 60_000);
+        assert!(candles.len() == 2);
+        assert!(candles[0].open_time_ms == 60_000 && candles[0].ticks == 3);
+        assert!(candles[0].open == 4. && candles[0].high == 6. && candles[0].low == 3.);
+        assert!(candles[0].close == 3.);
+        assert!(candles[1].open_time_ms == 180_000);
+    }
+
+    #[test]
+    fn should_backfill_from_recordings() {
+        // Arrange
+        let dir = std::env::temp_dir().join(format!("history_backfill_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut recorder = Recorder::new(RecorderConfig {
+            dir: dir.clone(),
+            rotate_bytes: u64::MAX,
+            rotate_after: Duration::from_secs(3600),
+            retention: RetentionPolicy::default(),
+            format: RecordFormat::Full,
+        })
+        .unwrap();
+        recorder.record(&summary(1., 2.)).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        recorder.record(&summary(1., 3.)).unwrap();
+        recorder.close().unwrap();
+        let mut history = History::new(Duration::from_secs(3600));
+
+        // Act
+        let count = history.backfill(&dir).unwrap();
+
+        // Assert
+        assert!(count == 2);
+        let spreads = history.spreads("ethbtc", 0, u64::MAX);
+        assert!(spreads.len() == 2 && spreads[1].spread == 2.);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn should_answer_queries_beyond_retention_from_backfilled_samples() {
+        // Arrange
+        let dir = std::env::temp_dir().join(format!("history_retention_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut recorder = Recorder::new(RecorderConfig {
+            dir: dir.clone(),
+            rotate_bytes: u64::MAX,
+            rotate_after: Duration::from_secs(3600),
+            retention: RetentionPolicy::default(),
+            format: RecordFormat::Full,
+        })
+        .unwrap();
+        recorder.record(&summary(1., 2.)).unwrap();
+        recorder.close().unwrap();
+        let mut history = History::new(Duration::from_secs(3600));
+        history.backfill(&dir).unwrap();
+        let recorded_at_ms = history.spreads("ethbtc", 0, u64::MAX)[0].timestamp_ms;
+        let hours = |hours: u64| recorded_at_ms + hours * 3_600_000;
+
+        // Act
+        history.insert(hours(10), &summary(1., 3.));
+        history.insert(hours(12), &summary(1., 4.));
+
+        // Assert
+        let older = history.spreads("ethbtc", 0, hours(1));
+        assert!(older.len() == 1 && older[0].timestamp_ms == recorded_at_ms);
+        // the live sample of hour 10 is beyond the retention by now
+        let spreads: Vec<f64> = (history.spreads("ethbtc", 0, u64::MAX).iter())
+            .map(|point| point.spread)
+            .collect();
+        assert!(spreads == [1., 3.]);
+        assert!(history.spreads("ethbtc", hours(11), u64::MAX).len() == 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,261 @@
+//! The queue between a connector and the aggregator. A connector hands its snapshots over without
+//! waiting for the aggregator, so an aggregator falling behind shows as a growing queue instead of
+//! latency silently building up in the socket buffers. A queue staying above the overload threshold
+//! is conflated: only the newest snapshot is kept, as it supersedes the queued ones anyway, and an
+//! overload event is published until the aggregator keeps up again.
+
+use crate::{aggregator::Aggregator, clock::Clock, log, spmc::Spmc};
+use keyrock_challenge_core::orderbook_snapshot::OrderbookSnapshot;
+use keyrock_challenge_proto::orderbook::{DropReason, OverloadEvent, TickTimings};
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+use tokio::sync::{Mutex, Notify};
+
+/**
+ * How many snapshots may be queued for how long before a queue is overloaded, parsed from
+ * `<snapshots>:<ms>`. A conflated queue recovers once no snapshot was superseded for as long.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverloadThreshold {
+    pub depth: usize,
+    pub sustain: Duration,
+}
+
+impl Default for OverloadThreshold {
+    fn default() -> Self {
+        OverloadThreshold {
+            depth: 16,
+            sustain: Duration::from_secs(1),
+        }
+    }
+}
+
+impl FromStr for OverloadThreshold {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (depth, ms) = raw.split_once(':').ok_or(())?;
+        let depth = depth.parse::<usize>().map_err(|_| ())?;
+        let ms = ms.parse::<u64>().map_err(|_| ())?;
+        if depth == 0 {
+            return Err(());
+        }
+        Ok(OverloadThreshold {
+            depth,
+            sustain: Duration::from_millis(ms),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: VecDeque<(OrderbookSnapshot, TickTimings)>,
+    conflating: bool,
+    /// snapshots replaced by a newer one since the last pop
+    superseded: u64,
+}
+
+/**
+ * A handle to the queue of one source, shared by its connector and the task draining it.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct InboundQueue {
+    state: Arc<StdMutex<State>>,
+    notify: Arc<Notify>,
+}
+
+impl InboundQueue {
+    pub fn new() -> Self {
+        InboundQueue::default()
+    }
+
+    /**
+     * Queues a snapshot for the aggregator. While conflating, it replaces the queued ones.
+     */
+    pub fn push(&self, snapshot: OrderbookSnapshot, timings: TickTimings) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.conflating {
+                state.superseded += state.pending.len() as u64;
+                state.pending.clear();
+            }
+            state.pending.push_back((snapshot, timings));
+        }
+        self.notify.notify_one();
+    }
+
+    /**
+     * Discards the queued snapshots, e.g. those of a connection that dropped meanwhile.
+     */
+    pub fn clear(&self) {
+        self.state.lock().unwrap().pending.clear();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    pub fn is_conflating(&self) -> bool {
+        self.state.lock().unwrap().conflating
+    }
+
+    fn set_conflating(&self, conflating: bool) {
+        self.state.lock().unwrap().conflating = conflating;
+    }
+
+    /**
+     * Takes the oldest snapshot, together with the depth of the queue before and the snapshots
+     * superseded since the last pop.
+     */
+    fn pop(&self) -> Option<(OrderbookSnapshot, TickTimings, usize, u64)> {
+        let mut state = self.state.lock().unwrap();
+        let depth = state.pending.len();
+        let superseded = std::mem::take(&mut state.superseded);
+        let (snapshot, timings) = state.pending.pop_front()?;
+        Some((snapshot, timings, depth, superseded))
+    }
+}
+
+/**
+ * Decides from the observed queue depths when a queue gets conflated and when it recovers.
+ */
+#[derive(Debug)]
+struct Detector {
+    threshold: OverloadThreshold,
+    /// since when the queue is above the threshold, or has been keeping up while conflated
+    since: Option<Instant>,
+    overloaded: bool,
+}
+
+impl Detector {
+    fn new(threshold: OverloadThreshold) -> Self {
+        Detector {
+            threshold,
+            since: None,
+            overloaded: false,
+        }
+    }
+
+    /**
+     * Observes the queue before a snapshot is taken. Returns the new state on a transition.
+     */
+    fn observe(&mut self, depth: usize, superseded: u64, now: Instant) -> Option<bool> {
+        let towards_other_state = match self.overloaded {
+            false => depth > self.threshold.depth,
+            true => superseded == 0,
+        };
+        if !towards_other_state {
+            self.since = None;
+            return None;
+        }
+        let since = *self.since.get_or_insert(now);
+        if now.duration_since(since) < self.threshold.sustain {
+            return None;
+        }
+        self.since = None;
+        self.overloaded = !self.overloaded;
+        Some(self.overloaded)
+    }
+}
+
+/**
+ * Hands the queued snapshots of the source to the aggregator, conflating the queue while it is
+ * overloaded and publishing an event whenever it gets conflated or recovers.
+ */
+pub async fn run(
+    source_id: usize,
+    exchange: &'static str,
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    threshold: OverloadThreshold,
+    overload_spmc: Arc<Mutex<Spmc<OverloadEvent>>>,
+    clock: Arc<dyn Clock>,
+) {
+    let queue = aggregator_arc.lock().await.inbound_queue(source_id);
+    let mut detector = Detector::new(threshold);
+
+    loop {
+        queue.notify.notified().await;
+        while let Some((snapshot, timings, depth, superseded)) = queue.pop() {
+            let mut aggregator = aggregator_arc.lock().await;
+            aggregator
+                .journal()
+                .record(DropReason::Overloaded, exchange, superseded);
+            if let Some(overloaded) = detector.observe(depth, superseded, clock.now()) {
+                queue.set_conflating(overloaded);
+                match overloaded {
+                    true => log::warning!(
+                        "{} snapshots of {} queued for {}, conflating them",
+                        depth,
+                        exchange,
+                        aggregator.symbol()
+                    ),
+                    false => log::warning!(
+                        "The aggregator of {} keeps up with {} again",
+                        aggregator.symbol(),
+                        exchange
+                    ),
+                }
+                let event = OverloadEvent {
+                    symbol: aggregator.symbol().to_string(),
+                    exchange: exchange.to_string(),
+                    overloaded,
+                    queue_depth: depth as u32,
+                    at_ms: clock
+                        .system_now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64,
+                };
+                let mut overload_spmc = overload_spmc.lock().await;
+                if !overload_spmc.is_empty() {
+                    overload_spmc.broadcast(event).await;
+                }
+            }
+            aggregator.process(source_id, snapshot, timings).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Detector, InboundQueue, OverloadThreshold};
+    use keyrock_challenge_core::orderbook_snapshot::SnapshotBuilder;
+    use keyrock_challenge_proto::orderbook::TickTimings;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_conflate_a_queue_staying_above_the_threshold() {
+        // Arrange
+        let threshold: OverloadThreshold = "2:100".parse().unwrap();
+        let mut detector = Detector::new(threshold);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let queue = InboundQueue::new();
+
+        // Act
+        let below = detector.observe(2, 0, at(0));
+        let above = detector.observe(3, 0, at(0));
+        let sustained = detector.observe(5, 0, at(100));
+        queue.set_conflating(sustained == Some(true));
+        for _ in 0..3 {
+            let empty = SnapshotBuilder::new().build(1).unwrap();
+            queue.push(empty, TickTimings::default());
+        }
+        let (_, _, depth, superseded) = queue.pop().unwrap();
+        let lagging = detector.observe(depth, superseded, at(150));
+        let keeping_up = detector.observe(1, 0, at(200));
+        let recovered = detector.observe(1, 0, at(300));
+
+        // Assert
+        assert!(below.is_none() && above.is_none());
+        assert!(sustained == Some(true));
+        assert!(depth == 1 && superseded == 2 && queue.depth() == 0);
+        assert!(lagging.is_none() && keeping_up.is_none());
+        assert!(recovered == Some(false));
+        assert!("0:100".parse::<OverloadThreshold>().is_err());
+    }
+}
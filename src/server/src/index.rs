@@ -0,0 +1,150 @@
+//! A reference rate over several symbols. The merged mids of the configured constituents are
+//! averaged by their weights and republished whenever the mid of a constituent changes, so a
+//! consumer tracking a basket does not have to subscribe to and combine every symbol itself.
+
+use crate::spmc::Spmc;
+use futures::stream::{self, StreamExt};
+use keyrock_challenge_proto::orderbook::{IndexConstituent, IndexValue, Summary};
+use std::{str::FromStr, sync::Arc};
+use tokio::sync::{mpsc::Receiver, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+
+/**
+ * A symbol of the index with its weight, parsed from `<symbol>:<weight>`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constituent {
+    pub symbol: String,
+    pub weight: f64,
+}
+
+impl FromStr for Constituent {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (symbol, weight) = raw.split_once(':').ok_or(())?;
+        let weight = weight.parse::<f64>().map_err(|_| ())?;
+        if symbol.is_empty() || !weight.is_finite() || weight <= 0. {
+            return Err(());
+        }
+        Ok(Constituent {
+            symbol: symbol.to_lowercase(),
+            weight,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Index {
+    constituents: Vec<IndexConstituent>,
+}
+
+impl Index {
+    pub fn new(constituents: &[Constituent]) -> Index {
+        Index {
+            constituents: constituents
+                .iter()
+                .map(|constituent| IndexConstituent {
+                    symbol: constituent.symbol.clone(),
+                    weight: constituent.weight,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    /**
+     * Takes the mid of the summary if it is the one of a constituent. Returns the new index value if
+     * the mid changed, none if the summary does not change the index or no constituent has a mid.
+     */
+    pub fn update(&mut self, summary: &Summary) -> Option<IndexValue> {
+        let constituent = self
+            .constituents
+            .iter_mut()
+            .find(|constituent| constituent.symbol == summary.symbol)?;
+        constituent.sequence = summary.sequence;
+        if constituent.mid == summary.mid {
+            return None;
+        }
+        constituent.mid = summary.mid;
+
+        let (weighted, weights) = self
+            .constituents
+            .iter()
+            .filter_map(|constituent| Some((constituent.mid?, constituent.weight)))
+            .fold((0., 0.), |(weighted, weights), (mid, weight)| {
+                (weighted + mid * weight, weights + weight)
+            });
+        if weights == 0. {
+            return None;
+        }
+        Some(IndexValue {
+            value: weighted / weights,
+            complete: self
+                .constituents
+                .iter()
+                .all(|constituent| constituent.mid.is_some()),
+            constituents: self.constituents.clone(),
+            aggregated_at_us: summary.aggregated_at_us,
+        })
+    }
+}
+
+/**
+ * Updates the index with the summaries of all its constituents and publishes its changes.
+ */
+pub async fn run(
+    rxs: Vec<Receiver<Summary>>,
+    mut index: Index,
+    spmc: Arc<Mutex<Spmc<IndexValue>>>,
+) {
+    let mut summaries = stream::select_all(rxs.into_iter().map(ReceiverStream::new));
+    while let Some(summary) = summaries.next().await {
+        if let Some(value) = index.update(&summary) {
+            let mut spmc = spmc.lock().await;
+            if !spmc.is_empty() {
+                spmc.broadcast(value).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Constituent, Index};
+    use keyrock_challenge_proto::orderbook::Summary;
+
+    fn summary(symbol: &str, mid: Option<f64>) -> Summary {
+        Summary {
+            symbol: symbol.to_string(),
+            mid,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_weight_constituent_mids() {
+        // Arrange
+        let constituents: Vec<Constituent> = ["ethbtc:3", "BTCUSDT:1"]
+            .iter()
+            .map(|raw| raw.parse().unwrap())
+            .collect();
+        let mut index = Index::new(&constituents);
+
+        // Act
+        let partial = index.update(&summary("ethbtc", Some(10.))).unwrap();
+        let unchanged = index.update(&summary("ethbtc", Some(10.)));
+        let other = index.update(&summary("ethusdt", Some(1.)));
+        let complete = index.update(&summary("btcusdt", Some(30.))).unwrap();
+        let heartbeat = index.update(&summary("btcusdt", None)).unwrap();
+
+        // Assert
+        assert!(partial.value == 10. && !partial.complete);
+        assert!(unchanged.is_none() && other.is_none());
+        assert!(complete.value == (10. * 3. + 30.) / 4. && complete.complete);
+        assert!(complete.constituents[1].symbol == "btcusdt");
+        assert!(heartbeat.value == 10. && !heartbeat.complete);
+        assert!("ethbtc:0".parse::<Constituent>().is_err());
+        assert!("ethbtc".parse::<Constituent>().is_err());
+    }
+}
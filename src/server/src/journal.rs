@@ -0,0 +1,185 @@
+//! A journal of every item that was dropped instead of delivered: summaries missed by slow
+//! consumers, summaries conflated for capped subscribers and stale exchange messages. Drops are
+//! counted per reason and subject and flushed once per second, so even a burst of drops only adds a
+//! few entries. With a file the journal survives restarts for post-incident analysis.
+
+use crate::{clock::Clock, log};
+use keyrock_challenge_proto::orderbook::{DropEntry, DropReason};
+use prost::Message;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// entries kept in memory if the journal has no file
+const MAX_RECENT_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct State {
+    pending: HashMap<(DropReason, String), u64>,
+    recent: VecDeque<DropEntry>,
+    file: Option<File>,
+}
+
+#[derive(Debug, Default)]
+pub struct Journal {
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+impl Journal {
+    /**
+     * A journal only keeping the most recent entries in memory.
+     */
+    pub fn in_memory() -> Self {
+        Journal::default()
+    }
+
+    /**
+     * A journal appending its entries to the given file.
+     */
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal {
+            path: Some(path.to_path_buf()),
+            state: Mutex::new(State {
+                file: Some(file),
+                ..Default::default()
+            }),
+        })
+    }
+
+    pub fn record(&self, reason: DropReason, subject: &str, count: u64) {
+        if count == 0 {
+            return;
+        }
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .entry((reason, subject.to_string()))
+            .or_default() += count;
+    }
+
+    /**
+     * Turns the drops counted since the last flush into entries stamped with the given time.
+     */
+    pub fn flush(&self, at_ms: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut entries: Vec<DropEntry> = state
+            .pending
+            .drain()
+            .map(|((reason, subject), count)| DropEntry {
+                at_ms,
+                reason: reason as i32,
+                subject,
+                count,
+            })
+            .collect();
+        entries.sort_by(|a, b| (a.reason, &a.subject).cmp(&(b.reason, &b.subject)));
+
+        if let Some(file) = &mut state.file {
+            let mut encoded = Vec::new();
+            for entry in &entries {
+                entry.encode_length_delimited(&mut encoded)?;
+            }
+            file.write_all(&encoded)?;
+        }
+        for entry in entries {
+            if state.recent.len() >= MAX_RECENT_ENTRIES {
+                state.recent.pop_front();
+            }
+            state.recent.push_back(entry);
+        }
+        Ok(())
+    }
+
+    /**
+     * The entries within the range, read from the file if the journal has one, so it includes the
+     * entries of previous runs.
+     */
+    pub fn entries(&self, from_ms: u64, to_ms: u64) -> io::Result<Vec<DropEntry>> {
+        let in_range = |entry: &DropEntry| entry.at_ms >= from_ms && entry.at_ms <= to_ms;
+        let path = match &self.path {
+            Some(path) => path,
+            None => {
+                let state = self.state.lock().unwrap();
+                return Ok(state
+                    .recent
+                    .iter()
+                    .filter(|e| in_range(e))
+                    .cloned()
+                    .collect());
+            }
+        };
+
+        let mut content = Vec::new();
+        File::open(path)?.read_to_end(&mut content)?;
+        let mut buffer = content.as_slice();
+        let mut entries = Vec::new();
+        // an entry cut off by a crash ends the journal
+        while let Ok(entry) = DropEntry::decode_length_delimited(&mut buffer) {
+            if in_range(&entry) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/**
+ * Flushes the journal once per second.
+ */
+pub async fn run(journal: Arc<Journal>, clock: Arc<dyn Clock>) {
+    loop {
+        clock.sleep(FLUSH_INTERVAL).await;
+        let at_ms = clock
+            .system_now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        if let Err(error) = journal.flush(at_ms) {
+            log::warning!("Unable to write the drop journal: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+    use keyrock_challenge_proto::orderbook::DropReason;
+    use std::fs;
+
+    #[test]
+    fn should_count_drops_per_second_and_read_them_back_after_restart() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("journal-{}.pb", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let journal = Journal::open(&path).unwrap();
+
+        // Act
+        journal.record(DropReason::SlowConsumer, "summaries", 3);
+        journal.record(DropReason::SlowConsumer, "summaries", 2);
+        journal.record(DropReason::Conflated, "dashboard", 1);
+        journal.flush(1000).unwrap();
+        journal.flush(2000).unwrap();
+        journal.record(DropReason::Stale, "Binance", 1);
+        journal.flush(3000).unwrap();
+        drop(journal);
+        let reopened = Journal::open(&path).unwrap();
+
+        // Assert
+        let entries = reopened.entries(0, 2000).unwrap();
+        assert!(entries.len() == 2);
+        assert!(entries[0].reason == DropReason::SlowConsumer as i32 && entries[0].count == 5);
+        assert!(entries[1].subject == "dashboard" && entries[1].at_ms == 1000);
+        assert!(reopened.entries(2500, u64::MAX).unwrap()[0].subject == "Binance");
+        fs::remove_file(&path).unwrap();
+    }
+}
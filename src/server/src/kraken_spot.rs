@@ -0,0 +1,394 @@
+//! The Kraken connector. Unlike the partial book streams of the other venues, Kraken sends the book
+//! once on subscription and only the changed levels afterwards, so the connector keeps a local copy
+//! of the book and hands the aggregator a full snapshot of it after every message.
+
+use crate::{
+    connector_sdk::{self, ExchangeConnector, LocalBook, ReconnectPolicy, Update},
+    diagnostics::Probe,
+    exchange_status::StatusEndpoint,
+    schema::{Field, Shape},
+    trade_through::Trade,
+};
+use keyrock_challenge_core::orderbook_snapshot::{OrderbookSnapshot, Side};
+use keyrock_challenge_proto::orderbook::VenueStatus;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::Sender;
+use tungstenite::{connect, Message};
+use url::Url;
+
+const EXCHANGE: &str = "Kraken";
+/// the book subscription only comes in these depths
+const BOOK_DEPTHS: [usize; 5] = [10, 25, 100, 500, 1000];
+pub const MAX_DEPTH: usize = 1000;
+
+pub const STATUS_ENDPOINT: StatusEndpoint = StatusEndpoint {
+    exchange: EXCHANGE,
+    url: "https://api.kraken.com/0/public/SystemStatus",
+    parse: parse_status,
+};
+
+const URL: &str = "wss://ws.kraken.com";
+
+/// price, volume and timestamp, followed by an `r` if the level was republished
+const LEVEL: Shape = Shape::Array(&Shape::String);
+/// the snapshot or the changes of one or both sides
+const PAYLOAD: Shape = Shape::Object(&[
+    Field::optional("as", Shape::Array(&LEVEL)),
+    Field::optional("bs", Shape::Array(&LEVEL)),
+    Field::optional("a", Shape::Array(&LEVEL)),
+    Field::optional("b", Shape::Array(&LEVEL)),
+    Field::optional("c", Shape::String),
+]);
+/// a book message with a single payload, the events of the socket are objects and not validated
+static BOOK_SCHEMA: Shape = Shape::Tuple(&[Shape::Number, PAYLOAD, Shape::String, Shape::String]);
+/// a book message with the changes of the asks and the bids in payloads of their own
+static SPLIT_BOOK_SCHEMA: Shape = Shape::Tuple(&[
+    Shape::Number,
+    PAYLOAD,
+    PAYLOAD,
+    Shape::String,
+    Shape::String,
+]);
+
+/**
+ * The Kraken pair of a symbol, e.g. `ETH/XBT` for `ethbtc`, as Kraken calls bitcoin XBT. A symbol
+ * without a known quote asset is passed on uppercased, for Kraken to reject its subscription.
+ */
+fn pair(symbol: &str) -> String {
+    let asset = |asset: &str| match asset {
+        "btc" => "XBT".to_string(),
+        other => other.to_uppercase(),
+    };
+    match connector_sdk::split_symbol(symbol) {
+        Some((base, quote)) => format!("{}/{}", asset(base), asset(quote)),
+        None => symbol.to_uppercase(),
+    }
+}
+
+/**
+ * Subscribes to a channel of a symbol, the book channel with the given depth.
+ */
+fn subscribe(channel: &str, symbol: &str, depth: Option<usize>) -> String {
+    let mut subscription = json!({ "name": channel });
+    if let Some(depth) = depth {
+        subscription["depth"] = json!(depth);
+    }
+    json!({
+        "event": "subscribe",
+        "pair": [pair(symbol)],
+        "subscription": subscription
+    })
+    .to_string()
+}
+
+pub fn probe(symbol: &str) -> Probe {
+    Probe {
+        exchange: EXCHANGE,
+        url: URL.to_string(),
+        subscribe: Some(subscribe("book", symbol, Some(BOOK_DEPTHS[0]))),
+    }
+}
+
+/**
+ * Parses `{"error": [], "result": {"status": "online", ...}}`. While the venue is in `maintenance`
+ * or `cancel_only` its book does not trade, `post_only` and `limit_only` still match orders.
+ */
+fn parse_status(raw: &Value) -> Result<VenueStatus, ()> {
+    match raw["result"]["status"].as_str() {
+        Some("online" | "post_only" | "limit_only") => Ok(VenueStatus::Operational),
+        Some("maintenance" | "cancel_only") => Ok(VenueStatus::Maintenance),
+        _ => Err(()),
+    }
+}
+
+/**
+ * Parses a timestamp in seconds with a fractional part, e.g. `"1534614057.321597"`.
+ */
+fn parse_timestamp_us(raw: &Value) -> Option<u64> {
+    let seconds = raw.as_str()?.parse::<f64>().ok()?;
+    (seconds.is_finite() && seconds >= 0.).then(|| (seconds * 1e6).round() as u64)
+}
+
+/**
+ * The book of the subscription, built from its snapshot and kept up to date with its deltas. Each
+ * side is kept at the depth of the subscription, as Kraken stops updating the levels beyond it.
+ */
+#[derive(Debug)]
+struct Book {
+    levels: LocalBook,
+    depth: usize,
+    /// whether the snapshot arrived, deltas before it have nothing to apply to
+    synced: bool,
+}
+
+impl Book {
+    fn new(depth: usize) -> Self {
+        Book {
+            levels: LocalBook::new(),
+            depth,
+            synced: false,
+        }
+    }
+
+    /**
+     * Applies `[price, volume, timestamp, ...]` levels to a side, a volume of zero removes the level.
+     * Keeps the latest timestamp of the levels in `latest_us`.
+     */
+    fn apply(&mut self, side: Side, raw: &Value, latest_us: &mut Option<u64>) -> Result<(), ()> {
+        for entry in raw.as_array().ok_or(())? {
+            self.levels.set(
+                side,
+                connector_sdk::parse_decimal(&entry[0])?,
+                connector_sdk::parse_decimal(&entry[1])?,
+            );
+            if let Some(timestamp_us) = parse_timestamp_us(&entry[2]) {
+                *latest_us = (*latest_us).max(Some(timestamp_us));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Applies a book message, `[channel_id, {"as": [...], "bs": [...]}, "book-10", "ETH/XBT"]` for
+     * the snapshot and `[channel_id, {"a": [...]}, {"b": [...], "c": "..."}, "book-10", "ETH/XBT"]`
+     * for deltas, where either side may be missing. Events such as heartbeats are objects and thus
+     * fail, as do deltas before the snapshot. Returns the latest timestamp of the changed levels.
+     */
+    fn update(&mut self, deserialized: &Value) -> Result<Option<u64>, ()> {
+        let message = deserialized.as_array().ok_or(())?;
+        if message.len() < 4 {
+            return Err(());
+        }
+        let mut latest_us = None;
+        for payload in &message[1..message.len() - 2] {
+            if payload.get("as").is_some() || payload.get("bs").is_some() {
+                self.levels.clear();
+                self.apply(Side::Bids, &payload["bs"], &mut latest_us)?;
+                self.apply(Side::Asks, &payload["as"], &mut latest_us)?;
+                self.synced = true;
+                continue;
+            }
+            if !self.synced {
+                return Err(());
+            }
+            if let Some(bids) = payload.get("b") {
+                self.apply(Side::Bids, bids, &mut latest_us)?;
+            }
+            if let Some(asks) = payload.get("a") {
+                self.apply(Side::Asks, asks, &mut latest_us)?;
+            }
+        }
+        self.levels.truncate(self.depth);
+        Ok(latest_us)
+    }
+}
+
+fn deserialize(
+    book: &mut Book,
+    deserialized: &Value,
+    depth: usize,
+) -> Result<OrderbookSnapshot, ()> {
+    let latest_us = book.update(deserialized)?;
+    let mut snapshot = book
+        .levels
+        .snapshot(EXCHANGE, depth)
+        .map_err(|error| connector_sdk::reject(EXCHANGE, error))?;
+    snapshot.exchange_timestamp_us = latest_us;
+    Ok(snapshot)
+}
+
+/**
+ * Parses `[channel_id, [["0.0745", "1.2", "1672515782.136447", "b", "l", ""], ...], "trade",
+ * "ETH/XBT"]`, where a side of `b` stands for a buy.
+ */
+fn deserialize_trades(deserialized: &Value) -> Result<Vec<Trade>, ()> {
+    if deserialized[2].as_str() != Some("trade") {
+        return Err(());
+    }
+    deserialized[1]
+        .as_array()
+        .ok_or(())?
+        .iter()
+        .map(|trade| {
+            Ok(Trade {
+                exchange: EXCHANGE,
+                price: connector_sdk::parse_decimal(&trade[0])?,
+                amount: connector_sdk::parse_decimal(&trade[1])?,
+                buy: trade[3].as_str().ok_or(())? == "b",
+                exchange_timestamp_us: parse_timestamp_us(&trade[2]),
+            })
+        })
+        .collect()
+}
+
+/**
+ * The book channel of the smallest depth covering the aggregator's.
+ */
+#[derive(Debug)]
+struct KrakenConnector {
+    book: Book,
+}
+
+impl ExchangeConnector for KrakenConnector {
+    fn url(&self, _: &str, _: usize) -> String {
+        URL.to_string()
+    }
+
+    fn subscribe(&mut self, symbol: &str, depth: usize) -> Vec<String> {
+        let book_depth = BOOK_DEPTHS
+            .into_iter()
+            .find(|book_depth| *book_depth >= depth)
+            .unwrap_or(MAX_DEPTH);
+        self.book = Book::new(book_depth);
+        vec![subscribe("book", symbol, Some(book_depth))]
+    }
+
+    fn next_snapshot(&mut self, message: &Value, depth: usize) -> Update {
+        match deserialize(&mut self.book, message, depth) {
+            Ok(snapshot) => Update::Snapshot(snapshot),
+            Err(_) => Update::Ignored,
+        }
+    }
+
+    fn schema(&self, message: &Value) -> Option<&'static Shape> {
+        match message.as_array()?.len() {
+            4 => Some(&BOOK_SCHEMA),
+            _ => Some(&SPLIT_BOOK_SCHEMA),
+        }
+    }
+}
+
+pub fn connector() -> Box<dyn ExchangeConnector> {
+    Box::new(KrakenConnector {
+        book: Book::new(BOOK_DEPTHS[0]),
+    })
+}
+
+async fn run_trade_session(symbol: &str, tx: Sender<Trade>) -> Result<(), tungstenite::Error> {
+    let (mut socket, _) = connect(Url::parse(URL).unwrap())?;
+
+    socket.write_message(Message::Text(subscribe("trade", symbol, None)))?;
+
+    loop {
+        let msg = socket.read_message()?;
+        let content = msg.into_text()?;
+        let trades = serde_json::from_str::<Value>(&content)
+            .map_err(|_| ())
+            .and_then(|deserialized| deserialize_trades(&deserialized));
+        for trade in trades.unwrap_or_default() {
+            if tx.send(trade).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub async fn run_trades(symbol: String, tx: Sender<Trade>, policy: ReconnectPolicy) {
+    connector_sdk::run_with_reconnect(EXCHANGE, policy, || run_trade_session(&symbol, tx.clone()))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize, deserialize_trades, pair, Book, BOOK_SCHEMA, SPLIT_BOOK_SCHEMA};
+    use crate::schema;
+    use keyrock_challenge_core::{
+        decimal::Decimal,
+        orderbook_snapshot::{Side, SnapshotError},
+    };
+    use serde_json::json;
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn should_map_symbols_to_kraken_pairs() {
+        assert!(pair("ethbtc") == "ETH/XBT");
+        assert!(pair("btcusdt") == "XBT/USDT");
+        assert!(pair("ethusd") == "ETH/USD");
+        assert!(pair("usdt") == "USDT");
+    }
+
+    #[test]
+    fn should_apply_deltas_to_the_snapshot() {
+        // Arrange
+        let mut book = Book::new(10);
+        let snapshot = json!([336, {
+            "as": [
+                ["0.0746", "1.0", "1672515782.100000"],
+                ["0.0747", "2.0", "1672515782.100000"],
+                ["0.0748", "2.0", "1672515782.100000"]
+            ],
+            "bs": [["0.0745", "1.5", "1672515782.100000"], ["0.0744", "3.0", "1672515782.100000"]]
+        }, "book-10", "ETH/XBT"]);
+        let delta = json!([336,
+            {"a": [["0.0746", "0.00000000", "1672515782.200000"]]},
+            {"b": [["0.07455", "0.5", "1672515782.300000"]], "c": "974942666"},
+            "book-10", "ETH/XBT"]);
+
+        // Act
+        let early = deserialize(&mut book, &delta, 2);
+        let synced = deserialize(&mut book, &snapshot, 2).unwrap();
+        let updated = deserialize(&mut book, &delta, 2).unwrap();
+        let heartbeat = deserialize(&mut book, &json!({"event": "heartbeat"}), 2);
+
+        // Assert
+        assert!(schema::validate(&BOOK_SCHEMA, &snapshot).is_empty());
+        assert!(schema::validate(&SPLIT_BOOK_SCHEMA, &delta).is_empty());
+        assert!(early.is_err() && heartbeat.is_err());
+        assert!(synced.asks.unwrap()[0].price == decimal("0.0746"));
+        assert!(synced.exchange_timestamp_us == Some(1_672_515_782_100_000));
+        let bids = updated.bids.unwrap();
+        assert!(bids[0].price == decimal("0.07455") && bids[1].price == decimal("0.0745"));
+        assert!(bids[0].exchange == "Kraken");
+        let asks = updated.asks.unwrap();
+        assert!(asks[0].price == decimal("0.0747") && asks[1].price == decimal("0.0748"));
+        assert!(updated.exchange_timestamp_us == Some(1_672_515_782_300_000));
+    }
+
+    #[test]
+    fn should_keep_the_depth_of_the_subscription() {
+        // Arrange
+        let mut book = Book::new(1);
+        let snapshot = json!([336, {
+            "as": [["0.0746", "1.0", "1672515782.100000"]],
+            "bs": [["0.0745", "1.5", "1672515782.100000"]]
+        }, "book-1", "ETH/XBT"]);
+        let delta =
+            json!([336, {"b": [["0.0744", "1.0", "1672515782.200000"]]}, "book-1", "ETH/XBT"]);
+
+        // Act
+        deserialize(&mut book, &snapshot, 1).unwrap();
+        let updated = deserialize(&mut book, &delta, 1).unwrap();
+
+        // Assert
+        // without the truncation, the asks would be the side too shallow for two levels
+        assert!(
+            book.levels.snapshot("Kraken", 2).unwrap_err()
+                == SnapshotError::TooShallow {
+                    side: Side::Bids,
+                    levels: 1
+                }
+        );
+        assert!(updated.bids.unwrap()[0].price == decimal("0.0745"));
+    }
+
+    #[test]
+    fn should_parse_trades() {
+        let trades = deserialize_trades(&json!([
+            337,
+            [
+                ["0.0745", "1.2", "1672515782.136447", "b", "l", ""],
+                ["0.0744", "0.3", "1672515782.136448", "s", "m", ""]
+            ],
+            "trade",
+            "ETH/XBT"
+        ]))
+        .unwrap();
+
+        assert!(trades.len() == 2 && trades[0].buy && !trades[1].buy);
+        assert!(trades[0].exchange_timestamp_us == Some(1_672_515_782_136_447));
+        assert!(deserialize_trades(&json!({"event": "heartbeat"})).is_err());
+    }
+}
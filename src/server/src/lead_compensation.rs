@@ -0,0 +1,234 @@
+use crate::{aggregator::Aggregator, clock::Clock};
+use keyrock_challenge_core::orderbook_snapshot::OrderbookSnapshot;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+const LATENCY_SAMPLES: usize = 128;
+const MAX_PENDING_SNAPSHOTS: usize = 64;
+/// how often the release task looks for held back snapshots while none were due
+const RELEASE_CHECK_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Default)]
+struct Venue {
+    latencies_us: VecDeque<u64>,
+    pending: VecDeque<(Instant, OrderbookSnapshot)>,
+}
+
+impl Venue {
+    fn median_latency_us(&self) -> Option<u64> {
+        if self.latencies_us.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<u64> = self.latencies_us.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+}
+
+/**
+ * Time-aligns the venues by holding back the snapshots of a faster venue for the difference between
+ * its median latency and the median latency of the slowest venue, bounded by the configured window.
+ * The latency of a venue can only be measured if its snapshots carry the exchange's event time;
+ * as long as that is not the case for at least two venues, snapshots are released immediately.
+ */
+#[derive(Debug)]
+pub struct LeadCompensator {
+    window: Duration,
+    venues: Vec<Venue>,
+}
+
+impl LeadCompensator {
+    pub fn new(window: Duration, venues: usize) -> Self {
+        LeadCompensator {
+            window,
+            venues: (0..venues).map(|_| Venue::default()).collect(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        venue_id: usize,
+        snapshot: OrderbookSnapshot,
+        received_at: Instant,
+        latency_us: Option<u64>,
+    ) {
+        let venue = &mut self.venues[venue_id];
+
+        if let Some(latency_us) = latency_us {
+            if venue.latencies_us.len() == LATENCY_SAMPLES {
+                venue.latencies_us.pop_front();
+            }
+            venue.latencies_us.push_back(latency_us);
+        }
+        // only the latest due snapshot is ever released, so dropping the oldest one is lossless
+        // as long as the buffer covers the window
+        if venue.pending.len() == MAX_PENDING_SNAPSHOTS {
+            venue.pending.pop_front();
+        }
+        venue.pending.push_back((received_at, snapshot));
+    }
+
+    /**
+     * How many snapshots of the given venue are held back until its delay passed.
+     */
+    pub fn pending(&self, venue_id: usize) -> usize {
+        self.venues[venue_id].pending.len()
+    }
+
+    /**
+     * Returns the delay currently applied to the given venue.
+     */
+    pub fn delay(&self, venue_id: usize) -> Duration {
+        let own_latency = match self.venues[venue_id].median_latency_us() {
+            Some(latency) => latency,
+            None => return Duration::ZERO,
+        };
+        let slowest_latency = self
+            .venues
+            .iter()
+            .enumerate()
+            .filter(|(other_id, _)| *other_id != venue_id)
+            .filter_map(|(_, other)| other.median_latency_us())
+            .max();
+
+        match slowest_latency {
+            Some(slowest_latency) if slowest_latency > own_latency => std::cmp::min(
+                Duration::from_micros(slowest_latency - own_latency),
+                self.window,
+            ),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /**
+     * Takes the latest snapshot of every venue whose delay has passed, together with the time it was received.
+     */
+    pub fn release(&mut self, now: Instant) -> Vec<(usize, Instant, OrderbookSnapshot)> {
+        let delays: Vec<Duration> = (0..self.venues.len()).map(|id| self.delay(id)).collect();
+        let mut released = Vec::new();
+
+        for (venue_id, venue) in self.venues.iter_mut().enumerate() {
+            let mut latest = None;
+            while let Some((received_at, _)) = venue.pending.front() {
+                if *received_at + delays[venue_id] > now {
+                    break;
+                }
+                latest = venue.pending.pop_front();
+            }
+            if let Some((received_at, snapshot)) = latest {
+                released.push((venue_id, received_at, snapshot));
+            }
+        }
+
+        released
+    }
+
+    /**
+     * How long after the given time the next held back snapshot is due, None if none is held back.
+     */
+    pub fn next_release(&self, now: Instant) -> Option<Duration> {
+        (self.venues.iter().enumerate())
+            .filter_map(|(venue_id, venue)| {
+                let (received_at, _) = venue.pending.front()?;
+                Some((*received_at + self.delay(venue_id)).saturating_duration_since(now))
+            })
+            .min()
+    }
+}
+
+/**
+ * Releases the snapshots held back by the lead compensation once they are due, so that a venue
+ * going quiet does not keep its last snapshot from being published.
+ */
+pub async fn run(aggregator_arc: Arc<Mutex<Aggregator>>, clock: Arc<dyn Clock>) {
+    loop {
+        let next_release = aggregator_arc.lock().await.release_held_back().await;
+        clock
+            .sleep(next_release.map_or(RELEASE_CHECK_INTERVAL, |due| {
+                due.min(RELEASE_CHECK_INTERVAL)
+            }))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LeadCompensator;
+    use keyrock_challenge_core::orderbook_snapshot::OrderbookSnapshot;
+    use std::time::{Duration, Instant};
+
+    fn snapshot() -> OrderbookSnapshot {
+        OrderbookSnapshot {
+            bids: Some(Vec::new()),
+            asks: Some(Vec::new()),
+            exchange_timestamp_us: None,
+        }
+    }
+
+    #[test]
+    fn should_release_immediately_without_latencies() {
+        let mut compensator = LeadCompensator::new(Duration::from_millis(100), 2);
+        let now = Instant::now();
+
+        compensator.push(0, snapshot(), now, None);
+
+        assert_eq!(compensator.release(now).len(), 1);
+    }
+
+    #[test]
+    fn should_delay_faster_venue_by_latency_difference() {
+        // Arrange
+        let mut compensator = LeadCompensator::new(Duration::from_millis(100), 2);
+        let now = Instant::now();
+        compensator.push(1, snapshot(), now, Some(50_000));
+        compensator.release(now);
+
+        // Act
+        compensator.push(0, snapshot(), now, Some(20_000));
+
+        // Assert
+        assert_eq!(compensator.delay(0), Duration::from_millis(30));
+        assert_eq!(compensator.delay(1), Duration::ZERO);
+        assert!(compensator.release(now).is_empty());
+        assert!(compensator
+            .release(now + Duration::from_millis(29))
+            .is_empty());
+        assert_eq!(compensator.release(now + Duration::from_millis(30))[0].0, 0);
+    }
+
+    #[test]
+    fn should_tell_when_the_next_held_back_snapshot_is_due() {
+        // Arrange
+        let mut compensator = LeadCompensator::new(Duration::from_millis(100), 2);
+        let now = Instant::now();
+        compensator.push(1, snapshot(), now, Some(50_000));
+        compensator.release(now);
+        assert_eq!(compensator.next_release(now), None);
+
+        // Act
+        compensator.push(0, snapshot(), now, Some(20_000));
+
+        // Assert
+        assert_eq!(
+            compensator.next_release(now + Duration::from_millis(10)),
+            Some(Duration::from_millis(20))
+        );
+        compensator.release(now + Duration::from_millis(30));
+        assert_eq!(compensator.next_release(now), None);
+    }
+
+    #[test]
+    fn should_bound_delay_by_window() {
+        let mut compensator = LeadCompensator::new(Duration::from_millis(10), 2);
+        let now = Instant::now();
+
+        compensator.push(0, snapshot(), now, Some(0));
+        compensator.push(1, snapshot(), now, Some(1_000_000));
+
+        assert_eq!(compensator.delay(0), Duration::from_millis(10));
+    }
+}
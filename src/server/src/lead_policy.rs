@@ -0,0 +1,33 @@
+use std::str::FromStr;
+
+/**
+ * What the aggregator does while the stream of one venue leads the others by more than the lead
+ * tolerance, i.e. the other streams lag badly and their books may no longer reflect the market.
+ * Parsed from `publish-anyway`, `suppress-publish`, `publish-single-exchange` or `mark-degraded`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeadPolicy {
+    /// merge and publish as usual, only a warning is logged
+    #[default]
+    PublishAnyway,
+    /// publish nothing until a lagging venue delivers again
+    SuppressPublish,
+    /// publish the books of the leading venue alone
+    PublishSingleExchange,
+    /// merge and publish as usual, with `lead_degraded` set on the summaries
+    MarkDegraded,
+}
+
+impl FromStr for LeadPolicy {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "publish-anyway" => Ok(LeadPolicy::PublishAnyway),
+            "suppress-publish" => Ok(LeadPolicy::SuppressPublish),
+            "publish-single-exchange" => Ok(LeadPolicy::PublishSingleExchange),
+            "mark-degraded" => Ok(LeadPolicy::MarkDegraded),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Races the venues against each other on every change of the top of the book: once both venues
+//! showed the same new best price, the one showing it first won the race by the time in between.
+//! The rolling win rates quantify which feed truly leads, independent of the exchange timestamps.
+
+use keyrock_challenge_core::orderbook_snapshot::Side;
+use keyrock_challenge_proto::orderbook::VenueLeadRace;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// a price the other venue did not show within this time is no race
+const MATCH_WINDOW: Duration = Duration::from_secs(2);
+const ROLLING_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// bounds the moves waiting for the other venue if a venue moves excessively often
+const MAX_PENDING: usize = 256;
+
+#[derive(Debug)]
+struct Move {
+    symbol: String,
+    exchange: String,
+    side: Side,
+    price: f64,
+    at: Instant,
+}
+
+#[derive(Debug)]
+struct Race {
+    at: Instant,
+    winner: String,
+    loser: String,
+    lead: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct LeadRace {
+    /// the best price per symbol, exchange and side as last observed
+    tops: HashMap<(String, String, Side), f64>,
+    /// changes not yet shown by the other venue, oldest first
+    pending: VecDeque<Move>,
+    races: VecDeque<Race>,
+}
+
+impl LeadRace {
+    pub fn new() -> Self {
+        LeadRace::default()
+    }
+
+    /**
+     * Observes the best prices of a book of the exchange, received at `now`. Only the venues of the
+     * same symbol race each other, the races of all symbols add up to the stats of a venue.
+     */
+    pub fn observe(
+        &mut self,
+        symbol: &str,
+        exchange: &str,
+        best_bid: Option<f64>,
+        best_ask: Option<f64>,
+        now: Instant,
+    ) {
+        for (side, price) in [(Side::Bids, best_bid), (Side::Asks, best_ask)] {
+            let price = match price {
+                Some(price) => price,
+                None => continue,
+            };
+            let top = (symbol.to_string(), exchange.to_string(), side);
+            let previous = self.tops.insert(top, price);
+            // the first book of a venue tells when it connected, not which one leads
+            if previous.is_some_and(|previous| previous != price) {
+                self.on_move(symbol, exchange, side, price, now);
+            }
+        }
+    }
+
+    fn on_move(&mut self, symbol: &str, exchange: &str, side: Side, price: f64, now: Instant) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|pending| now.duration_since(pending.at) > MATCH_WINDOW)
+        {
+            self.pending.pop_front();
+        }
+
+        let matched = self.pending.iter().position(|pending| {
+            pending.symbol == symbol
+                && pending.exchange != exchange
+                && pending.side == side
+                && pending.price == price
+        });
+        match matched {
+            Some(index) => {
+                let leader = self.pending.remove(index).unwrap();
+                self.races.push_back(Race {
+                    at: now,
+                    lead: now.duration_since(leader.at),
+                    winner: leader.exchange,
+                    loser: exchange.to_string(),
+                });
+            }
+            None => {
+                if self.pending.len() >= MAX_PENDING {
+                    self.pending.pop_front();
+                }
+                self.pending.push_back(Move {
+                    symbol: symbol.to_string(),
+                    exchange: exchange.to_string(),
+                    side,
+                    price,
+                    at: now,
+                });
+            }
+        }
+    }
+
+    pub fn stats(&mut self, now: Instant) -> Vec<VenueLeadRace> {
+        while self
+            .races
+            .front()
+            .is_some_and(|race| now.duration_since(race.at) > ROLLING_WINDOW)
+        {
+            self.races.pop_front();
+        }
+
+        let mut exchanges: Vec<&String> = (self.tops.keys())
+            .map(|(_, exchange, _)| exchange)
+            .collect();
+        exchanges.sort();
+        exchanges.dedup();
+        exchanges
+            .into_iter()
+            .map(|exchange| {
+                let won: Vec<&Race> = self
+                    .races
+                    .iter()
+                    .filter(|race| &race.winner == exchange)
+                    .collect();
+                let races = self
+                    .races
+                    .iter()
+                    .filter(|race| &race.winner == exchange || &race.loser == exchange)
+                    .count() as u64;
+                let wins = won.len() as u64;
+                let leads: u128 = won.iter().map(|race| race.lead.as_micros()).sum();
+                VenueLeadRace {
+                    exchange: exchange.clone(),
+                    window_secs: ROLLING_WINDOW.as_secs() as u32,
+                    races,
+                    wins,
+                    win_rate: match races {
+                        0 => 0.,
+                        _ => wins as f64 / races as f64,
+                    },
+                    mean_lead_us: match wins {
+                        0 => 0,
+                        _ => (leads / wins as u128) as u64,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LeadRace;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_credit_venue_showing_a_new_best_price_first() {
+        // Arrange
+        let mut race = LeadRace::new();
+        let started = Instant::now();
+        let at = |ms: u64| started + Duration::from_millis(ms);
+
+        // Act
+        race.observe("ethbtc", "Binance", Some(10.), Some(11.), at(0));
+        race.observe("ethbtc", "Bitstamp", Some(10.), Some(11.), at(5));
+        // Binance leads the bid by 20ms, Bitstamp the ask by 40ms
+        race.observe("ethbtc", "Binance", Some(10.5), Some(11.), at(100));
+        race.observe("ethbtc", "Bitstamp", Some(10.5), Some(10.8), at(120));
+        race.observe("ethbtc", "Binance", Some(10.5), Some(10.8), at(160));
+        // never shown by Bitstamp
+        race.observe("ethbtc", "Binance", Some(10.6), Some(10.8), at(200));
+        race.observe("ethbtc", "Bitstamp", Some(10.6), Some(10.8), at(5000));
+        let stats = race.stats(at(5000));
+
+        // Assert
+        assert!(stats[0].exchange == "Binance" && stats[0].races == 2 && stats[0].wins == 1);
+        assert!(stats[0].mean_lead_us == 20_000);
+        assert!(stats[1].exchange == "Bitstamp" && stats[1].wins == 1);
+        assert!(stats[1].win_rate == 0.5 && stats[1].mean_lead_us == 40_000);
+    }
+
+    #[test]
+    fn should_only_race_venues_of_the_same_symbol() {
+        // Arrange
+        let mut race = LeadRace::new();
+        let started = Instant::now();
+        let at = |ms: u64| started + Duration::from_millis(ms);
+        race.observe("ethbtc", "Binance", Some(10.), Some(11.), at(0));
+        race.observe("btcusdt", "Bitstamp", Some(10.), Some(11.), at(5));
+
+        // Act
+        race.observe("ethbtc", "Binance", Some(10.5), Some(11.), at(100));
+        race.observe("btcusdt", "Bitstamp", Some(10.5), Some(11.), at(120));
+        let stats = race.stats(at(120));
+
+        // Assert
+        assert!(stats.len() == 2);
+        assert!(stats.iter().all(|venue| venue.races == 0));
+    }
+}
@@ -0,0 +1,149 @@
+//! The server's log. Warnings report conditions the server works around on its own, e.g. a venue
+//! reconnecting, alerts those an operator should look into. `--log-level alert` silences the
+//! warnings, `off` both. Notices, e.g. a pipeline starting, are logged along with the warnings.
+//!
+//! The messages are `tracing` events, emitted within the spans of the symbol's pipeline, the
+//! exchange and the task they were logged from, so each of them carries the `symbol` and `exchange`
+//! as fields. `--log-format pretty` (default) writes them for reading in a terminal, `json` one
+//! object per line for a log collector.
+
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use tracing::Metadata;
+use tracing_subscriber::{filter, fmt, prelude::*, registry::Registry, Layer};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    #[default]
+    Warning = 1,
+    Alert = 2,
+    Off = 3,
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "warning" => Ok(Level::Warning),
+            "alert" => Ok(Level::Alert),
+            "off" => Ok(Level::Off),
+            _ => Err(()),
+        }
+    }
+}
+
+/**
+ * How the messages are written to stdout.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// multi-line and colored, for development
+    #[default]
+    Pretty,
+    /// an object per line, for production
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw {
+            "pretty" => Ok(Format::Pretty),
+            "json" => Ok(Format::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warning as u8);
+
+/**
+ * Logs the messages of the given level and above only.
+ */
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        1 => Level::Warning,
+        2 => Level::Alert,
+        _ => Level::Off,
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+/**
+ * Whether the span or event is written at the current level. Spans always are, as the events
+ * within them take their fields from them. The notices of the libraries, e.g. of tonic, are left out.
+ */
+fn shown(metadata: &Metadata<'_>) -> bool {
+    if metadata.is_span() {
+        return true;
+    }
+    let level = match *metadata.level() {
+        tracing::Level::ERROR => Level::Alert,
+        tracing::Level::WARN => Level::Warning,
+        tracing::Level::INFO if metadata.target().starts_with(env!("CARGO_CRATE_NAME")) => {
+            Level::Warning
+        }
+        _ => return false,
+    };
+    enabled(level)
+}
+
+/**
+ * Installs the subscriber writing the messages in the given format. The level is checked on every
+ * message, so it can be changed at runtime with [`set_level`].
+ */
+pub fn init(format: Format) {
+    let layer: Box<dyn Layer<Registry> + Send + Sync> = match format {
+        Format::Pretty => fmt::layer().pretty().boxed(),
+        Format::Json => fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter::filter_fn(shown)))
+        .init();
+}
+
+/**
+ * Logs a condition the server works around on its own, formatted like `println!`, optionally with
+ * fields ahead of the message like `tracing::warn!`.
+ */
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        ::tracing::warn!($($arg)*)
+    };
+}
+
+/**
+ * Logs a condition an operator should look into, formatted like `println!`, optionally with fields
+ * ahead of the message like `tracing::error!`.
+ */
+macro_rules! alert {
+    ($($arg:tt)*) => {
+        ::tracing::error!($($arg)*)
+    };
+}
+
+/**
+ * Logs a notice, e.g. of a pipeline starting, along with the warnings.
+ */
+macro_rules! info {
+    ($($arg:tt)*) => {
+        ::tracing::info!($($arg)*)
+    };
+}
+
+pub(crate) use {alert, info, warning};
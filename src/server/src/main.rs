@@ -1,46 +1,1198 @@
 mod aggregator;
+mod audit;
+mod bandwidth;
 mod binance_spot;
 mod bitstamp_spot;
+mod book_analytics;
+mod book_shape;
+mod canary;
+#[cfg(test)]
+mod capture;
+mod catch_up;
+mod clock;
+mod coinbase_spot;
+mod config;
+mod config_file;
+mod connector_sdk;
+mod consumer_group;
+mod contribution_stats;
+mod crossing;
+mod delta_recording;
+mod diagnostics;
+mod distribution;
+mod empty_book_policy;
+mod enrichment;
+mod exchange_registry;
+mod exchange_source;
+mod exchange_status;
+mod failure_domain;
+mod fair_price;
+mod fan_out;
 mod grpc;
-mod orderbook_snapshot;
+mod handover;
+mod history;
+mod inbound_queue;
+mod index;
+mod journal;
+mod kraken_spot;
+mod lead_compensation;
+mod lead_policy;
+mod lead_race;
+mod log;
+mod maintenance;
+mod memory_budget;
+mod memory_watermark;
+mod okx_spot;
+mod publish_trigger;
+mod quiet_period;
+mod quorum;
+mod record_codec;
+mod recorder;
+mod reload;
+mod schema;
+mod sequence_store;
+mod shadow;
+mod simulated_spot;
+mod source_selector;
 mod spmc;
+mod spread_smoothing;
+mod stage_timings;
+mod staleness;
+#[cfg(test)]
+mod test_fixtures;
+mod trade_through;
+mod upstream;
 
 use aggregator::Aggregator;
-use grpc::OrderbookAggregatorServer;
-use orderbook_snapshot::OrderbookSnapshot;
+use bandwidth::Bandwidth;
+use config::Config;
+use connector_sdk::ReconnectPolicy;
+use contribution_stats::ContributionStats;
+use exchange_registry::DisplayNames;
+use exchange_source::ExchangeSource;
+use failure_domain::{FailureDomain, Supervisor};
+use fan_out::FanOut;
+use grpc::{
+    Aggregators, MarketDataServer, OrderbookAdminServer, OrderbookAggregatorServer,
+    OrderbookDebugServer, Symbols,
+};
+use history::History;
+use index::Index;
+use journal::Journal;
+use lead_race::LeadRace;
+use memory_budget::Sizing;
+use memory_watermark::Watermark;
+use publish_trigger::PublishTrigger;
+use sequence_store::SequenceStore;
+use shadow::Shadow;
 
-use keyrock_challenge_proto::orderbook;
-use tokio::sync::Mutex;
-use tonic::transport::Server;
+use clock::Clock;
+use keyrock_challenge_proto::orderbook::{
+    self, BookShape, CrossingEvent, ExchangeSnapshot, FairPrice, OverloadEvent, RecordedSummary,
+    ShadowComparison, Summary, TickTimings, TradeThrough,
+};
+use reload::Changes;
+use schema::Quarantine;
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tracing::Instrument;
 
-use std::{net::ToSocketAddrs, sync::Arc};
+use std::{
+    fs,
+    net::ToSocketAddrs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
 
-const SERVER_URL: &str = "[::1]:8080";
+const STATS_BUFFER_SIZE: usize = 64;
+const RECORDER_BUFFER_SIZE: usize = 256;
+const HISTORY_BUFFER_SIZE: usize = 64;
+const MERGE_BUFFER_SIZE: usize = 256;
+// holds the summaries published while the symbol waits for its share of the fan-out
+const DELIVERY_BUFFER_SIZE: usize = 256;
+const CROSSING_BUFFER_SIZE: usize = 64;
+const FAIR_PRICE_BUFFER_SIZE: usize = 64;
+const LEAD_RACE_BUFFER_SIZE: usize = 64;
+const TRADE_BUFFER_SIZE: usize = 256;
+const INDEX_BUFFER_SIZE: usize = 64;
+// holds the summaries published during the delay of the external distribution
+const DISTRIBUTION_BUFFER_SIZE: usize = 4096;
+const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const RELOAD_BUFFER_SIZE: usize = 4;
+
+/**
+ * The aggregator of a symbol with the stream its summaries are broadcast on.
+ */
+struct Pipeline {
+    symbol: String,
+    aggregator: Arc<Mutex<Aggregator>>,
+    spmr: Arc<Mutex<spmc::Spmc<Summary>>>,
+    latest_summary: watch::Receiver<Option<Summary>>,
+    /// hands the summaries to the task broadcasting them to the spmr
+    delivery: mpsc::Sender<Summary>,
+    /// whether enough exchanges are live to serve the summaries, set once started
+    ready: watch::Receiver<bool>,
+    source_ids: Vec<usize>,
+    /// the tasks feeding the aggregator, stopped once the pipeline is dropped
+    tasks: FailureDomain,
+}
+
+/**
+ * What the pipelines of all symbols share, kept to start those of the symbols added by a reload.
+ */
+struct Shared {
+    clock: Arc<dyn Clock>,
+    journal: Arc<Journal>,
+    /// set in strict schema mode only
+    quarantine: Option<Arc<Quarantine>>,
+    debug_spmc: Option<Arc<Mutex<spmc::Spmc<TickTimings>>>>,
+    snapshot_spmc: Arc<Mutex<spmc::Spmc<ExchangeSnapshot>>>,
+    shadow_spmc: Option<Arc<Mutex<spmc::Spmc<ShadowComparison>>>>,
+    overload_spmc: Arc<Mutex<spmc::Spmc<OverloadEvent>>>,
+    /// the summaries of all symbols, recorded and counted in the contribution stats
+    merged_spmc: Arc<Mutex<spmc::Spmc<Summary>>>,
+    history: Arc<Mutex<History>>,
+    crossing_spmc: Arc<Mutex<spmc::Spmc<CrossingEvent>>>,
+    fair_price_spmc: Arc<Mutex<spmc::Spmc<FairPrice>>>,
+    book_shape_spmc: Arc<Mutex<spmc::Spmc<BookShape>>>,
+    /// set with `--trade-throughs` only
+    trade_through_spmc: Option<Arc<Mutex<spmc::Spmc<TradeThrough>>>>,
+    fan_out: Arc<FanOut>,
+    exchange_sources: Vec<ExchangeSource>,
+    reconnect_policy: ReconnectPolicy,
+    supervisor: Supervisor,
+}
+
+/**
+ * The sequence file of the symbol, the passed path with the symbol as suffix. Named by the symbol,
+ * so reordering, adding or removing symbols keeps every sequence with its symbol.
+ */
+fn sequence_file(config: &Config, symbol: &str) -> Option<PathBuf> {
+    config.sequence_file.clone().map(|path| {
+        let mut path = path.into_os_string();
+        path.push(format!(".{}", symbol));
+        PathBuf::from(path)
+    })
+}
+
+/**
+ * The summaries recorded within the warm start window before the server started, none if no warm
+ * start is configured or the recordings cannot be read, e.g. on the very first start.
+ */
+fn warm_start(config: &Config, clock: &dyn Clock) -> Vec<RecordedSummary> {
+    let (window, recorder) = match (config.warm_start, &config.recorder) {
+        (Some(window), Some(recorder)) => (window, recorder),
+        _ => return Vec::new(),
+    };
+    let now_ms = clock
+        .system_now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    match recorder::read_recent(
+        &recorder.dir,
+        now_ms.saturating_sub(window.as_millis() as u64),
+    ) {
+        Ok(recent) => {
+            log::info!(
+                "Loaded {} summaries of the last {} minutes from {}",
+                recent.len(),
+                window.as_secs() / 60,
+                recorder.dir.display()
+            );
+            recent
+        }
+        Err(error) => {
+            log::warning!("Starting without the recent summaries: {}", error);
+            Vec::new()
+        }
+    }
+}
+
+async fn build_pipeline(
+    config: &Config,
+    index: usize,
+    shared: &Shared,
+) -> Result<Pipeline, String> {
+    let Shared {
+        clock,
+        journal,
+        quarantine,
+        debug_spmc,
+        snapshot_spmc,
+        shadow_spmc,
+        fan_out,
+        exchange_sources,
+        ..
+    } = shared;
+    let symbol = config.symbols[index].clone();
+    let spmr = Arc::new(Mutex::new(spmc::Spmc::with_history(config.replay_buffer)));
+    spmr.lock().await.set_journal(journal.clone(), "summaries");
+    let mut aggregator: Aggregator = Aggregator::new(
+        spmr.clone(),
+        debug_spmc.clone(),
+        SequenceStore::open(sequence_file(config, &symbol))?,
+        symbol.clone(),
+        exchange_sources
+            .iter()
+            .map(|source| source.exchange.to_string())
+            .collect(),
+    );
+    let source_ids: Vec<usize> = exchange_sources
+        .iter()
+        .enumerate()
+        .map(|(venue_id, source)| aggregator.register_source(venue_id, source.kind))
+        .collect();
+    let (delivery, delivery_rx) = mpsc::channel(DELIVERY_BUFFER_SIZE);
+    aggregator.set_delivery(delivery.clone());
+    aggregator.set_clock(clock.clone());
+    aggregator.set_journal(journal.clone());
+    if let Some(quarantine) = quarantine {
+        aggregator.set_quarantine(quarantine.clone());
+    }
+    aggregator.set_snapshot_spmc(snapshot_spmc.clone());
+    aggregator.set_lead_compensation(config.lead_compensation_window);
+    aggregator.set_empty_book_policy(config.empty_book_policy);
+    aggregator.set_lead_policy(config.lead_policy);
+    aggregator
+        .set_lead_tolerance(config.lead_tolerance)
+        .map_err(|_| "--lead-tolerance has to be at least 1".to_string())?;
+    aggregator.set_merge_strategy(config.merge_strategy);
+    if let (Some(strategy), Some(shadow_spmc)) = (config.shadow_merge_strategy, shadow_spmc) {
+        aggregator.set_shadow(Shadow::new(strategy, shadow_spmc.clone()));
+    }
+    if let Some(smoothing) = config.spread_smoothing {
+        aggregator.set_spread_smoothing(smoothing);
+    }
+    aggregator.set_publish_trigger(config.publish_trigger);
+    for enricher in &config.enrichers {
+        aggregator.add_enricher(enricher.build());
+    }
+    for exchange in &config.excluded_exchanges {
+        aggregator
+            .set_excluded(exchange, true)
+            .map_err(|_| format!("Unable to exclude unknown exchange '{}'", exchange))?;
+    }
+    aggregator.set_maintenance(config.maintenance_windows.clone());
+    aggregator.set_staleness(config.staleness.clone());
+    aggregator.set_quiet_periods(config.quiet_periods.clone());
+    aggregator.set_display_names(DisplayNames::new(config.display_names.clone()));
+    aggregator.set_expose_provenance(config.expose_provenance);
+    aggregator
+        .set_min_live(config.min_live_exchanges)
+        .map_err(|_| {
+            format!(
+                "--min-live-exchanges has to be between 1 and the number of exchanges, got {}",
+                config.min_live_exchanges
+            )
+        })?;
+    let depths = config.depths_of(&symbol);
+    aggregator
+        .set_side_depths(depths)
+        .map_err(|_| format!("--depth has to be at least 1, got {}", config.depth))?;
+    let max_depth = config.max_depth.unwrap_or(depths.max());
+    aggregator.set_max_depth(max_depth).map_err(|_| {
+        format!(
+            "--max-depth has to be at least the depth {} of {}, got {}",
+            depths.max(),
+            symbol,
+            max_depth
+        )
+    })?;
+    if let Some(quorum) = config.quorum {
+        aggregator.set_quorum(quorum).map_err(|_| {
+            format!(
+                "The quorum has to be between 1 and the number of exchanges, got {}",
+                quorum.min_venues
+            )
+        })?;
+    }
+    let latest_summary = aggregator.latest_summary();
+    let mut tasks = FailureDomain::for_symbol(shared.supervisor.clone(), &symbol);
+    // it owns the only receiver of the summaries to deliver, so it cannot restart
+    tasks.spawn(
+        format!("{} delivery", symbol),
+        fan_out::run(fan_out.clone(), symbol.clone(), delivery_rx, spmr.clone()),
+    );
+
+    Ok(Pipeline {
+        symbol,
+        aggregator: Arc::new(Mutex::new(aggregator)),
+        spmr,
+        latest_summary,
+        delivery,
+        ready: watch::channel(false).1,
+        source_ids,
+        tasks,
+    })
+}
+
+/**
+ * Starts the tasks feeding the pipeline and publishing its summaries.
+ */
+fn start_pipeline(config: &Config, pipeline: &mut Pipeline, shared: &Shared) {
+    let Shared {
+        clock,
+        exchange_sources,
+        reconnect_policy,
+        overload_spmc,
+        merged_spmc,
+        ..
+    } = shared;
+    // the relay and the simulated venues do not depend on the exchanges' status
+    let polls_exchange_status = config.upstream.is_none() && !config.simulated;
+    // the tasks of a symbol's pipeline are restarted on their own if they panic, and the blocking
+    // connectors run on threads of their own, so no symbol holds up the others
+    let aggregator = &pipeline.aggregator;
+    match config.upstream.clone() {
+        Some(upstream) => {
+            let delivery = pipeline.delivery.clone();
+            pipeline
+                .tasks
+                .supervise(format!("{} relay", pipeline.symbol), move || {
+                    upstream::run_relay(upstream.clone(), delivery.clone())
+                });
+        }
+        None => {
+            for (venue_id, (source, source_id)) in exchange_sources
+                .iter()
+                .zip(&pipeline.source_ids)
+                .enumerate()
+            {
+                let (source, source_id, exchange) = (source.clone(), *source_id, source.exchange);
+                let aggregator = aggregator.clone();
+                let name = format!("{} {}", pipeline.symbol, exchange);
+                // created within the span of the task, so the exchange is logged along with it
+                let exchange_span = move || tracing::info_span!("exchange", exchange);
+                match config.simulated {
+                    true => {
+                        let clock = clock.clone();
+                        pipeline.tasks.supervise(name.clone(), move || {
+                            simulated_spot::run_stream(
+                                source_id,
+                                aggregator.clone(),
+                                exchange,
+                                venue_id as u64 + 1,
+                                clock.clone(),
+                            )
+                            .instrument(exchange_span())
+                        });
+                    }
+                    false => {
+                        let reconnect_policy = reconnect_policy.clone();
+                        pipeline.tasks.spawn_isolated(name.clone(), move || {
+                            source
+                                .connect(source_id, aggregator.clone(), reconnect_policy.clone())
+                                .instrument(exchange_span())
+                        });
+                    }
+                }
+
+                let (aggregator, clock) = (pipeline.aggregator.clone(), clock.clone());
+                let (overload, overload_spmc) = (config.overload, overload_spmc.clone());
+                pipeline
+                    .tasks
+                    .supervise(format!("{} queue", name), move || {
+                        inbound_queue::run(
+                            source_id,
+                            exchange,
+                            aggregator.clone(),
+                            overload,
+                            overload_spmc.clone(),
+                            clock.clone(),
+                        )
+                        .instrument(exchange_span())
+                    });
+            }
+        }
+    }
+
+    let symbol = &pipeline.symbol;
+    if let Some(quorum) = config.quorum {
+        let (aggregator, clock) = (aggregator.clone(), clock.clone());
+        pipeline
+            .tasks
+            .supervise(format!("{} quorum", symbol), move || {
+                quorum::run(aggregator.clone(), quorum, clock.clone())
+            });
+    }
+
+    // publishes the heartbeats of the quiet periods, and of the pauses requested by an admin
+    let (quiet_aggregator, quiet_clock) = (aggregator.clone(), clock.clone());
+    pipeline
+        .tasks
+        .supervise(format!("{} quiet periods", symbol), move || {
+            quiet_period::run(
+                quiet_aggregator.clone(),
+                quiet_period::HEARTBEAT_INTERVAL,
+                quiet_clock.clone(),
+            )
+        });
+
+    if let Some(interval) = config.staleness.check_interval() {
+        let (aggregator, clock) = (aggregator.clone(), clock.clone());
+        pipeline
+            .tasks
+            .supervise(format!("{} staleness", symbol), move || {
+                staleness::run(aggregator.clone(), interval, clock.clone())
+            });
+    }
+
+    if !config.audit_interval.is_zero() {
+        let (aggregator, clock) = (aggregator.clone(), clock.clone());
+        let audit_interval = config.audit_interval;
+        pipeline
+            .tasks
+            .supervise(format!("{} audit", symbol), move || {
+                audit::run(aggregator.clone(), audit_interval, clock.clone())
+            });
+    }
+
+    if !config.lead_compensation_window.is_zero() {
+        let (aggregator, clock) = (aggregator.clone(), clock.clone());
+        pipeline
+            .tasks
+            .supervise(format!("{} lead compensation", symbol), move || {
+                lead_compensation::run(aggregator.clone(), clock.clone())
+            });
+    }
+
+    let (trigger_aggregator, trigger_clock) = (aggregator.clone(), clock.clone());
+    match config.publish_trigger {
+        PublishTrigger::Timer(interval) => {
+            pipeline
+                .tasks
+                .supervise(format!("{} publish timer", symbol), move || {
+                    publish_trigger::run(
+                        trigger_aggregator.clone(),
+                        interval,
+                        trigger_clock.clone(),
+                    )
+                });
+        }
+        PublishTrigger::Conflated(_) | PublishTrigger::Adaptive { .. } => {
+            pipeline
+                .tasks
+                .supervise(format!("{} conflation", symbol), move || {
+                    publish_trigger::run_conflation(
+                        trigger_aggregator.clone(),
+                        trigger_clock.clone(),
+                    )
+                });
+        }
+        _ => {}
+    }
+
+    if polls_exchange_status && !config.exchange_status_interval.is_zero() {
+        let endpoints: Vec<_> = exchange_sources
+            .iter()
+            .filter_map(|source| source.status_endpoint.clone())
+            .collect();
+        let (aggregator, clock) = (aggregator.clone(), clock.clone());
+        let exchange_status_interval = config.exchange_status_interval;
+        pipeline
+            .tasks
+            .supervise(format!("{} exchange status", symbol), move || {
+                exchange_status::run(
+                    endpoints.clone(),
+                    aggregator.clone(),
+                    exchange_status_interval,
+                    clock.clone(),
+                )
+            });
+    }
+
+    let (spmr, merged_spmc) = (pipeline.spmr.clone(), merged_spmc.clone());
+    pipeline
+        .tasks
+        .supervise(format!("{} merge", symbol), move || {
+            let (spmr, merged_spmc) = (spmr.clone(), merged_spmc.clone());
+            async move {
+                let mut merge_rx = spmr.lock().await.create_receiver(MERGE_BUFFER_SIZE);
+                while let Some(summary) = merge_rx.recv().await {
+                    merged_spmc.lock().await.broadcast(summary).await;
+                }
+            }
+        });
+
+    // a venue that is down does not hold up the others, its connector keeps retrying meanwhile
+    let (ready_tx, ready) = watch::channel(config.upstream.is_some());
+    pipeline.ready = ready;
+    if config.upstream.is_none() {
+        let (aggregator, clock, symbol) = (aggregator.clone(), clock.clone(), symbol.clone());
+        pipeline
+            .tasks
+            .spawn(format!("{} readiness", symbol), async move {
+                while !aggregator.lock().await.is_ready() {
+                    clock.sleep(READINESS_POLL_INTERVAL).await;
+                }
+                let health = aggregator.lock().await.health();
+                if health.live_venues < health.venues.len() as u32 {
+                    log::warning!(
+                        %symbol,
+                        "Serving {} with {} of {} exchanges live",
+                        symbol,
+                        health.live_venues,
+                        health.venues.len()
+                    );
+                }
+                ready_tx.send_replace(true);
+            });
+    }
+}
+
+/**
+ * Starts the tasks deriving the history, the crossings, the fair prices, the book shapes and the
+ * trade-throughs from the pipeline's summaries.
+ */
+fn start_derived_streams(config: &Config, pipeline: &mut Pipeline, shared: &Shared) {
+    let clock = &shared.clock;
+    let (spmr, live_history, history_clock) =
+        (pipeline.spmr.clone(), shared.history.clone(), clock.clone());
+    pipeline
+        .tasks
+        .supervise(format!("{} history", pipeline.symbol), move || {
+            let (spmr, history, clock) =
+                (spmr.clone(), live_history.clone(), history_clock.clone());
+            async move {
+                let mut history_rx = spmr.lock().await.create_receiver(HISTORY_BUFFER_SIZE);
+                while let Some(summary) = history_rx.recv().await {
+                    let unix_ms = clock
+                        .system_now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u64;
+                    history.lock().await.insert(unix_ms, &summary);
+                }
+            }
+        });
+
+    let (spmr, crossings, crossing_clock) = (
+        pipeline.spmr.clone(),
+        shared.crossing_spmc.clone(),
+        clock.clone(),
+    );
+    let crossing_filter = config.crossing_filter;
+    pipeline
+        .tasks
+        .supervise(format!("{} crossings", pipeline.symbol), move || {
+            let (spmr, crossings, clock) =
+                (spmr.clone(), crossings.clone(), crossing_clock.clone());
+            async move {
+                let crossing_rx = spmr.lock().await.create_receiver(CROSSING_BUFFER_SIZE);
+                crossing::run(crossing_filter, crossing_rx, crossings, clock).await;
+            }
+        });
+    let (spmr, fair_prices) = (pipeline.spmr.clone(), shared.fair_price_spmc.clone());
+    pipeline
+        .tasks
+        .supervise(format!("{} fair price", pipeline.symbol), move || {
+            let (spmr, fair_prices) = (spmr.clone(), fair_prices.clone());
+            async move {
+                let fair_price_rx = spmr.lock().await.create_receiver(FAIR_PRICE_BUFFER_SIZE);
+                fair_price::run(fair_price_rx, fair_prices).await;
+            }
+        });
+    if !config.book_shape_interval.is_zero() {
+        let (latest_summary, book_shapes, book_shape_clock) = (
+            pipeline.latest_summary.clone(),
+            shared.book_shape_spmc.clone(),
+            clock.clone(),
+        );
+        let book_shape_interval = config.book_shape_interval;
+        pipeline
+            .tasks
+            .supervise(format!("{} book shape", pipeline.symbol), move || {
+                book_shape::run(
+                    latest_summary.clone(),
+                    book_shape_interval,
+                    book_shapes.clone(),
+                    book_shape_clock.clone(),
+                )
+            });
+    }
+
+    if let Some(trade_through_spmc) = &shared.trade_through_spmc {
+        let (trade_tx, trade_rx) = tokio::sync::mpsc::channel(TRADE_BUFFER_SIZE);
+        for source in &shared.exchange_sources {
+            let (trades, symbol) = (source.trades, pipeline.symbol.clone());
+            let (trade_tx, exchange) = (trade_tx.clone(), source.exchange);
+            let reconnect_policy = shared.reconnect_policy.clone();
+            pipeline.tasks.spawn_isolated(
+                format!("{} {} trades", pipeline.symbol, source.exchange),
+                move || {
+                    trades(symbol.clone(), trade_tx.clone(), reconnect_policy.clone())
+                        .instrument(tracing::info_span!("exchange", exchange))
+                },
+            );
+        }
+        // the trades are received from a channel of the trade streams, so it cannot restart
+        pipeline.tasks.spawn(
+            format!("{} trade-throughs", pipeline.symbol),
+            trade_through::run(
+                trade_rx,
+                pipeline.latest_summary.clone(),
+                DisplayNames::new(config.display_names.clone()),
+                trade_through_spmc.clone(),
+            ),
+        );
+    }
+}
+
+/**
+ * Builds and starts the pipeline of the symbol at the index along with its derived streams, the
+ * same way for the symbols the server starts with as for those added by a reload.
+ */
+async fn launch_pipeline(
+    config: &Config,
+    index: usize,
+    shared: &Shared,
+    recent: &[RecordedSummary],
+) -> Result<Pipeline, String> {
+    let mut pipeline = build_pipeline(config, index, shared).await?;
+    // filled before the pipeline publishes, so the replay continues with the live summaries
+    pipeline.spmr.lock().await.preload(
+        (recent.iter())
+            .filter_map(|recorded| recorded.summary.clone())
+            .filter(|summary| summary.symbol == pipeline.symbol),
+    );
+    start_pipeline(config, &mut pipeline, shared);
+    start_derived_streams(config, &mut pipeline, shared);
+    Ok(pipeline)
+}
+
+/**
+ * Applies the reloaded configurations to the running pipelines, and starts and stops those of the
+ * symbols added and removed. The servers keep serving meanwhile.
+ */
+async fn apply_reloads(
+    mut running: Config,
+    mut pipelines: Vec<Pipeline>,
+    shared: Shared,
+    symbols: Symbols,
+    aggregators: Aggregators,
+    mut reloads: mpsc::Receiver<Config>,
+) {
+    while let Some(reloaded) = reloads.recv().await {
+        let changes = match Changes::between(&running, &reloaded) {
+            Ok(changes) => changes,
+            Err(error) => {
+                log::alert!("Unable to apply the reloaded configuration: {}", error);
+                continue;
+            }
+        };
+
+        if let Some(log_level) = changes.log_level {
+            log::set_level(log_level);
+            running.log_level = log_level;
+        }
+        for pipeline in &pipelines {
+            let mut aggregator = pipeline.aggregator.lock().await;
+            if let Some(lead_tolerance) = changes.lead_tolerance {
+                // validated along with the changes
+                aggregator.set_lead_tolerance(lead_tolerance).unwrap();
+            }
+            if let Some(publish_trigger) = changes.publish_trigger {
+                aggregator.set_publish_trigger(publish_trigger);
+            }
+        }
+        if let Some(lead_tolerance) = changes.lead_tolerance {
+            running.lead_tolerance = lead_tolerance;
+        }
+        if let Some(publish_trigger) = changes.publish_trigger {
+            running.publish_trigger = publish_trigger;
+        }
+
+        for symbol in &changes.removed_symbols {
+            symbols.remove(symbol);
+            if let Some(index) = pipelines
+                .iter()
+                .position(|pipeline| &pipeline.symbol == symbol)
+            {
+                // dropping the pipeline stops its tasks, which ends the subscriptions to it
+                let pipeline = pipelines.remove(index);
+                aggregators.remove(&pipeline.aggregator);
+            }
+            log::info!(%symbol, "Stopped the pipeline of {}", symbol);
+        }
+        // the symbols whose pipelines did not start are left out, so the next reload retries them
+        let mut failed = Vec::new();
+        for symbol in &changes.added_symbols {
+            let index = (reloaded.symbols.iter())
+                .position(|reloaded| reloaded == symbol)
+                .unwrap();
+            // the summaries recorded before the start do not reach back to an added symbol
+            let pipeline = match launch_pipeline(&reloaded, index, &shared, &[]).await {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    log::alert!(%symbol, "Unable to start the pipeline of {}: {}", symbol, error);
+                    failed.push(symbol.clone());
+                    continue;
+                }
+            };
+            symbols.add(
+                symbol.clone(),
+                pipeline.spmr.clone(),
+                pipeline.latest_summary.clone(),
+                pipeline.ready.clone(),
+            );
+            aggregators.add(pipeline.aggregator.clone());
+            pipelines.push(pipeline);
+            log::info!(%symbol, "Started the pipeline of {}", symbol);
+        }
+        running.symbols = (reloaded.symbols.into_iter())
+            .filter(|symbol| !failed.contains(symbol))
+            .collect();
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let spmr = Arc::new(Mutex::new(spmc::Spmc::new()));
-    let aggregator: Aggregator =
-        Aggregator::new(spmr.clone(), "Binance".to_string(), "Bitstamp".to_string());
-    let aggregator = Mutex::new(aggregator);
-    let aggregator = Arc::new(aggregator);
+    let config = Config::from_args();
+    log::init(config.log_format);
+    log::set_level(config.log_level);
+    let clock = clock::system();
+    let journal = Arc::new(match &config.journal_file {
+        Some(path) => Journal::open(path).unwrap_or_else(|error| {
+            panic!("Unable to open the journal {}: {}", path.display(), error)
+        }),
+        None => Journal::in_memory(),
+    });
+    let supervisor = Supervisor::new(clock.clone());
+    // the tasks serving all symbols, which run as long as the server
+    let mut tasks = FailureDomain::new(supervisor.clone());
+    let (live_journal, journal_clock) = (journal.clone(), clock.clone());
+    tasks.supervise("journal".to_string(), move || {
+        journal::run(live_journal.clone(), journal_clock.clone())
+    });
+    let debug_spmc = match config.debug_stream {
+        true => Some(Arc::new(Mutex::new(spmc::Spmc::new()))),
+        false => None,
+    };
+    let snapshot_spmc = Arc::new(Mutex::new(spmc::Spmc::new()));
+    // the comparisons of all symbols are published on the same debug stream
+    let shadow_spmc = config
+        .shadow_merge_strategy
+        .map(|_| Arc::new(Mutex::new(spmc::Spmc::new())));
+    let exchange_sources = exchange_source::registry(&config.exchanges)
+        .unwrap_or_else(|exchange| panic!("Unable to aggregate unknown exchange '{}'", exchange));
+    if config.upstream.is_some() && config.symbols.len() > 1 {
+        panic!("--upstream relays the summaries of a single symbol");
+    }
+    for window in &config.maintenance_windows {
+        if !exchange_sources
+            .iter()
+            .any(|source| source.exchange == window.exchange)
+        {
+            panic!(
+                "Maintenance window for unknown exchange '{}'",
+                window.exchange
+            );
+        }
+    }
+    for stale_after in &config.staleness.timeouts {
+        if !exchange_sources
+            .iter()
+            .any(|source| source.exchange == stale_after.exchange)
+        {
+            panic!(
+                "Staleness timeout for unknown exchange '{}'",
+                stale_after.exchange
+            );
+        }
+    }
+    let max_depth = config.max_depth.unwrap_or_else(|| {
+        (config.symbols.iter())
+            .map(|symbol| config.depths_of(symbol).max())
+            .max()
+            .unwrap_or(config.depth)
+    });
+    // the simulated venues deliver any depth
+    if config.upstream.is_none() && !config.simulated {
+        for source in &exchange_sources {
+            if max_depth > source.max_depth {
+                panic!(
+                    "{} delivers at most {} levels, the max depth is {}",
+                    source.exchange, source.max_depth, max_depth
+                );
+            }
+        }
+    }
+    let recent = warm_start(&config, clock.as_ref());
+    let mut history = History::new(config.history_retention);
+    for dir in &config.backfill_dirs {
+        let count = history
+            .backfill(dir)
+            .unwrap_or_else(|error| panic!("Unable to backfill {}: {}", dir.display(), error));
+        log::info!("Backfilled {} summaries from {}", count, dir.display());
+    }
+    for recorded in &recent {
+        if let Some(summary) = &recorded.summary {
+            history.insert(recorded.recorded_at_ms, summary);
+        }
+    }
+    let history = Arc::new(Mutex::new(history));
+    let crossing_spmc = Arc::new(Mutex::new(spmc::Spmc::new()));
+    let fair_price_spmc = Arc::new(Mutex::new(spmc::Spmc::new()));
+    let book_shape_spmc = Arc::new(Mutex::new(spmc::Spmc::new()));
+    if config.trade_throughs && (config.upstream.is_some() || config.simulated) {
+        panic!("--trade-throughs needs the trade streams of the exchanges");
+    }
+    let trade_through_spmc = config
+        .trade_throughs
+        .then(|| Arc::new(Mutex::new(spmc::Spmc::new())));
+    let shared = Shared {
+        clock: clock.clone(),
+        journal: journal.clone(),
+        quarantine: config.strict_schema.then(|| Arc::new(Quarantine::new())),
+        debug_spmc: debug_spmc.clone(),
+        snapshot_spmc: snapshot_spmc.clone(),
+        shadow_spmc: shadow_spmc.clone(),
+        overload_spmc: Arc::new(Mutex::new(spmc::Spmc::new())),
+        merged_spmc: Arc::new(Mutex::new(spmc::Spmc::new())),
+        history: history.clone(),
+        crossing_spmc: crossing_spmc.clone(),
+        fair_price_spmc: fair_price_spmc.clone(),
+        book_shape_spmc: book_shape_spmc.clone(),
+        trade_through_spmc: trade_through_spmc.clone(),
+        fan_out: Arc::new(FanOut::new(config.fan_out_rate, clock.clone())),
+        exchange_sources: exchange_sources.clone(),
+        reconnect_policy: ReconnectPolicy {
+            maintenance: config.maintenance_windows.clone(),
+            storm: config.reconnect_storm,
+            heartbeat: config.heartbeat,
+            clock: clock.clone(),
+            ..Default::default()
+        },
+        supervisor: supervisor.clone(),
+    };
+    let mut pipelines = Vec::new();
+    for index in 0..config.symbols.len() {
+        pipelines.push(launch_pipeline(&config, index, &shared, &recent).await?);
+    }
+    // served without the `x-symbol` header and redistributed externally
+    let spmr = pipelines[0].spmr.clone();
+    let latest_summary = pipelines[0].latest_summary.clone();
+
+    let contribution_stats = Arc::new(Mutex::new(ContributionStats::new(clock.now())));
+    let (stats, stats_spmr, stats_clock) = (
+        contribution_stats.clone(),
+        shared.merged_spmc.clone(),
+        clock.clone(),
+    );
+    // a restarted task subscribes anew, missing the summaries published meanwhile
+    tasks.supervise("contribution stats".to_string(), move || {
+        let (stats, spmr, clock) = (stats.clone(), stats_spmr.clone(), stats_clock.clone());
+        async move {
+            let mut stats_rx = spmr.lock().await.create_receiver(STATS_BUFFER_SIZE);
+            while let Some(summary) = stats_rx.recv().await {
+                stats.lock().await.record(&summary, clock.now());
+            }
+        }
+    });
+
+    let lead_race = Arc::new(Mutex::new(LeadRace::new()));
+    let (lead_race_spmc, live_lead_race) = (snapshot_spmc.clone(), lead_race.clone());
+    let lead_race_clock = clock.clone();
+    tasks.supervise("lead race".to_string(), move || {
+        let (spmc, lead_race) = (lead_race_spmc.clone(), live_lead_race.clone());
+        let clock = lead_race_clock.clone();
+        async move {
+            let mut lead_race_rx = spmc.lock().await.create_receiver(LEAD_RACE_BUFFER_SIZE);
+            while let Some(snapshot) = lead_race_rx.recv().await {
+                lead_race.lock().await.observe(
+                    &snapshot.symbol,
+                    &exchange_registry::internal_name(snapshot.exchange_id, &snapshot.exchange),
+                    snapshot.bids.first().map(|level| level.price),
+                    snapshot.asks.first().map(|level| level.price),
+                    clock.now(),
+                );
+            }
+        }
+    });
 
-    let agg_01 = aggregator.clone();
-    let agg_02 = aggregator.clone();
+    let index_spmc = match config.index.is_empty() {
+        true => None,
+        false => {
+            let mut index_rxs = Vec::new();
+            for constituent in &config.index {
+                let pipeline = pipelines
+                    .iter()
+                    .find(|pipeline| pipeline.symbol == constituent.symbol)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Index constituent '{}' is not an aggregated --symbol",
+                            constituent.symbol
+                        )
+                    });
+                index_rxs.push(
+                    pipeline
+                        .spmr
+                        .lock()
+                        .await
+                        .create_receiver(INDEX_BUFFER_SIZE),
+                );
+            }
+            let index_spmc = Arc::new(Mutex::new(spmc::Spmc::new()));
+            tasks.spawn(
+                "index".to_string(),
+                index::run(index_rxs, Index::new(&config.index), index_spmc.clone()),
+            );
+            Some(index_spmc)
+        }
+    };
+
+    // recorded along with their symbol, so the warm start hands each pipeline its own summaries
+    if let Some(recorder_config) = config.recorder.clone() {
+        let recorder_spmr = shared.merged_spmc.clone();
+        tasks.supervise("recorder".to_string(), move || {
+            let (recorder_config, spmr) = (recorder_config.clone(), recorder_spmr.clone());
+            async move {
+                let recorder_rx = spmr.lock().await.create_receiver(RECORDER_BUFFER_SIZE);
+                recorder::run(recorder_config, recorder_rx).await;
+            }
+        });
+    }
 
-    let binance_stream = tokio::spawn(async move { binance_spot::run_stream(0, agg_01).await });
-    let bitstamp_stream = tokio::spawn(async move { bitstamp_spot::run_stream(1, agg_02).await });
+    // only the summary streams are redistributed, the history and the derived streams stay internal
+    let external_server = match &config.external_listen {
+        Some(_) => {
+            let external_spmc =
+                Arc::new(Mutex::new(spmc::Spmc::with_history(config.replay_buffer)));
+            let (external_latest_tx, external_latest_rx) = tokio::sync::watch::channel(None);
+            let distribution_rx = spmr.lock().await.create_receiver(DISTRIBUTION_BUFFER_SIZE);
+            // publishes the latest summary through the only sender of its watch, so it cannot restart
+            tasks.spawn(
+                "distribution".to_string(),
+                distribution::run(
+                    config.distribution,
+                    distribution_rx,
+                    external_spmc.clone(),
+                    external_latest_tx,
+                    clock.clone(),
+                ),
+            );
+            let mut external_server = OrderbookAggregatorServer::new(
+                external_spmc,
+                contribution_stats.clone(),
+                Arc::new(Mutex::new(History::new(Duration::ZERO))),
+                external_latest_rx,
+            );
+            external_server.set_clock(clock.clone());
+            external_server.set_sizing(
+                Sizing {
+                    replay_buffer: config.replay_buffer,
+                    subscriber_queue: config.subscriber_queue,
+                    history_retention: Duration::ZERO,
+                }
+                .to_proto(config.memory_budget),
+            );
+            external_server.set_ready(pipelines[0].ready.clone());
+            external_server.set_journal(journal.clone());
+            external_server.set_bandwidth(Bandwidth::new(
+                config.default_bandwidth_cap,
+                config.bandwidth_caps.clone(),
+            ));
+            Some(external_server)
+        }
+        None => None,
+    };
 
-    let server = OrderbookAggregatorServer::new(spmr.clone());
-    let grpc = Server::builder()
+    let sizing = Sizing {
+        replay_buffer: config.replay_buffer,
+        subscriber_queue: config.subscriber_queue,
+        history_retention: config.history_retention,
+    }
+    .to_proto(config.memory_budget);
+    let mut server = OrderbookAggregatorServer::new(
+        spmr.clone(),
+        contribution_stats,
+        history.clone(),
+        latest_summary,
+    );
+    for pipeline in &pipelines {
+        server.add_symbol(
+            pipeline.symbol.clone(),
+            pipeline.spmr.clone(),
+            pipeline.latest_summary.clone(),
+            pipeline.ready.clone(),
+        );
+    }
+    server.set_ready(pipelines[0].ready.clone());
+    server.set_sizing(sizing);
+    server.set_clock(clock.clone());
+    server.set_fan_out(shared.fan_out.clone());
+    server.set_crossing_spmc(crossing_spmc);
+    if config.upstream.is_none() {
+        server.set_overload_spmc(shared.overload_spmc.clone());
+    }
+    server.set_fair_price_spmc(fair_price_spmc);
+    if !config.book_shape_interval.is_zero() {
+        server.set_book_shape_spmc(book_shape_spmc);
+    }
+    if let Some(trade_through_spmc) = trade_through_spmc {
+        server.set_trade_through_spmc(trade_through_spmc);
+    }
+    if let Some(index_spmc) = index_spmc {
+        server.set_index_spmc(index_spmc);
+    }
+    server.set_lead_race(lead_race);
+    server.set_supervisor(supervisor);
+    server.set_journal(journal);
+    server.set_bandwidth(Bandwidth::new(
+        config.default_bandwidth_cap,
+        config.bandwidth_caps.clone(),
+    ));
+    let symbols = server.symbols();
+    if let Some(memory_watermark) = config.memory_watermark {
+        let (symbols, history) = (symbols.clone(), history.clone());
+        let (history_retention, watermark_clock) = (config.history_retention, clock.clone());
+        tasks.supervise("memory watermark".to_string(), move || {
+            memory_watermark::run(
+                Watermark::new(memory_watermark),
+                symbols.clone(),
+                history.clone(),
+                history_retention,
+                watermark_clock.clone(),
+            )
+        });
+    }
+    let mut admin_server = OrderbookAdminServer::new(pipelines[0].aggregator.clone());
+    for pipeline in &pipelines[1..] {
+        admin_server.add_aggregator(pipeline.aggregator.clone());
+    }
+    let aggregators = admin_server.aggregators();
+    admin_server.set_probes(exchange_sources.iter().map(|source| source.probe).collect());
+    let (drain_tx, drain_rx) = watch::channel(false);
+    if config.reuse_port {
+        admin_server.set_drain(drain_tx);
+    }
+    let market_data_server = MarketDataServer::new(snapshot_spmc);
+    let debug_server = debug_spmc.map(|debug_spmc| {
+        let mut debug_server = OrderbookDebugServer::new(debug_spmc);
+        if let Some(shadow_spmc) = shadow_spmc {
+            debug_server.set_shadow_spmc(shadow_spmc);
+        }
+        orderbook::orderbook_debug_server::OrderbookDebugServer::new(debug_server)
+    });
+    let readiness: Vec<_> = (pipelines.iter())
+        .map(|pipeline| pipeline.ready.clone())
+        .collect();
+
+    let server_builder = || -> Result<Server, Box<dyn std::error::Error>> {
+        let server_builder = Server::builder();
+        Ok(match &config.tls {
+            Some(tls) => {
+                let identity = Identity::from_pem(fs::read(&tls.cert)?, fs::read(&tls.key)?);
+                server_builder.tls_config(ServerTlsConfig::new().identity(identity))?
+            }
+            None => server_builder,
+        })
+    };
+    // bound before the old server is drained, so no connection attempt finds the port closed
+    let listener = handover::bind(
+        config.listen.to_socket_addrs()?.next().unwrap(),
+        config.reuse_port,
+    )?;
+    if let Some(canary_latency) = config.canary_latency {
+        if config.tls.is_some() {
+            panic!("--canary-ms subscribes over plaintext and cannot be combined with TLS");
+        }
+        let url = canary::url(listener.local_addr()?);
+        let (latest_summary, canary_clock) = (pipelines[0].latest_summary.clone(), clock.clone());
+        tasks.supervise("canary".to_string(), move || {
+            canary::run(
+                url.clone(),
+                latest_summary.clone(),
+                canary_latency,
+                canary_clock.clone(),
+            )
+        });
+    }
+    // without a configuration file to reload, the pipelines run as long as the server
+    if let Some(config_file) = config.config_file.clone() {
+        let (reload_tx, reload_rx) = mpsc::channel(RELOAD_BUFFER_SIZE);
+        // they own the pipelines and the single channel between them, so they cannot restart
+        tasks.spawn(
+            "config file watch".to_string(),
+            reload::run(config_file, reload_tx, clock.clone()),
+        );
+        tasks.spawn(
+            "config reload".to_string(),
+            apply_reloads(
+                config.clone(),
+                pipelines,
+                shared,
+                symbols,
+                aggregators,
+                reload_rx,
+            ),
+        );
+    }
+    let external_grpc = match (external_server, &config.external_listen) {
+        (Some(external_server), Some(external_listen)) => {
+            let external_listener = handover::bind(
+                external_listen.to_socket_addrs()?.next().unwrap(),
+                config.reuse_port,
+            )?;
+            let external_grpc = server_builder()?
+                .add_service(
+                    orderbook::orderbook_aggregator_server::OrderbookAggregatorServer::new(
+                        external_server,
+                    ),
+                )
+                .serve_with_incoming_shutdown(
+                    TcpListenerStream::new(external_listener),
+                    handover::drain_requested(drain_rx.clone()),
+                );
+            futures::future::Either::Left(external_grpc)
+        }
+        _ => futures::future::Either::Right(futures::future::pending()),
+    };
+    let grpc = server_builder()?
         .add_service(orderbook::orderbook_aggregator_server::OrderbookAggregatorServer::new(server))
-        .serve(SERVER_URL.to_socket_addrs().unwrap().next().unwrap());
+        .add_service(orderbook::orderbook_admin_server::OrderbookAdminServer::new(admin_server))
+        .add_service(orderbook::market_data_server::MarketDataServer::new(
+            market_data_server,
+        ))
+        .add_optional_service(debug_server)
+        .serve_with_incoming_shutdown(
+            TcpListenerStream::new(listener),
+            handover::drain_requested(drain_rx.clone()),
+        );
+
+    // the old server is drained once every symbol is ready, this one refuses their streams until
+    // then
+    if let Some(take_over) = config.take_over.clone() {
+        tasks.spawn("take over".to_string(), async move {
+            for mut ready in readiness {
+                // a pipeline stopped before being ready holds up none of the others
+                while !*ready.borrow() {
+                    if ready.changed().await.is_err() {
+                        break;
+                    }
+                }
+            }
+            match handover::take_over(take_over).await {
+                Ok(()) => {}
+                Err(error) => log::warning!("Failed to drain the old server: {}", error),
+            }
+        });
+    }
+    // the subscribers left over after the timeout reconnect to the successor once this server exits
+    let drained = async {
+        handover::drain_requested(drain_rx).await;
+        clock.sleep(config.drain_timeout).await;
+    };
 
-    // for maximum reliability we want the programm to stop in case any of the network streams fail
+    // the pipelines restart their tasks on their own, so ending up here means a listener failed, or
+    // the server was drained
     tokio::select! {
-        _ = binance_stream => {},
-        _ = bitstamp_stream => {},
-        _ = grpc => {}
+        _ = grpc => {},
+        _ = external_grpc => {},
+        _ = drained => {}
     };
     Ok(())
 }
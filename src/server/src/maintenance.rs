@@ -0,0 +1,84 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/**
+ * A known maintenance of an exchange, parsed from `<exchange>:<start>-<end>` with start and end
+ * given in unix seconds, e.g. `Binance:1760500000-1760507200`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    pub exchange: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (exchange, range) = raw.split_once(':').ok_or(())?;
+        let (start, end) = range.split_once('-').ok_or(())?;
+        let unix_secs = |raw: &str| {
+            raw.parse::<u64>()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                .map_err(|_| ())
+        };
+        let (start, end) = (unix_secs(start)?, unix_secs(end)?);
+
+        if exchange.is_empty() || start >= end {
+            return Err(());
+        }
+        Ok(MaintenanceWindow {
+            exchange: exchange.to_string(),
+            start,
+            end,
+        })
+    }
+}
+
+/**
+ * Returns when the maintenance of the exchange ends if one is ongoing, None otherwise. A window is
+ * ongoing from its start up to, but excluding, its end. Start and end are absolute times, so a
+ * window crossing midnight is no different from any other. Of several ongoing windows the latest
+ * end is returned, an overlapping window that has not started yet is not taken into account.
+ */
+pub fn active_until(
+    windows: &[MaintenanceWindow],
+    exchange: &str,
+    now: SystemTime,
+) -> Option<SystemTime> {
+    windows
+        .iter()
+        .filter(|window| window.exchange == exchange)
+        .filter(|window| window.start <= now && now < window.end)
+        .map(|window| window.end)
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{active_until, MaintenanceWindow};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn should_parse_windows_and_find_active_one() {
+        // Arrange
+        let windows: Vec<MaintenanceWindow> =
+            ["Binance:100-200", "Binance:150-300", "Bitstamp:0-50"]
+                .iter()
+                .map(|raw| raw.parse().unwrap())
+                .collect();
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+
+        // Act & Assert
+        assert!(active_until(&windows, "Binance", at(99)).is_none());
+        assert!(active_until(&windows, "Binance", at(120)) == Some(at(200)));
+        assert!(active_until(&windows, "Binance", at(160)) == Some(at(300)));
+        assert!(active_until(&windows, "Binance", at(300)).is_none());
+        assert!(active_until(&windows, "Bitstamp", at(120)).is_none());
+        assert!("Binance:200-100".parse::<MaintenanceWindow>().is_err());
+        assert!("Binance".parse::<MaintenanceWindow>().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Sizes the buffers that grow with load from a single memory budget, so small containers and big
+//! hosts run with one knob. The budget is split between the replay buffer, the subscriber queues and
+//! the history; each share is converted into a length with a rough estimate of the bytes per entry.
+
+use crate::history;
+use keyrock_challenge_proto::orderbook::MemorySizing;
+use std::time::Duration;
+
+const ESTIMATED_SUMMARY_BYTES: usize = 1024;
+const ESTIMATED_SUMMARIES_PER_SEC: usize = 20;
+/// the subscriber queues are sized for this many concurrent subscribers
+const ESTIMATED_SUBSCRIBERS: usize = 32;
+const REPLAY_BUFFER_PERCENT: usize = 20;
+const SUBSCRIBER_QUEUES_PERCENT: usize = 30;
+const HISTORY_PERCENT: usize = 50;
+const MIN_REPLAY_BUFFER: usize = 16;
+const MIN_SUBSCRIBER_QUEUE: usize = 8;
+const MIN_HISTORY_RETENTION: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sizing {
+    /// how many summaries are kept to be replayed to resuming subscribers
+    pub replay_buffer: usize,
+    /// how many summaries may queue up for each subscriber
+    pub subscriber_queue: usize,
+    pub history_retention: Duration,
+}
+
+impl Sizing {
+    pub fn from_budget(budget_bytes: usize) -> Self {
+        let share = |percent: usize| budget_bytes / 100 * percent;
+        let history_bytes_per_sec = ESTIMATED_SUMMARIES_PER_SEC * history::SAMPLE_BYTES;
+        Sizing {
+            replay_buffer: (share(REPLAY_BUFFER_PERCENT) / ESTIMATED_SUMMARY_BYTES)
+                .max(MIN_REPLAY_BUFFER),
+            subscriber_queue: (share(SUBSCRIBER_QUEUES_PERCENT)
+                / ESTIMATED_SUBSCRIBERS
+                / ESTIMATED_SUMMARY_BYTES)
+                .max(MIN_SUBSCRIBER_QUEUE),
+            history_retention: Duration::from_secs(
+                (share(HISTORY_PERCENT) / history_bytes_per_sec) as u64,
+            )
+            .max(MIN_HISTORY_RETENTION),
+        }
+    }
+
+    pub fn to_proto(self, budget_bytes: Option<usize>) -> MemorySizing {
+        MemorySizing {
+            budget_bytes: budget_bytes.map(|budget_bytes| budget_bytes as u64),
+            replay_buffer: self.replay_buffer as u32,
+            subscriber_queue: self.subscriber_queue as u32,
+            history_retention_secs: self.history_retention.as_secs(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sizing, MIN_REPLAY_BUFFER};
+
+    #[test]
+    fn should_size_buffers_proportionally_to_the_budget() {
+        // Arrange
+        let mb = 1024 * 1024;
+
+        // Act
+        let small = Sizing::from_budget(16 * mb);
+        let big = Sizing::from_budget(1024 * mb);
+        let tiny = Sizing::from_budget(mb / 64);
+
+        // Assert
+        assert!(big.replay_buffer > small.replay_buffer * 60);
+        assert!(big.subscriber_queue > small.subscriber_queue * 60);
+        assert!(big.history_retention > small.history_retention * 60);
+        assert!(tiny.replay_buffer == MIN_REPLAY_BUFFER);
+    }
+}
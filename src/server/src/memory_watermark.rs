@@ -0,0 +1,170 @@
+//! Watches the memory held by the buffers that grow with load — the replay buffer, the subscriber
+//! queues and the history — and sheds load while it is above the configured watermark.
+
+use crate::{clock::Clock, grpc::Symbols, history::History, log};
+use prost::Message;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// the alarm only clears once the usage fell below this share of the watermark
+const RELEASE_PERCENT: usize = 80;
+/// while shedding load the history only covers this share of its retention
+const SHEDDING_RETENTION_DIVISOR: u32 = 2;
+
+/**
+ * The estimated bytes held by each of the watched buffers.
+ */
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryUsage {
+    pub replay_buffer: usize,
+    pub subscriber_queues: usize,
+    pub history: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.replay_buffer + self.subscriber_queues + self.history
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pressure {
+    Normal,
+    High,
+}
+
+/**
+ * Raises the alarm once the usage reaches the watermark and clears it once the usage dropped below
+ * 80% of it, so a usage hovering around the watermark does not toggle the shedding on every check.
+ */
+#[derive(Debug)]
+pub struct Watermark {
+    high: usize,
+    low: usize,
+    pressure: Pressure,
+}
+
+impl Watermark {
+    pub fn new(high: usize) -> Self {
+        Watermark {
+            high,
+            low: high / 100 * RELEASE_PERCENT,
+            pressure: Pressure::Normal,
+        }
+    }
+
+    /**
+     * Returns the new pressure if the usage made it change.
+     */
+    pub fn update(&mut self, usage: &MemoryUsage) -> Option<Pressure> {
+        let pressure = match self.pressure {
+            Pressure::Normal if usage.total() >= self.high => Pressure::High,
+            Pressure::High if usage.total() < self.low => Pressure::Normal,
+            unchanged => unchanged,
+        };
+        if pressure == self.pressure {
+            return None;
+        }
+        self.pressure = pressure;
+        Some(pressure)
+    }
+
+    pub fn pressure(&self) -> Pressure {
+        self.pressure
+    }
+}
+
+/**
+ * The usage of the buffers of all symbols served, and of the history they share.
+ */
+async fn measure(symbols: &Symbols, history: &Mutex<History>) -> MemoryUsage {
+    let mut usage = MemoryUsage {
+        history: history.lock().await.memory_usage(),
+        ..Default::default()
+    };
+    for (spmc, latest_summary) in symbols.served() {
+        // queued summaries are estimated to be as large as the latest one
+        let summary_bytes = latest_summary
+            .borrow()
+            .as_ref()
+            .map_or(0, |summary| summary.encoded_len());
+        let spmc = spmc.lock().await;
+        usage.replay_buffer += spmc
+            .history()
+            .map(|summary| summary.encoded_len())
+            .sum::<usize>();
+        usage.subscriber_queues += spmc.queued() * summary_bytes;
+    }
+    usage
+}
+
+/**
+ * Checks the memory usage of all symbols every second. While it is above the watermark the spmcs of
+ * all symbols shed load, including those of symbols added meanwhile, and the history retention is
+ * halved.
+ */
+pub async fn run(
+    mut watermark: Watermark,
+    symbols: Symbols,
+    history: Arc<Mutex<History>>,
+    history_retention: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        clock.sleep(CHECK_INTERVAL).await;
+        let usage = measure(&symbols, &history).await;
+
+        match watermark.update(&usage) {
+            Some(Pressure::High) => {
+                log::warning!(
+                    "memory usage of {} KB (replay buffer {} KB, subscriber queues {} KB, history {} KB) reached the watermark, shedding load",
+                    usage.total() / 1024,
+                    usage.replay_buffer / 1024,
+                    usage.subscriber_queues / 1024,
+                    usage.history / 1024
+                );
+                history
+                    .lock()
+                    .await
+                    .set_retention(history_retention / SHEDDING_RETENTION_DIVISOR);
+            }
+            Some(Pressure::Normal) => {
+                log::warning!(
+                    "memory usage back to {} KB, stopped shedding load",
+                    usage.total() / 1024
+                );
+                history.lock().await.set_retention(history_retention);
+            }
+            None => {}
+        }
+        for (spmc, _) in symbols.served() {
+            spmc.lock()
+                .await
+                .set_shedding(watermark.pressure() == Pressure::High);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryUsage, Pressure, Watermark};
+
+    #[test]
+    fn should_raise_alarm_at_watermark_and_clear_it_below_release_level() {
+        // Arrange
+        let mut watermark = Watermark::new(1000);
+        let usage = |history: usize| MemoryUsage {
+            replay_buffer: 200,
+            subscriber_queues: 100,
+            history,
+        };
+
+        // Act & Assert
+        assert!(watermark.update(&usage(600)).is_none());
+        assert!(watermark.update(&usage(700)) == Some(Pressure::High));
+        assert!(watermark.update(&usage(600)).is_none());
+        assert!(watermark.update(&usage(400)) == Some(Pressure::Normal));
+        assert!(watermark.update(&usage(400)).is_none());
+    }
+}
@@ -0,0 +1,509 @@
+//! The OKX connector. The books channel sends 400 levels per side once on subscription and then the
+//! changed levels, each message carrying a CRC32 checksum of the best 25 levels of the resulting book.
+//! The connector verifies it on every message, as well as that the `prevSeqId` of every update is the
+//! `seqId` of the message before, and subscribes again to get a fresh snapshot as soon as its local
+//! copy of the book diverged from the one of OKX or a message was missed.
+
+use crate::{
+    connector_sdk::{self, ExchangeConnector, LocalBook, ReconnectPolicy, Update},
+    diagnostics::Probe,
+    exchange_status::StatusEndpoint,
+    log,
+    schema::{Field, Shape},
+    trade_through::Trade,
+};
+use keyrock_challenge_core::{
+    decimal::Decimal,
+    orderbook_snapshot::{OrderbookSnapshot, Side},
+};
+use keyrock_challenge_proto::orderbook::VenueStatus;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+use tungstenite::{connect, Message};
+use url::Url;
+
+const EXCHANGE: &str = "OKX";
+/// the books channel keeps 400 levels per side
+pub const MAX_DEPTH: usize = 400;
+/// levels per side the checksum is calculated from
+const CHECKSUM_DEPTH: usize = 25;
+
+pub const STATUS_ENDPOINT: StatusEndpoint = StatusEndpoint {
+    exchange: EXCHANGE,
+    url: "https://www.okx.com/api/v5/system/status?state=ongoing",
+    parse: parse_status,
+};
+
+const URL: &str = "wss://ws.okx.com:8443/ws/v5/public";
+
+/// price, size, a deprecated field and the number of orders
+const LEVEL: Shape = Shape::Tuple(&[
+    Shape::Decimal,
+    Shape::Decimal,
+    Shape::Decimal,
+    Shape::Decimal,
+]);
+const BOOK: Shape = Shape::Object(&[
+    Field::required("bids", Shape::Array(&LEVEL)),
+    Field::required("asks", Shape::Array(&LEVEL)),
+    Field::optional("ts", Shape::Decimal),
+    Field::required("checksum", Shape::Number),
+    Field::optional("prevSeqId", Shape::Number),
+    Field::optional("seqId", Shape::Number),
+]);
+/// a books message, the events of the socket are not validated
+static BOOKS_SCHEMA: Shape = Shape::Object(&[
+    Field::optional(
+        "arg",
+        Shape::Object(&[
+            Field::required("channel", Shape::String),
+            Field::required("instId", Shape::String),
+        ]),
+    ),
+    Field::required("action", Shape::String),
+    Field::required("data", Shape::Array(&BOOK)),
+]);
+
+/**
+ * The OKX instrument of a symbol, e.g. `ETH-BTC` for `ethbtc`. A symbol without a known quote asset
+ * is passed on uppercased, for OKX to reject its subscription.
+ */
+fn inst_id(symbol: &str) -> String {
+    match connector_sdk::split_symbol(symbol) {
+        Some((base, quote)) => format!("{}-{}", base, quote).to_uppercase(),
+        None => symbol.to_uppercase(),
+    }
+}
+
+/**
+ * Subscribes to (or unsubscribes from) a channel of a symbol, e.g. `books` for `ETH-BTC`.
+ */
+fn request(op: &str, channel: &str, symbol: &str) -> String {
+    json!({
+        "op": op,
+        "args": [{"channel": channel, "instId": inst_id(symbol)}]
+    })
+    .to_string()
+}
+
+pub fn probe(symbol: &str) -> Probe {
+    Probe {
+        exchange: EXCHANGE,
+        url: URL.to_string(),
+        subscribe: Some(request("subscribe", "books", symbol)),
+    }
+}
+
+/**
+ * Parses the ongoing system maintenances, `{"code": "0", "data": [{"state": "ongoing", ...}]}`.
+ */
+fn parse_status(raw: &Value) -> Result<VenueStatus, ()> {
+    if raw["code"].as_str() != Some("0") {
+        return Err(());
+    }
+    let maintenances = raw["data"].as_array().ok_or(())?;
+    match maintenances
+        .iter()
+        .any(|maintenance| maintenance["state"].as_str() == Some("ongoing"))
+    {
+        true => Ok(VenueStatus::Maintenance),
+        false => Ok(VenueStatus::Operational),
+    }
+}
+
+/**
+ * The CRC-32 (IEEE 802.3) of the bytes, as used by OKX.
+ */
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/**
+ * Why a books message was not applied.
+ */
+#[derive(Debug, PartialEq, Eq)]
+enum Rejection {
+    /// not a books message, or an update before the snapshot
+    Ignored,
+    /// the local book diverged from the one of OKX and has to be subscribed again
+    ChecksumMismatch { expected: i32, actual: i32 },
+    /// the update does not follow the message before, which has to be subscribed again
+    Gap { expected: i64, actual: i64 },
+}
+
+/**
+ * The book of the subscription, built from its snapshot and kept up to date with its updates.
+ */
+#[derive(Debug, Default)]
+struct Book {
+    levels: LocalBook,
+    /// the price and size of each level as sent by OKX, which the checksum is calculated from
+    raw: HashMap<(Side, Decimal), (String, String)>,
+    /// whether the snapshot arrived, updates before it have nothing to apply to
+    synced: bool,
+    /// the `seqId` of the last applied message, if OKX sent one
+    seq_id: Option<i64>,
+}
+
+impl Book {
+    fn reset(&mut self) {
+        self.levels.clear();
+        self.raw.clear();
+        self.synced = false;
+        self.seq_id = None;
+    }
+
+    fn set(&mut self, side: Side, entry: &Value) -> Result<(), ()> {
+        let price = connector_sdk::parse_decimal(&entry[0])?;
+        let amount = connector_sdk::parse_decimal(&entry[1])?;
+        self.levels.set(side, price, amount);
+        if amount == Decimal::ZERO {
+            self.raw.remove(&(side, price));
+        } else {
+            let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+            self.raw
+                .insert((side, price), (text(&entry[0]), text(&entry[1])));
+        }
+        Ok(())
+    }
+
+    /**
+     * The checksum of the best 25 levels, `bid price:bid size:ask price:ask size:...` with the levels
+     * of both sides alternating until the shorter side ran out.
+     */
+    fn checksum(&self) -> i32 {
+        let best = |side: Side| -> Vec<&(String, String)> {
+            self.levels
+                .best(side, CHECKSUM_DEPTH)
+                .iter()
+                .filter_map(|(price, _)| self.raw.get(&(side, *price)))
+                .collect()
+        };
+        let (bids, asks) = (best(Side::Bids), best(Side::Asks));
+        let mut fields = Vec::new();
+        for index in 0..CHECKSUM_DEPTH {
+            for side in [&bids, &asks] {
+                if let Some((price, size)) = side.get(index) {
+                    fields.push(price.as_str());
+                    fields.push(size.as_str());
+                }
+            }
+        }
+        crc32(fields.join(":").as_bytes()) as i32
+    }
+
+    /**
+     * Applies a books message, `{"arg": {...}, "action": "snapshot", "data": [{"bids": [["0.0745",
+     * "1.5", "0", "3"], ...], "asks": [...], "ts": "1597026383085", "checksum": -855196043}]}` for
+     * the snapshot and the same with `"action": "update"` for the changed levels, where a size of
+     * zero removes the level. Each message carries its `seqId` and the `prevSeqId` of the message
+     * before. Returns the time of the message in unix microseconds.
+     */
+    fn update(&mut self, deserialized: &Value) -> Result<Option<u64>, Rejection> {
+        let data = &deserialized["data"][0];
+        match deserialized["action"].as_str() {
+            Some("snapshot") => {
+                self.reset();
+                self.synced = true;
+            }
+            Some("update") if self.synced => {
+                if let (Some(expected), Some(actual)) = (self.seq_id, data["prevSeqId"].as_i64()) {
+                    if actual != expected {
+                        self.reset();
+                        return Err(Rejection::Gap { expected, actual });
+                    }
+                }
+            }
+            _ => return Err(Rejection::Ignored),
+        }
+        self.seq_id = data["seqId"].as_i64();
+        for (side, raw) in [(Side::Bids, &data["bids"]), (Side::Asks, &data["asks"])] {
+            for entry in raw.as_array().ok_or(Rejection::Ignored)? {
+                self.set(side, entry).map_err(|_| Rejection::Ignored)?;
+            }
+        }
+
+        let expected = data["checksum"].as_i64().ok_or(Rejection::Ignored)? as i32;
+        let actual = self.checksum();
+        if expected != actual {
+            self.reset();
+            return Err(Rejection::ChecksumMismatch { expected, actual });
+        }
+        Ok(data["ts"]
+            .as_str()
+            .and_then(|ts| ts.parse::<u64>().ok())
+            .map(|ts_ms| ts_ms * 1_000))
+    }
+}
+
+fn deserialize(
+    book: &mut Book,
+    deserialized: &Value,
+    depth: usize,
+) -> Result<OrderbookSnapshot, Rejection> {
+    let time_us = book.update(deserialized)?;
+    let mut snapshot = book.levels.snapshot(EXCHANGE, depth).map_err(|error| {
+        connector_sdk::reject(EXCHANGE, error);
+        Rejection::Ignored
+    })?;
+    snapshot.exchange_timestamp_us = time_us;
+    Ok(snapshot)
+}
+
+/**
+ * Parses `{"arg": {...}, "data": [{"px": "0.0745", "sz": "1.2", "side": "buy", "ts": "...", ...}]}`,
+ * where the side is the one of the taker.
+ */
+fn deserialize_trades(deserialized: &Value) -> Result<Vec<Trade>, ()> {
+    if deserialized["arg"]["channel"].as_str() != Some("trades") {
+        return Err(());
+    }
+    deserialized["data"]
+        .as_array()
+        .ok_or(())?
+        .iter()
+        .map(|trade| {
+            Ok(Trade {
+                exchange: EXCHANGE,
+                price: connector_sdk::parse_decimal(&trade["px"])?,
+                amount: connector_sdk::parse_decimal(&trade["sz"])?,
+                buy: trade["side"].as_str().ok_or(())? == "buy",
+                exchange_timestamp_us: trade["ts"]
+                    .as_str()
+                    .and_then(|ts| ts.parse::<u64>().ok())
+                    .map(|ts_ms| ts_ms * 1_000),
+            })
+        })
+        .collect()
+}
+
+/**
+ * The books channel, subscribed again whenever the checksum of the local book does not match.
+ */
+#[derive(Debug, Default)]
+struct OkxConnector {
+    book: Book,
+    /// whether the books channel was subscribed before, which has to be left to subscribe again
+    subscribed: bool,
+}
+
+impl ExchangeConnector for OkxConnector {
+    fn url(&self, _: &str, _: usize) -> String {
+        URL.to_string()
+    }
+
+    fn subscribe(&mut self, symbol: &str, _: usize) -> Vec<String> {
+        let mut requests = Vec::new();
+        if self.subscribed {
+            requests.push(request("unsubscribe", "books", symbol));
+        }
+        requests.push(request("subscribe", "books", symbol));
+        self.subscribed = true;
+        requests
+    }
+
+    fn next_snapshot(&mut self, message: &Value, depth: usize) -> Update {
+        match deserialize(&mut self.book, message, depth) {
+            Ok(snapshot) => Update::Snapshot(snapshot),
+            Err(Rejection::ChecksumMismatch { expected, actual }) => {
+                log::warning!(
+                    "{} book checksum {} does not match {}",
+                    EXCHANGE,
+                    actual,
+                    expected
+                );
+                Update::Resubscribe
+            }
+            Err(Rejection::Gap { expected, actual }) => {
+                log::warning!(
+                    "{} update follows {} instead of {}",
+                    EXCHANGE,
+                    actual,
+                    expected
+                );
+                Update::Gap
+            }
+            Err(Rejection::Ignored) => Update::Ignored,
+        }
+    }
+
+    fn schema(&self, message: &Value) -> Option<&'static Shape> {
+        message.get("event").is_none().then_some(&BOOKS_SCHEMA)
+    }
+}
+
+pub fn connector() -> Box<dyn ExchangeConnector> {
+    Box::<OkxConnector>::default()
+}
+
+async fn run_trade_session(symbol: &str, tx: Sender<Trade>) -> Result<(), tungstenite::Error> {
+    let (mut socket, _) = connect(Url::parse(URL).unwrap())?;
+
+    socket.write_message(Message::Text(request("subscribe", "trades", symbol)))?;
+
+    loop {
+        let msg = socket.read_message()?;
+        let content = msg.into_text()?;
+        let trades = serde_json::from_str::<Value>(&content)
+            .map_err(|_| ())
+            .and_then(|deserialized| deserialize_trades(&deserialized));
+        for trade in trades.unwrap_or_default() {
+            if tx.send(trade).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub async fn run_trades(symbol: String, tx: Sender<Trade>, policy: ReconnectPolicy) {
+    connector_sdk::run_with_reconnect(EXCHANGE, policy, || run_trade_session(&symbol, tx.clone()))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, deserialize, deserialize_trades, inst_id, Book, Rejection, BOOKS_SCHEMA};
+    use crate::schema;
+    use keyrock_challenge_core::decimal::Decimal;
+    use serde_json::{json, Value};
+
+    fn decimal(raw: &str) -> Decimal {
+        raw.parse().unwrap()
+    }
+
+    fn books(action: &str, bids: Value, asks: Value, checksum: &str) -> Value {
+        json!({
+            "arg": {"channel": "books", "instId": "ETH-BTC"},
+            "action": action,
+            "data": [{
+                "bids": bids,
+                "asks": asks,
+                "ts": "1597026383085",
+                "checksum": crc32(checksum.as_bytes()) as i32
+            }]
+        })
+    }
+
+    #[test]
+    fn should_map_symbols_and_calculate_crc32() {
+        assert!(inst_id("ethbtc") == "ETH-BTC" && inst_id("btcusdt") == "BTC-USDT");
+        assert!(crc32(b"123456789") == 0xCBF4_3926);
+    }
+
+    #[test]
+    fn should_apply_updates_with_matching_checksums() {
+        // Arrange
+        let mut book = Book::default();
+        let snapshot = books(
+            "snapshot",
+            json!([["0.0745", "1.5", "0", "3"], ["0.0744", "3.0", "0", "1"]]),
+            json!([["0.0746", "1.0", "0", "2"]]),
+            "0.0745:1.5:0.0746:1.0:0.0744:3.0",
+        );
+        let update = books(
+            "update",
+            json!([["0.0745", "0", "0", "0"]]),
+            json!([["0.0747", "2.50", "0", "1"]]),
+            "0.0744:3.0:0.0746:1.0:0.0747:2.50",
+        );
+
+        // Act
+        let early = deserialize(&mut book, &update, 1);
+        let synced = deserialize(&mut book, &snapshot, 1).unwrap();
+        let updated = deserialize(&mut book, &update, 1).unwrap();
+
+        // Assert
+        assert!(schema::validate(&BOOKS_SCHEMA, &update).is_empty());
+        assert!(matches!(early, Err(Rejection::Ignored)));
+        assert!(synced.bids.unwrap()[0].price == decimal("0.0745"));
+        let bids = updated.bids.unwrap();
+        assert!(bids[0].price == decimal("0.0744") && bids[0].exchange == "OKX");
+        assert!(updated.exchange_timestamp_us == Some(1_597_026_383_085_000));
+    }
+
+    #[test]
+    fn should_desync_on_a_checksum_mismatch() {
+        // Arrange
+        let mut book = Book::default();
+        let snapshot = books(
+            "snapshot",
+            json!([["0.0745", "1.5", "0", "3"]]),
+            json!([["0.0746", "1.0", "0", "2"]]),
+            "0.0745:1.5:0.0746:1.0",
+        );
+        let missed_update = books(
+            "update",
+            json!([["0.0744", "3.0", "0", "1"]]),
+            json!([]),
+            "0.0745:1.0:0.0746:1.0:0.0744:3.0",
+        );
+
+        // Act
+        deserialize(&mut book, &snapshot, 1).unwrap();
+        let diverged = deserialize(&mut book, &missed_update, 1);
+        let after = deserialize(&mut book, &missed_update, 1);
+
+        // Assert
+        assert!(matches!(diverged, Err(Rejection::ChecksumMismatch { .. })));
+        assert!(matches!(after, Err(Rejection::Ignored)));
+    }
+
+    #[test]
+    fn should_desync_on_a_sequence_gap() {
+        // Arrange
+        let mut book = Book::default();
+        let sequenced = |mut message: Value, prev_seq_id: i64, seq_id: i64| {
+            message["data"][0]["prevSeqId"] = json!(prev_seq_id);
+            message["data"][0]["seqId"] = json!(seq_id);
+            message
+        };
+        let snapshot = books(
+            "snapshot",
+            json!([["0.0745", "1.5", "0", "3"]]),
+            json!([["0.0746", "1.0", "0", "2"]]),
+            "0.0745:1.5:0.0746:1.0",
+        );
+        let unchanged = books("update", json!([]), json!([]), "0.0745:1.5:0.0746:1.0");
+
+        // Act
+        deserialize(&mut book, &sequenced(snapshot, -1, 10), 1).unwrap();
+        let following = deserialize(&mut book, &sequenced(unchanged.clone(), 10, 15), 1);
+        let gap = deserialize(&mut book, &sequenced(unchanged.clone(), 18, 20), 1);
+        let after = deserialize(&mut book, &sequenced(unchanged, 20, 21), 1);
+
+        // Assert
+        assert!(following.is_ok());
+        assert!(
+            gap.unwrap_err()
+                == Rejection::Gap {
+                    expected: 15,
+                    actual: 18
+                }
+        );
+        assert!(matches!(after, Err(Rejection::Ignored)));
+    }
+
+    #[test]
+    fn should_parse_trades() {
+        let trades = deserialize_trades(&json!({
+            "arg": {"channel": "trades", "instId": "ETH-BTC"},
+            "data": [{"px": "0.0745", "sz": "1.2", "side": "buy", "ts": "1630048897897"}]
+        }))
+        .unwrap();
+
+        assert!(trades[0].buy && trades[0].amount == decimal("1.2"));
+        assert!(trades[0].exchange_timestamp_us == Some(1_630_048_897_897_000));
+        assert!(deserialize_trades(&json!({"event": "subscribe"})).is_err());
+    }
+}
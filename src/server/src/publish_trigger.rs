@@ -0,0 +1,134 @@
+//! When the aggregator publishes a summary. Systems reacting to every tick want every update, ones
+//! sampling the book prefer a fixed rate, and ones only trading the top of the book do not care
+//! about changes deeper down. Slow consumers are protected from tick storms by conflating the
+//! updates to at most one summary per interval, which can also adapt to how busy the market is.
+
+use crate::{aggregator::Aggregator, clock::Clock};
+use keyrock_challenge_proto::orderbook::{Level, Summary};
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PublishTrigger {
+    /// on every update of any source
+    #[default]
+    EveryUpdate,
+    /// only on a fixed interval, regardless of how often the sources update
+    Timer(Duration),
+    /// on updates that changed the best bid or the best ask, including their amounts
+    TopOfBookChange,
+    /// on updates, but at most once per interval, updates within it are published with the
+    /// freshest books once it passed
+    Conflated(Duration),
+    /// conflated with an interval between `min` and `max`, the busier the market the shorter
+    Adaptive { min: Duration, max: Duration },
+}
+
+impl FromStr for PublishTrigger {
+    type Err = ();
+
+    /**
+     * Parses `every-update`, `timer:<ms>`, `top-of-book`, `conflate:<ms>` or
+     * `adaptive:<min ms>-<max ms>`.
+     */
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.split_once(':') {
+            None if raw == "every-update" => Ok(PublishTrigger::EveryUpdate),
+            None if raw == "top-of-book" => Ok(PublishTrigger::TopOfBookChange),
+            Some(("timer", ms)) => match ms.parse::<u64>() {
+                Ok(ms) if ms > 0 => Ok(PublishTrigger::Timer(Duration::from_millis(ms))),
+                _ => Err(()),
+            },
+            Some(("conflate", ms)) => match ms.parse::<u64>() {
+                Ok(ms) if ms > 0 => Ok(PublishTrigger::Conflated(Duration::from_millis(ms))),
+                _ => Err(()),
+            },
+            Some(("adaptive", range)) => {
+                let (min, max) = range.split_once('-').ok_or(())?;
+                let min = min.parse::<u64>().map_err(|_| ())?;
+                let max = max.parse::<u64>().map_err(|_| ())?;
+                match min > 0 && min <= max {
+                    true => Ok(PublishTrigger::Adaptive {
+                        min: Duration::from_millis(min),
+                        max: Duration::from_millis(max),
+                    }),
+                    false => Err(()),
+                }
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+impl PublishTrigger {
+    pub fn on_update(self) -> bool {
+        !matches!(self, PublishTrigger::Timer(_))
+    }
+}
+
+/**
+ * The best bid and the best ask of the summary.
+ */
+pub fn top_of_book(summary: &Summary) -> (Option<Level>, Option<Level>) {
+    (summary.bids.first().cloned(), summary.asks.first().cloned())
+}
+
+/**
+ * Publishes the aggregation once per interval, for the timer trigger.
+ */
+pub async fn run(
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        clock.sleep(interval).await;
+        aggregator_arc.lock().await.publish_on_timer().await;
+    }
+}
+
+/**
+ * Publishes the updates conflated during an interval as soon as it passed, for the conflated and
+ * the adaptive trigger.
+ */
+pub async fn run_conflation(aggregator_arc: Arc<Mutex<Aggregator>>, clock: Arc<dyn Clock>) {
+    let mut wait = match aggregator_arc.lock().await.conflation_interval() {
+        Some(interval) => interval,
+        None => return,
+    };
+    loop {
+        clock.sleep(wait).await;
+        wait = match aggregator_arc.lock().await.publish_conflated().await {
+            Some(wait) => wait,
+            None => return,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PublishTrigger;
+    use std::time::Duration;
+
+    #[test]
+    fn should_parse_publish_triggers() {
+        assert!("every-update".parse() == Ok(PublishTrigger::EveryUpdate));
+        assert!("top-of-book".parse() == Ok(PublishTrigger::TopOfBookChange));
+        assert!("timer:250".parse() == Ok(PublishTrigger::Timer(Duration::from_millis(250))));
+        assert!("timer:0".parse::<PublishTrigger>().is_err());
+        assert!("timer".parse::<PublishTrigger>().is_err());
+        assert!(
+            "conflate:100".parse() == Ok(PublishTrigger::Conflated(Duration::from_millis(100)))
+        );
+        assert!("conflate:0".parse::<PublishTrigger>().is_err());
+        assert!(
+            "adaptive:10-500".parse()
+                == Ok(PublishTrigger::Adaptive {
+                    min: Duration::from_millis(10),
+                    max: Duration::from_millis(500)
+                })
+        );
+        assert!("adaptive:500-10".parse::<PublishTrigger>().is_err());
+        assert!("adaptive:0-10".parse::<PublishTrigger>().is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Scheduled quiet periods of a deployment, e.g. the daily rollover of a venue during which its books
+//! are known to jump around. During such a period the aggregator either publishes nothing at all or
+//! only heartbeats flagged with `quiet_period`, and reports the period in its health.
+
+use crate::{aggregator::Aggregator, clock::Clock};
+use keyrock_challenge_proto::orderbook::QuietMode;
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+/// how often heartbeats are published during a period in heartbeat mode
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * A daily period in UTC, parsed from `<HH:MM>-<HH:MM>=<suppress|heartbeat>`. A period ending before
+ * it starts lasts over midnight, e.g. `23:55-00:05=heartbeat`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietPeriod {
+    /// since midnight UTC
+    pub start: Duration,
+    pub end: Duration,
+    pub mode: QuietMode,
+}
+
+fn time_of_day(raw: &str) -> Result<Duration, ()> {
+    let (hours, minutes) = raw.split_once(':').ok_or(())?;
+    let hours = hours.parse::<u64>().map_err(|_| ())?;
+    let minutes = minutes.parse::<u64>().map_err(|_| ())?;
+    match hours < 24 && minutes < 60 {
+        true => Ok(Duration::from_secs((hours * 60 + minutes) * 60)),
+        false => Err(()),
+    }
+}
+
+impl FromStr for QuietPeriod {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (range, mode) = raw.split_once('=').ok_or(())?;
+        let (start, end) = range.split_once('-').ok_or(())?;
+        let (start, end) = (time_of_day(start)?, time_of_day(end)?);
+        let mode = match mode {
+            "suppress" => QuietMode::Suppress,
+            "heartbeat" => QuietMode::Heartbeat,
+            _ => return Err(()),
+        };
+        if start == end {
+            return Err(());
+        }
+        Ok(QuietPeriod { start, end, mode })
+    }
+}
+
+impl QuietPeriod {
+    fn contains(&self, now: SystemTime) -> bool {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+        let now = Duration::from_nanos((since_epoch.as_nanos() % DAY.as_nanos()) as u64);
+        match self.start < self.end {
+            true => self.start <= now && now < self.end,
+            false => self.start <= now || now < self.end,
+        }
+    }
+}
+
+/**
+ * The mode of the quiet periods ongoing at the given time. Suppressing wins over heartbeats if
+ * periods overlap.
+ */
+pub fn quiet_mode(periods: &[QuietPeriod], now: SystemTime) -> QuietMode {
+    let modes = periods.iter().filter(|period| period.contains(now));
+    modes.fold(QuietMode::None, |mode, period| match (mode, period.mode) {
+        (QuietMode::Suppress, _) | (_, QuietMode::Suppress) => QuietMode::Suppress,
+        _ => QuietMode::Heartbeat,
+    })
+}
+
+/**
+ * Publishes a heartbeat once per interval during periods in heartbeat mode and while publishing is
+ * paused, since the venues' updates do not trigger publishes then.
+ */
+pub async fn run(
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        clock.sleep(interval).await;
+        aggregator_arc
+            .lock()
+            .await
+            .publish_heartbeat_if_quiet()
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quiet_mode, QuietPeriod};
+    use keyrock_challenge_proto::orderbook::QuietMode;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn should_find_the_mode_of_ongoing_periods() {
+        // Arrange
+        let periods: Vec<QuietPeriod> = ["23:55-00:05=heartbeat", "00:00-00:01=suppress"]
+            .iter()
+            .map(|raw| raw.parse().unwrap())
+            .collect();
+        // a day after the epoch at the given time of day
+        let at = |hours: u64, minutes: u64| {
+            UNIX_EPOCH + Duration::from_secs(24 * 60 * 60 + (hours * 60 + minutes) * 60)
+        };
+
+        // Act & Assert
+        assert!(quiet_mode(&periods, at(23, 54)) == QuietMode::None);
+        assert!(quiet_mode(&periods, at(23, 59)) == QuietMode::Heartbeat);
+        assert!(quiet_mode(&periods, at(0, 0)) == QuietMode::Suppress);
+        assert!(quiet_mode(&periods, at(0, 4)) == QuietMode::Heartbeat);
+        assert!(quiet_mode(&periods, at(0, 5)) == QuietMode::None);
+        assert!("10:00-10:00=suppress".parse::<QuietPeriod>().is_err());
+        assert!("24:00-01:00=suppress".parse::<QuietPeriod>().is_err());
+        assert!("10:00-11:00=quiet".parse::<QuietPeriod>().is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Publishing only while enough venues are fresh. Below the quorum the merged book would silently
+//! reflect a minority of the market, so only heartbeats flagged with `quorum_lost` are published.
+//! This way clients can tell a quiet market from a server that is blind.
+
+use crate::{aggregator::Aggregator, clock::Clock};
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+/**
+ * At least `min_venues` venues need a snapshot younger than `max_age`, parsed from `<k>:<ms>`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quorum {
+    pub min_venues: usize,
+    pub max_age: Duration,
+}
+
+impl FromStr for Quorum {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let (min_venues, ms) = raw.split_once(':').ok_or(())?;
+        let min_venues = min_venues.parse::<usize>().map_err(|_| ())?;
+        let ms = ms.parse::<u64>().map_err(|_| ())?;
+        if min_venues == 0 || ms == 0 {
+            return Err(());
+        }
+        Ok(Quorum {
+            min_venues,
+            max_age: Duration::from_millis(ms),
+        })
+    }
+}
+
+/**
+ * Publishes a heartbeat every `max_age` while the quorum is lost, since the venues that went
+ * quiet no longer trigger publishes.
+ */
+pub async fn run(aggregator_arc: Arc<Mutex<Aggregator>>, quorum: Quorum, clock: Arc<dyn Clock>) {
+    loop {
+        clock.sleep(quorum.max_age).await;
+        aggregator_arc
+            .lock()
+            .await
+            .publish_heartbeat_if_blind()
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quorum;
+    use std::time::Duration;
+
+    #[test]
+    fn should_parse_quorum() {
+        assert!(
+            "2:500".parse()
+                == Ok(Quorum {
+                    min_venues: 2,
+                    max_age: Duration::from_millis(500),
+                })
+        );
+        assert!("0:500".parse::<Quorum>().is_err());
+        assert!("2".parse::<Quorum>().is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! The on-disk formats of the recorder. A codec writes one recorded summary after the other into
+//! the zstd stream of a recording and reads them back in the same order. Each format has a file
+//! suffix of its own, so recordings of different formats can share a directory and are still read
+//! back with the right codec.
+
+use crate::delta_recording::{DeltaDecoder, DeltaEncoder};
+use keyrock_challenge_proto::orderbook::{RecordedEntry, RecordedSummary, Summary};
+use prost::Message;
+use std::io;
+
+pub trait RecordCodec: Send {
+    fn encode(&mut self, recorded_at_ms: u64, summary: &Summary) -> io::Result<Vec<u8>>;
+
+    /// decodes the entry at the start of the buffer and advances the buffer past it
+    fn decode(&mut self, buffer: &mut &[u8]) -> io::Result<RecordedSummary>;
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/**
+ * Every summary as a length-delimited `RecordedSummary`.
+ */
+#[derive(Debug, Default)]
+pub struct ProtobufCodec;
+
+impl RecordCodec for ProtobufCodec {
+    fn encode(&mut self, recorded_at_ms: u64, summary: &Summary) -> io::Result<Vec<u8>> {
+        Ok(RecordedSummary {
+            recorded_at_ms,
+            summary: Some(summary.clone()),
+        }
+        .encode_length_delimited_to_vec())
+    }
+
+    fn decode(&mut self, buffer: &mut &[u8]) -> io::Result<RecordedSummary> {
+        RecordedSummary::decode_length_delimited(buffer).map_err(invalid_data)
+    }
+}
+
+/**
+ * Length-delimited `RecordedEntry`s holding either a keyframe or the changes to the previous summary.
+ */
+#[derive(Debug)]
+pub struct DeltaCodec {
+    encoder: DeltaEncoder,
+    decoder: DeltaDecoder,
+}
+
+impl DeltaCodec {
+    pub fn new(keyframe_interval: u32) -> Self {
+        DeltaCodec {
+            encoder: DeltaEncoder::new(keyframe_interval),
+            decoder: DeltaDecoder::default(),
+        }
+    }
+}
+
+impl RecordCodec for DeltaCodec {
+    fn encode(&mut self, recorded_at_ms: u64, summary: &Summary) -> io::Result<Vec<u8>> {
+        Ok(RecordedEntry {
+            recorded_at_ms,
+            entry: Some(self.encoder.encode(summary)),
+        }
+        .encode_length_delimited_to_vec())
+    }
+
+    fn decode(&mut self, buffer: &mut &[u8]) -> io::Result<RecordedSummary> {
+        let entry = RecordedEntry::decode_length_delimited(buffer).map_err(invalid_data)?;
+        let summary = entry
+            .entry
+            .ok_or(())
+            .and_then(|entry| self.decoder.decode(entry))
+            .map_err(|_| invalid_data("Delta entry does not fit the previous summary"))?;
+        Ok(RecordedSummary {
+            recorded_at_ms: entry.recorded_at_ms,
+            summary: Some(summary),
+        })
+    }
+}
+
+/**
+ * Every summary as a `RecordedSummary` JSON object on a line of its own, readable with any JSON
+ * tooling once decompressed.
+ */
+#[derive(Debug, Default)]
+pub struct JsonLinesCodec;
+
+impl RecordCodec for JsonLinesCodec {
+    fn encode(&mut self, recorded_at_ms: u64, summary: &Summary) -> io::Result<Vec<u8>> {
+        let recorded = RecordedSummary {
+            recorded_at_ms,
+            summary: Some(summary.clone()),
+        };
+        let mut line = serde_json::to_vec(&recorded).map_err(invalid_data)?;
+        line.push(b'\n');
+        Ok(line)
+    }
+
+    fn decode(&mut self, buffer: &mut &[u8]) -> io::Result<RecordedSummary> {
+        let end = buffer
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or_else(|| invalid_data("Unterminated line"))?;
+        let recorded = serde_json::from_slice(&buffer[..end]).map_err(invalid_data)?;
+        *buffer = &buffer[end + 1..];
+        Ok(recorded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonLinesCodec, RecordCodec};
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+
+    #[test]
+    fn should_round_trip_json_lines() {
+        // Arrange
+        let mut codec = JsonLinesCodec;
+        let summary = Summary {
+            spread: Some(0.5),
+            bids: vec![Level {
+                exchange: "Binance".to_string(),
+                price: 10.,
+                amount: 1.5,
+                ..Default::default()
+            }],
+            sequence: 7,
+            ..Default::default()
+        };
+
+        // Act
+        let mut content = codec.encode(1, &summary).unwrap();
+        content.extend(codec.encode(2, &Summary::default()).unwrap());
+        let mut buffer = content.as_slice();
+        let first = codec.decode(&mut buffer).unwrap();
+        let second = codec.decode(&mut buffer).unwrap();
+
+        // Assert
+        assert!(first.recorded_at_ms == 1 && first.summary == Some(summary));
+        assert!(second.recorded_at_ms == 2 && buffer.is_empty());
+        assert!(content.iter().filter(|byte| **byte == b'\n').count() == 2);
+    }
+}
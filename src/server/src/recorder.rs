@@ -0,0 +1,554 @@
+use crate::{
+    log,
+    record_codec::{DeltaCodec, JsonLinesCodec, ProtobufCodec, RecordCodec},
+};
+use keyrock_challenge_proto::orderbook::{RecordedSummary, Summary};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc::Receiver;
+
+const FILE_PREFIX: &str = "summaries-";
+const FILE_SUFFIX: &str = ".pb.zst";
+const DELTA_FILE_SUFFIX: &str = ".delta.pb.zst";
+const JSON_LINES_FILE_SUFFIX: &str = ".jsonl.zst";
+const COMPRESSION_LEVEL: i32 = 3;
+
+/**
+ * How summaries are written, parsed from `full`, `delta:<keyframe_interval>` or `jsonl`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordFormat {
+    /// every summary as it was published, as length-delimited protobuf
+    #[default]
+    Full,
+    /// only the changes to the previous summary, with a full summary every given amount of entries
+    Delta(u32),
+    /// every summary as a line of JSON
+    JsonLines,
+}
+
+impl FromStr for RecordFormat {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.split_once(':') {
+            None if raw == "full" => Ok(RecordFormat::Full),
+            None if raw == "jsonl" => Ok(RecordFormat::JsonLines),
+            Some(("delta", keyframe_interval)) => match keyframe_interval.parse::<u32>() {
+                Ok(keyframe_interval) if keyframe_interval > 0 => {
+                    Ok(RecordFormat::Delta(keyframe_interval))
+                }
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+impl RecordFormat {
+    fn suffix(self) -> &'static str {
+        match self {
+            RecordFormat::Full => FILE_SUFFIX,
+            RecordFormat::Delta(_) => DELTA_FILE_SUFFIX,
+            RecordFormat::JsonLines => JSON_LINES_FILE_SUFFIX,
+        }
+    }
+
+    /**
+     * The format of a recording according to its file name, none if it is not a recording.
+     * The keyframe interval of a delta recording is only needed to write it.
+     */
+    fn of(name: &str) -> Option<RecordFormat> {
+        if !name.starts_with(FILE_PREFIX) {
+            return None;
+        }
+        // the delta suffix also ends with the one of full recordings
+        [
+            RecordFormat::Delta(1),
+            RecordFormat::Full,
+            RecordFormat::JsonLines,
+        ]
+        .into_iter()
+        .find(|format| name.ends_with(format.suffix()))
+    }
+
+    fn codec(self) -> Box<dyn RecordCodec> {
+        match self {
+            RecordFormat::Full => Box::new(ProtobufCodec),
+            RecordFormat::Delta(keyframe_interval) => Box::new(DeltaCodec::new(keyframe_interval)),
+            RecordFormat::JsonLines => Box::new(JsonLinesCodec),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    pub max_age: Option<Duration>,
+    pub max_total_bytes: Option<u64>,
+    /// expired recordings are moved here instead of being deleted
+    pub archive_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    pub rotate_bytes: u64,
+    pub rotate_after: Duration,
+    pub retention: RetentionPolicy,
+    pub format: RecordFormat,
+}
+
+struct OpenFile {
+    path: PathBuf,
+    encoder: zstd::Encoder<'static, BufWriter<File>>,
+    /// a new one for every file, so delta recordings start with a keyframe
+    codec: Box<dyn RecordCodec>,
+    opened_at: Instant,
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/**
+ * All recordings in the directory, sorted from oldest to newest.
+ */
+pub fn list_recordings(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut recordings = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if RecordFormat::of(&entry.file_name().to_string_lossy()).is_some() {
+            recordings.push(entry.path());
+        }
+    }
+    // the names embed a zero padded timestamp, so they sort chronologically
+    recordings.sort();
+    Ok(recordings)
+}
+
+/**
+ * Reads back all summaries of a recording in the order they were recorded, whatever its format.
+ * Delta recordings are reconstructed into full summaries.
+ */
+pub fn read_recording(path: &Path) -> io::Result<Vec<RecordedSummary>> {
+    decode_recording(path, false)
+}
+
+/**
+ * Reads the recording, or with `truncated` as much of it as was written completely, as a file the
+ * recorder did not close, e.g. as the server crashed, ends in the middle of an entry.
+ */
+fn decode_recording(path: &Path, truncated: bool) -> io::Result<Vec<RecordedSummary>> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut codec = RecordFormat::of(&name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a recording"))?
+        .codec();
+    let mut content = Vec::new();
+    let read = zstd::Decoder::new(File::open(path)?)?.read_to_end(&mut content);
+    if !truncated {
+        read?;
+    }
+
+    let mut buffer = content.as_slice();
+    let mut recorded = Vec::new();
+    while !buffer.is_empty() {
+        match codec.decode(&mut buffer) {
+            Ok(entry) => recorded.push(entry),
+            Err(_) if truncated => break,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(recorded)
+}
+
+/**
+ * The unix milliseconds a recording was started at, which precede all its entries.
+ */
+fn started_at_ms(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_string_lossy();
+    name.strip_prefix(FILE_PREFIX)?.get(..16)?.parse().ok()
+}
+
+/**
+ * The summaries recorded in the directory since the given unix milliseconds, oldest first. Only
+ * the most recent recordings are read, back to the first one started before that time, and those
+ * the recorder did not close are read as far as they were written.
+ */
+pub fn read_recent(dir: &Path, since_ms: u64) -> io::Result<Vec<RecordedSummary>> {
+    let mut recordings = Vec::new();
+    for path in list_recordings(dir)?.iter().rev() {
+        recordings.push(decode_recording(path, true)?);
+        if started_at_ms(path).is_none_or(|started_at_ms| started_at_ms <= since_ms) {
+            break;
+        }
+    }
+    Ok(recordings
+        .into_iter()
+        .rev()
+        .flatten()
+        .filter(|recorded| recorded.recorded_at_ms >= since_ms)
+        .collect())
+}
+
+/**
+ * Writes the published summaries, stamped with the time they were recorded, to zstd compressed
+ * files in the configured format.
+ * A new file is started once the current one exceeds the configured size or age; whenever a file
+ * is closed the retention policy is applied to the closed recordings.
+ */
+pub struct Recorder {
+    config: RecorderConfig,
+    file: Option<OpenFile>,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig) -> io::Result<Recorder> {
+        fs::create_dir_all(&config.dir)?;
+        if let Some(archive_dir) = &config.retention.archive_dir {
+            fs::create_dir_all(archive_dir)?;
+        }
+        Ok(Recorder { config, file: None })
+    }
+
+    pub fn record(&mut self, summary: &Summary) -> io::Result<()> {
+        if self.should_rotate()? {
+            self.close()?;
+        }
+        if self.file.is_none() {
+            self.file = Some(self.open()?);
+        }
+
+        let file = self.file.as_mut().unwrap();
+        let encoded = file.codec.encode(unix_now_ms(), summary)?;
+        file.encoder.write_all(&encoded)
+    }
+
+    pub fn close(&mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.encoder.finish()?.flush()?;
+            self.apply_retention()?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self) -> io::Result<bool> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        let size = file.encoder.get_ref().get_ref().metadata()?.len();
+        Ok(
+            size >= self.config.rotate_bytes
+                || file.opened_at.elapsed() >= self.config.rotate_after,
+        )
+    }
+
+    fn open(&self) -> io::Result<OpenFile> {
+        let format = self.config.format;
+        let path = self.config.dir.join(format!(
+            "{}{:016}{}",
+            FILE_PREFIX,
+            unix_now_ms(),
+            format.suffix()
+        ));
+        let writer = BufWriter::new(File::create(&path)?);
+
+        Ok(OpenFile {
+            path,
+            encoder: zstd::Encoder::new(writer, COMPRESSION_LEVEL)?,
+            codec: format.codec(),
+            opened_at: Instant::now(),
+        })
+    }
+
+    /**
+     * Closed recordings sorted from oldest to newest, with their size and age.
+     */
+    fn recordings(&self) -> io::Result<Vec<(PathBuf, u64, Duration)>> {
+        let current = self.file.as_ref().map(|file| file.path.clone());
+        let mut recordings = Vec::new();
+
+        for path in list_recordings(&self.config.dir)? {
+            if Some(&path) == current.as_ref() {
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            let age = metadata.modified()?.elapsed().unwrap_or(Duration::ZERO);
+            recordings.push((path, metadata.len(), age));
+        }
+        Ok(recordings)
+    }
+
+    fn apply_retention(&self) -> io::Result<()> {
+        let retention = &self.config.retention;
+        let recordings = self.recordings()?;
+        let mut total_bytes: u64 = recordings.iter().map(|(_, size, _)| size).sum();
+
+        for (path, size, age) in recordings {
+            let too_old = retention.max_age.is_some_and(|max_age| age > max_age);
+            let too_large = retention
+                .max_total_bytes
+                .is_some_and(|max_total_bytes| total_bytes > max_total_bytes);
+            if !too_old && !too_large {
+                continue;
+            }
+
+            self.expire(&path)?;
+            total_bytes -= size;
+        }
+        Ok(())
+    }
+
+    fn expire(&self, path: &Path) -> io::Result<()> {
+        match &self.config.retention.archive_dir {
+            Some(archive_dir) => fs::rename(path, archive_dir.join(path.file_name().unwrap())),
+            None => fs::remove_file(path),
+        }
+    }
+}
+
+/**
+ * Records everything received until the channel closes. Runs on a blocking thread since all the
+ * file handling is synchronous.
+ */
+pub async fn run(config: RecorderConfig, mut rx: Receiver<Summary>) {
+    let result = tokio::task::spawn_blocking(move || -> io::Result<()> {
+        let mut recorder = Recorder::new(config)?;
+        while let Some(summary) = rx.blocking_recv() {
+            recorder.record(&summary)?;
+        }
+        recorder.close()
+    })
+    .await
+    .expect("Recorder thread panicked");
+
+    if let Err(error) = result {
+        log::warning!("Recorder stopped: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        list_recordings, read_recent, read_recording, RecordFormat, Recorder, RecorderConfig,
+        RetentionPolicy,
+    };
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+    use std::{fs, path::PathBuf, time::Duration};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("recorder_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn should_write_compressed_summaries() {
+        // Arrange
+        let dir = test_dir("write");
+        let mut recorder = Recorder::new(RecorderConfig {
+            dir: dir.clone(),
+            rotate_bytes: u64::MAX,
+            rotate_after: Duration::from_secs(3600),
+            retention: RetentionPolicy::default(),
+            format: RecordFormat::Full,
+        })
+        .unwrap();
+
+        // Act
+        for sequence in 1..=3 {
+            recorder
+                .record(&Summary {
+                    sequence,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        recorder.close().unwrap();
+
+        // Assert
+        let files: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let sequences: Vec<u64> = read_recording(&files[0])
+            .unwrap()
+            .iter()
+            .map(|recorded| recorded.summary.as_ref().unwrap().sequence)
+            .collect();
+        assert_eq!(sequences, vec![1, 2, 3]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn should_rotate_and_archive_beyond_retention_size() {
+        // Arrange
+        let dir = test_dir("retention");
+        let archive_dir = dir.join("archive");
+        let mut recorder = Recorder::new(RecorderConfig {
+            dir: dir.clone(),
+            rotate_bytes: u64::MAX,
+            rotate_after: Duration::ZERO,
+            retention: RetentionPolicy {
+                max_age: None,
+                max_total_bytes: Some(0),
+                archive_dir: Some(archive_dir.clone()),
+            },
+            format: RecordFormat::Full,
+        })
+        .unwrap();
+
+        // Act
+        for sequence in 1..=3 {
+            recorder
+                .record(&Summary {
+                    sequence,
+                    ..Default::default()
+                })
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        recorder.close().unwrap();
+
+        // Assert
+        assert_eq!(fs::read_dir(&archive_dir).unwrap().count(), 3);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn should_read_back_delta_recording() {
+        // Arrange
+        let dir = test_dir("delta");
+        let mut recorder = Recorder::new(RecorderConfig {
+            dir: dir.clone(),
+            rotate_bytes: u64::MAX,
+            rotate_after: Duration::from_secs(3600),
+            retention: RetentionPolicy::default(),
+            format: "delta:2".parse().unwrap(),
+        })
+        .unwrap();
+        let summaries: Vec<Summary> = (1..=5)
+            .map(|sequence| Summary {
+                bids: vec![Level {
+                    price: 10. + (sequence % 2) as f64,
+                    ..Default::default()
+                }],
+                sequence,
+                ..Default::default()
+            })
+            .collect();
+
+        // Act
+        for summary in &summaries {
+            recorder.record(summary).unwrap();
+        }
+        recorder.close().unwrap();
+
+        // Assert
+        let files: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert!(files[0].to_string_lossy().ends_with(".delta.pb.zst"));
+        let read: Vec<Summary> = read_recording(&files[0])
+            .unwrap()
+            .into_iter()
+            .map(|recorded| recorded.summary.unwrap())
+            .collect();
+        assert!(read == summaries);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn should_read_recordings_of_every_format_from_one_directory() {
+        // Arrange
+        let dir = test_dir("formats");
+        for format in ["full", "delta:3", "jsonl"] {
+            let mut recorder = Recorder::new(RecorderConfig {
+                dir: dir.clone(),
+                rotate_bytes: u64::MAX,
+                rotate_after: Duration::from_secs(3600),
+                retention: RetentionPolicy::default(),
+                format: format.parse().unwrap(),
+            })
+            .unwrap();
+            recorder
+                .record(&Summary {
+                    sequence: 1,
+                    ..Default::default()
+                })
+                .unwrap();
+            recorder.close().unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        fs::write(dir.join("notes.txt"), "not a recording").unwrap();
+
+        // Act
+        let recordings = list_recordings(&dir).unwrap();
+        let read: Vec<usize> = recordings
+            .iter()
+            .map(|path| read_recording(path).unwrap().len())
+            .collect();
+
+        // Assert
+        assert!(read == vec![1, 1, 1]);
+        assert!(recordings[2].to_string_lossy().ends_with(".jsonl.zst"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn should_read_recent_summaries_including_an_unfinished_recording() {
+        // Arrange
+        let dir = test_dir("recent");
+        let mut recorder = Recorder::new(RecorderConfig {
+            dir: dir.clone(),
+            rotate_bytes: u64::MAX,
+            rotate_after: Duration::ZERO,
+            retention: RetentionPolicy::default(),
+            format: RecordFormat::Full,
+        })
+        .unwrap();
+        for sequence in 1..=3 {
+            recorder
+                .record(&Summary {
+                    sequence,
+                    ..Default::default()
+                })
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        recorder.close().unwrap();
+        let recordings = list_recordings(&dir).unwrap();
+        let since_ms = read_recording(&recordings[1]).unwrap()[0].recorded_at_ms;
+        // as if the server had crashed while writing the newest recording
+        let newest = recordings.last().unwrap();
+        let mut content = fs::read(newest).unwrap();
+        content.extend_from_slice(&[0x28, 0xb5, 0x2f]);
+        fs::write(newest, content).unwrap();
+
+        // Act
+        let recent = read_recent(&dir, since_ms).unwrap();
+
+        // Assert
+        assert!(read_recording(newest).is_err());
+        let sequences: Vec<u64> = recent
+            .iter()
+            .map(|recorded| recorded.summary.as_ref().unwrap().sequence)
+            .collect();
+        assert!(sequences == vec![2, 3]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,181 @@
+//! Reloads the configuration file passed with `--config` whenever it changed, without restarting
+//! the server. The log level, the lead tolerance and the interval of a conflating publish trigger
+//! are applied to the running pipelines, and the pipelines of the symbols added to or removed from
+//! the file are started or stopped. All other settings take effect on the next restart.
+
+use crate::{clock::Clock, config::Config, log, publish_trigger::PublishTrigger};
+use std::{fs, path::PathBuf, sync::Arc, time::Duration, time::SystemTime};
+use tokio::sync::mpsc;
+
+/// how often the modification time of the file is checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * What a reloaded configuration changes of the settings that can be changed at runtime.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub log_level: Option<log::Level>,
+    pub lead_tolerance: Option<usize>,
+    /// only set if both the running and the reloaded trigger conflate, with another interval
+    pub publish_trigger: Option<PublishTrigger>,
+    pub added_symbols: Vec<String>,
+    pub removed_symbols: Vec<String>,
+}
+
+fn changed<T: PartialEq>(running: T, reloaded: T) -> Option<T> {
+    match running == reloaded {
+        true => None,
+        false => Some(reloaded),
+    }
+}
+
+fn conflates(publish_trigger: PublishTrigger) -> bool {
+    matches!(
+        publish_trigger,
+        PublishTrigger::Conflated(_) | PublishTrigger::Adaptive { .. }
+    )
+}
+
+impl Changes {
+    /**
+     * The changes from the running configuration to the reloaded one, or why the reloaded one
+     * cannot be applied.
+     */
+    pub fn between(running: &Config, reloaded: &Config) -> Result<Changes, String> {
+        let changes = Changes {
+            log_level: changed(running.log_level, reloaded.log_level),
+            lead_tolerance: changed(running.lead_tolerance, reloaded.lead_tolerance),
+            publish_trigger: match conflates(running.publish_trigger)
+                && conflates(reloaded.publish_trigger)
+            {
+                true => changed(running.publish_trigger, reloaded.publish_trigger),
+                false => None,
+            },
+            added_symbols: (reloaded.symbols.iter())
+                .filter(|symbol| !running.symbols.contains(symbol))
+                .cloned()
+                .collect(),
+            removed_symbols: (running.symbols.iter())
+                .filter(|symbol| !reloaded.symbols.contains(symbol))
+                .cloned()
+                .collect(),
+        };
+
+        if reloaded.lead_tolerance == 0 {
+            return Err("The lead tolerance has to be at least 1".to_string());
+        }
+        // the server-wide streams are derived from the first symbol
+        if reloaded.symbols[0] != running.symbols[0] {
+            return Err(format!(
+                "The first symbol {} cannot change without a restart",
+                running.symbols[0]
+            ));
+        }
+        if running.upstream.is_some() && !changes.added_symbols.is_empty() {
+            return Err("--upstream relays the summaries of a single symbol".to_string());
+        }
+        Ok(changes)
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/**
+ * Parses the command line again each time the configuration file was modified and passes the
+ * configuration on. A file that cannot be parsed is reported, the running configuration is kept.
+ */
+pub async fn run(path: PathBuf, reloads: mpsc::Sender<Config>, clock: Arc<dyn Clock>) {
+    let mut last_modified = modified(&path);
+    loop {
+        clock.sleep(CHECK_INTERVAL).await;
+        let now_modified = modified(&path);
+        if now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+
+        match Config::try_from_args() {
+            Ok(config) => {
+                if reloads.send(config).await.is_err() {
+                    return;
+                }
+            }
+            Err(error) => log::alert!(
+                "Unable to reload {}, keeping the running configuration: {}",
+                path.display(),
+                error
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Changes;
+    use crate::{config::Config, log, publish_trigger::PublishTrigger};
+    use std::time::Duration;
+
+    fn config(symbols: &[&str], publish_trigger: PublishTrigger) -> Config {
+        Config {
+            symbols: symbols.iter().map(|symbol| symbol.to_string()).collect(),
+            publish_trigger,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_collect_the_changes_applied_at_runtime() {
+        // Arrange
+        let conflated = |ms| PublishTrigger::Conflated(Duration::from_millis(ms));
+        let running = config(&["ethbtc", "btcusdt"], conflated(100));
+        let mut reloaded = config(&["ethbtc", "ltcbtc"], conflated(50));
+        reloaded.log_level = log::Level::Alert;
+        reloaded.lead_tolerance = 5;
+        // needs a restart
+        reloaded.depth = 20;
+
+        // Act
+        let changes = Changes::between(&running, &reloaded).unwrap();
+
+        // Assert
+        assert!(
+            changes
+                == Changes {
+                    log_level: Some(log::Level::Alert),
+                    lead_tolerance: Some(5),
+                    publish_trigger: Some(conflated(50)),
+                    added_symbols: vec!["ltcbtc".to_string()],
+                    removed_symbols: vec!["btcusdt".to_string()],
+                }
+        );
+        assert!(Changes::between(&running, &running).unwrap() == Changes::default());
+    }
+
+    #[test]
+    fn should_leave_other_publish_triggers_to_a_restart() {
+        let timer = config(&["ethbtc"], PublishTrigger::Timer(Duration::from_secs(1)));
+        let conflated = config(&["ethbtc"], PublishTrigger::Conflated(Duration::ZERO));
+
+        assert!(Changes::between(&timer, &conflated).unwrap() == Changes::default());
+        assert!(Changes::between(&conflated, &timer).unwrap() == Changes::default());
+    }
+
+    #[test]
+    fn should_reject_changes_needing_a_restart() {
+        let running = config(&["ethbtc", "btcusdt"], PublishTrigger::default());
+        let mut relay = config(&["ethbtc"], PublishTrigger::default());
+        relay.upstream = Some("http://127.0.0.1:50051".to_string());
+        let mut untolerant = config(&["ethbtc", "btcusdt"], PublishTrigger::default());
+        untolerant.lead_tolerance = 0;
+        let reordered = config(&["btcusdt", "ethbtc"], PublishTrigger::default());
+
+        assert!(Changes::between(&running, &reordered).is_err());
+        assert!(Changes::between(&relay, &running).is_err());
+        assert!(Changes::between(&running, &untolerant).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! Strict validation of the exchanges' payloads, enabled with `--strict-schema`. A connector
+//! describes the [`Shape`] of the book messages it reads, and every such message is checked against
+//! it before it is applied. A message with a field the connector does not know, a value of another
+//! type than expected or lacking a field the connector relies on is quarantined instead: it is kept
+//! in the [`Quarantine`], counted in the drop journal and not applied, so a changed API is noticed
+//! with its first message rather than once the book went wrong. A connector keeping a full book
+//! detects the skipped update as a gap and resynchronizes.
+//!
+//! Without strict mode unknown fields are ignored, and a message that cannot be read is dropped
+//! silently.
+
+use crate::connector_sdk;
+use keyrock_challenge_proto::orderbook::{QuarantineReport, QuarantinedMessage};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    sync::Mutex,
+};
+
+/// the quarantined messages kept, the oldest are dropped first
+const MAX_QUARANTINED: usize = 256;
+/// longer payloads are cut off in the quarantine
+const MAX_PAYLOAD_BYTES: usize = 4096;
+
+/**
+ * The expected shape of a JSON value.
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    String,
+    Number,
+    /// a number or a string holding one, as the venues send prices and amounts as either
+    Decimal,
+    /// any number of elements of the same shape
+    Array(&'static Shape),
+    /// exactly the given elements, e.g. a `[price, amount]` level
+    Tuple(&'static [Shape]),
+    /// an object of the given fields only
+    Object(&'static [Field]),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Field {
+    pub name: &'static str,
+    pub shape: Shape,
+    /// whether a message lacking the field is in violation
+    pub required: bool,
+}
+
+impl Field {
+    pub const fn required(name: &'static str, shape: Shape) -> Field {
+        Field {
+            name,
+            shape,
+            required: true,
+        }
+    }
+
+    pub const fn optional(name: &'static str, shape: Shape) -> Field {
+        Field {
+            name,
+            shape,
+            required: false,
+        }
+    }
+}
+
+/**
+ * How a message deviates from its shape. The path of a value names the elements of arrays `[]`, so
+ * the same deviation in every level of a ladder is reported once.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Violation {
+    Unexpected {
+        path: String,
+    },
+    Missing {
+        path: String,
+    },
+    Mismatch {
+        path: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Unexpected { path } => write!(f, "unexpected field {}", path),
+            Violation::Missing { path } => write!(f, "missing field {}", path),
+            Violation::Mismatch {
+                path,
+                expected,
+                found,
+            } => write!(f, "{} is {} instead of {}", path, found, expected),
+        }
+    }
+}
+
+impl Shape {
+    fn name(&self) -> String {
+        match self {
+            Shape::String => "a string".to_string(),
+            Shape::Number => "a number".to_string(),
+            Shape::Decimal => "a decimal".to_string(),
+            Shape::Array(_) => "an array".to_string(),
+            Shape::Tuple(elements) => format!("an array of {}", elements.len()),
+            Shape::Object(_) => "an object".to_string(),
+        }
+    }
+}
+
+fn found(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "a bool".to_string(),
+        Value::Number(_) => "a number".to_string(),
+        Value::String(_) if connector_sdk::parse_decimal(value).is_ok() => {
+            "a decimal string".to_string()
+        }
+        Value::String(_) => "a string".to_string(),
+        Value::Array(elements) => format!("an array of {}", elements.len()),
+        Value::Object(_) => "an object".to_string(),
+    }
+}
+
+fn push(violations: &mut Vec<Violation>, violation: Violation) {
+    if !violations.contains(&violation) {
+        violations.push(violation);
+    }
+}
+
+fn check(shape: &Shape, value: &Value, path: &str, violations: &mut Vec<Violation>) {
+    match (shape, value) {
+        (Shape::String, Value::String(_)) | (Shape::Number, Value::Number(_)) => {}
+        (Shape::Decimal, _) if connector_sdk::parse_decimal(value).is_ok() => {}
+        (Shape::Array(element), Value::Array(elements)) => {
+            let path = format!("{}[]", path);
+            for value in elements {
+                check(element, value, &path, violations);
+            }
+        }
+        (Shape::Tuple(shapes), Value::Array(elements)) if shapes.len() == elements.len() => {
+            for (index, (shape, value)) in shapes.iter().zip(elements).enumerate() {
+                check(shape, value, &format!("{}[{}]", path, index), violations);
+            }
+        }
+        (Shape::Object(fields), Value::Object(object)) => {
+            for field in fields.iter() {
+                let path = format!("{}.{}", path, field.name);
+                match object.get(field.name) {
+                    Some(value) => check(&field.shape, value, &path, violations),
+                    None if field.required => push(violations, Violation::Missing { path }),
+                    None => {}
+                }
+            }
+            for name in object.keys() {
+                if !fields.iter().any(|field| field.name == name) {
+                    let path = format!("{}.{}", path, name);
+                    push(violations, Violation::Unexpected { path });
+                }
+            }
+        }
+        _ => push(
+            violations,
+            Violation::Mismatch {
+                path: path.to_string(),
+                expected: shape.name(),
+                found: found(value),
+            },
+        ),
+    }
+}
+
+/**
+ * The ways the value deviates from the shape, each once, none if it conforms.
+ */
+pub fn validate(shape: &Shape, value: &Value) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    check(shape, value, "$", &mut violations);
+    violations
+}
+
+#[derive(Debug, Default)]
+struct State {
+    messages: VecDeque<QuarantinedMessage>,
+    counts: HashMap<String, u64>,
+    /// the violations of each exchange seen before, which are not logged again
+    seen: HashSet<(String, Violation)>,
+}
+
+/**
+ * The messages of the exchanges which deviated from the shape their connector expects, shared by
+ * the pipelines of all symbols.
+ */
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    state: Mutex<State>,
+}
+
+impl Quarantine {
+    pub fn new() -> Self {
+        Quarantine::default()
+    }
+
+    /**
+     * Keeps the raw message of the exchange along with its violations, returns those the exchange
+     * did not commit before.
+     */
+    pub fn record(
+        &self,
+        exchange: &str,
+        violations: &[Violation],
+        payload: &str,
+        at_ms: u64,
+    ) -> Vec<Violation> {
+        let mut state = self.state.lock().unwrap();
+        *state.counts.entry(exchange.to_string()).or_default() += 1;
+        let new: Vec<Violation> = violations
+            .iter()
+            .filter(|violation| {
+                state
+                    .seen
+                    .insert((exchange.to_string(), (*violation).clone()))
+            })
+            .cloned()
+            .collect();
+
+        let mut end = payload.len().min(MAX_PAYLOAD_BYTES);
+        while !payload.is_char_boundary(end) {
+            end -= 1;
+        }
+        if state.messages.len() >= MAX_QUARANTINED {
+            state.messages.pop_front();
+        }
+        state.messages.push_back(QuarantinedMessage {
+            exchange: exchange.to_string(),
+            at_ms,
+            violations: violations.iter().map(Violation::to_string).collect(),
+            payload: payload[..end].to_string(),
+        });
+        new
+    }
+
+    pub fn report(&self) -> QuarantineReport {
+        let state = self.state.lock().unwrap();
+        QuarantineReport {
+            messages: state.messages.iter().cloned().collect(),
+            counts: state.counts.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, Field, Quarantine, Shape, Violation};
+    use serde_json::json;
+
+    const LEVEL: Shape = Shape::Tuple(&[Shape::Decimal, Shape::Decimal]);
+    const EVENT: Shape = Shape::Object(&[
+        Field::required("e", Shape::String),
+        Field::required("u", Shape::Number),
+        Field::optional("E", Shape::Number),
+        Field::required("b", Shape::Array(&LEVEL)),
+    ]);
+
+    #[test]
+    fn should_report_each_deviation_once() {
+        // Arrange
+        let conforming = json!({"e": "depthUpdate", "u": 160, "b": [["0.0745", 1.5]]});
+        let changed = json!({
+            "e": "depthUpdate",
+            "u": "160",
+            "b": [["0.0745", "1.5", "0"], ["0.0744", "3.0", "0"], ["abc", "1.0"]],
+            "x": true
+        });
+
+        // Act
+        let violations = validate(&EVENT, &changed);
+
+        // Assert
+        assert!(validate(&EVENT, &conforming).is_empty());
+        assert!(
+            violations
+                == [
+                    Violation::Mismatch {
+                        path: "$.u".to_string(),
+                        expected: "a number".to_string(),
+                        found: "a decimal string".to_string(),
+                    },
+                    Violation::Mismatch {
+                        path: "$.b[]".to_string(),
+                        expected: "an array of 2".to_string(),
+                        found: "an array of 3".to_string(),
+                    },
+                    Violation::Mismatch {
+                        path: "$.b[][0]".to_string(),
+                        expected: "a decimal".to_string(),
+                        found: "a string".to_string(),
+                    },
+                    Violation::Unexpected {
+                        path: "$.x".to_string()
+                    },
+                ]
+        );
+        assert!(
+            validate(&EVENT, &json!({"e": "depthUpdate", "b": []}))
+                == [Violation::Missing {
+                    path: "$.u".to_string()
+                }]
+        );
+    }
+
+    #[test]
+    fn should_count_quarantined_messages_and_only_return_new_violations() {
+        // Arrange
+        let quarantine = Quarantine::new();
+        let unexpected = Violation::Unexpected {
+            path: "$.x".to_string(),
+        };
+        let missing = Violation::Missing {
+            path: "$.u".to_string(),
+        };
+
+        // Act
+        let first = quarantine.record(
+            "Binance",
+            std::slice::from_ref(&unexpected),
+            "{\"x\": 1}",
+            1000,
+        );
+        let second = quarantine.record(
+            "Binance",
+            &[unexpected.clone(), missing.clone()],
+            "{}",
+            2000,
+        );
+        let other = quarantine.record(
+            "OKX",
+            std::slice::from_ref(&unexpected),
+            &"é".repeat(4096),
+            3000,
+        );
+
+        // Assert
+        assert!(first == [unexpected.clone()] && second == [missing] && other == [unexpected]);
+        let report = quarantine.report();
+        assert!(report.counts["Binance"] == 2 && report.counts["OKX"] == 1);
+        assert!(report.messages[1].violations == ["unexpected field $.x", "missing field $.u"]);
+        assert!(report.messages[2].payload.len() == 4096);
+    }
+}
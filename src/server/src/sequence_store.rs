@@ -0,0 +1,185 @@
+use crate::log;
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// the sequence numbers reserved with every write of the file
+const RESERVED_BLOCK: u64 = 1000;
+
+/**
+ * Hands out the sequence numbers of the published summaries.
+ * If a file is configured, the end of a block of reserved numbers is persisted to it, so a
+ * restarted server continues the sequence after the block instead of starting from zero again.
+ * The file is only written once the block is used up, not on every summary.
+ * Every sequence is identified by an epoch, the unix milliseconds at which it started. It is
+ * persisted along with the block, so it only changes when the sequence starts from zero again.
+ */
+#[derive(Debug)]
+pub struct SequenceStore {
+    path: Option<PathBuf>,
+    last: u64,
+    /// the last number that may be handed out without persisting a new block
+    reserved: u64,
+    restarted: bool,
+    epoch: u64,
+}
+
+impl SequenceStore {
+    /**
+     * Fails if the file exists but does not hold a sequence number, optionally followed by the
+     * epoch of the sequence. A sequence persisted without its epoch continues in a new epoch.
+     */
+    pub fn open(path: Option<PathBuf>) -> Result<SequenceStore, String> {
+        let (last, epoch) = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(content) => {
+                    let invalid = || format!("Unable to parse sequence file {}", path.display());
+                    let mut fields = content.split_whitespace();
+                    let last = (fields.next())
+                        .and_then(|last| last.parse::<u64>().ok())
+                        .ok_or_else(invalid)?;
+                    let epoch = (fields.next())
+                        .map(|epoch| epoch.parse::<u64>().map_err(|_| invalid()))
+                        .transpose()?;
+                    (last, epoch)
+                }
+                Err(_) => (0, None),
+            },
+            None => (0, None),
+        };
+        let epoch = epoch.unwrap_or_else(|| {
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            since_epoch.as_millis() as u64
+        });
+
+        Ok(SequenceStore {
+            path,
+            last,
+            reserved: last,
+            restarted: true,
+            epoch,
+        })
+    }
+
+    /**
+     * The epoch of the sequence, see the struct.
+     */
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /**
+     * Returns the next sequence number and whether it is the first one since the server started.
+     * The block holding the number is persisted before it is returned, so it is never handed out
+     * twice. A restart skips what is left of the block.
+     */
+    pub fn next(&mut self) -> (u64, bool) {
+        self.last += 1;
+
+        if let Some(path) = self.path.as_ref().filter(|_| self.last > self.reserved) {
+            self.reserved = self.last + RESERVED_BLOCK - 1;
+            // appended rather than replacing the extension, which is the symbol of the file
+            let mut tmp_path = path.as_os_str().to_owned();
+            tmp_path.push(".tmp");
+            let persisted = fs::write(&tmp_path, format!("{} {}", self.reserved, self.epoch))
+                .and_then(|_| fs::rename(&tmp_path, path));
+            if let Err(error) = persisted {
+                log::warning!(
+                    "Unable to persist sequence to {}: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+
+        let restarted = self.restarted;
+        self.restarted = false;
+        (self.last, restarted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SequenceStore, RESERVED_BLOCK};
+    use std::fs;
+
+    #[test]
+    fn should_continue_sequence_after_restart() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("sequence_store_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut store = SequenceStore::open(Some(path.clone())).unwrap();
+        assert_eq!(store.next(), (1, true));
+        assert_eq!(store.next(), (2, false));
+        let persisted = fs::read_to_string(&path).unwrap();
+
+        // Act
+        let mut restarted_store = SequenceStore::open(Some(path.clone())).unwrap();
+
+        // Assert
+        assert_eq!(persisted, format!("{} {}", RESERVED_BLOCK, store.epoch()));
+        assert_eq!(restarted_store.next(), (RESERVED_BLOCK + 1, true));
+        assert_eq!(restarted_store.next(), (RESERVED_BLOCK + 2, false));
+        assert_eq!(restarted_store.epoch(), store.epoch());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn should_start_a_new_epoch_with_a_new_sequence() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("sequence_epoch_{}", std::process::id()));
+        fs::write(&path, "5000").unwrap();
+        let legacy_store = SequenceStore::open(Some(path.clone())).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        // Act
+        let store = SequenceStore::open(None).unwrap();
+
+        // Assert
+        assert!(store.epoch() > legacy_store.epoch());
+        assert!(legacy_store.epoch() > 0);
+        fs::write(&path, "5000 x").unwrap();
+        assert!(SequenceStore::open(Some(path.clone())).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn should_keep_the_sequences_of_stores_in_one_directory_apart() {
+        // Arrange
+        let dir = std::env::temp_dir().join(format!("sequence_stores_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (ethbtc, btcusdt) = (dir.join("seq.ethbtc"), dir.join("seq.btcusdt"));
+        fs::write(&btcusdt, "1000000").unwrap();
+        let blocks = 100;
+
+        // Act
+        let stores = [ethbtc.clone(), btcusdt.clone()].map(|path| {
+            std::thread::spawn(move || {
+                let mut store = SequenceStore::open(Some(path)).unwrap();
+                for _ in 0..blocks * RESERVED_BLOCK {
+                    store.next();
+                }
+            })
+        });
+        for store in stores {
+            store.join().unwrap();
+        }
+
+        // Assert
+        let read = |path: &std::path::Path| {
+            let persisted = fs::read_to_string(path).unwrap();
+            persisted.split_whitespace().next().unwrap().to_string()
+        };
+        assert_eq!(read(&ethbtc), (blocks * RESERVED_BLOCK).to_string());
+        assert_eq!(
+            read(&btcusdt),
+            (1000000 + blocks * RESERVED_BLOCK).to_string()
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,117 @@
+//! Runs a second merge strategy alongside the published one. Its summaries are never published,
+//! only compared to the published ones, so new merge logic can be validated on live data before it
+//! is promoted with `--merge`.
+
+use crate::spmc::Spmc;
+use keyrock_challenge_core::merge_strategy::MergeStrategy;
+use keyrock_challenge_proto::orderbook::{Level, ShadowComparison, Summary};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Debug)]
+pub struct Shadow {
+    pub strategy: MergeStrategy,
+    spmc: Arc<Mutex<Spmc<ShadowComparison>>>,
+    compared: u64,
+    diverged: u64,
+}
+
+fn differing(primary: &[Level], shadow: &[Level]) -> u32 {
+    let common = primary
+        .iter()
+        .zip(shadow)
+        .filter(|(primary, shadow)| primary != shadow)
+        .count();
+    (common + primary.len().abs_diff(shadow.len())) as u32
+}
+
+impl Shadow {
+    pub fn new(strategy: MergeStrategy, spmc: Arc<Mutex<Spmc<ShadowComparison>>>) -> Self {
+        Shadow {
+            strategy,
+            spmc,
+            compared: 0,
+            diverged: 0,
+        }
+    }
+
+    /**
+     * Compares the books merged by the shadow strategy to the published summary, before smoothing.
+     */
+    pub fn compare(&mut self, primary: &Summary, mut shadow: Summary) -> ShadowComparison {
+        shadow.sequence = primary.sequence;
+        shadow.symbol = primary.symbol.clone();
+        let differing_bids = differing(&primary.bids, &shadow.bids);
+        let differing_asks = differing(&primary.asks, &shadow.asks);
+        let spread_difference = match (shadow.spread, primary.raw_spread) {
+            (Some(shadow_spread), Some(primary_spread)) => Some(shadow_spread - primary_spread),
+            _ => None,
+        };
+
+        self.compared += 1;
+        if differing_bids > 0 || differing_asks > 0 || shadow.spread != primary.raw_spread {
+            self.diverged += 1;
+        }
+
+        ShadowComparison {
+            sequence: primary.sequence,
+            symbol: primary.symbol.clone(),
+            shadow: Some(shadow),
+            spread_difference,
+            differing_bids,
+            differing_asks,
+            compared: self.compared,
+            diverged: self.diverged,
+        }
+    }
+
+    pub async fn publish(&self, comparison: ShadowComparison) {
+        let mut spmc = self.spmc.lock().await;
+        if !spmc.is_empty() {
+            spmc.broadcast(comparison).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shadow;
+    use crate::spmc::Spmc;
+    use keyrock_challenge_core::merge_strategy::MergeStrategy;
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn summary(bids: Vec<(f64, f64)>, spread: f64) -> Summary {
+        Summary {
+            spread: Some(spread),
+            raw_spread: Some(spread),
+            bids: bids
+                .into_iter()
+                .map(|(price, amount)| Level {
+                    price,
+                    amount,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_count_diverging_levels_and_totals() {
+        // Arrange
+        let spmc = Arc::new(Mutex::new(Spmc::new()));
+        let mut shadow = Shadow::new(MergeStrategy::LargerAmountFirst, spmc);
+        let primary = summary(vec![(10., 1.), (10., 2.)], 1.);
+
+        // Act
+        let same = shadow.compare(&primary, summary(vec![(10., 1.), (10., 2.)], 1.));
+        let swapped = shadow.compare(&primary, summary(vec![(10., 2.), (10., 1.)], 1.));
+
+        // Assert
+        assert!(same.differing_bids == 0 && same.spread_difference == Some(0.));
+        assert!(swapped.differing_bids == 2 && swapped.differing_asks == 0);
+        assert!(swapped.compared == 2 && swapped.diverged == 1);
+    }
+}
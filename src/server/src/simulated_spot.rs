@@ -0,0 +1,100 @@
+//! A venue producing random walk books, which lets the server run without reaching any exchange.
+
+use crate::{aggregator::Aggregator, clock::Clock, exchange_registry};
+use keyrock_challenge_core::{
+    decimal::Decimal,
+    orderbook_snapshot::{BookLevel, OrderbookSnapshot, SnapshotBuilder},
+};
+use keyrock_challenge_proto::orderbook::TickTimings;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+const START_MID: f64 = 0.0745;
+const TICK_SIZE: f64 = 0.000001;
+
+/**
+ * Xorshift generator, good enough to make the books move and reproducible per seed.
+ */
+#[derive(Debug)]
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// a value in [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn simulate(exchange: &str, mid: f64, depth: usize, random: &mut Random) -> OrderbookSnapshot {
+    let exchange_id = exchange_registry::exchange_id(exchange) as i32;
+    let half_spread = TICK_SIZE * (1 + random.next() % 5) as f64;
+    let mut level = |price: f64| BookLevel {
+        exchange: exchange.to_string(),
+        price: Decimal::try_from(price).expect("Simulated an invalid price"),
+        amount: Decimal::try_from((random.unit() * 10.).max(0.001))
+            .expect("Simulated an invalid amount"),
+        exchange_id,
+        contributors: Vec::new(),
+        // the simulated venues send no raw messages
+        provenance: 0,
+    };
+
+    let bids = (0..depth)
+        .map(|i| level(mid - half_spread - TICK_SIZE * i as f64))
+        .collect();
+    let asks = (0..depth)
+        .map(|i| level(mid + half_spread + TICK_SIZE * i as f64))
+        .collect();
+    SnapshotBuilder::new()
+        .bids(bids)
+        .asks(asks)
+        .build(depth)
+        .expect("Simulated an invalid book")
+}
+
+pub async fn run_stream(
+    source_id: usize,
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    exchange: &'static str,
+    seed: u64,
+    clock: Arc<dyn Clock>,
+) {
+    let mut random = Random(seed.max(1));
+    let mut mid = START_MID;
+    let (depth, inbound) = {
+        let aggregator = aggregator_arc.lock().await;
+        (aggregator.max_depth(), aggregator.inbound_queue(source_id))
+    };
+
+    loop {
+        clock.sleep(TICK_INTERVAL).await;
+        mid += TICK_SIZE * (random.next() % 3) as f64 - TICK_SIZE;
+        let snapshot = simulate(exchange, mid, depth, &mut random);
+        inbound.push(snapshot, TickTimings::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{simulate, Random};
+
+    #[test]
+    fn should_simulate_uncrossed_sorted_book() {
+        let mut random = Random(42);
+
+        let snapshot = simulate("Binance", 0.0745, 10, &mut random);
+
+        let (bids, asks) = (snapshot.bids.unwrap(), snapshot.asks.unwrap());
+        assert!(bids[0].price < asks[0].price);
+        assert!(bids.windows(2).all(|pair| pair[0].price > pair[1].price));
+        assert!(asks.windows(2).all(|pair| pair[0].price < pair[1].price));
+    }
+}
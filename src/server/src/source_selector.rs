@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+/**
+ * The kind of feed a connector consumes. The order of the variants defines which one is preferred
+ * if a venue is connected through more than one feed: a locally maintained book from the diff stream
+ * is richer than the throttled partial book.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SourceKind {
+    PartialBook,
+    DiffBook,
+}
+
+#[derive(Debug)]
+struct Source {
+    venue_id: usize,
+    kind: SourceKind,
+    last_update: Option<Instant>,
+}
+
+/**
+ * Deduplicates sources feeding the same venue. Updates of a source are only accepted
+ * as long as no richer source of the same venue delivered data within the freshness window,
+ * so a venue is never treated as two venues and falls back to the poorer feed if the richer one dies.
+ */
+#[derive(Debug)]
+pub struct SourceSelector {
+    sources: Vec<Source>,
+    freshness: Duration,
+}
+
+impl SourceSelector {
+    pub fn new(freshness: Duration) -> Self {
+        SourceSelector {
+            sources: Vec::new(),
+            freshness,
+        }
+    }
+
+    /**
+     * Registers a feed of the given venue and returns the source id the connector has to report with.
+     */
+    pub fn register(&mut self, venue_id: usize, kind: SourceKind) -> usize {
+        self.sources.push(Source {
+            venue_id,
+            kind,
+            last_update: None,
+        });
+        self.sources.len() - 1
+    }
+
+    /**
+     * Records an update of the source and returns the venue it belongs to,
+     * or None if a richer source of the same venue is live and the update should be ignored.
+     */
+    pub fn accept(&mut self, source_id: usize, now: Instant) -> Option<usize> {
+        let source = &mut self.sources[source_id];
+        source.last_update = Some(now);
+        let (venue_id, kind) = (source.venue_id, source.kind);
+
+        let richer_source_live = self.sources.iter().any(|other| {
+            other.venue_id == venue_id
+                && other.kind > kind
+                && other
+                    .last_update
+                    .is_some_and(|last| now.duration_since(last) < self.freshness)
+        });
+
+        match richer_source_live {
+            true => None,
+            false => Some(venue_id),
+        }
+    }
+
+    /**
+     * Records that the connection of the source dropped and returns the venue it belongs to, or
+     * None if another source of the same venue is live and keeps feeding it.
+     */
+    pub fn disconnect(&mut self, source_id: usize, now: Instant) -> Option<usize> {
+        let source = &mut self.sources[source_id];
+        source.last_update = None;
+        let venue_id = source.venue_id;
+
+        let other_source_live = self.sources.iter().any(|other| {
+            other.venue_id == venue_id
+                && other
+                    .last_update
+                    .is_some_and(|last| now.duration_since(last) < self.freshness)
+        });
+
+        match other_source_live {
+            true => None,
+            false => Some(venue_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceKind, SourceSelector};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn should_prefer_richer_source_of_same_venue() {
+        // Arrange
+        let mut selector = SourceSelector::new(Duration::from_secs(1));
+        let partial = selector.register(0, SourceKind::PartialBook);
+        let diff = selector.register(0, SourceKind::DiffBook);
+        let other_venue = selector.register(1, SourceKind::PartialBook);
+        let now = Instant::now();
+
+        // Act & Assert
+        assert_eq!(selector.accept(partial, now), Some(0));
+        assert_eq!(selector.accept(diff, now), Some(0));
+        assert_eq!(selector.accept(partial, now), None);
+        assert_eq!(selector.accept(other_venue, now), Some(1));
+    }
+
+    #[test]
+    fn should_fall_back_once_richer_source_is_stale() {
+        // Arrange
+        let mut selector = SourceSelector::new(Duration::from_secs(1));
+        let partial = selector.register(0, SourceKind::PartialBook);
+        let diff = selector.register(0, SourceKind::DiffBook);
+        let now = Instant::now();
+        selector.accept(diff, now);
+
+        // Act
+        let accepted = selector.accept(partial, now + Duration::from_secs(2));
+
+        // Assert
+        assert_eq!(accepted, Some(0));
+    }
+
+    #[test]
+    fn should_only_disconnect_a_venue_without_other_live_sources() {
+        // Arrange
+        let mut selector = SourceSelector::new(Duration::from_secs(1));
+        let partial = selector.register(0, SourceKind::PartialBook);
+        let diff = selector.register(0, SourceKind::DiffBook);
+        let now = Instant::now();
+        selector.accept(partial, now);
+        selector.accept(diff, now);
+
+        // Act
+        let fed_by_partial = selector.disconnect(diff, now);
+        let disconnected = selector.disconnect(partial, now);
+
+        // Assert
+        assert_eq!(fed_by_partial, None);
+        assert_eq!(disconnected, Some(0));
+        assert_eq!(selector.accept(partial, now), Some(0));
+    }
+}
@@ -1,41 +1,486 @@
-use keyrock_challenge_proto::orderbook::Summary;
-use tokio::sync::mpsc::{self, Receiver, Sender};
+#[cfg(test)]
+use crate::capture::Capture;
+use crate::{clock::Clock, journal::Journal};
+use keyrock_challenge_proto::orderbook::DropReason;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError, Receiver, Sender},
+    Notify,
+};
+
+/// while shedding load only this share of the history capacity is retained
+const SHEDDING_HISTORY_DIVISOR: usize = 4;
+
+/**
+ * Turns a broadcast item into the one queued for a subscriber, e.g. cutting a summary down to the
+ * depth the subscriber asked for.
+ */
+struct Filter<T>(Box<dyn Fn(&T) -> T + Send + Sync>);
+
+impl<T> fmt::Debug for Filter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Filter")
+    }
+}
 
 #[derive(Debug)]
-pub struct Spmc {
-    senders: Vec<Sender<Summary>>,
+struct Queue<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    filter: Option<Filter<T>>,
+    /// the oldest items dropped to make room for newer ones
+    dropped: u64,
+    /// whether the queue only holds the latest item for a throttled subscriber, whose replaced
+    /// items are conflated rather than dropped for being slow
+    conflating: bool,
+    /// set once the spmc is dropped, the items queued until then are still received
+    closed: bool,
 }
 
-impl Spmc {
+#[derive(Debug)]
+struct SubscriberQueue<T> {
+    /// who the items are dropped for, the subject of the drop journal
+    client: String,
+    queue: Mutex<Queue<T>>,
+    notify: Notify,
+}
+
+#[derive(Debug)]
+struct Throttle {
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    /// when the next item may be received, right away if None
+    next_at: Option<Instant>,
+}
+
+/**
+ * The receiving end of a bounded queue which never holds up the broadcast: once it is full, the
+ * oldest queued item is dropped in favor of the newest.
+ */
+#[derive(Debug)]
+pub struct Subscriber<T> {
+    shared: Arc<SubscriberQueue<T>>,
+    throttle: Option<Throttle>,
+}
+
+impl<T> Subscriber<T> {
+    /**
+     * The next queued item, None once the spmc was dropped and the queue is drained. A throttled
+     * subscriber waits until its interval since the previous item passed and receives the latest
+     * item broadcast by then.
+     */
+    pub async fn recv(&mut self) -> Option<T> {
+        if let Some(Throttle {
+            clock,
+            next_at: Some(next_at),
+            ..
+        }) = &self.throttle
+        {
+            clock
+                .sleep(next_at.saturating_duration_since(clock.now()))
+                .await;
+        }
+        let item = self.next().await?;
+        if let Some(throttle) = &mut self.throttle {
+            throttle.next_at = Some(throttle.clock.now() + throttle.interval);
+        }
+        Some(item)
+    }
+
+    async fn next(&self) -> Option<T> {
+        loop {
+            let notified = self.shared.notify.notified();
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                if let Some(item) = queue.items.pop_front() {
+                    return Some(item);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /**
+     * Queues the items as the filter turns them, rather than as they are broadcast.
+     */
+    pub fn set_filter(&mut self, filter: impl Fn(&T) -> T + Send + Sync + 'static) {
+        self.shared.queue.lock().unwrap().filter = Some(Filter(Box::new(filter)));
+    }
+}
+
+/**
+ * A receiver of the spmc as listed in its state.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberState {
+    /// the client of a subscriber queue, None for a receiver holding up the broadcast
+    pub client: Option<String>,
+    pub buffer_size: usize,
+    pub queued: usize,
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+pub struct Spmc<T> {
+    /// the senders together with the buffer size of their channels
+    senders: Vec<(Sender<T>, usize)>,
+    queues: Vec<Arc<SubscriberQueue<T>>>,
+    /// the most recent items, replayable to receivers which missed them
+    history: VecDeque<T>,
+    history_capacity: usize,
+    shedding: bool,
+    /// where items missed by full receivers are recorded, under the name of the stream
+    journal: Option<(Arc<Journal>, &'static str)>,
+    #[cfg(test)]
+    capture: Option<Capture<T>>,
+}
+
+impl<T: Clone> Spmc<T> {
     pub fn new() -> Self {
+        Spmc::with_history(0)
+    }
+
+    pub fn with_history(history_capacity: usize) -> Self {
         Spmc {
-            senders: Vec::<Sender<Summary>>::new(),
+            senders: Vec::new(),
+            queues: Vec::new(),
+            history: VecDeque::with_capacity(history_capacity),
+            history_capacity,
+            shedding: false,
+            journal: None,
+            #[cfg(test)]
+            capture: None,
+        }
+    }
+
+    /**
+     * The retained items, oldest first. Since items are only broadcast while holding the spmc,
+     * reading the history and creating a receiver in one go neither misses nor duplicates an item.
+     */
+    pub fn history(&self) -> impl Iterator<Item = &T> {
+        self.history.iter()
+    }
+
+    /**
+     * Updates the retained items in place, e.g. so that items replayed after a change of the
+     * published format are consistent with the ones broadcast from then on.
+     */
+    pub fn update_history(&mut self, update: impl FnMut(&mut T)) {
+        self.history.iter_mut().for_each(update);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty() && self.queues.is_empty()
+    }
+
+    /**
+     * The amount of items sent but not yet received, summed over all receivers.
+     */
+    pub fn queued(&self) -> usize {
+        let receivers: usize = (self.senders.iter())
+            .map(|(sender, buffer)| buffer - sender.capacity())
+            .sum();
+        let queues: usize = (self.queues.iter())
+            .map(|shared| shared.queue.lock().unwrap().items.len())
+            .sum();
+        receivers + queues
+    }
+
+    /**
+     * While shedding load the history is cut down to a quarter of its capacity and receivers whose
+     * buffer is full miss items instead of holding up the broadcast.
+     */
+    pub fn set_shedding(&mut self, shedding: bool) {
+        self.shedding = shedding;
+        while self.history.len() > self.effective_history_capacity() {
+            self.history.pop_front();
+        }
+    }
+
+    fn effective_history_capacity(&self) -> usize {
+        match self.shedding {
+            true => self.history_capacity / SHEDDING_HISTORY_DIVISOR,
+            false => self.history_capacity,
         }
     }
 
-    pub async fn broadcast(&mut self, summary: Summary) {
+    /**
+     * Retains items published before the spmc was created, e.g. read back from the recordings at
+     * startup, as if they had been broadcast, but without sending them to any receiver.
+     */
+    pub fn preload(&mut self, items: impl IntoIterator<Item = T>) {
+        for item in items {
+            self.retain(&item);
+        }
+    }
+
+    fn retain(&mut self, item: &T) {
+        let history_capacity = self.effective_history_capacity();
+        if history_capacity > 0 {
+            while self.history.len() >= history_capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(item.clone());
+        }
+    }
+
+    pub fn set_journal(&mut self, journal: Arc<Journal>, stream: &'static str) {
+        self.journal = Some((journal, stream));
+    }
+
+    /**
+     * How many receivers and subscribers the next broadcast is handed to.
+     */
+    pub fn receivers(&self) -> usize {
+        self.senders.len() + self.queues.len()
+    }
+
+    /**
+     * Records every following broadcast in the capture, regardless of any receivers.
+     */
+    #[cfg(test)]
+    pub fn set_capture(&mut self, capture: Capture<T>) {
+        self.capture = Some(capture);
+    }
+
+    pub async fn broadcast(&mut self, item: T) {
+        #[cfg(test)]
+        if let Some(capture) = &self.capture {
+            capture.record(&item);
+        }
+        self.retain(&item);
         let mut index: usize = 0;
+        let mut missed: u64 = 0;
 
         loop {
             if index >= self.senders.len() {
                 break;
             }
-            let sender = &self.senders[index];
-            let result = sender.send(summary.clone()).await;
-            match result {
-                Ok(_) => {
+            let (sender, _) = &self.senders[index];
+            // a full receiver only misses the item, a closed one is dropped
+            let open = match self.shedding {
+                true => match sender.try_send(item.clone()) {
+                    Err(TrySendError::Full(_)) => {
+                        missed += 1;
+                        true
+                    }
+                    result => result.is_ok(),
+                },
+                false => sender.send(item.clone()).await.is_ok(),
+            };
+            match open {
+                true => {
                     index += 1;
                 }
-                Err(_) => {
+                false => {
                     let _ = &self.senders.remove(index);
                 }
             }
         }
+        if let Some((journal, stream)) = &self.journal {
+            journal.record(DropReason::SlowConsumer, stream, missed);
+        }
+
+        self.prune_queues();
+        for shared in &self.queues {
+            let dropped = {
+                let mut queue = shared.queue.lock().unwrap();
+                let dropped = queue.items.len() >= queue.capacity;
+                if dropped {
+                    queue.items.pop_front();
+                    queue.dropped += 1;
+                }
+                let item = match &queue.filter {
+                    Some(Filter(filter)) => filter(&item),
+                    None => item.clone(),
+                };
+                queue.items.push_back(item);
+                match queue.conflating {
+                    true => dropped.then_some(DropReason::Conflated),
+                    false => dropped.then_some(DropReason::SlowConsumer),
+                }
+            };
+            shared.notify.notify_one();
+            if let (Some(reason), Some((journal, _))) = (dropped, &self.journal) {
+                journal.record(reason, &shared.client, 1);
+            }
+        }
+    }
+
+    /**
+     * Removes the queues whose subscriber was dropped, which leaves the spmc as their only owner.
+     */
+    fn prune_queues(&mut self) {
+        self.queues.retain(|shared| Arc::strong_count(shared) > 1);
+    }
+
+    /**
+     * Removes the senders whose receiver and the queues whose subscriber was dropped.
+     */
+    pub fn prune(&mut self) {
+        self.senders.retain(|(sender, _)| !sender.is_closed());
+        self.prune_queues();
     }
 
-    pub fn create_receiver(&mut self, buffer: usize) -> Receiver<Summary> {
+    /**
+     * Each receiver and subscriber that was not dropped, with the number of items it has queued.
+     */
+    pub fn subscribers(&self) -> Vec<SubscriberState> {
+        let receivers = (self.senders.iter())
+            .filter(|(sender, _)| !sender.is_closed())
+            .map(|(sender, buffer_size)| SubscriberState {
+                client: None,
+                buffer_size: *buffer_size,
+                queued: buffer_size - sender.capacity(),
+                dropped: 0,
+            });
+        let queues = (self.queues.iter())
+            .filter(|shared| Arc::strong_count(shared) > 1)
+            .map(|shared| {
+                let queue = shared.queue.lock().unwrap();
+                SubscriberState {
+                    client: Some(shared.client.clone()),
+                    buffer_size: queue.capacity,
+                    queued: queue.items.len(),
+                    dropped: queue.dropped,
+                }
+            });
+        receivers.chain(queues).collect()
+    }
+
+    pub fn create_receiver(&mut self, buffer: usize) -> Receiver<T> {
         let (tx, rx) = mpsc::channel(buffer);
-        self.senders.push(tx);
+        self.senders.push((tx, buffer));
         rx
     }
+
+    /**
+     * A queue of the given capacity for a client, which unlike a receiver keeps the latest items
+     * rather than holding up the broadcast once the client falls behind. Every dropped item is
+     * recorded in the drop journal under the client.
+     */
+    pub fn subscribe(&mut self, capacity: usize, client: &str) -> Subscriber<T> {
+        let shared = Arc::new(SubscriberQueue {
+            client: client.to_string(),
+            queue: Mutex::new(Queue {
+                items: VecDeque::with_capacity(capacity),
+                capacity: capacity.max(1),
+                filter: None,
+                dropped: 0,
+                conflating: false,
+                closed: false,
+            }),
+            notify: Notify::new(),
+        });
+        self.queues.push(shared.clone());
+        Subscriber {
+            shared,
+            throttle: None,
+        }
+    }
+
+    /**
+     * A subscriber receiving at most one item per interval, always the latest: the items broadcast
+     * in between replace each other and are recorded in the drop journal as conflated.
+     */
+    pub fn subscribe_throttled(
+        &mut self,
+        interval: Duration,
+        client: &str,
+        clock: Arc<dyn Clock>,
+    ) -> Subscriber<T> {
+        let mut subscriber = self.subscribe(1, client);
+        subscriber.shared.queue.lock().unwrap().conflating = true;
+        subscriber.throttle = Some(Throttle {
+            interval,
+            clock,
+            next_at: None,
+        });
+        subscriber
+    }
+}
+
+impl<T> Drop for Spmc<T> {
+    fn drop(&mut self) {
+        for shared in &self.queues {
+            shared.queue.lock().unwrap().closed = true;
+            shared.notify.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Spmc;
+    use crate::{clock::ManualClock, journal::Journal};
+    use keyrock_challenge_proto::orderbook::DropReason;
+    use std::{sync::Arc, time::Duration};
+
+    #[tokio::test]
+    async fn should_drop_the_oldest_items_of_a_full_subscriber_only() {
+        // Arrange
+        let journal = Arc::new(Journal::in_memory());
+        let mut spmc = Spmc::new();
+        spmc.set_journal(journal.clone(), "summaries");
+        let mut stalled = spmc.subscribe(2, "dashboard");
+        let mut reading = spmc.subscribe(2, "trader");
+
+        // Act
+        let mut read = Vec::new();
+        for item in 1..=5 {
+            spmc.broadcast(item).await;
+            read.push(reading.recv().await.unwrap());
+        }
+        let dropped: Vec<_> = (spmc.subscribers().into_iter())
+            .map(|state| (state.client.unwrap(), state.dropped))
+            .collect();
+        drop(spmc);
+
+        // Assert
+        assert!(read == [1, 2, 3, 4, 5]);
+        assert!(dropped == [("dashboard".to_string(), 3), ("trader".to_string(), 0)]);
+        assert!(stalled.recv().await == Some(4) && stalled.recv().await == Some(5));
+        assert!(stalled.recv().await.is_none());
+        journal.flush(1000).unwrap();
+        let entries = journal.entries(0, u64::MAX).unwrap();
+        assert!(entries.iter().all(|entry| entry.subject == "dashboard"));
+        assert!(entries
+            .iter()
+            .all(|entry| entry.reason == DropReason::SlowConsumer as i32));
+        assert!(entries.iter().map(|entry| entry.count).sum::<u64>() == 3);
+    }
+
+    #[tokio::test]
+    async fn should_deliver_the_latest_item_once_per_interval_to_a_throttled_subscriber() {
+        // Arrange
+        let clock = Arc::new(ManualClock::new());
+        let mut spmc = Spmc::new();
+        let mut throttled =
+            spmc.subscribe_throttled(Duration::from_millis(100), "dashboard", clock.clone());
+
+        // Act
+        spmc.broadcast(1).await;
+        let first = throttled.recv().await;
+        for item in 2..=4 {
+            spmc.broadcast(item).await;
+        }
+        let next = throttled.recv();
+        tokio::pin!(next);
+        let early = futures::poll!(&mut next);
+        clock.advance(Duration::from_millis(100));
+        let due = futures::poll!(&mut next);
+
+        // Assert
+        assert!(first == Some(1));
+        assert!(early.is_pending());
+        assert!(due == std::task::Poll::Ready(Some(4)));
+    }
 }
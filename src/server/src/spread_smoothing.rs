@@ -0,0 +1,102 @@
+use std::{collections::VecDeque, str::FromStr};
+
+/**
+ * How the published spread is smoothed, parsed from `ema:<alpha>` with alpha in (0, 1] or
+ * `median:<ticks>`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpreadSmoothing {
+    /// exponential moving average, a higher alpha follows the raw spread more closely
+    Ema(f64),
+    /// median of the last ticks, ignores single tick outliers entirely
+    Median(usize),
+}
+
+impl FromStr for SpreadSmoothing {
+    type Err = ();
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.split_once(':').ok_or(())? {
+            ("ema", alpha) => match alpha.parse::<f64>().map_err(|_| ())? {
+                alpha if alpha > 0. && alpha <= 1. => Ok(SpreadSmoothing::Ema(alpha)),
+                _ => Err(()),
+            },
+            ("median", ticks) => match ticks.parse::<usize>().map_err(|_| ())? {
+                0 => Err(()),
+                ticks => Ok(SpreadSmoothing::Median(ticks)),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SpreadSmoother {
+    smoothing: SpreadSmoothing,
+    ema: Option<f64>,
+    recent: VecDeque<f64>,
+}
+
+impl SpreadSmoother {
+    pub fn new(smoothing: SpreadSmoothing) -> Self {
+        SpreadSmoother {
+            smoothing,
+            ema: None,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn smooth(&mut self, spread: f64) -> f64 {
+        match self.smoothing {
+            SpreadSmoothing::Ema(alpha) => {
+                let ema = match self.ema {
+                    Some(ema) => alpha * spread + (1. - alpha) * ema,
+                    None => spread,
+                };
+                self.ema = Some(ema);
+                ema
+            }
+            SpreadSmoothing::Median(ticks) => {
+                if self.recent.len() == ticks {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(spread);
+
+                let mut sorted: Vec<f64> = self.recent.iter().copied().collect();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                match sorted.len() % 2 {
+                    0 => (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.,
+                    _ => sorted[sorted.len() / 2],
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SpreadSmoother, SpreadSmoothing};
+
+    #[test]
+    fn should_ignore_single_tick_outlier_with_median() {
+        let mut smoother = SpreadSmoother::new("median:3".parse().unwrap());
+
+        let smoothed: Vec<f64> = [1., 1., 9., 1., 1.]
+            .iter()
+            .map(|spread| smoother.smooth(*spread))
+            .collect();
+
+        assert!(smoothed == vec![1., 1., 1., 1., 1.]);
+    }
+
+    #[test]
+    fn should_follow_spread_with_ema() {
+        let mut smoother = SpreadSmoother::new(SpreadSmoothing::Ema(0.5));
+
+        assert!(smoother.smooth(2.) == 2.);
+        assert!(smoother.smooth(4.) == 3.);
+        assert!(smoother.smooth(4.) == 3.5);
+        assert!("ema:0".parse::<SpreadSmoothing>().is_err());
+        assert!("mean:3".parse::<SpreadSmoothing>().is_err());
+    }
+}
@@ -0,0 +1,14 @@
+use std::time::Instant;
+
+pub fn elapsed_ns(since: Instant) -> u64 {
+    since.elapsed().as_nanos() as u64
+}
+
+/**
+ * Runs a single pipeline stage and returns its result together with the nanoseconds it took.
+ */
+pub fn timed<T>(stage: impl FnOnce() -> T) -> (T, u64) {
+    let started = Instant::now();
+    let result = stage();
+    (result, elapsed_ns(started))
+}
@@ -0,0 +1,21 @@
+//! Evicting the books of stalled venues on a timer. When a venue counts as stalled is decided by
+//! the [`Staleness`](keyrock_challenge_core::staleness::Staleness) timeouts of the core crate.
+
+use crate::{aggregator::Aggregator, clock::Clock};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+/**
+ * Checks the venues for staleness once per interval. A stalled venue does not trigger a publish
+ * itself, so the summary without its books is published from here.
+ */
+pub async fn run(
+    aggregator_arc: Arc<Mutex<Aggregator>>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        clock.sleep(interval).await;
+        aggregator_arc.lock().await.evict_stale().await;
+    }
+}
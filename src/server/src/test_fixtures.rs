@@ -0,0 +1,101 @@
+//! Loads the recorded order book fixtures in `src/server/fixtures`.
+//!
+//! Every fixture is a JSON file holding the books the connectors delivered, in the order they
+//! arrived, together with the summary the aggregator is expected to publish after the last of them:
+//!
+//! ```json
+//! {
+//!   "description": "...",
+//!   "ticks": [{ "exchange": "Binance", "bids": [["price", "amount"], ...], "asks": [...] }],
+//!   "expected": { "spread": "0.000017", "bids": [["Binance", "price", "amount"], ...], "asks": [...] }
+//! }
+//! ```
+
+use crate::{connector_sdk, exchange_registry};
+use keyrock_challenge_core::orderbook_snapshot::OrderbookSnapshot;
+use keyrock_challenge_proto::orderbook::Level;
+use serde_json::Value;
+use std::{fs, path::Path};
+
+pub struct Fixture {
+    pub name: String,
+    pub ticks: Vec<(String, OrderbookSnapshot)>,
+    pub spread: f64,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+/**
+ * The float an expected value is published as, the nearest one to its exact decimal.
+ */
+fn expected_number(raw: &Value, name: &str) -> f64 {
+    connector_sdk::parse_decimal(raw)
+        .unwrap_or_else(|_| panic!("Invalid expected {}", name))
+        .to_f64()
+}
+
+fn expected_levels(raw: &Value) -> Vec<Level> {
+    raw.as_array()
+        .expect("Expected levels have to be an array")
+        .iter()
+        .map(|entry| {
+            let exchange = entry[0].as_str().expect("Expected level without exchange");
+            Level {
+                exchange: exchange.to_string(),
+                price: expected_number(&entry[1], "price"),
+                amount: expected_number(&entry[2], "amount"),
+                exchange_id: exchange_registry::exchange_id(exchange) as i32,
+                contributors: Vec::new(),
+                provenance: 0,
+            }
+        })
+        .collect()
+}
+
+fn load(path: &Path, depth: usize) -> Fixture {
+    let content = fs::read_to_string(path).expect("Unable to read fixture");
+    let raw: Value = serde_json::from_str(&content)
+        .unwrap_or_else(|_| panic!("Invalid fixture {}", path.display()));
+
+    let ticks = raw["ticks"]
+        .as_array()
+        .expect("Fixture without ticks")
+        .iter()
+        .map(|tick| {
+            let exchange = tick["exchange"].as_str().expect("Tick without exchange");
+            let snapshot =
+                connector_sdk::parse_snapshot(exchange, &tick["bids"], &tick["asks"], depth)
+                    .unwrap_or_else(|error| {
+                        panic!("Invalid tick in {}: {}", path.display(), error)
+                    });
+            (exchange.to_string(), snapshot)
+        })
+        .collect();
+    let expected = &raw["expected"];
+
+    Fixture {
+        name: path.file_stem().unwrap().to_string_lossy().to_string(),
+        ticks,
+        spread: expected_number(&expected["spread"], "spread"),
+        bids: expected_levels(&expected["bids"]),
+        asks: expected_levels(&expected["asks"]),
+    }
+}
+
+/**
+ * Loads every fixture with the books cut to the given depth.
+ */
+pub fn load_all(depth: usize) -> Vec<Fixture> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .expect("Unable to read the fixtures directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+
+    paths.iter().map(|path| load(path, depth)).collect()
+}
@@ -0,0 +1,147 @@
+//! Detects trade-throughs, i.e. executions at a venue at a price worse than the best price of the
+//! opposite side of the merged book. A buy above the merged best ask could have been filled cheaper
+//! at another venue. Such events point at stale books as well as at venues worth routing to.
+
+use crate::{exchange_registry::DisplayNames, spmc::Spmc};
+use keyrock_challenge_core::decimal::Decimal;
+use keyrock_challenge_proto::orderbook::{Summary, TradeThrough};
+use std::sync::Arc;
+use tokio::sync::{mpsc::Receiver, watch, Mutex};
+
+/**
+ * An execution as reported by a venue's trade stream.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trade {
+    pub exchange: &'static str,
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// whether the taker bought, i.e. the trade lifted an ask rather than hit a bid
+    pub buy: bool,
+    /// event time reported by the exchange in microseconds since the unix epoch, if provided
+    pub exchange_timestamp_us: Option<u64>,
+}
+
+/**
+ * The trade-through of the trade against the summary, none if the trade was executed at or better
+ * than the merged best price it took out.
+ */
+pub fn trade_through(trade: &Trade, summary: &Summary) -> Option<TradeThrough> {
+    let best = match trade.buy {
+        true => summary.asks.first()?,
+        false => summary.bids.first()?,
+    };
+    let best_price = Decimal::try_from(best.price).ok()?;
+    let magnitude = match trade.buy {
+        true => trade.price - best_price,
+        false => best_price - trade.price,
+    };
+    if !magnitude.is_positive() {
+        return None;
+    }
+
+    Some(TradeThrough {
+        symbol: summary.symbol.clone(),
+        sequence: summary.sequence,
+        exchange: trade.exchange.to_string(),
+        buy: trade.buy,
+        price: trade.price.to_f64(),
+        amount: trade.amount.to_f64(),
+        best_exchange: best.exchange.clone(),
+        best_price: best.price,
+        magnitude: magnitude.to_f64(),
+        magnitude_bps: magnitude.to_f64() / best.price * 10_000.,
+        exchange_timestamp_us: trade.exchange_timestamp_us,
+    })
+}
+
+/**
+ * Checks every trade against the summary published last and publishes the trade-throughs.
+ */
+pub async fn run(
+    mut rx: Receiver<Trade>,
+    latest_summary: watch::Receiver<Option<Summary>>,
+    display_names: DisplayNames,
+    spmc: Arc<Mutex<Spmc<TradeThrough>>>,
+) {
+    while let Some(trade) = rx.recv().await {
+        let event = match &*latest_summary.borrow() {
+            Some(summary) => trade_through(&trade, summary),
+            None => None,
+        };
+        if let Some(mut event) = event {
+            event.exchange = display_names.label(&event.exchange).to_string();
+            spmc.lock().await.broadcast(event).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{trade_through, Trade};
+    use keyrock_challenge_proto::orderbook::{Level, Summary};
+
+    fn level(exchange: &str, price: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount: 1.,
+            ..Default::default()
+        }
+    }
+
+    fn trade(price: &str, buy: bool) -> Trade {
+        Trade {
+            exchange: "Bitstamp",
+            price: price.parse().unwrap(),
+            amount: "0.5".parse().unwrap(),
+            buy,
+            exchange_timestamp_us: Some(1_000),
+        }
+    }
+
+    #[test]
+    fn should_detect_trades_through_the_merged_book() {
+        // Arrange
+        let summary = Summary {
+            bids: vec![level("Binance", 0.0745)],
+            asks: vec![level("Binance", 0.0746)],
+            sequence: 7,
+            ..Default::default()
+        };
+
+        // Act
+        let bought = trade_through(&trade("0.07461", true), &summary).unwrap();
+        let sold = trade_through(&trade("0.07449", false), &summary).unwrap();
+
+        // Assert
+        assert!(bought.magnitude == 0.00001 && bought.best_exchange == "Binance");
+        assert!(bought.exchange == "Bitstamp" && bought.sequence == 7 && bought.buy);
+        assert!(sold.magnitude == 0.00001 && sold.best_price == 0.0745 && !sold.buy);
+        assert!((bought.magnitude_bps - 1.3404825737265416).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_ignore_trades_within_the_merged_book() {
+        // Arrange
+        let summary = Summary {
+            bids: vec![level("Binance", 0.0745)],
+            asks: vec![level("Binance", 0.0746)],
+            ..Default::default()
+        };
+
+        // Act
+        let at_best = trade_through(&trade("0.0746", true), &summary);
+        let inside = trade_through(&trade("0.07455", false), &summary);
+        let one_sided = trade_through(
+            &trade("0.0747", true),
+            &Summary {
+                bids: summary.bids.clone(),
+                ..Default::default()
+            },
+        );
+
+        // Assert
+        assert!(at_best.is_none() && inside.is_none() && one_sided.is_none());
+    }
+}
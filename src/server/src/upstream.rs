@@ -0,0 +1,151 @@
+use crate::log;
+use keyrock_challenge_proto::orderbook::{
+    orderbook_aggregator_client::OrderbookAggregatorClient, ResumeRequest, Summary,
+};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/**
+ * Remembers the last summary relayed from the upstream, so a reconnect resumes right after it.
+ */
+#[derive(Debug, Default)]
+pub struct ResumePoint {
+    last_sequence: Option<u64>,
+    last_epoch: u64,
+}
+
+impl ResumePoint {
+    pub fn request(&self) -> ResumeRequest {
+        ResumeRequest {
+            last_sequence: self.last_sequence.unwrap_or(0),
+            last_epoch: self.last_epoch,
+        }
+    }
+
+    /**
+     * Returns if the summary has to be relayed, which is not the case for one that already was.
+     * Starts over with the summary if the upstream started a new sequence, which is told by a new
+     * epoch, or by the restarted flag of an upstream not reporting epochs.
+     * Logs if the upstream could not replay everything since the last relayed summary.
+     */
+    pub fn advance(&mut self, summary: &Summary) -> bool {
+        match self.last_sequence {
+            Some(_) if summary.epoch != self.last_epoch => {
+                log::warning!("Upstream started a new sequence in epoch {}", summary.epoch)
+            }
+            Some(last_sequence) if summary.sequence <= last_sequence && summary.restarted => {
+                log::warning!("Upstream restarted without continuing its sequence")
+            }
+            Some(last_sequence) if summary.sequence <= last_sequence => return false,
+            Some(last_sequence) if summary.sequence > last_sequence + 1 => log::warning!(
+                "Upstream was unable to replay the summaries {} to {}",
+                last_sequence + 1,
+                summary.sequence - 1
+            ),
+            _ => {}
+        }
+        self.last_sequence = Some(summary.sequence);
+        self.last_epoch = summary.epoch;
+        true
+    }
+}
+
+/**
+ * Relays the summaries of an upstream server to the delivery of the pipeline. After the connection
+ * to the upstream was lost, it is asked to replay everything published since the last relayed
+ * summary.
+ */
+pub async fn run_relay(url: String, delivery: mpsc::Sender<Summary>) {
+    let mut resume_point = ResumePoint::default();
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let result: Result<(), Box<dyn std::error::Error + Send + Sync>> = async {
+            let mut client = OrderbookAggregatorClient::connect(url.clone()).await?;
+            let mut stream = client
+                .resume_book_summary(resume_point.request())
+                .await?
+                .into_inner();
+            backoff = INITIAL_BACKOFF;
+
+            while let Some(summary) = stream.message().await? {
+                if resume_point.advance(&summary) {
+                    // closed only once the pipeline stopped, along with this task
+                    let _ = delivery.send(summary).await;
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        match result {
+            Ok(_) => log::warning!(
+                "Upstream stream closed, reconnecting in {}ms",
+                backoff.as_millis()
+            ),
+            Err(error) => log::warning!(
+                "Upstream stream failed ({}), reconnecting in {}ms",
+                error,
+                backoff.as_millis()
+            ),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResumePoint;
+    use keyrock_challenge_proto::orderbook::Summary;
+
+    fn summary(sequence: u64) -> Summary {
+        Summary {
+            sequence,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_resume_after_last_relayed_summary() {
+        let mut resume_point = ResumePoint::default();
+        assert!(resume_point.request().last_sequence == 0);
+
+        assert!(resume_point.advance(&summary(7)));
+        assert!(resume_point.advance(&summary(8)));
+        assert!(!resume_point.advance(&summary(8)));
+        assert!(!resume_point.advance(&summary(3)));
+        assert!(resume_point.advance(&summary(12)));
+
+        assert!(resume_point.request().last_sequence == 12);
+        assert!(resume_point.advance(&Summary {
+            restarted: true,
+            ..summary(1)
+        }));
+    }
+
+    #[test]
+    fn should_start_over_once_the_upstream_starts_a_new_epoch() {
+        // Arrange
+        let mut resume_point = ResumePoint::default();
+        let summary = |epoch: u64, sequence: u64| Summary {
+            epoch,
+            sequence,
+            ..Default::default()
+        };
+        assert!(resume_point.advance(&summary(1_000, 500)));
+
+        // Act
+        // replayed after the upstream restarted without its sequence file, not restarted any more
+        let replayed = [2, 3].map(|sequence| resume_point.advance(&summary(2_000, sequence)));
+
+        // Assert
+        assert!(replayed == [true, true]);
+        assert!(!resume_point.advance(&summary(2_000, 3)));
+        let request = resume_point.request();
+        assert!(request.last_sequence == 3 && request.last_epoch == 2_000);
+    }
+}
@@ -0,0 +1,210 @@
+//! Boots the server binary with simulated venues and talks to it over a loopback gRPC channel, the
+//! way a downstream system linking the client library does.
+
+use keyrock_challenge_client::domain::{self, Exchange};
+use keyrock_challenge_proto::orderbook::{
+    orderbook_admin_client::OrderbookAdminClient,
+    orderbook_aggregator_client::OrderbookAggregatorClient, BookSummaryRequest, Empty,
+    ResumeRequest, Summary,
+};
+use std::{
+    net::TcpListener,
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+use tonic::{transport::Channel, Streaming};
+
+/// how long the server may take to bind its listener, it waits for the simulated venues first
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const DRAIN_SECS: u64 = 1;
+
+/**
+ * A server process, killed once the test is done with it.
+ */
+struct Server {
+    process: Child,
+    url: String,
+}
+
+impl Server {
+    /**
+     * Starts the server on a free loopback port and waits until it accepts connections.
+     */
+    async fn start() -> Server {
+        // the port is free once the probing listener is dropped, unless another process grabs it
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let process = Command::new(env!("CARGO_BIN_EXE_keyrock_challenge_server"))
+            .args(["--simulated", "--reuse-port"])
+            .args(["--listen", &format!("127.0.0.1:{}", port)])
+            .args(["--drain-secs", &DRAIN_SECS.to_string()])
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Unable to start the server");
+        let server = Server {
+            process,
+            url: format!("http://127.0.0.1:{}", port),
+        };
+
+        let started = Instant::now();
+        while server.client().await.is_none() {
+            assert!(
+                started.elapsed() < STARTUP_TIMEOUT,
+                "The server did not start listening"
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        server
+    }
+
+    async fn client(&self) -> Option<OrderbookAggregatorClient<Channel>> {
+        OrderbookAggregatorClient::connect(self.url.clone())
+            .await
+            .ok()
+    }
+
+    async fn admin(&self) -> OrderbookAdminClient<Channel> {
+        OrderbookAdminClient::connect(self.url.clone())
+            .await
+            .unwrap()
+    }
+
+    /**
+     * Waits for the process to exit on its own, None if it is still running after the timeout.
+     */
+    async fn exited_within(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            if let Some(status) = self.process.try_wait().unwrap() {
+                return Some(status);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        None
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+async fn receive(stream: &mut Streaming<Summary>, count: usize) -> Vec<domain::Summary> {
+    let mut summaries = Vec::new();
+    while summaries.len() < count {
+        let summary = stream.message().await.unwrap().expect("The stream ended");
+        summaries.push(domain::Summary::try_from(summary).unwrap());
+    }
+    summaries
+}
+
+fn consecutive(summaries: &[domain::Summary]) -> bool {
+    summaries
+        .windows(2)
+        .all(|pair| pair[1].sequence == pair[0].sequence + 1)
+}
+
+#[tokio::test]
+async fn should_stream_merged_summaries_of_the_simulated_venues() {
+    // Arrange
+    let server = Server::start().await;
+    let mut client = server.client().await.unwrap();
+
+    // Act
+    let mut stream = client
+        .book_summary(BookSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let summaries = receive(&mut stream, 5).await;
+
+    // Assert
+    assert!(consecutive(&summaries));
+    for summary in &summaries {
+        assert!(summary.symbol == "ethbtc" && !summary.bids.is_empty() && !summary.asks.is_empty());
+        assert!(summary
+            .bids
+            .windows(2)
+            .all(|pair| pair[0].price >= pair[1].price));
+        assert!(summary
+            .asks
+            .windows(2)
+            .all(|pair| pair[0].price <= pair[1].price));
+        assert!(summary.aggregated_at.is_some() && summary.mid.is_some());
+    }
+    // every exchange of the registry is simulated, and known to the client library
+    assert!(summaries
+        .iter()
+        .flat_map(|summary| summary.bids.iter().chain(&summary.asks))
+        .all(|level| !matches!(level.exchange, Exchange::Other(_))));
+}
+
+#[tokio::test]
+async fn should_resume_right_after_the_last_received_summary() {
+    // Arrange
+    let server = Server::start().await;
+    let mut client = server.client().await.unwrap();
+    let mut stream = client
+        .book_summary(BookSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let last = receive(&mut stream, 1).await.remove(0);
+    drop(stream);
+    // the summaries published meanwhile are missed by the dropped subscription
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Act
+    let mut resumed = client
+        .resume_book_summary(ResumeRequest {
+            last_sequence: last.sequence,
+            last_epoch: last.epoch,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let summaries = receive(&mut resumed, 5).await;
+
+    // Assert
+    assert!(summaries[0].sequence == last.sequence + 1);
+    assert!(consecutive(&summaries));
+}
+
+#[tokio::test]
+async fn should_stop_accepting_and_exit_once_drained() {
+    // Arrange
+    let mut server = Server::start().await;
+    let mut client = server.client().await.unwrap();
+    let mut stream = client
+        .book_summary(BookSummaryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    receive(&mut stream, 1).await;
+
+    // Act
+    server.admin().await.drain(Empty {}).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let subscribed_while_draining = receive(&mut stream, 1).await;
+    let connected_while_draining = server.client().await.is_some();
+    let status = server
+        .exited_within(Duration::from_secs(DRAIN_SECS + 10))
+        .await;
+    // the summaries received before the exit are still buffered
+    let stream_ended = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Ok(Some(_)) = stream.message().await {}
+    })
+    .await
+    .is_ok();
+
+    // Assert
+    assert!(subscribed_while_draining.len() == 1);
+    assert!(!connected_while_draining);
+    assert!(status.is_some_and(|status| status.success()));
+    assert!(stream_ended);
+}
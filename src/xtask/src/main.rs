@@ -0,0 +1,90 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+const CONNECTOR_TEMPLATE: &str = include_str!("../templates/connector.rs.tmpl");
+
+const USAGE: &str = "Usage: cargo xtask new-connector <name>";
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .ancestors()
+        .nth(2)
+        .unwrap()
+        .to_path_buf()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn display_name(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn new_connector(name: &str) -> Result<(), String> {
+    if !is_valid_name(name) {
+        return Err(format!(
+            "'{}' is not a valid connector name, use lowercase snake_case",
+            name
+        ));
+    }
+
+    let module = format!("{}_spot", name);
+    let path = workspace_root()
+        .join("src/server/src")
+        .join(format!("{}.rs", module));
+
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+
+    let source = CONNECTOR_TEMPLATE.replace("{{display_name}}", &display_name(name));
+    fs::write(&path, source)
+        .map_err(|error| format!("Unable to write {}: {}", path.display(), error))?;
+
+    println!("Created {}", path.display());
+    println!();
+    println!("Next steps:");
+    println!("  1. fill in the TODOs (endpoint, subscription, payload layout, maximum depth)");
+    println!(
+        "  2. declare the module in src/server/src/main.rs: `mod {};`",
+        module
+    );
+    println!(
+        "  3. add an `ExchangeSource` with `{}::connector` to the registry in src/server/src/exchange_source.rs",
+        module
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["new-connector", name] => new_connector(name),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}